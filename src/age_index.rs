@@ -0,0 +1,87 @@
+//! Index of the nodes of a section by age, kept in sync with the nodes as they
+//! join, leave or age, so that selecting nodes by age (relocation candidates,
+//! elders, drops) doesn't need to scan and sort the whole section.
+
+use Age;
+use node::Node;
+use prefix::Name;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Clone, Default)]
+pub struct AgeIndex {
+    ages: BTreeMap<Age, BTreeSet<Name>>,
+    len: usize,
+}
+
+impl AgeIndex {
+    pub fn new() -> Self {
+        AgeIndex::default()
+    }
+
+    pub fn build<'a, I: IntoIterator<Item = &'a Node>>(nodes: I) -> Self {
+        let mut index = AgeIndex::new();
+        for node in nodes {
+            index.insert(node);
+        }
+        index
+    }
+
+    pub fn insert(&mut self, node: &Node) {
+        if self.ages.entry(node.age()).or_default().insert(node.name()) {
+            self.len += 1;
+        }
+    }
+
+    pub fn remove(&mut self, node: &Node) {
+        let removed = if let Some(names) = self.ages.get_mut(&node.age()) {
+            names.remove(&node.name())
+        } else {
+            false
+        };
+
+        if removed {
+            self.len -= 1;
+            if self.ages[&node.age()].is_empty() {
+                let _ = self.ages.remove(&node.age());
+            }
+        }
+    }
+
+    pub fn extend(&mut self, other: AgeIndex) {
+        for (age, names) in other.ages {
+            let entry = self.ages.entry(age).or_default();
+            let before = entry.len();
+            entry.extend(names);
+            self.len += entry.len() - before;
+        }
+    }
+
+    /// Names of the nodes sorted by age (from youngest to oldest), then by name.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = Name> + '_ {
+        self.ages.values().flat_map(|names| names.iter().cloned())
+    }
+
+    /// Names of the nodes grouped by age, from youngest to oldest.
+    pub fn groups(&self) -> impl DoubleEndedIterator<Item = (Age, &BTreeSet<Name>)> {
+        self.ages.iter().map(|(&age, names)| (age, names))
+    }
+
+    /// Like `groups`, but only the nodes at most `age` old.
+    pub fn groups_up_to(
+        &self,
+        age: Age,
+    ) -> impl DoubleEndedIterator<Item = (Age, &BTreeSet<Name>)> {
+        self.ages.range(..=age).map(|(&age, names)| (age, names))
+    }
+
+    /// Returns whether the index contains exactly the given nodes, at their
+    /// current ages.
+    pub fn matches(&self, nodes: &BTreeMap<Name, Node>) -> bool {
+        self.len == nodes.len() &&
+            self.ages.iter().all(|(&age, names)| {
+                names.iter().all(|name| {
+                    nodes.get(name).map(|node| node.age()) == Some(age)
+                })
+            })
+    }
+}
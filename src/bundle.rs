@@ -0,0 +1,212 @@
+//! Single archive of the outputs of a run (see `Params::bundle_file`), for
+//! sharing and archiving the artifacts of an experiment as one unit.
+//!
+//! The archive is a gzipped tar holding:
+//!
+//! - `inputs/PATH`: the input files of the run (churn trace, layout),
+//! - `outputs/PATH`: the output files written by the run,
+//! - `snapshots/`: the checkpoint of the final state of the network (see
+//!   `soak`), from which the run can be continued,
+//! - `report.txt`: the termination, parameters and headline statistics,
+//! - `MANIFEST`: every other entry with its size and SHA3-256 hash, e.g.
+//!   `outputs/stats.dat 48213 9c1e...`.
+//!
+//! `PATH` is the path the file was given by, relative to its root, with `..`
+//! written as `__`, so files with the same name in different directories
+//! don't overwrite each other.
+//!
+//! The entries are sorted by name and carry no timestamps or owners, and the
+//! report holds no wall-clock timings, so the same run always produces the
+//! same archive.
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::collections::BTreeMap;
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path};
+use tar::{Builder, Header};
+use tiny_keccak::sha3_256;
+
+pub struct Bundle {
+    // Entry name and content.
+    entries: BTreeMap<String, Vec<u8>>,
+}
+
+impl Bundle {
+    pub fn new() -> Self {
+        Bundle { entries: BTreeMap::new() }
+    }
+
+    /// Add the input file under `inputs/`.
+    pub fn add_input<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let content = read(path)?;
+        self.add_content(&entry_name("inputs", path), content)
+    }
+
+    /// Add the output file under `outputs/`, if it exists.
+    pub fn add_output<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(());
+        }
+
+        let content = read(path)?;
+        self.add_content(&entry_name("outputs", path), content)
+    }
+
+    /// Add the files directly in the output directory under `outputs/`.
+    pub fn add_output_dir<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let entries = fs::read_dir(path).map_err(|error| {
+            format!("Couldn't read {}: {}", path.display(), error)
+        })?;
+
+        for entry in entries {
+            let entry = entry.map_err(|error| {
+                format!("Couldn't read {}: {}", path.display(), error)
+            })?;
+            if entry.path().is_file() {
+                self.add_output(entry.path())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add an entry with the given content. Adding a different content under
+    /// the name of an existing entry is an error.
+    pub fn add_content(&mut self, name: &str, content: Vec<u8>) -> Result<(), String> {
+        match self.entries.get(name) {
+            Some(existing) if *existing != content => {
+                return Err(format!("Couldn't bundle {}: another file has the same name", name));
+            }
+            _ => (),
+        }
+
+        let _ = self.entries.insert(name.to_string(), content);
+        Ok(())
+    }
+
+    /// Write the archive with the manifest of the entries added so far.
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let error = |error: io::Error| format!("Couldn't write {}: {}", path.display(), error);
+
+        let file = File::create(path).map_err(&error)?;
+        let mut builder = Builder::new(GzEncoder::new(file, Compression::default()));
+
+        append(&mut builder, "MANIFEST", self.manifest().as_bytes()).map_err(&error)?;
+        for (name, content) in &self.entries {
+            append(&mut builder, name, content).map_err(&error)?;
+        }
+
+        let _ = builder.into_inner().and_then(GzEncoder::finish).map_err(&error)?;
+        Ok(())
+    }
+
+    fn manifest(&self) -> String {
+        let mut manifest = String::new();
+        for (name, content) in &self.entries {
+            let mut hash = String::new();
+            for byte in &sha3_256(content) {
+                let _ = write!(hash, "{:02x}", byte);
+            }
+            let _ = writeln!(manifest, "{} {} {}", name, content.len(), hash);
+        }
+        manifest
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|error| format!("Couldn't read {}: {}", path.display(), error))
+}
+
+// Name of the file in the archive: its path under `dir`, without the root and
+// with the parent directories written as `__`.
+fn entry_name(dir: &str, path: &Path) -> String {
+    let mut name = dir.to_string();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => {
+                name.push('/');
+                name.push_str(&part.to_string_lossy());
+            }
+            Component::ParentDir => name.push_str("/__"),
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => (),
+        }
+    }
+    name
+}
+
+fn append(builder: &mut Builder<GzEncoder<File>>, name: &str, content: &[u8]) -> io::Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_cksum();
+    builder.append_data(&mut header, name, content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::env;
+    use std::io::Read;
+    use std::process;
+    use tar::Archive;
+
+    #[test]
+    fn entry_names() {
+        assert_eq!(entry_name("outputs", Path::new("stats.dat")), "outputs/stats.dat");
+        assert_eq!(entry_name("outputs", Path::new("./a/stats.dat")), "outputs/a/stats.dat");
+        assert_eq!(entry_name("inputs", Path::new("../trace.csv")), "inputs/__/trace.csv");
+        assert_eq!(entry_name("inputs", Path::new("/tmp/trace.csv")), "inputs/tmp/trace.csv");
+    }
+
+    #[test]
+    fn same_content_gives_same_archive() {
+        let dir = env::temp_dir().join(format!("datachains_sim-bundle-test-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for sub in &["a", "b"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+            fs::write(dir.join(sub).join("stats.dat"), sub.as_bytes()).unwrap();
+        }
+
+        let mut bundle = Bundle::new();
+        bundle.add_output(dir.join("a/stats.dat")).unwrap();
+        bundle.add_output(dir.join("b/stats.dat")).unwrap();
+        bundle.add_output(dir.join("missing.dat")).unwrap();
+        bundle.add_content("report.txt", b"report".to_vec()).unwrap();
+        assert!(bundle.add_content("report.txt", b"other".to_vec()).is_err());
+
+        let first = dir.join("first.tar.gz");
+        let second = dir.join("second.tar.gz");
+        bundle.write_to_file(&first).unwrap();
+        bundle.write_to_file(&second).unwrap();
+        let content = fs::read(&first).unwrap();
+        assert_eq!(content, fs::read(&second).unwrap());
+
+        let mut archive = Archive::new(GzDecoder::new(&content[..]));
+        let mut entries = BTreeMap::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().display().to_string();
+            let mut content = String::new();
+            let _ = entry.read_to_string(&mut content).unwrap();
+            let _ = entries.insert(name, content);
+        }
+        let _ = fs::remove_dir_all(&dir);
+
+        let a = entry_name("outputs", &dir.join("a/stats.dat"));
+        let b = entry_name("outputs", &dir.join("b/stats.dat"));
+        assert_eq!(entries[&a], "a");
+        assert_eq!(entries[&b], "b");
+        assert_eq!(entries["report.txt"], "report");
+        assert_eq!(entries["MANIFEST"].lines().count(), 3);
+        assert!(entries["MANIFEST"].starts_with(&format!("{} 1 ", a)));
+    }
+}
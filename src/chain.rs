@@ -1,36 +1,217 @@
 use Age;
+use HashMap;
+use HashSet;
 use byteorder::{ByteOrder, LittleEndian};
-use prefix::Name;
+use prefix::{Name, Prefix};
 use rand::{Rand, Rng};
+use snapshot::{Reader, Snapshot, Writer};
+use std::collections::VecDeque;
 use std::fmt;
+use std::mem;
 use std::ops::Deref;
+use std::rc::Rc;
 use tiny_keccak::sha3_256;
 
+/// Number of most recent blocks kept for diagnostics.
+const NUM_RECENT_BLOCKS: usize = 16;
+
 #[derive(Clone)]
 pub struct Chain {
     last_live: Option<Block>,
+    recent: VecDeque<Block>,
+    // Number of blocks inserted and ticks passed since `last_live`, to tell
+    // whether a node keeping only part of the chain would still have it.
+    blocks_since_live: u64,
+    ticks_since_live: u64,
+    // In full-chain mode: every block inserted, and the last lifecycle event of
+    // every node, to audit the blocks as they are inserted.
+    full: Option<Rc<Segment>>,
+    lifecycles: HashMap<Name, Event>,
+    violations: Vec<String>,
 }
 
 impl Chain {
     pub fn new() -> Self {
-        Chain { last_live: None }
+        Chain {
+            last_live: None,
+            recent: VecDeque::with_capacity(NUM_RECENT_BLOCKS),
+            blocks_since_live: 0,
+            ticks_since_live: 0,
+            full: None,
+            lifecycles: HashMap::default(),
+            violations: Vec::new(),
+        }
+    }
+
+    /// Switch the full-chain mode on: from now on, every block is kept and
+    /// audited (see `take_violations`).
+    pub fn keep_all(&mut self) {
+        if self.full.is_none() {
+            self.full = Some(Rc::new(Segment {
+                parents: Vec::new(),
+                blocks: self.recent.iter().cloned().collect(),
+            }));
+        }
     }
 
     pub fn insert(&mut self, block: Block) {
+        if self.recent.len() == NUM_RECENT_BLOCKS {
+            let _ = self.recent.pop_front();
+        }
+        self.recent.push_back(block.clone());
+        self.audit(&block);
+
         if let Event::Live = block.event {
-            self.last_live = Some(block)
+            self.last_live = Some(block);
+            self.blocks_since_live = 0;
+            self.ticks_since_live = 0;
+        } else {
+            self.blocks_since_live += 1;
         }
     }
 
     pub fn extend(&mut self, other: Chain) {
+        let num_other = other.recent.len() as u64;
+
+        // The nodes of the merged sections are distinct, so their lifecycles
+        // don't interleave.
+        let parents: Vec<_> = self.full.take().into_iter().chain(other.full).collect();
+        if !parents.is_empty() {
+            self.full = Some(Rc::new(Segment {
+                parents,
+                blocks: Vec::new(),
+            }));
+        }
+        self.lifecycles.extend(other.lifecycles);
+        self.violations.extend(other.violations);
+
+        for block in other.recent {
+            if self.recent.len() == NUM_RECENT_BLOCKS {
+                let _ = self.recent.pop_front();
+            }
+            self.recent.push_back(block);
+        }
+
         if let Some(block) = other.last_live {
-            self.last_live = Some(block)
+            self.last_live = Some(block);
+            self.blocks_since_live = other.blocks_since_live;
+            self.ticks_since_live = other.ticks_since_live;
+        } else {
+            self.blocks_since_live += num_other;
         }
     }
 
+    /// Advance the chain by one tick.
+    pub fn tick(&mut self) {
+        self.ticks_since_live += 1;
+    }
+
     pub fn last_live(&self) -> Option<Block> {
         self.last_live.clone()
     }
+
+    /// Returns whether `last_live` would still be retained when keeping only
+    /// the last `max_blocks` blocks and the blocks of the last `max_ticks`
+    /// ticks (0 meaning no limit).
+    pub fn is_last_live_retained(&self, max_blocks: u64, max_ticks: u64) -> bool {
+        (max_blocks == 0 || self.blocks_since_live < max_blocks) &&
+            (max_ticks == 0 || self.ticks_since_live < max_ticks)
+    }
+
+    /// The most recently inserted blocks, from oldest to newest.
+    pub fn recent(&self) -> &VecDeque<Block> {
+        &self.recent
+    }
+
+    /// Number of blocks of the full chain, including those inserted before the
+    /// section split off or merged, in full-chain mode.
+    pub fn num_blocks(&self) -> Option<usize> {
+        let mut visited = HashSet::default();
+        let mut pending: Vec<_> = self.full.iter().collect();
+        let mut result = 0;
+
+        while let Some(segment) = pending.pop() {
+            if visited.insert(&**segment as *const Segment) {
+                result += segment.blocks.len();
+                pending.extend(&segment.parents);
+            }
+        }
+
+        self.full.as_ref().map(|_| result)
+    }
+
+    /// Keep auditing only the lifecycles of the nodes matching the prefix,
+    /// after the section split.
+    pub fn retain_lifecycles(&mut self, prefix: Prefix) {
+        self.lifecycles.retain(|name, _| prefix.matches(*name));
+    }
+
+    /// The lifecycle violations found since the last call.
+    pub fn take_violations(&mut self) -> Vec<String> {
+        mem::take(&mut self.violations)
+    }
+
+    // In full-chain mode, check that the node's blocks form a valid lifecycle:
+    // `Live`, then alternately `Gone` and `Live`, and possibly a final `Dead`.
+    fn audit(&mut self, block: &Block) {
+        let segment = match self.full {
+            Some(ref mut segment) => segment,
+            None => return,
+        };
+        // The segment is shared after a split: start a new one on top of it.
+        if Rc::get_mut(segment).is_none() {
+            *segment = Rc::new(Segment {
+                parents: vec![segment.clone()],
+                blocks: Vec::new(),
+            });
+        }
+        Rc::get_mut(segment).unwrap().blocks.push(block.clone());
+
+        let previous = self.lifecycles.insert(block.name, block.event);
+        let valid = match (previous, block.event) {
+            (Some(Event::Dead), _) => false,
+            (None, Event::Live) |
+            (Some(Event::Gone), Event::Live) |
+            (Some(Event::Live), Event::Gone) |
+            (Some(Event::Live), Event::Dead) => true,
+            _ => false,
+        };
+
+        if !valid {
+            self.violations.push(match previous {
+                Some(previous) => format!("{:?} after {:?}: {:?}", block.event, previous, block),
+                None => format!("{:?} before Live: {:?}", block.event, block),
+            });
+        }
+    }
+}
+
+// Part of the full chain: the blocks inserted since the section split off or
+// merged, on top of the segments of the sections it came from. The sections
+// resulting from a split share their parent's segments instead of copying them.
+struct Segment {
+    parents: Vec<Rc<Segment>>,
+    blocks: Vec<Block>,
+}
+
+// The full chain isn't part of the snapshot, so the soak mode doesn't support
+// the full-chain mode.
+impl Snapshot for Chain {
+    fn save(&self, writer: &mut Writer) {
+        self.last_live.save(writer);
+        self.recent.save(writer);
+        self.blocks_since_live.save(writer);
+        self.ticks_since_live.save(writer);
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        let mut chain = Chain::new();
+        chain.last_live = Snapshot::load(reader)?;
+        chain.recent = Snapshot::load(reader)?;
+        chain.blocks_since_live = Snapshot::load(reader)?;
+        chain.ticks_since_live = Snapshot::load(reader)?;
+        Ok(chain)
+    }
 }
 
 impl fmt::Debug for Chain {
@@ -46,6 +227,12 @@ pub struct Block {
     age: Age,
 }
 
+impl fmt::Debug for Block {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Block({:?}, {:?}, age={})", self.event, self.name, self.age)
+    }
+}
+
 impl Block {
     pub fn new(event: Event, name: Name, age: Age) -> Self {
         Block { event, name, age }
@@ -57,6 +244,7 @@ impl Block {
             Event::Live => 0,
             Event::Dead => 1,
             Event::Gone => 2,
+            Event::Rejected => 3,
         };
 
         LittleEndian::write_u64(&mut bytes[1..], self.name.0);
@@ -66,21 +254,119 @@ impl Block {
     }
 }
 
+impl Snapshot for Block {
+    fn save(&self, writer: &mut Writer) {
+        self.event.save(writer);
+        self.name.save(writer);
+        self.age.save(writer);
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok(Block {
+            event: Snapshot::load(reader)?,
+            name: Snapshot::load(reader)?,
+            age: Snapshot::load(reader)?,
+        })
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Event {
     Live,
     Dead,
     Gone,
+    /// Rejected join attempt. Never stored in the chain, only used to
+    /// optionally trigger ageing.
+    Rejected,
+}
+
+impl Snapshot for Event {
+    fn save(&self, writer: &mut Writer) {
+        writer.put(*self as u8)
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        match reader.get::<u8>()? {
+            0 => Ok(Event::Live),
+            1 => Ok(Event::Dead),
+            2 => Ok(Event::Gone),
+            3 => Ok(Event::Rejected),
+            tag => Err(format!("invalid block event in snapshot: {}", tag)),
+        }
+    }
+}
+
+/// What a name derived from a hash is for (see `Hash::derive`).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Purpose {
+    /// Target of a relocation selected by an ageing check.
+    Ageing,
+    /// New target of a relocation the destination rejected.
+    Retry,
+    /// Target of the relocation of an elder past its term limit.
+    TermLimit,
+    /// Section a freshly joining node is redirected to.
+    JoinRedirect,
+}
+
+impl Purpose {
+    fn tag(self) -> &'static [u8] {
+        match self {
+            Purpose::Ageing => b"relocation/ageing",
+            Purpose::Retry => b"relocation/retry",
+            Purpose::TermLimit => b"relocation/term-limit",
+            Purpose::JoinRedirect => b"join/redirect",
+        }
+    }
 }
 
 #[derive(Clone, Copy, Eq, PartialEq)]
 pub struct Hash([u8; 32]);
 
 impl Hash {
+    pub fn digest(bytes: &[u8]) -> Self {
+        Hash(sha3_256(bytes))
+    }
+
     pub fn rehash(&self) -> Self {
         Hash(sha3_256(&self.0))
     }
 
+    /// Hash of `seed` for the given purpose, source prefix and attempt, so the
+    /// names derived for different purposes, sources or attempts are
+    /// independent even if derived from the same seed. Hashes the purpose tag,
+    /// a zero byte, the prefix length, the prefix bits (little-endian, padded
+    /// with zeros to 8 bytes), the attempt (little-endian, 4 bytes) and the
+    /// seed.
+    pub fn derive(purpose: Purpose, source: Prefix, attempt: u32, seed: &Hash) -> Self {
+        let tag = purpose.tag();
+        let mut bytes = Vec::with_capacity(tag.len() + 46);
+        bytes.extend_from_slice(tag);
+        bytes.push(0);
+        bytes.push(source.len());
+
+        let mut buffer = [0; 8];
+        LittleEndian::write_u64(&mut buffer, source.substituted_in(Name(0)).0);
+        bytes.extend_from_slice(&buffer);
+        LittleEndian::write_u32(&mut buffer[..4], attempt);
+        bytes.extend_from_slice(&buffer[..4]);
+        bytes.extend_from_slice(&seed.0);
+
+        Hash(sha3_256(&bytes))
+    }
+
+    /// The name made of the first 8 bytes of the hash, read as a big-endian
+    /// number. The remaining 24 bytes are discarded.
+    pub fn truncate_to_name(&self) -> Name {
+        let mut result: u64 = 0;
+
+        for value in &self.0[..8] {
+            result = result * 256 + u64::from(*value);
+        }
+
+        Name(result)
+    }
+
     #[allow(unused)]
     pub fn trailing_zeros(&self) -> u64 {
         let mut result = 0;
@@ -114,16 +400,13 @@ impl From<Name> for Hash {
     }
 }
 
-impl Into<Name> for Hash {
-    fn into(self) -> Name {
-        let mut result: u64 = 0;
 
-        for i in 0..8 {
-            let value = self.0[i];
-            result = result * 256 + u64::from(value);
+impl fmt::Display for Hash {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for byte in &self.0 {
+            write!(fmt, "{:02x}", byte)?;
         }
-
-        Name(result)
+        Ok(())
     }
 }
 
@@ -140,3 +423,148 @@ impl Rand for Hash {
         Hash(rng.gen())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, XorShiftRng};
+
+    const NUM_SAMPLES: usize = 1_000;
+
+    fn rng() -> XorShiftRng {
+        XorShiftRng::from_seed([0x5d2c_91e4, 0x0f6b_37a8, 0xc4e1_7a3d, 0x2b98_d06f])
+    }
+
+    #[test]
+    fn truncation_takes_first_eight_bytes_big_endian() {
+        let mut bytes = [0xff; 32];
+        for (index, byte) in bytes[..8].iter_mut().enumerate() {
+            *byte = index as u8 + 1;
+        }
+
+        assert_eq!(Hash(bytes).truncate_to_name(), Name(0x0102_0304_0506_0708));
+    }
+
+    #[test]
+    fn truncation_round_trips_names() {
+        let mut rng = rng();
+
+        for _ in 0..NUM_SAMPLES {
+            let name = Name(rng.gen());
+            assert_eq!(Hash::from(name).truncate_to_name(), name);
+        }
+    }
+
+    #[test]
+    fn derivation_is_deterministic() {
+        let mut rng = rng();
+        let seed: Hash = rng.gen();
+        let source = Prefix::EMPTY.extend(1).extend(0);
+
+        assert!(
+            Hash::derive(Purpose::Ageing, source, 3, &seed) ==
+                Hash::derive(Purpose::Ageing, source, 3, &seed)
+        );
+    }
+
+    #[test]
+    fn derivation_separates_purposes_sources_and_attempts() {
+        let mut rng = rng();
+        let purposes = [
+            Purpose::Ageing,
+            Purpose::Retry,
+            Purpose::TermLimit,
+            Purpose::JoinRedirect,
+        ];
+        // `0` and `00` have the same bits, differing only in the length.
+        let sources = [
+            Prefix::EMPTY,
+            Prefix::EMPTY.extend(0),
+            Prefix::EMPTY.extend(0).extend(0),
+            Prefix::EMPTY.extend(1),
+        ];
+
+        for _ in 0..NUM_SAMPLES / 100 {
+            let seed: Hash = rng.gen();
+            let mut names = HashSet::default();
+
+            for &purpose in &purposes {
+                for &source in &sources {
+                    for attempt in 0..4 {
+                        let name = Hash::derive(purpose, source, attempt, &seed).truncate_to_name();
+                        assert!(names.insert(name));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn last_live_is_dropped_after_retained_blocks() {
+        let mut chain = Chain::new();
+        chain.insert(Block::new(Event::Live, Name(1), 5));
+        assert!(chain.is_last_live_retained(2, 0));
+
+        chain.insert(Block::new(Event::Gone, Name(2), 5));
+        assert!(chain.is_last_live_retained(2, 0));
+
+        chain.insert(Block::new(Event::Dead, Name(3), 5));
+        assert!(!chain.is_last_live_retained(2, 0));
+        assert!(chain.is_last_live_retained(0, 0));
+
+        // A new `Live` block is retained again.
+        chain.insert(Block::new(Event::Live, Name(4), 5));
+        assert!(chain.is_last_live_retained(2, 0));
+    }
+
+    #[test]
+    fn last_live_is_dropped_after_retained_ticks() {
+        let mut chain = Chain::new();
+        chain.insert(Block::new(Event::Live, Name(1), 5));
+        chain.tick();
+        assert!(chain.is_last_live_retained(0, 2));
+
+        chain.tick();
+        assert!(!chain.is_last_live_retained(0, 2));
+        assert!(chain.is_last_live_retained(3, 0));
+    }
+
+    #[test]
+    fn merged_chain_keeps_retention_of_last_live() {
+        let mut first = Chain::new();
+        first.insert(Block::new(Event::Live, Name(1), 5));
+        first.tick();
+
+        // The other chain has no `Live` block, so its blocks follow ours.
+        let mut second = Chain::new();
+        second.insert(Block::new(Event::Gone, Name(2), 5));
+        second.insert(Block::new(Event::Dead, Name(3), 5));
+
+        let mut merged = first.clone();
+        merged.extend(second);
+        assert!(merged.is_last_live_retained(3, 2));
+        assert!(!merged.is_last_live_retained(2, 0));
+
+        // Otherwise the other chain's `Live` block is the last one.
+        let mut third = Chain::new();
+        third.insert(Block::new(Event::Live, Name(4), 5));
+
+        let mut merged = first;
+        merged.extend(third);
+        assert_eq!(merged.last_live().map(|block| block.name), Some(Name(4)));
+        assert!(merged.is_last_live_retained(1, 1));
+    }
+
+    #[test]
+    fn retries_do_not_cycle() {
+        let mut rng = rng();
+        let seed: Hash = rng.gen();
+        let source = Prefix::EMPTY.extend(0);
+
+        let mut names = HashSet::default();
+        for attempt in 0..NUM_SAMPLES as u32 {
+            let name = Hash::derive(Purpose::Retry, source, attempt, &seed).truncate_to_name();
+            assert!(names.insert(name));
+        }
+    }
+}
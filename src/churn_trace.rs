@@ -0,0 +1,349 @@
+//! Churn traces recorded on real networks.
+//!
+//! A trace is a CSV file with one membership event per line, in the form
+//! `TIMESTAMP,EVENT,NODE`, where `TIMESTAMP` is the wall-clock time of the event
+//! in seconds (e.g. Unix time, fractions allowed), `EVENT` is either `join` or
+//! `leave` and `NODE` is any string identifying the node in the trace. Empty
+//! lines, lines starting with `#` and a header line starting with `timestamp`
+//! are ignored. The lines don't need to be sorted.
+//!
+//! The time of the earliest event maps to iteration 0 and every
+//! `seconds_per_tick` seconds of wall-clock time to one iteration.
+//!
+//! Traces can also be generated, to drive several networks with identical
+//! churn, either per iteration or as a sequence of events in continuous time
+//! (see `ChurnTrace::generate_events`).
+
+use HashMap;
+use rand::Rng;
+use random;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::fs;
+use std::path::Path;
+
+/// Membership event of a node, identified by its index in the trace.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TraceEvent {
+    Join(usize),
+    Leave(usize),
+}
+
+#[derive(Clone)]
+pub struct ChurnTrace {
+    events: BTreeMap<u64, Vec<TraceEvent>>,
+    num_nodes: usize,
+    // Time of the event of each iteration, for event traces (empty otherwise).
+    times: Vec<f64>,
+}
+
+impl ChurnTrace {
+    pub fn load<P: AsRef<Path>>(path: P, seconds_per_tick: f64) -> Result<Self, String> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|error| {
+            format!("Couldn't read {}: {}", path.display(), error)
+        })?;
+
+        Self::parse(&content, seconds_per_tick).map_err(
+            |error| format!("{}:{}", path.display(), error),
+        )
+    }
+
+    /// Parse the CSV content of a trace file. Errors start with the number of
+    /// the offending line.
+    pub fn parse(content: &str, seconds_per_tick: f64) -> Result<Self, String> {
+        let mut node_indices = HashMap::default();
+        let mut records = Vec::new();
+
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("timestamp") {
+                continue;
+            }
+
+            let error = || format!("{}: invalid trace line: {}", index + 1, line);
+            let tokens: Vec<_> = line.split(',').map(str::trim).collect();
+            if tokens.len() != 3 {
+                return Err(error());
+            }
+
+            let timestamp: f64 = tokens[0].parse().map_err(|_| error())?;
+            if !timestamp.is_finite() {
+                return Err(error());
+            }
+            let num_nodes = node_indices.len();
+            let node = *node_indices.entry(tokens[2].to_string()).or_insert(num_nodes);
+            let event = match tokens[1] {
+                "join" => TraceEvent::Join(node),
+                "leave" => TraceEvent::Leave(node),
+                _ => return Err(error()),
+            };
+
+            records.push((timestamp, event));
+        }
+
+        let start = records
+            .iter()
+            .map(|&(timestamp, _)| timestamp)
+            .fold(f64::INFINITY, f64::min);
+        let mut events = BTreeMap::new();
+
+        // Stable sort, so events with the same timestamp keep the file order.
+        records.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (timestamp, event) in records {
+            let tick = ((timestamp - start) / seconds_per_tick) as u64;
+            events.entry(tick).or_insert_with(Vec::new).push(event);
+        }
+
+        Ok(ChurnTrace {
+            events,
+            num_nodes: node_indices.len(),
+            times: Vec::new(),
+        })
+    }
+
+    /// Generate a trace of `num_ticks` iterations in which on average
+    /// `join_rate` nodes join per iteration and every node that joined leaves
+    /// with `leave_probability` per iteration.
+    pub fn generate<R: Rng>(
+        rng: &mut R,
+        num_ticks: u64,
+        join_rate: f64,
+        leave_probability: f64,
+    ) -> Self {
+        let mut events = BTreeMap::new();
+        let mut live = Vec::new();
+        let mut num_nodes = 0;
+
+        for tick in 0..num_ticks {
+            let mut tick_events = Vec::new();
+
+            // Positions of the leaving nodes in `live`, skipping the staying ones
+            // in geometrically distributed gaps.
+            let mut leaving = Vec::new();
+            if leave_probability >= 1.0 {
+                leaving.extend(0..live.len());
+            } else if leave_probability > 0.0 {
+                let mut position = 0;
+                loop {
+                    let gap = (1.0 - rng.gen::<f64>()).ln() / (1.0 - leave_probability).ln();
+                    position += gap as usize;
+                    if position >= live.len() {
+                        break;
+                    }
+                    leaving.push(position);
+                    position += 1;
+                }
+            }
+
+            for position in leaving.into_iter().rev() {
+                tick_events.push(TraceEvent::Leave(live.swap_remove(position)));
+            }
+
+            let mut joins = join_rate as usize;
+            if rng.gen::<f64>() < join_rate.fract() {
+                joins += 1;
+            }
+
+            for _ in 0..joins {
+                tick_events.push(TraceEvent::Join(num_nodes));
+                live.push(num_nodes);
+                num_nodes += 1;
+            }
+
+            if !tick_events.is_empty() {
+                let _ = events.insert(tick, tick_events);
+            }
+        }
+
+        ChurnTrace {
+            events,
+            num_nodes,
+            times: Vec::new(),
+        }
+    }
+
+    /// Generate a trace of `num_events` events in continuous time, one per
+    /// iteration. Nodes join as a Poisson process of `join_rate` joins per unit
+    /// of time and every node stays for an exponentially distributed time with
+    /// `leave_rate` leaves per unit of time (0 for nodes that never leave).
+    pub fn generate_events<R: Rng>(
+        rng: &mut R,
+        num_events: u64,
+        join_rate: f64,
+        leave_rate: f64,
+    ) -> Self {
+        let mut events = BTreeMap::new();
+        let mut times = Vec::new();
+        let mut leaves = BinaryHeap::new();
+        let mut next_join = random::exponential(rng, join_rate);
+        let mut num_nodes = 0;
+
+        for tick in 0..num_events {
+            let leave_first = leaves.peek().is_some_and(|leave: &Scheduled| {
+                leave.time < next_join
+            });
+
+            let (time, event) = if leave_first {
+                let leave = leaves.pop().unwrap();
+                (leave.time, TraceEvent::Leave(leave.node))
+            } else {
+                let time = next_join;
+                next_join += random::exponential(rng, join_rate);
+                if leave_rate > 0.0 {
+                    leaves.push(Scheduled {
+                        time: time + random::exponential(rng, leave_rate),
+                        node: num_nodes,
+                    });
+                }
+                num_nodes += 1;
+                (time, TraceEvent::Join(num_nodes - 1))
+            };
+
+            let _ = events.insert(tick, vec![event]);
+            times.push(time);
+        }
+
+        ChurnTrace {
+            events,
+            num_nodes,
+            times,
+        }
+    }
+
+    /// Remove and return the events of the given iteration.
+    pub fn take(&mut self, iteration: u64) -> Vec<TraceEvent> {
+        self.events.remove(&iteration).unwrap_or_default()
+    }
+
+    /// Number of distinct nodes in the trace.
+    pub fn num_nodes(&self) -> usize {
+        self.num_nodes
+    }
+
+    /// Number of iterations the trace spans.
+    pub fn num_ticks(&self) -> u64 {
+        self.events.keys().last().map_or(0, |&tick| tick + 1)
+    }
+
+    /// Time of the event of the iteration, for event traces.
+    pub fn time(&self, iteration: u64) -> Option<f64> {
+        self.times.get(iteration as usize).cloned()
+    }
+}
+
+// Leave of a node scheduled at the time. Ordered so the earliest one is the
+// greatest, to pop it first from the heap.
+struct Scheduled {
+    time: f64,
+    node: usize,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.time.total_cmp(&self.time).then(other.node.cmp(&self.node))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use random::{RngKind, Seed};
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn parse_csv() {
+        let content = "timestamp,event,node\n\
+                       # comment\n\
+                       \n\
+                       1060.5, leave, a\n\
+                       1000,join,a\n\
+                       1000,join,b\n\
+                       1200,join,c\n";
+        let mut trace = ChurnTrace::parse(content, 60.0).unwrap();
+
+        assert_eq!(trace.num_nodes(), 3);
+        assert_eq!(trace.num_ticks(), 4);
+        assert_eq!(trace.take(0), vec![TraceEvent::Join(0), TraceEvent::Join(1)]);
+        assert_eq!(trace.take(1), vec![TraceEvent::Leave(0)]);
+        assert_eq!(trace.take(2), vec![]);
+        assert_eq!(trace.take(3), vec![TraceEvent::Join(2)]);
+    }
+
+    #[test]
+    fn parse_invalid_csv() {
+        for line in &[
+            "1000,join",
+            "1000,join,a,b",
+            "1000,rejoin,a",
+            "now,join,a",
+            "NaN,join,a",
+            "inf,leave,a",
+            "-inf,leave,a",
+        ] {
+            let content = format!("0,join,x\n{}\n", line);
+            let error = ChurnTrace::parse(&content, 60.0).err().unwrap();
+            assert!(error.starts_with("2: "), "{}", error);
+        }
+    }
+
+    #[test]
+    fn generate_events_in_time_order() {
+        let seed: Seed = "[1, 2, 3, 4]".parse().unwrap();
+        let mut rng = random::independent_rng(RngKind::XorShift, seed, 1);
+        let mut trace = ChurnTrace::generate_events(&mut rng, 5000, 2.0, 0.05);
+
+        let mut joined = BTreeSet::new();
+        let mut left = BTreeSet::new();
+        for tick in 0..5000 {
+            assert!(trace.time(tick).unwrap() >= trace.time(tick.saturating_sub(1)).unwrap());
+
+            match trace.take(tick)[..] {
+                [TraceEvent::Join(node)] => {
+                    assert_eq!(node, joined.len());
+                    let _ = joined.insert(node);
+                }
+                [TraceEvent::Leave(node)] => {
+                    assert!(joined.contains(&node));
+                    assert!(left.insert(node));
+                }
+                ref events => panic!("unexpected events at {}: {:?}", tick, events),
+            }
+        }
+        assert_eq!(trace.num_nodes(), joined.len());
+        assert!(trace.time(5000).is_none());
+
+        // 2 joins per unit of time, and on average 2 / 0.05 = 40 nodes staying.
+        let time = trace.time(4999).unwrap();
+        let join_rate = joined.len() as f64 / time;
+        assert!((1.8..2.2).contains(&join_rate), "{}", join_rate);
+        let staying = joined.len() - left.len();
+        assert!((20..60).contains(&staying), "{}", staying);
+    }
+
+    #[test]
+    fn generate_events_without_leaves() {
+        let seed: Seed = "[1, 2, 3, 4]".parse().unwrap();
+        let mut rng = random::independent_rng(RngKind::XorShift, seed, 1);
+        let mut trace = ChurnTrace::generate_events(&mut rng, 100, 1.0, 0.0);
+
+        for tick in 0..100 {
+            assert_eq!(trace.take(tick), vec![TraceEvent::Join(tick as usize)]);
+        }
+        assert_eq!(trace.num_nodes(), 100);
+    }
+}
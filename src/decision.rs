@@ -0,0 +1,542 @@
+//! Decisions of a section (whether to split, merge or relocate, and which nodes
+//! to relocate or promote), as pure functions of the section state. `Section`
+//! acts on them; keeping them separate allows testing them on hand-crafted
+//! states.
+
+use HashSet;
+use age_index::AgeIndex;
+use chain::Hash;
+use node::{self, Node};
+use params::{ElderTieBreak, Params, SplitMetric};
+use prefix::{Name, Prefix};
+use section::State;
+use std::collections::BTreeMap;
+
+/// Read-only view of the section state the decisions are based on.
+#[derive(Clone, Copy)]
+pub struct View<'a> {
+    pub prefix: Prefix,
+    pub state: State,
+    pub nodes: &'a BTreeMap<Name, Node>,
+    pub ages: &'a AgeIndex,
+    /// Whether a relocation from the section is already in progress.
+    pub relocating: bool,
+}
+
+impl<'a> View<'a> {
+    /// Returns whether both halves of the section have grown enough to split.
+    pub fn should_split(&self, params: &Params) -> bool {
+        // We can only split if both section post-split would remain with at least
+        // 2 * GROUP_SIZE - QUORUM adults.
+        let prefixes = self.prefix.split();
+        let num_adults0 = node::count_matching_adults(params, prefixes[0], self.nodes.values());
+        let num_adults1 = node::count_matching_adults(params, prefixes[1], self.nodes.values());
+        let limit = params.split_threshold();
+
+        match params.split_metric {
+            SplitMetric::Count => num_adults0 >= limit && num_adults1 >= limit,
+            SplitMetric::Capacity => {
+                // Each half must keep enough adults not to merge right away.
+                let capacity_limit = limit as f64 * params.avg_node_size();
+                let capacity0 =
+                    node::matching_adult_capacity(params, prefixes[0], self.nodes.values());
+                let capacity1 =
+                    node::matching_adult_capacity(params, prefixes[1], self.nodes.values());
+
+                num_adults0 >= params.merge_threshold() &&
+                    num_adults1 >= params.merge_threshold() &&
+                    capacity0 as f64 >= capacity_limit &&
+                    capacity1 as f64 >= capacity_limit
+            }
+        }
+    }
+
+    /// Returns whether the section has too few adults and must merge with its
+    /// sibling.
+    pub fn should_merge(&self, params: &Params) -> bool {
+        // The root section has nobody to merge with.
+        self.state != State::Startup &&
+            node::count_adults(params, self.nodes.values()) < params.merge_threshold()
+    }
+
+    /// Returns whether the section can start relocating a node: not during
+    /// startup or a split or merge, not if it would trigger merge and not while
+    /// another relocation is in progress.
+    pub fn can_relocate(&self, params: &Params) -> bool {
+        self.state == State::Stable &&
+            node::count_adults(params, self.nodes.values()) > params.merge_threshold() &&
+            !self.relocating
+    }
+
+    /// The node the ageing hash selects for relocation: the oldest one for which
+    /// `hash % 2^age == 0`. If there is more than one, the tie-breaking rule
+    /// applies.
+    pub fn ageing_relocation(&self, params: &Params, hash: &Hash) -> Option<Name> {
+        // Look at the oldest matching age groups first, so the (many) young
+        // nodes are usually not looked at.
+        let trailing_zeros = hash.trailing_zeros() as u8;
+        for (_, names) in self.ages.groups_up_to(trailing_zeros).rev() {
+            let candidates: Vec<_> = names
+                .iter()
+                .map(|name| &self.nodes[name])
+                .filter(|node| is_relocation_candidate(params, node))
+                .collect();
+
+            match candidates.len() {
+                0 => (),
+                1 => return Some(candidates[0].name()),
+                _ => return break_ties(candidates),
+            }
+        }
+
+        None
+    }
+
+    /// Like `ageing_relocation`, but prefers the nodes aged `adult_age - 1`, so
+    /// the relocation (which increments the age) makes them adults.
+    pub fn pre_adult_relocation(&self, params: &Params, hash: &Hash) -> Option<Name> {
+        let pre_adult = params.adult_age.saturating_sub(1);
+        if params.adult_age > 0 && hash.trailing_zeros() >= u64::from(pre_adult) {
+            let candidates: Vec<_> = self.ages
+                .groups_up_to(pre_adult)
+                .rev()
+                .take_while(|&(age, _)| age == pre_adult)
+                .flat_map(|(_, names)| names.iter().map(|name| &self.nodes[name]))
+                .filter(|node| is_relocation_candidate(params, node))
+                .collect();
+            if !candidates.is_empty() {
+                return break_ties(candidates);
+            }
+        }
+
+        self.ageing_relocation(params, hash)
+    }
+
+    /// The longest serving elder that exceeded the term limit, regardless of the
+    /// ageing hash.
+    pub fn term_limit_relocation(&self, params: &Params) -> Option<Name> {
+        if params.elder_term_limit == 0 && params.elder_age_limit == 0 {
+            return None;
+        }
+
+        if !self.can_relocate(params) {
+            return None;
+        }
+
+        self.nodes
+            .values()
+            .filter(|node| {
+                node.is_over_term_limit(params) && !node.is_in_relocation_cooldown(params)
+            })
+            .max_by_key(|node| (node.term(), node.age(), node.name()))
+            .map(|node| node.name())
+    }
+
+    /// The node to relocate to the sibling section of the given size, to even
+    /// out their sizes: the youngest adult, as that disrupts the section the
+    /// least.
+    pub fn balancing_relocation(&self, params: &Params, sibling_size: usize) -> Option<Name> {
+        if (self.nodes.len() as f64) <= params.balance_threshold * sibling_size as f64 {
+            return None;
+        }
+
+        // Same restrictions as for the regular relocations apply.
+        if node::count_adults(params, self.nodes.values()) <= params.merge_threshold() ||
+            self.relocating
+        {
+            return None;
+        }
+
+        self.ages
+            .iter()
+            .map(|name| &self.nodes[&name])
+            .find(|node| {
+                node.is_adult(params) && !node.is_in_relocation_cooldown(params)
+            })
+            .map(|node| node.name())
+    }
+
+    /// Returns whether the attacker controls the section: at least a quorum of
+    /// its elders is malicious.
+    pub fn is_malicious(&self, params: &Params) -> bool {
+        let malicious = self.nodes
+            .values()
+            .filter(|node| node.is_elder() && node.is_malicious())
+            .count();
+        malicious >= params.quorum()
+    }
+
+    /// The most elders an adversary controlling `fraction` of the nodes could
+    /// have in either half after a split: it is assumed to control the oldest
+    /// nodes and to have placed all their names in that half. Ties in age go to
+    /// the adversary.
+    pub fn worst_case_split_elders(&self, params: &Params, fraction: f64) -> usize {
+        let count = (fraction * self.nodes.len() as f64).ceil() as usize;
+        let adversary: HashSet<_> = self.ages.iter().rev().take(count).collect();
+
+        self.prefix
+            .split()
+            .iter()
+            .map(|prefix| {
+                let mut nodes: Vec<_> = self.nodes
+                    .values()
+                    .filter(|node| {
+                        node.age() >= params.min_elder_age &&
+                            (prefix.matches(node.name()) || adversary.contains(&node.name()))
+                    })
+                    .map(|node| (node.age(), adversary.contains(&node.name())))
+                    .collect();
+                nodes.sort_by(|a, b| b.cmp(a));
+                nodes
+                    .into_iter()
+                    .take(params.group_size)
+                    .filter(|&(_, malicious)| malicious)
+                    .count()
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The nodes that should be the elders: the oldest ones at least
+    /// `min_elder_age` old.
+    pub fn elders(&self, params: &Params) -> HashSet<Name> {
+        let mut elders = HashSet::default();
+
+        // Only the oldest age groups need to be looked at.
+        for (age, names) in self.ages.groups().rev() {
+            if age < params.min_elder_age || elders.len() >= params.group_size {
+                break;
+            }
+
+            let mut nodes: Vec<_> = names.iter().map(|name| &self.nodes[name]).collect();
+            if params.elder_tie_break == ElderTieBreak::Seniority {
+                // Stable sort, so nodes of the same tenure stay ordered by name.
+                nodes.sort_by_key(|node| node.tenure());
+            }
+
+            let remaining = params.group_size - elders.len();
+            elders.extend(nodes.into_iter().rev().take(remaining).map(|node| node.name()));
+        }
+
+        elders
+    }
+
+    /// The nodes passing the ageing check for the given hash, sorted by age.
+    pub fn relocation_candidates(&self, params: &Params, hash: &Hash) -> Vec<&'a Node> {
+        // The actual formula is: `hash % 2^age == 0`, the following is equivalent
+        // but more efficient:
+        let trailing_zeros = hash.trailing_zeros() as u8;
+        let nodes = self.nodes;
+        self.ages
+            .groups_up_to(trailing_zeros)
+            .flat_map(|(_, names)| names.iter().map(move |name| &nodes[name]))
+            .filter(|node| is_relocation_candidate(params, node))
+            .collect()
+    }
+}
+
+/// Whether the node can be relocated if its age passes the ageing check.
+pub fn is_relocation_candidate(params: &Params, node: &Node) -> bool {
+    !node.is_in_relocation_cooldown(params) &&
+        params.relocate_infants.allows(node.is_infant(params))
+}
+
+fn break_ties(mut nodes: Vec<&Node>) -> Option<Name> {
+    let total = nodes.iter().fold(0, |total, node| total ^ node.name().0);
+    nodes.sort_by_key(|node| node.name().0 ^ total);
+    nodes.first().map(|node| node.name())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_params;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    // Names in the `[00]` and `[01]` halves of section `[0]`.
+    const HALF0: u64 = 0;
+    const HALF1: u64 = 0x4000_0000_0000_0000;
+
+    fn nodes<I: IntoIterator<Item = (u64, u8)>>(nodes: I) -> BTreeMap<Name, Node> {
+        nodes
+            .into_iter()
+            .map(|(name, age)| (Name(name), Node::new(name, Name(name), age)))
+            .collect()
+    }
+
+    fn adults(base: u64, count: u64) -> Vec<(u64, u8)> {
+        (0..count).map(|i| (base + i, 5)).collect()
+    }
+
+    #[test]
+    fn split_requires_enough_adults_in_both_halves() {
+        let params = test_params(&[]);
+        let prefix = "0".parse().unwrap();
+
+        let mut unbalanced = adults(HALF0, 11);
+        unbalanced.extend(adults(HALF1, 10));
+        let unbalanced = nodes(unbalanced);
+        let ages = AgeIndex::build(unbalanced.values());
+        let view = View {
+            prefix,
+            state: State::Stable,
+            nodes: &unbalanced,
+            ages: &ages,
+            relocating: false,
+        };
+        assert!(!view.should_split(&params));
+
+        let mut balanced = adults(HALF0, 11);
+        balanced.extend(adults(HALF1, 11));
+        let balanced = nodes(balanced);
+        let ages = AgeIndex::build(balanced.values());
+        let view = View {
+            prefix,
+            state: State::Stable,
+            nodes: &balanced,
+            ages: &ages,
+            relocating: false,
+        };
+        assert!(view.should_split(&params));
+    }
+
+    #[test]
+    fn merge_below_threshold_except_root() {
+        let params = test_params(&[]);
+        let few = nodes(adults(HALF0, 7));
+        let few_ages = AgeIndex::build(few.values());
+        let enough = nodes(adults(HALF0, 8));
+        let enough_ages = AgeIndex::build(enough.values());
+
+        let view = |prefix, state, nodes, ages| {
+            View {
+                prefix,
+                state,
+                nodes,
+                ages,
+                relocating: false,
+            }
+        };
+
+        let prefix = "0".parse().unwrap();
+        assert!(view(prefix, State::Stable, &few, &few_ages).should_merge(&params));
+        assert!(!view(prefix, State::Stable, &enough, &enough_ages).should_merge(&params));
+        assert!(!view(Prefix::EMPTY, State::Startup, &few, &few_ages).should_merge(&params));
+    }
+
+    #[test]
+    fn ageing_relocates_oldest_matching_node() {
+        let params = test_params(&[]);
+        let nodes = nodes(vec![(HALF0, 5), (HALF0 + 1, 6), (HALF1, 7)]);
+        let ages = AgeIndex::build(nodes.values());
+        let view = View {
+            prefix: "0".parse().unwrap(),
+            state: State::Stable,
+            nodes: &nodes,
+            ages: &ages,
+            relocating: false,
+        };
+
+        // Hash divisible by 2^6, but not by 2^7.
+        let mut rng = XorShiftRng::from_seed([0x193a_6754, 0xa8a7_d469, 0x9783_0e05, 0x113b_a7bb]);
+        let hash = (0..)
+            .map(|_| rng.gen::<Hash>())
+            .find(|hash| hash.trailing_zeros() == 6)
+            .unwrap();
+
+        assert_eq!(view.ageing_relocation(&params, &hash), Some(Name(HALF0 + 1)));
+    }
+
+    #[test]
+    fn ageing_skips_nodes_in_relocation_cooldown() {
+        let params = test_params(&["--relocation-cooldown", "3"]);
+        let mut nodes = nodes(vec![(HALF0, 5), (HALF0 + 1, 6), (HALF1, 7)]);
+        let ages = AgeIndex::build(nodes.values());
+
+        // Hash divisible by 2^6, but not by 2^7.
+        let mut rng = XorShiftRng::from_seed([0x193a_6754, 0xa8a7_d469, 0x9783_0e05, 0x113b_a7bb]);
+        let hash = (0..)
+            .map(|_| rng.gen::<Hash>())
+            .find(|hash| hash.trailing_zeros() == 6)
+            .unwrap();
+
+        let relocation = |nodes: &BTreeMap<Name, Node>| {
+            let view = View {
+                prefix: "0".parse().unwrap(),
+                state: State::Stable,
+                nodes,
+                ages: &ages,
+                relocating: false,
+            };
+            view.ageing_relocation(&params, &hash)
+        };
+
+        // Every node just arrived.
+        assert_eq!(relocation(&nodes), None);
+
+        // Only the youngest node is past the cooldown.
+        for _ in 0..3 {
+            nodes.get_mut(&Name(HALF0)).unwrap().increment_tenure();
+        }
+        assert_eq!(relocation(&nodes), Some(Name(HALF0)));
+
+        // Past the cooldown, the oldest matching node goes first again.
+        for _ in 0..3 {
+            nodes.get_mut(&Name(HALF0 + 1)).unwrap().increment_tenure();
+        }
+        assert_eq!(relocation(&nodes), Some(Name(HALF0 + 1)));
+    }
+
+    #[test]
+    fn infant_relocation_setting_filters_ageing_candidates() {
+        let nodes = nodes(vec![(HALF0, 4), (HALF1, 5)]);
+        let ages = AgeIndex::build(nodes.values());
+        let view = View {
+            prefix: "0".parse().unwrap(),
+            state: State::Stable,
+            nodes: &nodes,
+            ages: &ages,
+            relocating: false,
+        };
+
+        let mut rng = XorShiftRng::from_seed([0x193a_6754, 0xa8a7_d469, 0x9783_0e05, 0x113b_a7bb]);
+        let mut hash = |trailing_zeros| {
+            (0..)
+                .map(|_| rng.gen::<Hash>())
+                .find(|hash: &Hash| hash.trailing_zeros() == trailing_zeros)
+                .unwrap()
+        };
+        // Matches only the infant, and both nodes.
+        let (infant_hash, any_hash) = (hash(4), hash(6));
+        let relocation = |setting, hash| {
+            view.ageing_relocation(&test_params(&["--relocate-infants", setting]), hash)
+        };
+
+        assert_eq!(relocation("yes", &infant_hash), Some(Name(HALF0)));
+        assert_eq!(relocation("yes", &any_hash), Some(Name(HALF1)));
+        assert_eq!(relocation("no", &infant_hash), None);
+        assert_eq!(relocation("no", &any_hash), Some(Name(HALF1)));
+        assert_eq!(relocation("only", &infant_hash), Some(Name(HALF0)));
+        assert_eq!(relocation("only", &any_hash), Some(Name(HALF0)));
+    }
+
+    #[test]
+    fn pre_adult_relocation_prefers_nodes_about_to_become_adults() {
+        let params = test_params(&["--relocation-choice", "pre-adult"]);
+        let nodes = nodes(vec![(HALF0, 4), (HALF0 + 1, 6), (HALF1, 3)]);
+        let ages = AgeIndex::build(nodes.values());
+        let view = View {
+            prefix: "0".parse().unwrap(),
+            state: State::Stable,
+            nodes: &nodes,
+            ages: &ages,
+            relocating: false,
+        };
+
+        let mut rng = XorShiftRng::from_seed([0x193a_6754, 0xa8a7_d469, 0x9783_0e05, 0x113b_a7bb]);
+        let mut hash = |trailing_zeros| {
+            (0..)
+                .map(|_| rng.gen::<Hash>())
+                .find(|hash: &Hash| hash.trailing_zeros() == trailing_zeros)
+                .unwrap()
+        };
+
+        // The node aged `adult_age - 1` beats the older one.
+        assert_eq!(view.pre_adult_relocation(&params, &hash(6)), Some(Name(HALF0)));
+        // Too few trailing zeros for it: the oldest matching node.
+        assert_eq!(view.pre_adult_relocation(&params, &hash(3)), Some(Name(HALF1)));
+    }
+
+    #[test]
+    fn seniority_breaks_elder_ties() {
+        let mut nodes = nodes(vec![(HALF0, 10), (HALF0 + 1, 6), (HALF1, 6)]);
+        for _ in 0..5 {
+            nodes.get_mut(&Name(HALF0 + 1)).unwrap().increment_tenure();
+        }
+        let ages = AgeIndex::build(nodes.values());
+        let view = View {
+            prefix: "0".parse().unwrap(),
+            state: State::Stable,
+            nodes: &nodes,
+            ages: &ages,
+            relocating: false,
+        };
+
+        let by_name = view.elders(&test_params(&["--group-size", "2"]));
+        assert_eq!(by_name, [Name(HALF0), Name(HALF1)].iter().cloned().collect());
+
+        let by_seniority =
+            view.elders(&test_params(&["--group-size", "2", "--elder-tie-break", "seniority"]));
+        assert_eq!(by_seniority, [Name(HALF0), Name(HALF0 + 1)].iter().cloned().collect());
+    }
+
+    #[test]
+    fn worst_case_split_elders_assumes_oldest_nodes_in_one_half() {
+        let params = test_params(&[]);
+        // Eight elders of age 6 in `[00]`, twelve infants in `[01]`.
+        let mut layout: Vec<_> = (0..8).map(|i| (HALF0 + i, 6)).collect();
+        layout.extend((0..12).map(|i| (HALF1 + i, 4)));
+        let nodes = nodes(layout);
+        let ages = AgeIndex::build(nodes.values());
+        let view = View {
+            prefix: "0".parse().unwrap(),
+            state: State::Stable,
+            nodes: &nodes,
+            ages: &ages,
+            relocating: false,
+        };
+
+        assert_eq!(view.worst_case_split_elders(&params, 0.0), 0);
+        // 4 of the 20 nodes: the adversary controls half of the elders of either
+        // half.
+        assert_eq!(view.worst_case_split_elders(&params, 0.2), 4);
+        assert_eq!(view.worst_case_split_elders(&params, 0.5), 8);
+    }
+
+    // Benchmark of the age-based selections on large sections. Run with
+    // `cargo test --release -- --ignored --nocapture age_index_scales`.
+    #[test]
+    #[ignore]
+    fn age_index_scales() {
+        use std::time::Instant;
+
+        let params = test_params(&[]);
+        let mut rng = XorShiftRng::from_seed([0x1b7e_4d32, 0x86c2_f10a, 0x5e2d_3b79, 0x0c4f_9a61]);
+        let hashes: Vec<Hash> = (0..1000).map(|_| rng.gen()).collect();
+
+        for &size in &[100, 1_000, 10_000, 100_000] {
+            let nodes = nodes((0..size).map(|_| {
+                let age = 4 + rng.gen::<u32>().trailing_zeros().min(20) as u8;
+                (rng.gen::<u64>() >> 1, age)
+            }));
+            let ages = AgeIndex::build(nodes.values());
+            let view = View {
+                prefix: "0".parse().unwrap(),
+                state: State::Stable,
+                nodes: &nodes,
+                ages: &ages,
+                relocating: false,
+            };
+
+            let start = Instant::now();
+            let selected = hashes
+                .iter()
+                .filter(|hash| view.ageing_relocation(&params, hash).is_some())
+                .count();
+            let ageing = start.elapsed() / hashes.len() as u32;
+
+            let start = Instant::now();
+            let elders = view.elders(&params).len();
+            let elder_update = start.elapsed();
+
+            println!(
+                "{:7} nodes: ageing relocation {:?} per hash ({} selected), elders {:?} ({})",
+                size,
+                ageing,
+                selected,
+                elder_update,
+                elders
+            );
+        }
+    }
+}
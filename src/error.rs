@@ -0,0 +1,46 @@
+//! Faults the simulation can recover from.
+//!
+//! Where they occur, they are passed up to `Network::tick`, which stops the
+//! simulation on them in the strict mode, and otherwise records them and
+//! carries on (see `Network::faults`).
+
+use prefix::Name;
+use std::fmt;
+use termination::Reason;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SimError {
+    /// No section is responsible for the target of a message, which is
+    /// dropped.
+    MissingSection(Name),
+    /// A relocation commit of the node didn't match any accepted relocation.
+    /// The destination handles the node as `Params::unmatched_commit` says.
+    UnmatchedCommit(Name),
+}
+
+impl SimError {
+    /// The reason to report when stopping the simulation on the fault.
+    pub fn reason(&self) -> Reason {
+        match *self {
+            SimError::UnmatchedCommit(_) => Reason::UnmatchedCommit,
+            _ => Reason::InvalidState(self.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SimError::MissingSection(target) => {
+                write!(fmt, "no section matching {:?} found", target)
+            }
+            SimError::UnmatchedCommit(name) => {
+                write!(
+                    fmt,
+                    "relocation commit of {:?} not found in incoming relocation cache",
+                    name
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,554 @@
+//! Export of the full event stream of a run (see `Params::event_trace_file`),
+//! for replaying and post-processing it offline, e.g. diffing two runs or
+//! investigating a rare split or merge without re-running with `-vvv`.
+//!
+//! Every action the sections take (including every message sent) and every
+//! node joining or dropping is written as one event, in the order they happen.
+//! In the JSON lines format, one object per event with only the fields that
+//! apply, e.g.:
+//!
+//! `{"iteration":120,"kind":"relocate-commit","node":"4a3f00c2b81d9e07",
+//! "age":6,"target":"c913be04d2a17f55","exchange":"12/3","detail":"ageing"}`
+//!
+//! The compact binary format starts with the magic bytes `DCSTRACE` and a
+//! version byte (1), followed by the events, each made of the kind code
+//! (`Kind::code`), the iteration (u64), a byte with a bit set for every field
+//! present (from the lowest: section prefix, node, age, target, exchange,
+//! value, detail) and the fields present, in that order: the prefix as its
+//! length (u8) and bits (u64), the node and target names (u64), the age (u8),
+//! the exchange as the section id and sequence number (u64 each), the value
+//! (u64) and the detail as its length (u16) and UTF-8 bytes. All numbers are
+//! little-endian. `EventReader` reads the events back, and the `trace-json`
+//! subcommand prints them in the JSON lines format.
+
+use Age;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use json::Object;
+use message::{Action, ExchangeId, Message};
+use node::{Node, NodeId, Origin};
+use params::TraceFormat;
+use prefix::{Name, Prefix};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8] = b"DCSTRACE";
+const VERSION: u8 = 1;
+
+/// Node joining or dropping from a section, as recorded by the section (for
+/// the event trace and the node history).
+pub enum Membership {
+    Joined(Member, Origin),
+    Dropped(Member),
+}
+
+impl Membership {
+    pub fn joined(node: &Node) -> Self {
+        Membership::Joined(Member::new(node), node.origin())
+    }
+
+    pub fn dropped(node: &Node) -> Self {
+        Membership::Dropped(Member::new(node))
+    }
+}
+
+/// The node as of joining or dropping.
+#[derive(Clone, Copy)]
+pub struct Member {
+    pub id: NodeId,
+    pub name: Name,
+    pub age: Age,
+    pub relocations: u64,
+}
+
+impl Member {
+    fn new(node: &Node) -> Self {
+        Member {
+            id: node.id(),
+            name: node.name(),
+            age: node.age(),
+            relocations: node.relocations(),
+        }
+    }
+}
+
+/// What happened.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Kind {
+    Join,
+    Drop,
+    Split,
+    Merge,
+    Reject,
+    RelocateRequest,
+    RelocateAccept,
+    RelocateReject,
+    RelocateCommit,
+    RelocateCancel,
+    JoinRedirect,
+    SiblingSize,
+    NeedAdults,
+    Abort,
+    Fault,
+    Bounce,
+}
+
+impl Kind {
+    const ALL: [Kind; 16] = [
+        Kind::Join,
+        Kind::Drop,
+        Kind::Split,
+        Kind::Merge,
+        Kind::Reject,
+        Kind::RelocateRequest,
+        Kind::RelocateAccept,
+        Kind::RelocateReject,
+        Kind::RelocateCommit,
+        Kind::RelocateCancel,
+        Kind::JoinRedirect,
+        Kind::SiblingSize,
+        Kind::NeedAdults,
+        Kind::Abort,
+        Kind::Fault,
+        Kind::Bounce,
+    ];
+
+    /// Code of the kind in the binary format.
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    /// Kind with the code in the binary format, if any.
+    pub fn from_code(code: u8) -> Option<Self> {
+        Self::ALL.get(usize::from(code)).cloned()
+    }
+
+    /// Name of the kind in the JSON lines format.
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Join => "join",
+            Kind::Drop => "drop",
+            Kind::Split => "split",
+            Kind::Merge => "merge",
+            Kind::Reject => "reject",
+            Kind::RelocateRequest => "relocate-request",
+            Kind::RelocateAccept => "relocate-accept",
+            Kind::RelocateReject => "relocate-reject",
+            Kind::RelocateCommit => "relocate-commit",
+            Kind::RelocateCancel => "relocate-cancel",
+            Kind::JoinRedirect => "join-redirect",
+            Kind::SiblingSize => "sibling-size",
+            Kind::NeedAdults => "need-adults",
+            Kind::Abort => "abort",
+            Kind::Fault => "fault",
+            Kind::Bounce => "bounce",
+        }
+    }
+}
+
+/// Single event. `prefix` is the section the event happened in, if known, and
+/// `detail` the origin of a joining node, the cause of a relocation or the
+/// reason of an abort or fault.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    pub iteration: u64,
+    pub kind: Kind,
+    pub prefix: Option<Prefix>,
+    pub node: Option<Name>,
+    pub age: Option<Age>,
+    pub target: Option<Name>,
+    pub exchange: Option<ExchangeId>,
+    pub value: Option<u64>,
+    pub detail: Option<String>,
+}
+
+impl Event {
+    fn new(iteration: u64, kind: Kind) -> Self {
+        Event {
+            iteration,
+            kind,
+            prefix: None,
+            node: None,
+            age: None,
+            target: None,
+            exchange: None,
+            value: None,
+            detail: None,
+        }
+    }
+
+    /// The event as a JSON object.
+    pub fn json(&self) -> String {
+        let mut object = Object::new();
+        let _ = object
+            .field("iteration", &self.iteration)
+            .field("kind", self.kind.name())
+            .optional_field("prefix", &self.prefix)
+            .optional_field("node", &self.node)
+            .optional_field("age", &self.age)
+            .optional_field("target", &self.target)
+            .optional_field("exchange", &self.exchange)
+            .optional_field("value", &self.value)
+            .optional_field("detail", &self.detail);
+        object.encode()
+    }
+
+    fn write_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let present = [
+            self.prefix.is_some(),
+            self.node.is_some(),
+            self.age.is_some(),
+            self.target.is_some(),
+            self.exchange.is_some(),
+            self.value.is_some(),
+            self.detail.is_some(),
+        ];
+        let mask = present
+            .iter()
+            .enumerate()
+            .fold(0u8, |mask, (bit, &present)| mask | ((present as u8) << bit));
+
+        writer.write_u8(self.kind.code())?;
+        writer.write_u64::<LittleEndian>(self.iteration)?;
+        writer.write_u8(mask)?;
+        if let Some(prefix) = self.prefix {
+            writer.write_u8(prefix.len())?;
+            writer.write_u64::<LittleEndian>(prefix.substituted_in(Name(0)).0)?;
+        }
+        if let Some(node) = self.node {
+            writer.write_u64::<LittleEndian>(node.0)?;
+        }
+        if let Some(age) = self.age {
+            writer.write_u8(age)?;
+        }
+        if let Some(target) = self.target {
+            writer.write_u64::<LittleEndian>(target.0)?;
+        }
+        if let Some(id) = self.exchange {
+            writer.write_u64::<LittleEndian>(id.0)?;
+            writer.write_u64::<LittleEndian>(id.1)?;
+        }
+        if let Some(value) = self.value {
+            writer.write_u64::<LittleEndian>(value)?;
+        }
+        if let Some(ref detail) = self.detail {
+            let bytes = &detail.as_bytes()[..detail.len().min(u16::MAX as usize)];
+            writer.write_u16::<LittleEndian>(bytes.len() as u16)?;
+            writer.write_all(bytes)?;
+        }
+
+        Ok(())
+    }
+
+    // Read the next event written by `write_binary`, or `None` at the end.
+    fn read_binary<R: Read>(reader: &mut R) -> io::Result<Option<Self>> {
+        let code = match reader.read_u8() {
+            Ok(code) => code,
+            Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let kind = Kind::from_code(code).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("invalid event kind {}", code))
+        })?;
+
+        let mut event = Event::new(reader.read_u64::<LittleEndian>()?, kind);
+        let mask = reader.read_u8()?;
+        let present = |bit: u8| mask & (1 << bit) != 0;
+
+        if present(0) {
+            let len = reader.read_u8()?;
+            let bits = reader.read_u64::<LittleEndian>()?;
+            event.prefix = Some(Prefix::new(len, Name(bits)));
+        }
+        if present(1) {
+            event.node = Some(Name(reader.read_u64::<LittleEndian>()?));
+        }
+        if present(2) {
+            event.age = Some(reader.read_u8()?);
+        }
+        if present(3) {
+            event.target = Some(Name(reader.read_u64::<LittleEndian>()?));
+        }
+        if present(4) {
+            let section = reader.read_u64::<LittleEndian>()?;
+            event.exchange = Some(ExchangeId(section, reader.read_u64::<LittleEndian>()?));
+        }
+        if present(5) {
+            event.value = Some(reader.read_u64::<LittleEndian>()?);
+        }
+        if present(6) {
+            let mut bytes = vec![0; usize::from(reader.read_u16::<LittleEndian>()?)];
+            reader.read_exact(&mut bytes)?;
+            event.detail = Some(String::from_utf8_lossy(&bytes).into_owned());
+        }
+
+        Ok(Some(event))
+    }
+}
+
+/// Reads the events back from a trace in the binary format.
+pub struct EventReader {
+    reader: BufReader<File>,
+    path: String,
+}
+
+impl EventReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref().display().to_string();
+        let error = |error: io::Error| format!("Couldn't read {}: {}", path, error);
+
+        let mut reader = BufReader::new(File::open(&path).map_err(&error)?);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic).map_err(&error)?;
+        if magic != MAGIC || reader.read_u8().map_err(&error)? != VERSION {
+            return Err(format!("{}: not a binary event trace", path));
+        }
+
+        Ok(EventReader { reader, path })
+    }
+}
+
+impl Iterator for EventReader {
+    type Item = Result<Event, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match Event::read_binary(&mut self.reader) {
+            Ok(event) => event.map(Ok),
+            Err(error) => Some(Err(format!("Couldn't read {}: {}", self.path, error))),
+        }
+    }
+}
+
+pub struct EventTrace {
+    writer: Option<BufWriter<File>>,
+    format: TraceFormat,
+}
+
+impl EventTrace {
+    pub fn create<P: AsRef<Path>>(path: P, format: TraceFormat) -> Result<Self, String> {
+        let path = path.as_ref();
+        let error = |error: io::Error| format!("Couldn't create {}: {}", path.display(), error);
+
+        let mut writer = BufWriter::new(File::create(path).map_err(&error)?);
+        if format == TraceFormat::Binary {
+            writer.write_all(MAGIC).map_err(&error)?;
+            writer.write_u8(VERSION).map_err(&error)?;
+        }
+
+        Ok(EventTrace {
+            writer: Some(writer),
+            format,
+        })
+    }
+
+    /// Record the action taken in the iteration.
+    pub fn action(&mut self, iteration: u64, action: &Action) {
+        let event = match *action {
+            Action::Reject(ref node) => {
+                let mut event = Event::new(iteration, Kind::Reject);
+                event.node = Some(node.name());
+                event.age = Some(node.age());
+                event
+            }
+            Action::Merge(prefix) => {
+                let mut event = Event::new(iteration, Kind::Merge);
+                event.prefix = Some(prefix);
+                event
+            }
+            Action::Split(prefix) => {
+                let mut event = Event::new(iteration, Kind::Split);
+                event.prefix = Some(prefix);
+                event
+            }
+            Action::Send(ref message) => message_event(iteration, message),
+            Action::Abort(prefix, ref reason) => {
+                let mut event = Event::new(iteration, Kind::Abort);
+                event.prefix = Some(prefix);
+                event.detail = Some(format!("{:?}", reason));
+                event
+            }
+            Action::Fault(prefix, ref error) => {
+                let mut event = Event::new(iteration, Kind::Fault);
+                event.prefix = Some(prefix);
+                event.detail = Some(error.to_string());
+                event
+            }
+        };
+
+        self.write(&event);
+    }
+
+    /// Record the node joining or dropping from the section.
+    pub fn membership(&mut self, iteration: u64, prefix: Prefix, membership: &Membership) {
+        let event = match *membership {
+            Membership::Joined(member, origin) => {
+                let mut event = Event::new(iteration, Kind::Join);
+                event.node = Some(member.name);
+                event.age = Some(member.age);
+                event.detail = Some(format!("{:?}", origin).to_lowercase());
+                event
+            }
+            Membership::Dropped(member) => {
+                let mut event = Event::new(iteration, Kind::Drop);
+                event.node = Some(member.name);
+                event.age = Some(member.age);
+                event
+            }
+        };
+
+        self.write(&Event {
+            prefix: Some(prefix),
+            ..event
+        });
+    }
+
+    /// Write out the buffered events.
+    pub fn flush(&mut self) {
+        let result = match self.writer {
+            Some(ref mut writer) => writer.flush(),
+            None => return,
+        };
+
+        if let Err(error) = result {
+            error!("Couldn't write event trace, stopping it: {}", error);
+            self.writer = None;
+        }
+    }
+
+    fn write(&mut self, event: &Event) {
+        let result = match self.writer {
+            Some(ref mut writer) => {
+                match self.format {
+                    TraceFormat::Jsonl => writeln!(writer, "{}", event.json()),
+                    TraceFormat::Binary => event.write_binary(writer),
+                }
+            }
+            None => return,
+        };
+
+        if let Err(error) = result {
+            error!("Couldn't write event trace, stopping it: {}", error);
+            self.writer = None;
+        }
+    }
+}
+
+fn message_event(iteration: u64, message: &Message) -> Event {
+    let (kind, id, node, target) = match *message {
+        Message::RelocateRequest { id, node_name, target } => {
+            (Kind::RelocateRequest, Some(id), Some(node_name), target)
+        }
+        Message::RelocateAccept { id, node_name, target } => {
+            (Kind::RelocateAccept, Some(id), Some(node_name), target)
+        }
+        Message::RelocateReject { id, node_name, target } => {
+            (Kind::RelocateReject, Some(id), Some(node_name), target)
+        }
+        Message::RelocateCommit { id, ref node, target, .. } => {
+            (Kind::RelocateCommit, Some(id), Some(node.name()), target)
+        }
+        Message::RelocateCancel { id, node_name, target } => {
+            (Kind::RelocateCancel, Some(id), Some(node_name), target)
+        }
+        Message::JoinRedirect { ref node, target } => {
+            (Kind::JoinRedirect, None, Some(node.name()), target)
+        }
+        Message::Bounce { ref node } => (Kind::Bounce, None, Some(node.name()), node.name()),
+        Message::SiblingSize { target, .. } => (Kind::SiblingSize, None, None, target),
+        Message::NeedAdults { target, .. } => (Kind::NeedAdults, None, None, target),
+    };
+
+    let mut event = Event::new(iteration, kind);
+    event.exchange = id;
+    event.node = node;
+    event.target = Some(target);
+
+    match *message {
+        Message::RelocateCommit { ref node, cause, .. } => {
+            event.age = Some(node.age());
+            event.detail = Some(cause.name().to_string());
+        }
+        Message::JoinRedirect { ref node, .. } |
+        Message::Bounce { ref node } => event.age = Some(node.age()),
+        Message::SiblingSize { source, size, .. } => {
+            event.prefix = Some(source);
+            event.value = Some(size as u64);
+        }
+        Message::NeedAdults { source, .. } => event.prefix = Some(source),
+        _ => (),
+    }
+
+    event
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use random;
+    use simulation::Simulation;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::sync::atomic::AtomicBool;
+    use test_params;
+
+    fn run(path: &Path, format: &str) {
+        let params = test_params(&[
+            "--seed",
+            "[1, 2, 3, 4]",
+            "--iterations",
+            "200",
+            "--trace-file",
+            &path.display().to_string(),
+            "--trace-format",
+            format,
+        ]);
+        random::reseed(params.rng, params.seed);
+
+        let mut simulation = Simulation::new(params.clone());
+        let _ = simulation.run(&params, 0, &AtomicBool::new(true));
+    }
+
+    #[test]
+    fn binary_trace_reads_back_as_json_trace() {
+        let dir = env::temp_dir();
+        let jsonl = dir.join(format!("datachains_sim-trace-test-{}.jsonl", process::id()));
+        let binary = dir.join(format!("datachains_sim-trace-test-{}.bin", process::id()));
+        run(&jsonl, "jsonl");
+        run(&binary, "binary");
+
+        let expected = fs::read_to_string(&jsonl).unwrap();
+        let events: Vec<_> = EventReader::open(&binary).unwrap().map(Result::unwrap).collect();
+        let _ = fs::remove_file(&jsonl);
+        let _ = fs::remove_file(&binary);
+
+        assert!(events.iter().any(|event| event.kind == Kind::RelocateCommit));
+        assert!(events.iter().any(|event| event.kind == Kind::Split));
+        let actual: Vec<_> = events.iter().map(Event::json).collect();
+        assert_eq!(actual, expected.lines().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn event_round_trip() {
+        let event = Event {
+            iteration: 7,
+            kind: Kind::SiblingSize,
+            prefix: Some(Prefix::EMPTY.extend(1).extend(0)),
+            node: Some(Name(u64::MAX)),
+            age: Some(9),
+            target: None,
+            exchange: Some(ExchangeId(3, 4)),
+            value: Some(12),
+            detail: Some("ageing".to_string()),
+        };
+
+        let mut bytes = Vec::new();
+        event.write_binary(&mut bytes).unwrap();
+        Event::new(8, Kind::Fault).write_binary(&mut bytes).unwrap();
+
+        let mut reader = &bytes[..];
+        assert_eq!(Event::read_binary(&mut reader).unwrap(), Some(event));
+        assert_eq!(Event::read_binary(&mut reader).unwrap(), Some(Event::new(8, Kind::Fault)));
+        assert_eq!(Event::read_binary(&mut reader).unwrap(), None);
+    }
+}
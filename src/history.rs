@@ -0,0 +1,196 @@
+//! History of individual nodes, for survival analysis.
+
+use HashSet;
+use event_trace::Membership;
+use node::{Node, NodeId};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+struct Record {
+    joined: u64,
+    left: Option<u64>,
+    age: u64,
+    relocations: u64,
+}
+
+pub struct History {
+    records: BTreeMap<NodeId, Record>,
+    // Ids of the nodes present at the last observation.
+    alive: HashSet<NodeId>,
+    last_iteration: u64,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History {
+            records: BTreeMap::new(),
+            alive: HashSet::default(),
+            last_iteration: 0,
+        }
+    }
+
+    /// Record the node joining or dropping from a section in the given
+    /// iteration, as the section reports it. Relocated nodes and restarted ones
+    /// rejoining join under the same id, so neither relocations nor restarts
+    /// count as departures.
+    pub fn record(&mut self, iteration: u64, membership: &Membership) {
+        match *membership {
+            Membership::Joined(member, _) => {
+                let record = self.records.entry(member.id).or_insert(Record {
+                    joined: iteration,
+                    left: None,
+                    age: 0,
+                    relocations: 0,
+                });
+                record.left = None;
+                record.age = u64::from(member.age);
+                record.relocations = member.relocations;
+            }
+            Membership::Dropped(member) => {
+                if let Some(record) = self.records.get_mut(&member.id) {
+                    record.left = Some(iteration);
+                    record.age = u64::from(member.age);
+                }
+            }
+        }
+    }
+
+    /// Record the nodes present in the network at the end of the given
+    /// iteration, on top of the joins and drops (see `record`): this covers the
+    /// nodes present from the start and those lost without dropping (e.g. in a
+    /// relocation), and updates the ages. Nodes seen for the first time are
+    /// considered joined in this iteration, nodes no longer present are
+    /// considered left, until they are seen again.
+    pub fn observe<'a, I: IntoIterator<Item = &'a Node>>(&mut self, iteration: u64, nodes: I) {
+        let mut alive = HashSet::default();
+
+        for node in nodes {
+            let record = self.records.entry(node.id()).or_insert(Record {
+                joined: iteration,
+                left: None,
+                age: 0,
+                relocations: 0,
+            });
+            record.left = None;
+            record.age = u64::from(node.age());
+            record.relocations = node.relocations();
+
+            let _ = alive.insert(node.id());
+        }
+
+        for id in self.alive.difference(&alive) {
+            if let Some(record) = self.records.get_mut(id) {
+                record.left = Some(iteration);
+            }
+        }
+
+        self.alive = alive;
+        self.last_iteration = iteration;
+    }
+
+    /// Write one line per node ever observed, in the form:
+    /// `id joined left event age relocations`, where `event` is 1 if the node
+    /// left the network at iteration `left`, or 0 if it was still alive at the
+    /// end of the run (censored, `left` being the last iteration). `age` is
+    /// the last observed age.
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) {
+        let path = path.as_ref();
+
+        let mut file = File::create(path).unwrap_or_else(|_| {
+            panic!("Couldn't create file {}!", path.display())
+        });
+
+        for (id, record) in &self.records {
+            let (left, event) = match record.left {
+                Some(left) => (left, 1),
+                None => (self.last_iteration, 0),
+            };
+
+            let _ = writeln!(
+                file,
+                "{} {} {} {} {} {}",
+                id,
+                record.joined,
+                left,
+                event,
+                record.age,
+                record.relocations
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_params;
+    use prefix::Name;
+
+    fn entries(history: &History) -> Vec<(NodeId, u64, Option<u64>)> {
+        history
+            .records
+            .iter()
+            .map(|(&id, record)| (id, record.joined, record.left))
+            .collect()
+    }
+
+    #[test]
+    fn restart_is_not_a_departure() {
+        let params = test_params(&[]);
+        let node = Node::new(7, Name(1), 8);
+        let mut history = History::new();
+
+        history.observe(0, vec![&node]);
+        // Disconnected for an iteration, then back under the same id.
+        history.observe(1, Vec::new());
+        assert_eq!(entries(&history), vec![(7, 0, Some(1))]);
+        history.observe(2, vec![&node.rejoined(&params)]);
+        assert_eq!(entries(&history), vec![(7, 0, None)]);
+    }
+
+    #[test]
+    fn join_and_drop_within_an_iteration() {
+        let node = Node::new(4, Name(1), 4);
+        let mut history = History::new();
+
+        history.observe(0, Vec::new());
+        history.record(1, &Membership::joined(&node));
+        history.record(1, &Membership::dropped(&node));
+        history.observe(1, Vec::new());
+        assert_eq!(entries(&history), vec![(4, 1, Some(1))]);
+
+        // Relocated in and dropped before the end of the iteration.
+        let relocated = node.relocated(Name(2));
+        history.record(2, &Membership::joined(&relocated));
+        history.record(2, &Membership::dropped(&relocated));
+        history.observe(2, Vec::new());
+        assert_eq!(entries(&history), vec![(4, 1, Some(2))]);
+        assert_eq!(history.records[&4].relocations, 1);
+    }
+
+    #[test]
+    fn relocation_is_not_a_departure() {
+        let node = Node::new(3, Name(1), 6);
+        let mut history = History::new();
+
+        history.observe(0, vec![&node]);
+        history.observe(1, vec![&node.relocated(Name(2))]);
+        history.observe(2, Vec::new());
+        assert_eq!(entries(&history), vec![(3, 0, Some(2))]);
+        assert_eq!(history.records[&3].relocations, 1);
+    }
+
+    #[test]
+    fn restart_keeps_the_relocations() {
+        let params = test_params(&[]);
+        let node = Node::new(5, Name(1), 8).relocated(Name(2));
+        let mut history = History::new();
+
+        history.observe(0, vec![&node]);
+        history.observe(1, Vec::new());
+        history.observe(2, vec![&node.rejoined(&params)]);
+        assert_eq!(history.records[&5].relocations, 1);
+    }
+}
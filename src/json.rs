@@ -0,0 +1,143 @@
+//! JSON encoding shared by the JSON outputs (the event and relocation traces
+//! and the telemetry).
+//!
+//! Names are written as 16 hex digits, prefixes as their bits and exchange ids
+//! as `SECTION/SEQUENCE`, all as strings; missing values as `null`.
+
+use message::{ExchangeId, RelocationCause};
+use prefix::{Name, Prefix};
+use stats::Aggregator;
+
+/// Value that can be written as JSON.
+pub trait Json {
+    fn json(&self) -> String;
+}
+
+macro_rules! impl_json_for_numbers {
+    ($($ty:ty),*) => {
+        $(
+            impl Json for $ty {
+                fn json(&self) -> String {
+                    self.to_string()
+                }
+            }
+        )*
+    }
+}
+
+impl_json_for_numbers!(u8, u32, u64, usize);
+
+impl Json for str {
+    fn json(&self) -> String {
+        let mut output = String::with_capacity(self.len() + 2);
+        output.push('"');
+        for c in self.chars() {
+            match c {
+                '"' => output.push_str("\\\""),
+                '\\' => output.push_str("\\\\"),
+                '\n' => output.push_str("\\n"),
+                c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+                c => output.push(c),
+            }
+        }
+        output.push('"');
+        output
+    }
+}
+
+impl Json for String {
+    fn json(&self) -> String {
+        self.as_str().json()
+    }
+}
+
+impl Json for Name {
+    fn json(&self) -> String {
+        format!("\"{:016x}\"", self.0)
+    }
+}
+
+impl Json for Prefix {
+    fn json(&self) -> String {
+        format!("\"{}\"", self)
+    }
+}
+
+impl Json for ExchangeId {
+    fn json(&self) -> String {
+        format!("\"{}/{}\"", self.0, self.1)
+    }
+}
+
+impl Json for RelocationCause {
+    fn json(&self) -> String {
+        self.name().json()
+    }
+}
+
+impl Json for Aggregator {
+    fn json(&self) -> String {
+        format!("{{\"min\":{},\"max\":{},\"avg\":{:.2}}}", self.min, self.max, self.avg)
+    }
+}
+
+impl<T: Json> Json for Option<T> {
+    fn json(&self) -> String {
+        match *self {
+            Some(ref value) => value.json(),
+            None => "null".to_string(),
+        }
+    }
+}
+
+/// JSON object with its fields in the order they were added.
+pub struct Object {
+    fields: Vec<String>,
+}
+
+impl Object {
+    pub fn new() -> Self {
+        Object { fields: Vec::new() }
+    }
+
+    pub fn field<T: Json + ?Sized>(&mut self, key: &str, value: &T) -> &mut Self {
+        self.fields.push(format!("\"{}\":{}", key, value.json()));
+        self
+    }
+
+    /// The field, unless the value is missing.
+    pub fn optional_field<T: Json>(&mut self, key: &str, value: &Option<T>) -> &mut Self {
+        if let Some(ref value) = *value {
+            let _ = self.field(key, value);
+        }
+        self
+    }
+
+    pub fn encode(&self) -> String {
+        format!("{{{}}}", self.fields.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_object() {
+        let mut object = Object::new();
+        let _ = object
+            .field("iteration", &12u64)
+            .field("node", &Name(0x4a3f))
+            .field("prefix", &Prefix::EMPTY.extend(0).extend(1))
+            .field("exchange", &ExchangeId(12, 3))
+            .field("detail", "a \"b\"\n")
+            .field("age", &None::<u8>)
+            .optional_field("target", &None::<Name>);
+
+        assert_eq!(
+            object.encode(),
+            "{\"iteration\":12,\"node\":\"0000000000004a3f\",\"prefix\":\"01\",\
+             \"exchange\":\"12/3\",\"detail\":\"a \\\"b\\\"\\n\",\"age\":null}"
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! Initial network layouts, to start the simulation from exact sections instead
+//! of the root section.
+//!
+//! A layout is a CSV file with one node per line, in the form
+//! `PREFIX,NAME,AGE`, where `PREFIX` is the prefix of the node's section in
+//! binary (empty for the root section), `NAME` is the node name in hex and
+//! `AGE` its age. A line with only a `PREFIX` declares a section without nodes.
+//! Empty lines and lines starting with `#` are ignored.
+//!
+//! The sections must cover the whole name space without overlapping and every
+//! node name must match its section's prefix. The elders are elected as usual.
+
+use Age;
+use HashSet;
+use prefix::{Name, Prefix};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub struct Layout {
+    sections: BTreeMap<Prefix, Vec<(Name, Age)>>,
+}
+
+impl Layout {
+    /// Read the layout from the file, returning an error if it's invalid.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|error| {
+            format!("Couldn't read {}: {}", path.display(), error)
+        })?;
+
+        Self::parse(&content).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    /// Parse the layout from the content of a layout file. Errors about a
+    /// single line start with its number.
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut sections = BTreeMap::new();
+        let mut names = HashSet::default();
+
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |detail: &str| format!("{}: {}: {}", index + 1, detail, line);
+            let tokens: Vec<_> = line.split(',').map(str::trim).collect();
+            if tokens.len() != 1 && tokens.len() != 3 {
+                return Err(error("invalid layout line"));
+            }
+
+            let prefix: Prefix = tokens[0].parse().map_err(|_| error("invalid prefix"))?;
+            let nodes = sections.entry(prefix).or_insert_with(Vec::new);
+            if tokens.len() == 1 {
+                continue;
+            }
+
+            let name = u64::from_str_radix(tokens[1], 16)
+                .map(Name)
+                .map_err(|_| error("invalid name"))?;
+            let age = tokens[2].parse().map_err(|_| error("invalid age"))?;
+            if !prefix.matches(name) {
+                return Err(error("name doesn't match the prefix"));
+            }
+            if !names.insert(name) {
+                return Err(error("duplicate name"));
+            }
+
+            nodes.push((name, age));
+        }
+
+        if sections.is_empty() {
+            return Err("no sections".to_string());
+        }
+
+        for prefix in sections.keys() {
+            let mut ancestor = *prefix;
+            while ancestor != Prefix::EMPTY {
+                ancestor = ancestor.shorten();
+                if sections.contains_key(&ancestor) {
+                    return Err(format!("sections {} and {} overlap", ancestor, prefix));
+                }
+            }
+        }
+
+        let coverage: u128 = sections
+            .keys()
+            .map(|prefix| 1u128 << (64 - prefix.len()))
+            .sum();
+        if coverage != 1u128 << 64 {
+            return Err("sections don't cover the whole name space".to_string());
+        }
+
+        Ok(Layout { sections })
+    }
+
+    /// The sections with the names and ages of their nodes.
+    pub fn sections(&self) -> &BTreeMap<Prefix, Vec<(Name, Age)>> {
+        &self.sections
+    }
+
+    pub fn num_nodes(&self) -> usize {
+        self.sections.values().map(Vec::len).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_layout() {
+        let content = "# prefix,name,age\n\
+                       0,1000000000000000,5\n\
+                       0, 2000000000000000, 7\n\
+                       \n\
+                       10\n\
+                       11,c000000000000000,4\n";
+        let layout = Layout::parse(content).unwrap();
+
+        let prefixes: Vec<_> = layout.sections().keys().map(|prefix| prefix.to_string()).collect();
+        assert_eq!(prefixes, vec!["0", "10", "11"]);
+        assert_eq!(layout.num_nodes(), 3);
+        assert_eq!(
+            layout.sections()[&"0".parse().unwrap()],
+            vec![(Name(0x1000_0000_0000_0000), 5), (Name(0x2000_0000_0000_0000), 7)]
+        );
+        assert!(layout.sections()[&"10".parse().unwrap()].is_empty());
+    }
+
+    #[test]
+    fn parse_invalid_layout() {
+        for &(content, expected) in &[
+            ("0,1\n1\n", "1: invalid layout line"),
+            ("2\n", "1: invalid prefix"),
+            ("0,xyz,5\n1\n", "1: invalid name"),
+            ("0,1,old\n1\n", "1: invalid age"),
+            ("0,8000000000000000,5\n1\n", "1: name doesn't match the prefix"),
+            ("0,1,5\n0,1,6\n1\n", "2: duplicate name"),
+            ("# nothing\n", "no sections"),
+            ("0\n01\n1\n", "sections 0 and 01 overlap"),
+            ("0\n10\n", "sections don't cover the whole name space"),
+        ] {
+            let error = Layout::parse(content).err().unwrap();
+            assert!(error.starts_with(expected), "{}: {}", content, error);
+        }
+    }
+}
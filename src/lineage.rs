@@ -0,0 +1,168 @@
+//! Lineage of sections through splits and merges.
+
+use prefix::Prefix;
+use snapshot::{Reader, Snapshot, Writer};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+/// Stable identifier of a section. A new one is assigned to every section
+/// created by a split or a merge.
+pub type SectionId = u64;
+
+struct Record {
+    prefix: Prefix,
+    parents: Vec<SectionId>,
+    created: u64,
+    ended: Option<u64>,
+}
+
+impl Snapshot for Record {
+    fn save(&self, writer: &mut Writer) {
+        self.prefix.save(writer);
+        self.parents.save(writer);
+        self.created.save(writer);
+        self.ended.save(writer);
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok(Record {
+            prefix: Snapshot::load(reader)?,
+            parents: Snapshot::load(reader)?,
+            created: Snapshot::load(reader)?,
+            ended: Snapshot::load(reader)?,
+        })
+    }
+}
+
+pub struct Lineage {
+    // Indexed by `SectionId`.
+    records: Vec<Record>,
+}
+
+impl Lineage {
+    pub fn new() -> Self {
+        Lineage { records: Vec::new() }
+    }
+
+    /// Register new section with the given parents and return its id.
+    pub fn create(&mut self, prefix: Prefix, parents: Vec<SectionId>, iteration: u64) -> SectionId {
+        self.records.push(Record {
+            prefix,
+            parents,
+            created: iteration,
+            ended: None,
+        });
+
+        self.records.len() as SectionId - 1
+    }
+
+    /// Mark the section as ended (split or merged into other sections).
+    pub fn end(&mut self, id: SectionId, iteration: u64) {
+        self.records[id as usize].ended = Some(iteration)
+    }
+
+    /// Iteration in which the section was created.
+    pub fn created(&self, id: SectionId) -> u64 {
+        self.records[id as usize].created
+    }
+
+    /// Iteration in which the section ended, if it did.
+    pub fn ended(&self, id: SectionId) -> Option<u64> {
+        self.records[id as usize].ended
+    }
+
+    /// Lifetimes (in iterations) of the sections that ended.
+    pub fn lifetimes(&self) -> Vec<u64> {
+        self.records
+            .iter()
+            .filter_map(|record| record.ended.map(|ended| ended - record.created))
+            .collect()
+    }
+
+    /// Number of splits and merges that undid the previous one: merges back
+    /// into the prefix of a section that split, and splits back into the prefix
+    /// of a section that merged.
+    pub fn num_reversals(&self) -> usize {
+        self.records
+            .iter()
+            .filter(|record| {
+                record.parents.iter().any(|&parent| {
+                    self.records[parent as usize].parents.iter().any(|&grandparent| {
+                        self.records[grandparent as usize].prefix == record.prefix
+                    })
+                })
+            })
+            .count()
+    }
+
+    /// Number of distinct ancestor sections of the section: its parents, their
+    /// parents and so on, each counted once even if reached through several
+    /// merges. This is not the number of splits and merges that led to it, as a
+    /// merge brings in the ancestors of all the merged sections.
+    pub fn num_ancestors(&self, id: SectionId) -> usize {
+        let mut visited = vec![false; self.records.len()];
+        let mut stack = self.records[id as usize].parents.clone();
+        let mut count = 0;
+
+        while let Some(id) = stack.pop() {
+            if visited[id as usize] {
+                continue;
+            }
+            visited[id as usize] = true;
+            count += 1;
+
+            stack.extend(&self.records[id as usize].parents);
+        }
+
+        count
+    }
+
+    /// Write one line per section ever created, in the form:
+    /// `id prefix created ended parents`, where `ended` is `-` for sections
+    /// still alive and `parents` is a comma separated list of ids (`-` if none).
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) {
+        let path = path.as_ref();
+
+        let mut file = File::create(path).unwrap_or_else(|_| {
+            panic!("Couldn't create file {}!", path.display())
+        });
+
+        for (id, record) in self.records.iter().enumerate() {
+            let ended = record.ended.map_or_else(
+                || "-".to_string(),
+                |ended| ended.to_string(),
+            );
+            let parents = if record.parents.is_empty() {
+                "-".to_string()
+            } else {
+                record
+                    .parents
+                    .iter()
+                    .map(|id| id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            };
+
+            let _ = writeln!(
+                file,
+                "{} [{}] {} {} {}",
+                id,
+                record.prefix,
+                record.created,
+                ended,
+                parents
+            );
+        }
+    }
+}
+
+impl Snapshot for Lineage {
+    fn save(&self, writer: &mut Writer) {
+        self.records.save(writer)
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok(Lineage { records: Snapshot::load(reader)? })
+    }
+}
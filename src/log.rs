@@ -1,11 +1,17 @@
 //! Logging and log syntax highlighting.
+//!
+//! Log lines are prefixed with the simulation context they were logged in:
+//! the iteration, the round of action processing within it (0 before the first
+//! section tick) and the section whose code is running, if any. Lines logged
+//! outside of an iteration have no prefix.
 
 use colored::{ColoredString, Colorize};
 use prefix::{Name, Prefix};
+use std::cell::Cell;
 use std::fmt::Debug;
-use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-static VERBOSITY: AtomicUsize = ATOMIC_USIZE_INIT;
+static VERBOSITY: AtomicUsize = AtomicUsize::new(0);
 
 pub const ERROR: usize = 1;
 pub const INFO: usize = 2;
@@ -19,30 +25,109 @@ pub fn verbosity() -> usize {
     VERBOSITY.load(Ordering::Relaxed)
 }
 
-/// Log error.
+#[derive(Clone, Copy, Default)]
+struct Context {
+    iteration: Option<u64>,
+    round: u64,
+    section: Option<Prefix>,
+}
+
+thread_local! {
+    static CONTEXT: Cell<Context> = Cell::new(Context::default());
+}
+
+/// Log as part of the given iteration, before its first round, until the
+/// returned guard is dropped.
+pub fn enter_iteration(iteration: u64) -> IterationGuard {
+    CONTEXT.with(|context| {
+        context.set(Context {
+            iteration: Some(iteration),
+            round: 0,
+            section: None,
+        })
+    });
+    IterationGuard
+}
+
+pub struct IterationGuard;
+
+impl Drop for IterationGuard {
+    fn drop(&mut self) {
+        CONTEXT.with(|context| context.set(Context::default()))
+    }
+}
+
+/// Start logging the next round of action processing.
+pub fn next_round() {
+    CONTEXT.with(|context| {
+        let mut current = context.get();
+        current.round += 1;
+        context.set(current)
+    })
+}
+
+fn set_section(section: Option<Prefix>) {
+    CONTEXT.with(|context| {
+        let mut current = context.get();
+        current.section = section;
+        context.set(current)
+    })
+}
+
+/// Log as code of the given section until the returned guard is dropped.
+pub fn enter_section(section: Prefix) -> SectionGuard {
+    let previous = CONTEXT.with(Cell::get).section;
+    set_section(Some(section));
+    SectionGuard { previous }
+}
+
+pub struct SectionGuard {
+    previous: Option<Prefix>,
+}
+
+impl Drop for SectionGuard {
+    fn drop(&mut self) {
+        set_section(self.previous)
+    }
+}
+
+/// The current context, formatted to prefix a log line.
+pub fn context() -> String {
+    let current = CONTEXT.with(Cell::get);
+    let mut result = match current.iteration {
+        Some(iteration) => format!("{}.{} ", iteration, current.round),
+        None => String::new(),
+    };
+    if let Some(section) = current.section {
+        result.push_str(&format!("{}: ", prefix(&section)));
+    }
+    result
+}
+
+/// Log error, prefixed with the simulation context.
 macro_rules! error {
     ($($arg:tt)*) => {
         if $crate::log::verbosity() >= $crate::log::ERROR {
             use $crate::colored::Colorize;
-            println!("{}", format!($($arg)*).red())
+            println!("{}{}", $crate::log::context(), format!($($arg)*).red())
         }
     }
 }
 
-/// Log info.
+/// Log info, prefixed with the simulation context.
 macro_rules! info {
     ($($arg:tt)*) => {
         if $crate::log::verbosity() >= $crate::log::INFO {
-            println!($($arg)*)
+            println!("{}{}", $crate::log::context(), format_args!($($arg)*))
         }
     }
 }
 
-/// Log debug
+/// Log debug, prefixed with the simulation context.
 macro_rules! debug {
     ($($arg:tt)*) => {
         if $crate::log::verbosity() >= $crate::log::DEBUG {
-            println!($($arg)*)
+            println!("{}{}", $crate::log::context(), format_args!($($arg)*))
         }
     }
 }
@@ -2,54 +2,114 @@ extern crate byteorder;
 extern crate colored;
 extern crate clap;
 extern crate ctrlc;
+extern crate flate2;
 extern crate rand;
+extern crate tar;
 extern crate tiny_keccak;
 
 #[macro_use]
 mod log;
 
+mod age_index;
+mod bundle;
 mod chain;
+mod churn_trace;
+mod decision;
+mod error;
+mod event_trace;
+mod history;
+mod json;
+mod layout;
+mod lineage;
 mod message;
+mod model;
 mod network;
 mod node;
 mod params;
 mod parse;
 mod prefix;
+mod provenance;
 mod random;
+mod relocation_trace;
 mod section;
+mod simulation;
+mod snapshot;
+mod soak;
 mod stats;
+mod telemetry;
+mod termination;
+mod victim;
 
-use clap::{App, Arg, ArgMatches};
-use colored::Colorize;
+use bundle::Bundle;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use event_trace::EventReader;
+use layout::Layout;
 use network::Network;
-use params::Params;
-use random::Seed;
+use params::{EventRates, ForcedChange, OperatorProfile, Params, Scheduler, Setting,
+             SyntheticChurn};
+use prefix::Prefix;
+use random::{RngKind, Seed};
+use simulation::{Observer, Policies, Simulation};
+use soak::{Checkpoint, Checkpointer};
+use stats::{Distribution, Histogram, Sample};
 use std::cmp;
 use std::collections;
+use std::collections::BTreeMap;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::BuildHasherDefault;
+use std::fs;
+use std::mem;
 use std::panic;
+use std::path::Path;
+use std::process;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use telemetry::Telemetry;
+use termination::{Reason, Termination};
 
 type Age = u8;
 
 fn main() {
-    let params = get_params();
+    let matches = app().get_matches();
+    let params = parse_params(&matches);
+
+    if let Some(matches) = matches.subcommand_matches("trace-json") {
+        print_event_trace(matches.value_of("PATH").unwrap());
+        return;
+    }
+
+    if params.dry_run {
+        print_derived_quantities(&params);
+        print_dead_ends(&params);
+        return;
+    }
+
+    check_dead_ends(&params);
+
+    if let Some(ref path) = params.layout_file {
+        if let Err(error) = Layout::load(path) {
+            println!("Invalid layout: {}", error);
+            process::exit(1);
+        }
+    }
 
     if params.disable_colors || cfg!(windows) {
         colored::control::set_override(false);
     }
 
+    let rng = params.rng;
     let seed = params.seed;
-    random::reseed(seed);
+    random::reseed(rng, seed);
 
-    // Print seed on panic.
+    // Print seed (and the rng algorithm it applies to) on panic.
     let default_hook = panic::take_hook();
     panic::set_hook(Box::new(move |info| {
         default_hook(info);
-        println!("{:?}", seed);
+        println!("{:?} ({:?})", seed, rng);
+        if let Some(state) = random::save_state() {
+            println!("{:?}", state);
+        }
     }));
 
     log::set_verbosity(params.verbosity);
@@ -61,44 +121,582 @@ fn main() {
         let _ = ctrlc::set_handler(move || { running.store(false, Ordering::Relaxed); });
     }
 
-    let mut network = Network::new(params.clone());
-    let mut max_prefix_len_diff = 0;
+    if let Some(matches) = matches.subcommand_matches("ab") {
+        run_ab(params, matches, &running);
+        return;
+    }
 
-    for i in 0..params.num_iterations {
+    // Resume the soak run from its latest checkpoint, if any.
+    let checkpoint = params.soak_dir.as_ref().and_then(|dir| {
+        Checkpoint::latest(dir).unwrap_or_else(|error| panic!("{}", error))
+    });
+    let (network, first_iteration) = if let Some(checkpoint) = checkpoint {
         info!(
-            "{}",
-            format!("Iteration: {}", format!("{}", i).bold()).green()
+            "Resuming from {} after iteration {}",
+            checkpoint.path.display(),
+            checkpoint.iteration
         );
+        let network = checkpoint.restore(params.clone()).unwrap_or_else(
+            |error| panic!("{}", error),
+        );
+        (network, checkpoint.iteration + 1)
+    } else {
+        (Network::new(params.clone()), 0)
+    };
+
+    let mut simulation = Simulation::with_network(network, Policies::new(&params));
+    if params.stats_frequency > 0 {
+        simulation.add_observer(Box::new(TickStatsPrinter::new(&params)));
+    }
+    if params.diff_sections > 0 {
+        simulation.add_observer(Box::new(SectionTableDiff::new(
+            params.diff_sections,
+            params.diff_sections_threshold,
+        )));
+    }
+    if let Some(ref dir) = params.soak_dir {
+        let checkpointer = Checkpointer::new(dir, params.soak_frequency, params.soak_keep)
+            .unwrap_or_else(|error| panic!("{}", error));
+        simulation.add_observer(Box::new(checkpointer));
+    }
+    let telemetry_file = params.telemetry_file.clone().or_else(|| {
+        params.soak_dir.as_ref().map(|dir| {
+            Path::new(dir).join("telemetry.jsonl").display().to_string()
+        })
+    });
+    if let Some(ref path) = telemetry_file {
+        // A soak run keeps appending to the telemetry of the runs it resumes.
+        let telemetry = Telemetry::create(
+            path,
+            params.soak_dir.is_some(),
+            params.telemetry_frequency,
+            params.telemetry_summaries.clone(),
+        ).unwrap_or_else(|error| panic!("{}", error));
+        simulation.add_observer(Box::new(telemetry));
+    }
+
+    let (termination, state_hashes) = simulation.run(&params, first_iteration, &running);
+    let time = simulation.time(termination.iteration);
+    let network = simulation.into_network();
 
-        network.tick(i);
+    println!("\n===== Summary =====");
+    println!("\nTermination: {}", termination);
+    println!("\n{:?}\n", params);
+    let summary = network.stats().summary();
+    println!("{}", summary);
+    // A panic may leave the network halfway through a tick, so only the
+    // statistics recorded by the iterations completed before it are printed.
+    if let Reason::Panic(_) = termination.reason {
+        println!("Partial summary: the derived statistics are skipped after a panic.\n");
+    } else {
+        print_report(&params, &termination, time, &network, &summary);
+    }
 
-        if params.stats_frequency > 0 && i % params.stats_frequency == 0 {
-            print_tick_stats(&network, &mut max_prefix_len_diff);
+    if let Some(ref path) = params.split_audit_file {
+        let mut output = String::from("iteration,prefix,elders,margin\n");
+        for audit in network.split_audits() {
+            output.push_str(&format!(
+                "{},{},{},{}\n",
+                audit.iteration,
+                audit.prefix,
+                audit.elders,
+                audit.margin(&params)
+            ));
+        }
+        if let Err(error) = fs::write(path, output) {
+            error!("Couldn't write {}: {}", path, error);
         }
+    }
+
+    if let Some(ref path) = params.file {
+        network.stats().write_to_file(path, params.file_format);
+    }
+
+    if let Some(ref path) = params.lineage_file {
+        network.lineage().write_to_file(path);
+    }
+
+    if let Some(ref path) = params.relocation_trace {
+        network.relocation_trace().write_to_file(path);
+    }
+
+    if let Some(ref path) = params.survival_file {
+        network.history().write_to_file(path);
+    }
 
-        if !running.load(Ordering::Relaxed) {
-            break;
+    if let Some(ref path) = params.state_hash_file {
+        if let Err(error) = fs::write(path, state_hashes) {
+            error!("Couldn't write {}: {}", path, error);
         }
     }
 
-    println!("\n===== Summary =====");
-    println!("\n{:?}\n", params);
-    println!("{}", network.stats().summary());
+    if let Some(ref path) = params.bundle_file {
+        match bundle_outputs(&params, &termination, &network) {
+            Ok(bundle) => {
+                if let Err(error) = bundle.write_to_file(path) {
+                    error!("{}", error);
+                }
+            }
+            Err(error) => error!("{}", error),
+        }
+    }
+
+    if termination.reason.is_failure() {
+        process::exit(1);
+    }
+}
+
+// Print the statistics derived from the final state of the network.
+fn print_report(
+    params: &Params,
+    termination: &Termination,
+    time: Option<f64>,
+    network: &Network,
+    summary: &Sample,
+) {
+    println!(
+        "Relocation requests per relocation: {:.3}",
+        summary.relocation_requests() as f64 / cmp::max(summary.relocations(), 1) as f64
+    );
+    println!("Relocation efficiency: {:.2}%", summary.relocation_efficiency());
+    println!(
+        "Relocations per node (of those in the network): {:.3}",
+        network.relocations_per_node()
+    );
+    let latency = network.relocation_latency_distribution().summary();
+    println!(
+        "Relocation latency (section ticks from the first request to the commit): \
+         avg {:.2}, max {}",
+        latency.avg,
+        latency.max
+    );
+    let outcome = network.outcome();
+    println!("Score: {:.4} ({})\n", outcome.score(&params.score_weights), outcome);
+    if let Some(time) = time {
+        let rate = |count: u64| count as f64 / time.max(f64::MIN_POSITIVE);
+        println!("Simulated time: {:.2} ({} events)", time, termination.iteration + 1);
+        println!(
+            "Per unit of time: {:.4} relocations, {:.4} rejections, {:.4} merges, {:.4} splits\n",
+            rate(summary.relocations()),
+            rate(summary.rejections()),
+            rate(summary.merges()),
+            rate(summary.splits())
+        );
+    }
+    println!("Network eras (averages per iteration; churn counts the nodes added or removed):");
+    println!("{}", network.eras());
     println!("Age distribution:");
     let age = network.age_distribution();
-    println!("{}\n{}", age, age.summary());
+    println!("{}\n{}", show(params, &age), age.summary());
+    println!("Spread of the section average ages: {:.3}\n", network.section_age_spread());
+    if params.histogram_width > 0 {
+        println!("Section size distribution:");
+        let sizes = network.section_size_distribution();
+        println!("{}\n{}", show(params, &sizes), sizes.summary());
+    }
+    println!("Observed vs expected (see `model`):");
+    println!(
+        "  Ageing relocations per churn event: {:.4} vs {:.4}",
+        summary.ageing_relocations() as f64 / cmp::max(summary.ageing_events(), 1) as f64,
+        model::expected_relocation_rate(params)
+    );
+    println!("  Age distribution (%):");
+    let total_nodes = cmp::max(age.total(), 1) as f64;
+    let expected = model::expected_age_distribution(params, age.summary().max as Age);
+    let last = expected.keys().last().cloned();
+    for (value, expected) in expected {
+        let observed = if Some(value) == last {
+            age.count_at_least(u64::from(value))
+        } else {
+            age.count(u64::from(value))
+        };
+        println!(
+            "{:6}:\t{:6.2} vs {:6.2}",
+            value,
+            100.0 * observed as f64 / total_nodes,
+            100.0 * expected
+        );
+    }
+    println!();
+    if params.ageing_audit {
+        println!("Ageing checks by age:");
+        for (age, counts) in network.ageing_counts() {
+            let checked = cmp::max(counts.checked, 1) as f64;
+            println!(
+                "{:6}:\t{:8} checked, {:8} eligible ({:.5} vs {:.5}), {:8} selected, \
+                 {:8} relocated",
+                age,
+                counts.checked,
+                counts.eligible,
+                counts.eligible as f64 / checked,
+                2f64.powi(-i32::from(*age)),
+                counts.selected,
+                counts.relocated
+            );
+        }
+        println!();
+    }
+    println!();
+    println!("Relocation requests by destination prefix length:");
+    for (len, responses) in network.relocation_responses() {
+        println!(
+            "{:6}:\t{:8} accepted, {:8} rejected ({:8} busy), {:6.2}% accepted",
+            len,
+            responses.accepted,
+            responses.rejected,
+            responses.busy,
+            100.0 * responses.acceptance_rate()
+        );
+    }
+    println!();
+    let stale_views = network.stale_views();
+    println!("Neighbour views (lag of {} iterations):", params.view_lag);
+    println!(
+        "Delayed:                {} ({} from sections split or merged since)",
+        stale_views.delayed,
+        stale_views.outdated
+    );
+    println!("Sibling size error:     {}", stale_views.size_error);
+    println!("Wasted rescues:         {}", stale_views.wasted_rescues);
+    println!("Misdirected balancing:  {}", stale_views.misdirected_balancing);
+    println!("Dropped sibling sizes:  {}", stale_views.dropped_sizes);
+    println!();
+    if params.global_relocation_cap > 0 {
+        println!(
+            "Relocation requests deferred by the cap of {} per iteration: {}",
+            params.global_relocation_cap,
+            network.deferred_relocations()
+        );
+        println!("Requests queued at the end of each iteration:");
+        let depths = network.relocation_queue_depth_distribution();
+        println!("{}\n{}", depths, depths.summary());
+        println!("Iterations the deferred requests waited:");
+        let delays = network.relocation_delay_distribution();
+        println!("{}\n{}", delays, delays.summary());
+    }
+    println!("Elder age distribution:");
+    let elder_age = network.elder_age_distribution();
+    println!("{}\n{}", show(params, &elder_age), elder_age.summary());
+    println!("Age at first promotion to elder:");
+    let promotion_age = network.first_promotion_age_distribution();
+    println!("{}\n{}", promotion_age, promotion_age.summary());
+    println!("Iterations short of elders after relocating an elder:");
+    let elder_gaps = network.elder_gap_distribution();
+    println!("{}\n{}", elder_gaps, elder_gaps.summary());
+    println!("Iterations until complete after a split:");
+    let completion_gaps = network.completion_gap_distribution();
+    println!("{}\n{}", completion_gaps, completion_gaps.summary());
+    println!("Adults imbalance between the halves of the section at relocation commits:");
+    let commit_imbalances = network.commit_imbalance_distribution();
+    println!("{}\n{}", commit_imbalances, commit_imbalances.summary());
+    println!(
+        "Size of the larger sibling section in percent of the smaller one ({} balancing \
+         relocations):",
+        summary.balancing_relocations()
+    );
+    println!("{}", network.sibling_size_ratio_distribution().summary());
+    println!("Intervals between relocations from the same section:");
+    println!("{}", network.section_relocation_intervals().summary());
+    println!("Intervals between relocations of the same node by age:");
+    println!("{}", network.node_relocation_intervals_by_age());
+    println!("Statistics by node origin:");
+    for (origin, (counts, ages)) in network.stats_by_origin() {
+        let arrivals = cmp::max(counts.arrivals, 1) as f64;
+        println!(
+            "{:>9}:\t{:6} arrivals, {:6} drops ({:.3}), {:6} promotions ({:.3}), ages: {:?}",
+            format!("{:?}", origin),
+            counts.arrivals,
+            counts.drops,
+            counts.drops as f64 / arrivals,
+            counts.promotions,
+            counts.promotions as f64 / arrivals,
+            ages.summary()
+        );
+    }
+    println!();
+    println!("Age distribution by prefix length:");
+    println!("{}", network.age_distribution_by_prefix_len());
+    if !network.param_changes().is_empty() {
+        println!("Parameter changes:");
+        let changes = network.param_changes();
+        for change in changes.first() {
+            println!("{:6}:\t{:?}", change.iteration, change.setting);
+        }
+        print_omitted(changes.count(), changes.first().len());
+        println!();
+    }
+
+    if !params.chaos.is_empty() {
+        println!("Chaos epochs (averages per iteration; faults and violations in total):");
+        println!("{}", network.chaos_epochs());
+        if termination.reason.is_failure() {
+            println!(
+                "Settings at termination: {}\n",
+                network.chaos_epochs().current().unwrap_or("-")
+            );
+        }
+    }
+
+    if !network.disasters().is_empty() {
+        println!("Disasters:");
+        for &(iteration, victims, count) in network.disasters() {
+            println!("{:6}:\t{} nodes removed ({})", iteration, count, victims);
+        }
+        println!();
+    }
+
+    if params.upgrade_interval > 0 {
+        println!("Merges and elder changes per iteration:");
+        let (upgrades, background) = network.upgrade_churn();
+        for &(label, churn) in &[("Upgrades", upgrades), ("Otherwise", background)] {
+            println!(
+                "{:>9}:\t{:6} iterations, {:.4} merges, {:.3} elder changes",
+                label,
+                churn.iterations,
+                churn.merges_per_iteration(),
+                churn.elder_changes_per_iteration()
+            );
+        }
+        println!();
+    }
+
+    if params.operators > 0 {
+        println!("Elder rotation across operators (iterations ended with an elder seat):");
+        println!("{}", network.elder_rotation());
+    }
+
+    if params.malicious_fraction > 0.0 {
+        println!(
+            "Sections controlled by the attacker: {} of {}",
+            network.num_malicious_sections(),
+            summary.sections()
+        );
+        for &(label, malicious) in &[("Honest", false), ("Malicious", true)] {
+            let (ages, relocations) = network.stats_by_allegiance(malicious);
+            println!(
+                "{:>9}:\t{:6} nodes, {:.3} relocations per node, ages: {:?}",
+                label,
+                ages.total(),
+                relocations as f64 / cmp::max(ages.total(), 1) as f64,
+                ages.summary()
+            );
+        }
+        println!(
+            "Attacker's joins placed in the section they chose: {} of {} ({:.1}%{})",
+            summary.chosen_section_joins(),
+            summary.malicious_joins(),
+            summary.chosen_section_joins() as f64 * 100.0 /
+                cmp::max(summary.malicious_joins(), 1) as f64,
+            if params.join_relocation {
+                ", with join relocation"
+            } else {
+                ""
+            }
+        );
+        println!();
+    }
+
+    if params.split_audit_fraction > 0.0 {
+        let audits = network.split_audits();
+        let takeovers = audits
+            .iter()
+            .filter(|audit| audit.margin(params) >= 0)
+            .count();
+        println!(
+            "Split audit (adversary with {:.1}% of the nodes):",
+            params.split_audit_fraction * 100.0
+        );
+        println!("  Takeover possible in {} of {} splits", takeovers, audits.len());
+        println!("  Worst-case malicious elders:");
+        let elders = network.split_audit_elder_distribution();
+        println!("{}\n{}\n", elders, elders.summary());
+    }
+
+    if let Some((churn, iteration, prefix)) = network.max_tick_churn() {
+        println!(
+            "Maximum simultaneous churn in a section: {} (iteration {}, section {})",
+            churn,
+            iteration,
+            log::prefix(&prefix)
+        );
+    }
+    println!("Nodes added or removed per section per tick:");
+    let tick_churn = network.tick_churn_distribution();
+    println!("{}\n{}", tick_churn, tick_churn.summary());
+
+    let (checks, mismatches) = network.merge_elder_checks();
+    println!(
+        "Merges electing other elders than the oldest pre-merge nodes: {} of {}",
+        mismatches.count(),
+        checks
+    );
+    for mismatch in mismatches.first().iter().take(10) {
+        println!(
+            "{:6}:\t[{}] {} of {} elders, missing {:?}, extra {:?}",
+            mismatch.iteration,
+            mismatch.prefix,
+            mismatch.actual,
+            mismatch.expected,
+            mismatch.missing,
+            mismatch.extra
+        );
+    }
+    if params.timing {
+        println!(
+            "Time spent merging sections: {:.3}s in {} merges",
+            network.merge_time().as_secs_f64(),
+            summary.merges()
+        );
+    }
+    println!();
+
+    if !network.faults().is_empty() {
+        println!("Faults: {}", network.faults().count());
+        for &(iteration, section, ref error) in network.faults().first().iter().take(10) {
+            match section {
+                Some(prefix) => println!("{:6}:\t[{}] {}", iteration, prefix, error),
+                None => println!("{:6}:\t{}", iteration, error),
+            }
+        }
+        println!();
+    }
+
+    if params.full_chain {
+        let violations = network.chain_violations();
+        println!("Node lifecycle violations in the section chains: {}", violations.count());
+        for &(iteration, prefix, ref violation) in violations.first().iter().take(10) {
+            println!("{:6}:\t[{}] {}", iteration, prefix, violation);
+        }
+        println!();
+    }
+
+    if params.economics {
+        println!("Credits by age:");
+        for (age, (count, credits)) in network.credits_by_age() {
+            println!(
+                "{:6}:\t{:6} nodes, {:10} credits, {:10.2} per node",
+                age,
+                count,
+                credits,
+                credits as f64 / count as f64
+            );
+        }
+        println!();
+    }
+
+    println!("Section iterations by state:");
+    for (state, ticks) in network.state_ticks() {
+        println!("{:>9}:\t{:10}", format!("{:?}", state), ticks);
+    }
+    println!();
+    println!("Random draws by category:");
+    let draws = random::draws_by_category();
+    for &(category, count) in &draws {
+        println!("{:>11}:\t{:12}", format!("{:?}", category), count);
+    }
+    println!(
+        "{:>11}:\t{:12}",
+        "Total",
+        draws.iter().map(|&(_, count)| count).sum::<u64>()
+    );
+    println!();
+
     println!("Section size distribution:");
     println!("{}", network.section_size_aggregator());
+    println!("Section size spread (std dev / avg): {:.3}\n", network.section_size_spread());
+    if params.max_node_size > 1 {
+        println!("Node size distribution:");
+        let size = network.node_size_distribution();
+        println!("{}\n{}", size, size.summary());
+        println!("Section capacity distribution:");
+        println!("{}", network.section_capacity_aggregator());
+    }
     println!("Prefix length distribution:");
     println!("{}", network.prefix_len_aggregator());
+    println!("Name entropy distribution (%):");
+    println!("{}", network.name_entropy_aggregator());
+    println!("Section lifetime distribution (ended sections):");
+    println!("{}", network.section_lifetime_aggregator());
+    println!(
+        "Splits and merges undoing the previous one: {}\n",
+        network.lineage().num_reversals()
+    );
+    println!("Distinct ancestor sections per section:");
+    println!("{}", network.ancestors_aggregator());
+    if params.top_churned > 0 {
+        println!("Most churned sections:");
+        for (prefix, history) in network.most_churned(params.top_churned) {
+            let total: u64 = history.iter().map(|&(_, churn)| churn.total()).sum();
+            println!("[{}]: {} churn events", prefix, total);
+
+            for (id, churn) in history {
+                let ended = network.lineage().ended(id).map_or_else(
+                    || "-".to_string(),
+                    |ended| ended.to_string(),
+                );
+                println!(
+                    "    #{} ({}..{}): {:?}",
+                    id,
+                    network.lineage().created(id),
+                    ended,
+                    churn
+                );
+            }
+        }
+        println!();
+    }
+
+    if params.routing {
+        println!("Message hop count distribution:");
+        let hops = network.hop_distribution();
+        println!("{}\n{}", hops, hops.summary());
+        println!("Message latency distribution (section ticks):");
+        let latency = network.latency_distribution();
+        println!("{}\n{}", latency, latency.summary());
+    }
+
+    if params.shrink_after > 0 {
+        println!("Shrink test merges:");
+        for &(iteration, prefix, sources) in network.shrink_merges() {
+            println!("{:6}:\t[{}] from {} sections", iteration, prefix, sources);
+        }
+        println!("\nShrink test invariant violations:");
+        let violations = network.shrink_violations();
+        for &(iteration, ref violation) in violations.first() {
+            println!("{:6}:\t{}", iteration, violation);
+        }
+        print_omitted(violations.count(), violations.first().len());
+        println!(
+            "\nShrink test {}\n",
+            if network.is_collapsed() {
+                "completed"
+            } else {
+                "did not complete"
+            }
+        );
+    }
+    if params.merge_chunk_samples > 0 {
+        println!("Chunks below replication count at merge (%):");
+        println!("{}", network.merge_dips_aggregator());
+        println!("Surviving replicas at merge (worst chunk per merge):");
+        let replicas = network.merge_replicas_distribution();
+        println!("{}\n{}", replicas, replicas.summary());
+    }
 
-    if let Some(path) = params.file {
-        network.stats().write_to_file(path);
+    for hex in &params.lookup {
+        println!("Provenance of names starting with {}:", hex);
+        for (name, entries) in network.provenance().lookup(hex) {
+            println!("{:016x}", name.0);
+            for entry in entries {
+                println!("{}", entry);
+            }
+        }
+        println!();
     }
 }
 
-fn get_params() -> Params {
-    let matches = App::new("SAFE network simulation")
+fn app() -> App<'static, 'static> {
+    App::new("SAFE network simulation")
         .about("Simulates evolution of SAFE network")
         .arg(
             Arg::with_name("SEED")
@@ -107,6 +705,14 @@ fn get_params() -> Params {
                 .help("Random seed")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("RNG")
+                .long("rng")
+                .help("Random number generator algorithm")
+                .takes_value(true)
+                .possible_values(&["xorshift", "pcg", "chacha"])
+                .default_value("xorshift"),
+        )
         .arg(
             Arg::with_name("ITERATIONS")
                 .short("n")
@@ -131,6 +737,25 @@ fn get_params() -> Params {
                 .takes_value(true)
                 .default_value("4"),
         )
+        .arg(
+            Arg::with_name("JOIN_RELOCATION")
+                .long("join-relocation")
+                .help(
+                    "Send joining nodes to a section derived from the hash of their name instead \
+                     of the one they chose",
+                ),
+        )
+        .arg(
+            Arg::with_name("JOIN_MODEL")
+                .long("join-model")
+                .help(
+                    "Whether every section gets a joining node each iteration (section) or \
+                     joining nodes join the section of a random existing node (contact)",
+                )
+                .takes_value(true)
+                .possible_values(&["section", "contact"])
+                .default_value("section"),
+        )
         .arg(
             Arg::with_name("ADULT_AGE")
                 .short("a")
@@ -139,6 +764,52 @@ fn get_params() -> Params {
                 .takes_value(true)
                 .default_value("5"),
         )
+        .arg(
+            Arg::with_name("MIN_ELDER_AGE")
+                .long("min-elder-age")
+                .help("Minimum age of a node to be promoted to elder (0 to allow any age)")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("ELDER_TIE_BREAK")
+                .long("elder-tie-break")
+                .help(
+                    "How to choose between nodes of the same age on the elder boundary: by \
+                     name, or by time in the section",
+                )
+                .takes_value(true)
+                .possible_values(&["name", "seniority"])
+                .default_value("name"),
+        )
+        .arg(
+            Arg::with_name("STARTUP_AGE")
+                .long("startup-age")
+                .help("Age of nodes joining during startup [default: adult age]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("AGE_ON_SPLIT")
+                .long("age-on-split")
+                .help("Increment the age of every node of a section when the section splits"),
+        )
+        .arg(
+            Arg::with_name("STARTUP_INFANTS")
+                .long("startup-infants")
+                .help(
+                    "Nodes joining during startup go through the infant stage instead of getting the startup age",
+                ),
+        )
+        .arg(
+            Arg::with_name("MIN_SECTION_LIFETIME")
+                .long("min-section-lifetime")
+                .help(
+                    "Number of iterations a section created by a split or a merge must exist \
+                     before it can split or merge again (0 = no limit)",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
         .arg(
             Arg::with_name("MAX_SECTION_SIZE")
                 .short("s")
@@ -149,6 +820,106 @@ fn get_params() -> Params {
                 .takes_value(true)
                 .default_value("60"),
         )
+        .arg(
+            Arg::with_name("STRICT")
+                .long("strict")
+                .help(
+                    "Abort as soon as a section exceeds the maximum section size, dumping it to \
+                     the dump file, or on a recoverable fault (e.g. a relocation commit not \
+                     matching any incoming relocation)",
+                ),
+        )
+        .arg(
+            Arg::with_name("PARANOID")
+                .long("paranoid")
+                .help(
+                    "Verify after every split and merge that the section prefixes cover the \
+                     whole name space without overlapping, and after every iteration that the \
+                     sections' age indices are in sync with their nodes",
+                ),
+        )
+        .arg(
+            Arg::with_name("TIMING")
+                .long("timing")
+                .help(
+                    "Measure and report the wall-clock time spent merging sections (makes \
+                     the output differ between identical runs)",
+                ),
+        )
+        .arg(
+            Arg::with_name("DUMP_FILE")
+                .long("dump-file")
+                .help("Output file for the section dump in strict mode")
+                .takes_value(true)
+                .default_value("section_dump.txt"),
+        )
+        .arg(
+            Arg::with_name("MAX_NODE_SIZE")
+                .long("max-node-size")
+                .help(
+                    "Maximum storage size of a node (sizes are drawn uniformly from 1 to this)",
+                )
+                .takes_value(true)
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("SPLIT_METRIC")
+                .long("split-metric")
+                .help(
+                    "Whether sections split based on the number of adults or on their total \
+                     size",
+                )
+                .takes_value(true)
+                .possible_values(&["count", "capacity"])
+                .default_value("count"),
+        )
+        .arg(
+            Arg::with_name("MAX_PREFIX_LEN")
+                .long("max-prefix-len")
+                .help("Maximum length of a section prefix (1 to 64)")
+                .takes_value(true)
+                .default_value("64"),
+        )
+        .arg(
+            Arg::with_name("MAX_DEPTH")
+                .long("max-depth")
+                .help(
+                    "What a section that should split but is already at the maximum prefix \
+                     length does: abort the simulation or refuse to split",
+                )
+                .takes_value(true)
+                .possible_values(&["abort", "refuse"])
+                .default_value("abort"),
+        )
+        .arg(
+            Arg::with_name("CHAIN_RETENTION_BLOCKS")
+                .long("chain-retention-blocks")
+                .help(
+                    "Number of most recent chain blocks the nodes keep; relocations can only \
+                     be triggered by a retained Live block (0 = keep all)",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("CHAIN_RETENTION_TICKS")
+                .long("chain-retention-ticks")
+                .help(
+                    "Number of iterations the nodes keep the chain blocks for; relocations can \
+                     only be triggered by a retained Live block (0 = keep all)",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("FULL_CHAIN")
+                .long("full-chain")
+                .help(
+                    "Keep every block of the section chains, record a Dead block for every \
+                     elder dropping and report the nodes whose blocks don't form a valid \
+                     lifecycle (Live, alternately Gone and Live, possibly a final Dead)",
+                ),
+        )
         .arg(
             Arg::with_name("MAX_RELOCATION_ATTEMPTS")
                 .short("r")
@@ -157,6 +928,106 @@ fn get_params() -> Params {
                 .takes_value(true)
                 .default_value("25"),
         )
+        .arg(
+            Arg::with_name("ELDER_REJECT_PROBABILITY")
+                .long("elder-reject-probability")
+                .help(
+                    "Probability that an elder rejects a relocation request (the request needs \
+                     a quorum of approvals)",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("CANCEL_LOSS")
+                .long("cancel-loss")
+                .help("Probability that a relocation cancel message is lost")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("RELOCATION_TIMEOUT")
+                .long("relocation-timeout")
+                .help(
+                    "Number of iterations after which an accepted relocation that was never \
+                     committed, or a requested one that was never accepted or rejected, is \
+                     dropped (0 = never)",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("UNMATCHED_COMMIT")
+                .long("unmatched-commit")
+                .help(
+                    "How to recover from a relocation commit not matching any incoming \
+                     relocation (strict mode aborts instead)",
+                )
+                .takes_value(true)
+                .possible_values(&["rejoin", "bounce"])
+                .default_value("rejoin"),
+        )
+        .arg(
+            Arg::with_name("TARGET_DERIVATION")
+                .long("target-derivation")
+                .help(
+                    "How relocation and join redirect targets are derived from hashes: legacy \
+                     or separated (by purpose, source prefix and attempt)",
+                )
+                .takes_value(true)
+                .possible_values(&["legacy", "separated"])
+                .default_value("legacy"),
+        )
+        .arg(
+            Arg::with_name("MAX_RELOCATION_DISTANCE")
+                .long("max-relocation-distance")
+                .help(
+                    "Maximum number of trailing prefix bits in which the relocation target can \
+                     differ from the source section (0 = unconstrained)",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("RELOCATE_INFANTS")
+                .long("relocate-infants")
+                .help("Whether infants are eligible for relocation")
+                .takes_value(true)
+                .possible_values(&["yes", "no", "only"])
+                .default_value("yes"),
+        )
+        .arg(
+            Arg::with_name("DROP_MODEL")
+                .long("drop-model")
+                .help(
+                    "Whether younger nodes are more likely to drop (age) or all nodes are \
+                     equally likely to (uniform)",
+                )
+                .takes_value(true)
+                .possible_values(&["age", "uniform"])
+                .default_value("age"),
+        )
+        .arg(
+            Arg::with_name("RELOCATION_CHOICE")
+                .long("relocation-choice")
+                .help(
+                    "Which node passing the ageing check is relocated: the oldest one, or one \
+                     about to become adult if any (pre-adult)",
+                )
+                .takes_value(true)
+                .possible_values(&["oldest", "pre-adult"])
+                .default_value("oldest"),
+        )
+        .arg(
+            Arg::with_name("SHRINK_AFTER")
+                .long("shrink-after")
+                .help(
+                    "Shrink test: grow the network to this many nodes, then stop all joins and \
+                     drop nodes until the network collapses to the root section (0 to disable)",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
         .arg(
             Arg::with_name("MAX_INFANTS_PER_SECTION")
                 .short("I")
@@ -166,22 +1037,598 @@ fn get_params() -> Params {
                 .default_value("1"),
         )
         .arg(
-            Arg::with_name("STATS_FREQUENCY")
-                .short("F")
-                .long("stats-frequency")
+            Arg::with_name("INFANT_ADMISSION_PROBABILITY")
+                .long("infant-admission-probability")
                 .help(
-                    "how often (every which iteration) to output network statistics",
+                    "Probability to admit an infant over the infant cap, decaying geometrically \
+                     with each infant over the cap (0 for a hard cap)",
                 )
                 .takes_value(true)
-                .default_value("10"),
+                .default_value("0"),
         )
         .arg(
-            Arg::with_name("FILE")
-                .long("file")
-                .short("f")
-                .help("Output file for network structure data")
+            Arg::with_name("ELDER_TERM_LIMIT")
+                .long("elder-term-limit")
+                .help(
+                    "Number of iterations after which an elder is forcibly relocated (0 = unlimited)",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("ELDER_AGE_LIMIT")
+                .long("elder-age-limit")
+                .help("Age at which an elder is forcibly relocated (0 = unlimited)")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("RELOCATION_COOLDOWN")
+                .long("relocation-cooldown")
+                .help(
+                    "Number of iterations after arrival during which a node can't be relocated again",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("REJECTIONS_TRIGGER_AGEING")
+                .long("rejections-trigger-ageing")
+                .help("Count rejected join attempts as churn events for ageing"),
+        )
+        .arg(
+            Arg::with_name("REJECT_REUSED_NAMES")
+                .long("reject-reused-names")
+                .help(
+                    "Reject nodes joining or relocated under the name of a node that is or was \
+                     in the network",
+                ),
+        )
+        .arg(
+            Arg::with_name("BALANCE_THRESHOLD")
+                .long("balance-threshold")
+                .help(
+                    "Sibling section size ratio that triggers a balancing relocation (0 = disabled)",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("COMMIT_BALANCE_THRESHOLD")
+                .long("commit-balance-threshold")
+                .help(
+                    "Difference in adults between the halves of the destination section below \
+                     which a relocated node lands in a random half instead of the one with \
+                     fewer adults (0 = always the one with fewer adults)",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("RESCUE_WINDOW")
+                .long("rescue-window")
+                .help(
+                    "Number of iterations a section short of adults asks its neighbours for \
+                     relocations before merging (0 = merge right away)",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("VIEW_LAG")
+                .long("view-lag")
+                .help(
+                    "Number of iterations before a section learns the size of its sibling or \
+                     the need for adults of a neighbour (0 = right away)",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("GLOBAL_RELOCATION_CAP")
+                .long("global-relocation-cap")
+                .help(
+                    "Maximum number of relocation requests sent across the network per \
+                     iteration, the excess being queued fairly across the sections and sent in \
+                     the following iterations (0 = no cap)",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("UPGRADE_INTERVAL")
+                .long("upgrade-interval")
+                .help("Number of iterations between network upgrades (0 = no upgrades)")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("UPGRADE_FRACTION")
+                .long("upgrade-fraction")
+                .help("Average fraction of nodes restarting during a network upgrade")
+                .takes_value(true)
+                .default_value("0.1"),
+        )
+        .arg(
+            Arg::with_name("UPGRADE_WINDOW")
+                .long("upgrade-window")
+                .help("Number of iterations over which the restarts of an upgrade are spread")
+                .takes_value(true)
+                .default_value("10"),
+        )
+        .arg(
+            Arg::with_name("BLACKOUT")
+                .long("blackout")
+                .help(
+                    "Make sections overlapping PREFIX refuse joins and relocations for DURATION \
+                     iterations starting at START (format: PREFIX:START:DURATION)",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("FORCE_SPLIT")
+                .long("force-split")
+                .help(
+                    "Split the section with PREFIX at ITERATION regardless of its size \
+                     (format: PREFIX:ITERATION)",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("FORCE_MERGE")
+                .long("force-merge")
+                .help(
+                    "Merge all sections under PREFIX into one at ITERATION regardless of their \
+                     sizes (format: PREFIX:ITERATION)",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("DISASTER")
+                .long("disaster")
+                .help(
+                    "Remove COUNT nodes (0 = all eligible) at ITERATION, on top of the regular \
+                     drops, VICTIMS being the oldest nodes (oldest), random elders (elders), \
+                     random nodes (uniform), the attacker's nodes (malicious) or random nodes \
+                     under PREFIX (prefix=PREFIX) (format: ITERATION:COUNT:VICTIMS)",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("PARAM_CHANGE")
+                .long("param-change")
+                .help(
+                    "Change a parameter at ITERATION, NAME being its option name (one of \
+                     init-age, adult-age, min-elder-age, max-infants-per-section, \
+                     infant-admission-probability, relocate-infants, max-relocation-attempts, \
+                     elder-term-limit, drop-model, relocation-choice, \
+                     commit-balance-threshold, max-relocation-distance) \
+                     (format: ITERATION:NAME=VALUE)",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("CHAOS")
+                .long("chaos")
+                .help(
+                    "Set a parameter to a random value within the range at random iterations, \
+                     NAME being its option name (one of the numeric --param-change ones). \
+                     Values that would leave the network unable to grow are skipped \
+                     (format: NAME=MIN..MAX)",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("CHAOS_INTERVAL")
+                .long("chaos-interval")
+                .help("Average number of iterations between the --chaos changes")
+                .takes_value(true)
+                .default_value("1000"),
+        )
+        .arg(
+            Arg::with_name("LAYOUT_FILE")
+                .long("layout-file")
+                .help(
+                    "CSV file of `PREFIX,NAME,AGE` lines (NAME in hex) defining the sections \
+                     and nodes to start with, instead of the empty root section",
+                )
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("CHURN_TRACE")
+                .long("churn-trace")
+                .help(
+                    "CSV file of `TIMESTAMP,EVENT,NODE` lines (EVENT being join or leave) \
+                     recorded on a real network, driving all joins and drops",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("TRACE_TICK_SECONDS")
+                .long("trace-tick-seconds")
+                .help("Number of seconds of the churn trace per iteration")
+                .takes_value(true)
+                .default_value("60"),
+        )
+        .arg(
+            Arg::with_name("SCHEDULER")
+                .long("scheduler")
+                .help(
+                    "Whether the iterations are fixed ticks, or single joins and leaves in \
+                     continuous time (at --event-join-rate and --event-leave-rate), with the \
+                     messages arriving after --event-message-delay in between",
+                )
+                .takes_value(true)
+                .possible_values(&["iterations", "events"])
+                .default_value("iterations"),
+        )
+        .arg(
+            Arg::with_name("EVENT_JOIN_RATE")
+                .long("event-join-rate")
+                .help("Average number of nodes joining per unit of time (events scheduler)")
+                .takes_value(true)
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("EVENT_LEAVE_RATE")
+                .long("event-leave-rate")
+                .help(
+                    "Rate at which every node leaves, per unit of time (events scheduler, 0 = \
+                     never)",
+                )
+                .takes_value(true)
+                .default_value("0.0002"),
+        )
+        .arg(
+            Arg::with_name("EVENT_MESSAGE_DELAY")
+                .long("event-message-delay")
+                .help(
+                    "Average time a message takes to arrive, exponentially distributed \
+                     (events scheduler)",
+                )
+                .takes_value(true)
+                .default_value("0.01"),
+        )
+        .arg(
+            Arg::with_name("OPERATOR_PROFILE")
+                .long("operator-profile")
+                .help(
+                    "Make FRACTION of the joining nodes drop FACTOR times more likely during \
+                     DURATION iterations of every PERIOD iterations, offset by PHASE (format: \
+                     FRACTION:PERIOD:PHASE:DURATION:FACTOR)",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("MALICIOUS_FRACTION")
+                .long("malicious-fraction")
+                .help("Fraction of joining nodes controlled by the attacker")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("OPERATORS")
+                .long("operators")
+                .help(
+                    "Number of operators running the joining nodes, to report how the elder \
+                     seats rotate among them (0 = don't track operators)",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("RELOCATION_DENIAL")
+                .long("relocation-denial")
+                .help(
+                    "Sections with a malicious quorum of elders reject all incoming \
+                     relocations",
+                ),
+        )
+        .arg(
+            Arg::with_name("SPLIT_AUDIT_FRACTION")
+                .long("split-audit-fraction")
+                .help(
+                    "On every split, compute how many elders of either half an adversary \
+                     controlling this fraction of the oldest nodes of the section could get \
+                     (0 disables)",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("SPLIT_AUDIT_FILE")
+                .long("split-audit-file")
+                .help("Output file for the split audit time series")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("AGEING_AUDIT")
+                .long("ageing-audit")
+                .help(
+                    "Report, by age, how many nodes the ageing check applied to, passed it, \
+                     were selected and were relocated (slows the simulation down)",
+                ),
+        )
+        .arg(
+            Arg::with_name("ECONOMICS")
+                .long("economics")
+                .help("Track farming rewards (credits) of nodes"),
+        )
+        .arg(
+            Arg::with_name("CLIENT_LOAD")
+                .long("client-load")
+                .help("Average number of client requests each section handles per iteration")
+                .takes_value(true)
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("ENTROPY_BITS")
+                .long("entropy-bits")
+                .help("Number of bits below the section prefix to measure name entropy over")
+                .takes_value(true)
+                .default_value("3"),
+        )
+        .arg(
+            Arg::with_name("ROUTING")
+                .long("routing")
+                .help("Route messages hop by hop through the sections instead of directly"),
+        )
+        .arg(
+            Arg::with_name("HOP_DELAY")
+                .long("hop-delay")
+                .help("Number of section ticks each routing hop takes")
+                .takes_value(true)
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("HOP_LOSS")
+                .long("hop-loss")
+                .help("Probability that a routing hop is lost and has to be retried")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("REPLICATION_COUNT")
+                .long("replication-count")
+                .help("Number of nodes each chunk is stored on")
+                .takes_value(true)
+                .default_value("8"),
+        )
+        .arg(
+            Arg::with_name("MERGE_CHUNK_SAMPLES")
+                .long("merge-chunk-samples")
+                .help(
+                    "Number of chunks sampled at each merge to measure data availability \
+                     (0 to disable)",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("TOP_CHURNED")
+                .long("top-churned")
+                .help("Number of most churned section prefixes to print at the end")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("DIFF_SECTIONS")
+                .long("diff-sections")
+                .help(
+                    "Print every N iterations the changes to the section table since the last \
+                     print: new and removed prefixes and sections whose size changed by more \
+                     than --diff-sections-threshold (0 disables)",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("DIFF_SECTIONS_THRESHOLD")
+                .long("diff-sections-threshold")
+                .help("Size change of a section above which --diff-sections prints it")
+                .takes_value(true)
+                .default_value("5"),
+        )
+        .arg(
+            Arg::with_name("STATS_FREQUENCY")
+                .short("F")
+                .long("stats-frequency")
+                .help(
+                    "how often (every which iteration) to output network statistics",
+                )
+                .takes_value(true)
+                .default_value("10"),
+        )
+        .arg(
+            Arg::with_name("HISTOGRAM_WIDTH")
+                .long("histogram-width")
+                .help(
+                    "Width of the bars when showing the age and section size distributions as \
+                     histograms in the summary and the periodic statistics (0 = plain tables)",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("HISTOGRAM_LOG")
+                .long("histogram-log")
+                .help("Scale the histogram bars logarithmically with the counts"),
+        )
+        .arg(
+            Arg::with_name("SCORE_WEIGHTS")
+                .long("score-weights")
+                .help(
+                    "Weights of the run outcomes in its score: the average fraction of \
+                     complete sections (counting for the run), the relocations, the elder \
+                     promotions and demotions per node joining or dropping and the fraction \
+                     of joins rejected (counting against it). The rates per node are scaled \
+                     to 0..1 as RATE/(1+RATE), so every outcome counts at most its weight \
+                     (format: comma-separated NAME=WEIGHT, NAME one of completeness, \
+                     relocation-cost, rejection-rate, elder-churn)",
+                )
+                .takes_value(true)
+                .default_value(
+                    "completeness=1,relocation-cost=1,rejection-rate=1,elder-churn=1",
+                ),
+        )
+        .arg(
+            Arg::with_name("FILE")
+                .long("file")
+                .short("f")
+                .help("Output file for network structure data")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("SOAK")
+                .long("soak")
+                .help(
+                    "Soak mode for long runs: checkpoint the network into this directory \
+                     (resuming from the latest checkpoint in it), stream the telemetry to it \
+                     unless --telemetry is given, and keep only the recent stats in memory",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("SOAK_FREQUENCY")
+                .long("soak-frequency")
+                .help("Checkpoint every N iterations in soak mode")
+                .takes_value(true)
+                .default_value("1000"),
+        )
+        .arg(
+            Arg::with_name("SOAK_KEEP")
+                .long("soak-keep")
+                .help("Number of most recent checkpoints to keep in soak mode")
+                .takes_value(true)
+                .default_value("3"),
+        )
+        .arg(
+            Arg::with_name("TELEMETRY")
+                .long("telemetry")
+                .help(
+                    "File to stream one JSON object per sampled iteration to, with the core \
+                     counters and the --telemetry-summary distributions",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("TELEMETRY_FREQUENCY")
+                .long("telemetry-frequency")
+                .help("Write the telemetry every N iterations")
+                .takes_value(true)
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("TELEMETRY_SUMMARY")
+                .long("telemetry-summary")
+                .help("Distribution to summarize (min, max, avg) in the telemetry")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .possible_values(&["age", "elder-age", "section-size", "prefix-len"]),
+        )
+        .arg(
+            Arg::with_name("FILE_FORMAT")
+                .long("file-format")
+                .help(
+                    "Columns of the network structure data file: the legacy ones, or those \
+                     followed by per-iteration deltas and the number of complete sections",
+                )
+                .takes_value(true)
+                .possible_values(&["legacy", "extended"])
+                .default_value("legacy"),
+        )
+        .arg(
+            Arg::with_name("LINEAGE_FILE")
+                .long("lineage-file")
+                .help("Output file for the lineage of sections through splits and merges")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("RELOCATION_TRACE")
+                .long("relocation-trace")
+                .help(
+                    "Output file (JSON lines) for the messages of every relocation exchange, \
+                     with the iterations they were sent in and the node's names and ages",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("TRACE_FILE")
+                .long("trace-file")
+                .help(
+                    "Output file for every action, message, join and drop of the run, with \
+                     the iteration it happened in, for replaying it offline",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("TRACE_FORMAT")
+                .long("trace-format")
+                .help("Format of the --trace-file: JSON lines or compact binary")
+                .takes_value(true)
+                .possible_values(&["jsonl", "binary"])
+                .default_value("jsonl"),
+        )
+        .arg(
+            Arg::with_name("SURVIVAL_FILE")
+                .long("survival-file")
+                .help("Output file for the lifetime of every node, for survival analysis")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("STATE_HASH_FREQUENCY")
+                .long("state-hash-frequency")
+                .help(
+                    "How often (every which iteration) to output a hash of the network state, \
+                     to find where two runs diverge (0 = never)",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("STATE_HASH_FILE")
+                .long("state-hash-file")
+                .help("Output file for the network state hashes")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("BUNDLE")
+                .long("bundle")
+                .help(
+                    "Output file (gzipped tar) gathering the input and output files of the \
+                     run, a snapshot of the final state, a report and a manifest of their sizes \
+                     and hashes",
+                )
+                .value_name("PATH")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("LOOKUP")
+                .long("lookup")
+                .help(
+                    "Print how and when every node name starting with the hex NAME got it, \
+                     tracking all names during the run",
+                )
+                .value_name("NAME")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
         .arg(Arg::with_name("VERBOSITY").short("v").multiple(true).help(
             "Log verbosity",
         ))
@@ -191,43 +1638,836 @@ fn get_params() -> Params {
                 .long("disable-colors")
                 .help("Disable colored output"),
         )
-        .get_matches();
+        .arg(Arg::with_name("DRY_RUN").long("dry-run").help(
+            "Only print the quantities derived from the parameters, without running the \
+             simulation",
+        ))
+        .arg(Arg::with_name("FORCE").long("force").help(
+            "Run even if the parameters make the network unable to grow (e.g. sections \
+             reaching the maximum size before they can split)",
+        ))
+        .subcommand(
+            SubCommand::with_name("ab")
+                .about(
+                    "Run two networks with the same seed and identical churn, the second one \
+                     with some parameters changed, and compare them",
+                )
+                .arg(
+                    Arg::with_name("B")
+                        .long("b")
+                        .help(
+                            "Parameter of the second network, in the form NAME=VALUE (see \
+                             --param-change for the supported names)",
+                        )
+                        .value_name("SETTING")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("JOIN_RATE")
+                        .long("join-rate")
+                        .help(
+                            "Average number of nodes joining per iteration (ignored with \
+                             --churn-trace)",
+                        )
+                        .takes_value(true)
+                        .default_value("1"),
+                )
+                .arg(
+                    Arg::with_name("LEAVE_PROBABILITY")
+                        .long("leave-probability")
+                        .help(
+                            "Probability that a node leaves in any given iteration (ignored \
+                             with --churn-trace)",
+                        )
+                        .takes_value(true)
+                        .default_value("0.0002"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("trace-json")
+                .about("Print an event trace in the binary format as JSON lines")
+                .arg(
+                    Arg::with_name("PATH")
+                        .help("Event trace written with --trace-format binary")
+                        .required(true),
+                ),
+        )
+}
 
+fn parse_params(matches: &ArgMatches) -> Params {
     let seed = match matches.value_of("SEED") {
         Some(seed) => seed.parse().expect("SEED must be in form `[1, 2, 3, 4]`"),
         None => Seed::random(),
     };
 
+    let adult_age = get_number(matches, "ADULT_AGE");
+    let startup_age = if matches.is_present("STARTUP_AGE") {
+        get_number(matches, "STARTUP_AGE")
+    } else {
+        adult_age
+    };
+
+    let entropy_bits = get_number(matches, "ENTROPY_BITS");
+    if entropy_bits == 0 || entropy_bits > 16 {
+        panic!("ENTROPY_BITS must be between 1 and 16.");
+    }
+
+    let hop_loss = get_number(matches, "HOP_LOSS");
+    if !(0.0..1.0).contains(&hop_loss) {
+        panic!("HOP_LOSS must be at least 0 and less than 1.");
+    }
+
+    let lookup: Vec<String> = matches
+        .values_of("LOOKUP")
+        .map(|values| values.map(String::from).collect())
+        .unwrap_or_default();
+    if lookup.iter().any(|name| {
+        name.is_empty() || name.len() > 16 || !name.chars().all(|c| c.is_ascii_hexdigit())
+    })
+    {
+        panic!("LOOKUP must be a hex name prefix of at most 16 digits.");
+    }
+
+    let trace_tick_seconds = get_number(matches, "TRACE_TICK_SECONDS");
+    if trace_tick_seconds <= 0.0 {
+        panic!("TRACE_TICK_SECONDS must be positive.");
+    }
+
+    let scheduler = matches.value_of("SCHEDULER").unwrap().parse().expect(
+        "SCHEDULER must be one of iterations, events",
+    );
+    if scheduler == Scheduler::Events {
+        if matches.is_present("CHURN_TRACE") {
+            panic!("SCHEDULER events can't be used with CHURN_TRACE.");
+        }
+        if matches.is_present("ROUTING") {
+            panic!("SCHEDULER events can't be used with ROUTING.");
+        }
+    }
+
+    let event_rates = EventRates {
+        join_rate: get_number(matches, "EVENT_JOIN_RATE"),
+        leave_rate: get_number(matches, "EVENT_LEAVE_RATE"),
+        message_delay: get_number(matches, "EVENT_MESSAGE_DELAY"),
+    };
+    if event_rates.join_rate <= 0.0 || event_rates.leave_rate < 0.0 ||
+        event_rates.message_delay < 0.0
+    {
+        panic!(
+            "EVENT_JOIN_RATE must be positive, EVENT_LEAVE_RATE and EVENT_MESSAGE_DELAY not \
+             negative."
+        );
+    }
+
+    let max_node_size = get_number(matches, "MAX_NODE_SIZE");
+    if max_node_size == 0 {
+        panic!("MAX_NODE_SIZE must be at least 1.");
+    }
+
+    let max_prefix_len = get_number(matches, "MAX_PREFIX_LEN");
+    if max_prefix_len == 0 || max_prefix_len > Prefix::MAX_LEN {
+        panic!("MAX_PREFIX_LEN must be between 1 and {}.", Prefix::MAX_LEN);
+    }
+
+    let soak_frequency = get_number(matches, "SOAK_FREQUENCY");
+    let soak_keep = get_number(matches, "SOAK_KEEP");
+    if soak_frequency == 0 || soak_keep == 0 {
+        panic!("SOAK_FREQUENCY and SOAK_KEEP must be at least 1.");
+    }
+    let telemetry_frequency = get_number(matches, "TELEMETRY_FREQUENCY");
+    if telemetry_frequency == 0 {
+        panic!("TELEMETRY_FREQUENCY must be at least 1.");
+    }
+    if matches.is_present("SOAK") && matches.is_present("FULL_CHAIN") {
+        panic!("The soak mode doesn't support the full-chain mode.");
+    }
+
+    let operator_profiles: Vec<OperatorProfile> = matches
+        .values_of("OPERATOR_PROFILE")
+        .map(|values| {
+            values
+                .map(|value| {
+                    value.parse().unwrap_or_else(|_| {
+                        panic!(
+                            "OPERATOR_PROFILE must be in form \
+                             `FRACTION:PERIOD:PHASE:DURATION:FACTOR` with PERIOD > 0, \
+                             DURATION <= PERIOD and FACTOR >= 0"
+                        )
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    if operator_profiles.iter().map(|profile| profile.fraction).sum::<f64>() > 1.0 {
+        panic!("The FRACTIONs of the OPERATOR_PROFILEs must add up to at most 1.");
+    }
+
+    let rng: RngKind = matches.value_of("RNG").unwrap().parse().expect(
+        "RNG must be one of xorshift, pcg, chacha",
+    );
+
     Params {
+        rng,
         seed,
-        num_iterations: get_number(&matches, "ITERATIONS"),
-        group_size: get_number(&matches, "GROUP_SIZE"),
-        init_age: get_number(&matches, "INIT_AGE"),
-        adult_age: get_number(&matches, "ADULT_AGE"),
-        max_section_size: get_number(&matches, "MAX_SECTION_SIZE"),
-        max_relocation_attempts: get_number(&matches, "MAX_RELOCATION_ATTEMPTS"),
-        max_infants_per_section: get_number(&matches, "MAX_INFANTS_PER_SECTION"),
-        stats_frequency: get_number(&matches, "STATS_FREQUENCY"),
+        num_iterations: get_number(matches, "ITERATIONS"),
+        group_size: get_number(matches, "GROUP_SIZE"),
+        init_age: get_number(matches, "INIT_AGE"),
+        join_relocation: matches.is_present("JOIN_RELOCATION"),
+        join_model: matches.value_of("JOIN_MODEL").unwrap().parse().expect(
+            "JOIN_MODEL must be one of section, contact",
+        ),
+        adult_age,
+        min_elder_age: get_number(matches, "MIN_ELDER_AGE"),
+        elder_tie_break: matches.value_of("ELDER_TIE_BREAK").unwrap().parse().expect(
+            "ELDER_TIE_BREAK must be one of name, seniority",
+        ),
+        startup_age,
+        startup_infants: matches.is_present("STARTUP_INFANTS"),
+        age_on_split: matches.is_present("AGE_ON_SPLIT"),
+        min_section_lifetime: get_number(matches, "MIN_SECTION_LIFETIME"),
+        max_section_size: get_number(matches, "MAX_SECTION_SIZE"),
+        strict: matches.is_present("STRICT"),
+        paranoid: matches.is_present("PARANOID"),
+        timing: matches.is_present("TIMING"),
+        dump_file: matches.value_of("DUMP_FILE").unwrap().to_string(),
+        max_node_size,
+        split_metric: matches.value_of("SPLIT_METRIC").unwrap().parse().expect(
+            "SPLIT_METRIC must be one of count, capacity",
+        ),
+        max_prefix_len,
+        max_depth: matches.value_of("MAX_DEPTH").unwrap().parse().expect(
+            "MAX_DEPTH must be one of abort, refuse",
+        ),
+        chain_retention_blocks: get_number(matches, "CHAIN_RETENTION_BLOCKS"),
+        chain_retention_ticks: get_number(matches, "CHAIN_RETENTION_TICKS"),
+        full_chain: matches.is_present("FULL_CHAIN"),
+        max_relocation_attempts: get_number(matches, "MAX_RELOCATION_ATTEMPTS"),
+        elder_reject_probability: get_number(matches, "ELDER_REJECT_PROBABILITY"),
+        cancel_loss: get_number(matches, "CANCEL_LOSS"),
+        relocation_timeout: get_number(matches, "RELOCATION_TIMEOUT"),
+        unmatched_commit: matches.value_of("UNMATCHED_COMMIT").unwrap().parse().expect(
+            "UNMATCHED_COMMIT must be one of rejoin, bounce",
+        ),
+        target_derivation: matches.value_of("TARGET_DERIVATION").unwrap().parse().expect(
+            "TARGET_DERIVATION must be one of legacy, separated",
+        ),
+        max_relocation_distance: get_number(matches, "MAX_RELOCATION_DISTANCE"),
+        relocate_infants: matches.value_of("RELOCATE_INFANTS").unwrap().parse().expect(
+            "RELOCATE_INFANTS must be one of yes, no, only",
+        ),
+        drop_model: matches.value_of("DROP_MODEL").unwrap().parse().expect(
+            "DROP_MODEL must be one of age, uniform",
+        ),
+        relocation_choice: matches.value_of("RELOCATION_CHOICE").unwrap().parse().expect(
+            "RELOCATION_CHOICE must be one of oldest, pre-adult",
+        ),
+        shrink_after: get_number(matches, "SHRINK_AFTER"),
+        max_infants_per_section: get_number(matches, "MAX_INFANTS_PER_SECTION"),
+        infant_admission_probability: get_number(matches, "INFANT_ADMISSION_PROBABILITY"),
+        elder_term_limit: get_number(matches, "ELDER_TERM_LIMIT"),
+        elder_age_limit: get_number(matches, "ELDER_AGE_LIMIT"),
+        relocation_cooldown: get_number(matches, "RELOCATION_COOLDOWN"),
+        rejections_trigger_ageing: matches.is_present("REJECTIONS_TRIGGER_AGEING"),
+        reject_reused_names: matches.is_present("REJECT_REUSED_NAMES"),
+        balance_threshold: get_number(matches, "BALANCE_THRESHOLD"),
+        commit_balance_threshold: get_number(matches, "COMMIT_BALANCE_THRESHOLD"),
+        rescue_window: get_number(matches, "RESCUE_WINDOW"),
+        view_lag: get_number(matches, "VIEW_LAG"),
+        global_relocation_cap: get_number(matches, "GLOBAL_RELOCATION_CAP"),
+        upgrade_interval: get_number(matches, "UPGRADE_INTERVAL"),
+        upgrade_fraction: get_number(matches, "UPGRADE_FRACTION"),
+        upgrade_window: get_number(matches, "UPGRADE_WINDOW"),
+        blackouts: matches
+            .values_of("BLACKOUT")
+            .map(|values| {
+                values
+                    .map(|value| {
+                        value.parse().expect(
+                            "BLACKOUT must be in form `PREFIX:START:DURATION`",
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+        force_splits: get_forced_changes(matches, "FORCE_SPLIT"),
+        force_merges: get_forced_changes(matches, "FORCE_MERGE"),
+        disasters: matches
+            .values_of("DISASTER")
+            .map(|values| {
+                values
+                    .map(|value| {
+                        value.parse().expect(
+                            "DISASTER must be in form `ITERATION:COUNT:VICTIMS`, VICTIMS being \
+                             one of oldest, elders, uniform, malicious, prefix=PREFIX",
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+        param_changes: matches
+            .values_of("PARAM_CHANGE")
+            .map(|values| {
+                values
+                    .map(|value| {
+                        value.parse().unwrap_or_else(|_| {
+                            panic!("PARAM_CHANGE must be in form `ITERATION:NAME=VALUE`")
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+        chaos: matches
+            .values_of("CHAOS")
+            .map(|values| {
+                values
+                    .map(|value| {
+                        value.parse().unwrap_or_else(|_| {
+                            panic!("CHAOS must be in form `NAME=MIN..MAX`")
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+        chaos_interval: get_number(matches, "CHAOS_INTERVAL"),
+        layout_file: matches.value_of("LAYOUT_FILE").map(String::from),
+        churn_trace: matches.value_of("CHURN_TRACE").map(String::from),
+        trace_tick_seconds,
+        synthetic_churn: None,
+        scheduler,
+        event_rates,
+        operator_profiles,
+        malicious_fraction: get_number(matches, "MALICIOUS_FRACTION"),
+        operators: get_number(matches, "OPERATORS"),
+        relocation_denial: matches.is_present("RELOCATION_DENIAL"),
+        split_audit_fraction: get_number(matches, "SPLIT_AUDIT_FRACTION"),
+        ageing_audit: matches.is_present("AGEING_AUDIT"),
+        split_audit_file: matches.value_of("SPLIT_AUDIT_FILE").map(String::from),
+        soak_dir: matches.value_of("SOAK").map(String::from),
+        soak_frequency,
+        soak_keep,
+        telemetry_file: matches.value_of("TELEMETRY").map(String::from),
+        telemetry_frequency,
+        telemetry_summaries: matches
+            .values_of("TELEMETRY_SUMMARY")
+            .map(|values| {
+                values
+                    .map(|value| {
+                        value.parse().expect(
+                            "TELEMETRY_SUMMARY must be one of age, elder-age, section-size, \
+                             prefix-len",
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+        economics: matches.is_present("ECONOMICS"),
+        client_load: get_number(matches, "CLIENT_LOAD"),
+        routing: matches.is_present("ROUTING"),
+        hop_delay: get_number(matches, "HOP_DELAY"),
+        hop_loss,
+        entropy_bits,
+        replication_count: get_number(matches, "REPLICATION_COUNT"),
+        merge_chunk_samples: get_number(matches, "MERGE_CHUNK_SAMPLES"),
+        top_churned: get_number(matches, "TOP_CHURNED"),
+        stats_frequency: get_number(matches, "STATS_FREQUENCY"),
+        histogram_width: get_number(matches, "HISTOGRAM_WIDTH"),
+        histogram_log: matches.is_present("HISTOGRAM_LOG"),
+        score_weights: matches.value_of("SCORE_WEIGHTS").unwrap().parse().unwrap_or_else(
+            |_| panic!("SCORE_WEIGHTS must be in form `NAME=WEIGHT,...`"),
+        ),
+        diff_sections: get_number(matches, "DIFF_SECTIONS"),
+        diff_sections_threshold: get_number(matches, "DIFF_SECTIONS_THRESHOLD"),
         file: matches.value_of("FILE").map(String::from),
+        file_format: matches.value_of("FILE_FORMAT").unwrap().parse().expect(
+            "FILE_FORMAT must be one of legacy, extended",
+        ),
+        lineage_file: matches.value_of("LINEAGE_FILE").map(String::from),
+        relocation_trace: matches.value_of("RELOCATION_TRACE").map(String::from),
+        event_trace_file: matches.value_of("TRACE_FILE").map(String::from),
+        event_trace_format: matches.value_of("TRACE_FORMAT").unwrap().parse().expect(
+            "TRACE_FORMAT must be one of jsonl, binary",
+        ),
+        survival_file: matches.value_of("SURVIVAL_FILE").map(String::from),
+        state_hash_frequency: get_number(matches, "STATE_HASH_FREQUENCY"),
+        state_hash_file: matches.value_of("STATE_HASH_FILE").map(String::from),
+        bundle_file: matches.value_of("BUNDLE").map(String::from),
+        lookup,
         verbosity: matches.occurrences_of("VERBOSITY") as usize + 1,
         disable_colors: matches.is_present("DISABLE_COLORS"),
+        dry_run: matches.is_present("DRY_RUN"),
+        force: matches.is_present("FORCE"),
+    }
+}
+
+/// Parameters for the tests: the defaults overridden by the given command line
+/// arguments (without the program name).
+#[cfg(test)]
+pub fn test_params(args: &[&str]) -> Params {
+    let mut full = vec!["datachains_sim"];
+    full.extend_from_slice(args);
+    parse_params(&app().get_matches_from(full))
+}
+
+// Run two networks with the same seed and the same churn, generated up front
+// unless a churn trace is given, the second one with the parameters changed by
+// the `B` settings. As both start from the same random state, their randomness
+// only diverges once the changed parameters make them behave differently.
+fn run_ab(params: Params, matches: &ArgMatches, running: &AtomicBool) {
+    let mut params_a = params;
+    if params_a.churn_trace.is_none() {
+        params_a.synthetic_churn = Some(SyntheticChurn {
+            join_rate: get_number(matches, "JOIN_RATE"),
+            leave_probability: get_number(matches, "LEAVE_PROBABILITY"),
+        });
+    }
+
+    let mut params_b = params_a.clone();
+    for value in matches.values_of("B").unwrap() {
+        let setting: Setting = value.parse().unwrap_or_else(|_| {
+            panic!("B must be in form `NAME=VALUE`")
+        });
+        params_b.apply(setting);
+    }
+    check_dead_ends(&params_b);
+
+    let (network_a, termination_a) = run_to_completion(&params_a, running);
+    let (network_b, termination_b) = run_to_completion(&params_b, running);
+
+    println!("\n===== A/B comparison =====\n");
+    println!("B: {}\n", matches.values_of("B").unwrap().collect::<Vec<_>>().join(", "));
+
+    let row = |label: &str, a: String, b: String| println!("{:<28}{:>24}{:>24}", label, a, b);
+    let summary_a = network_a.stats().summary();
+    let summary_b = network_b.stats().summary();
+    let age_a = network_a.age_distribution().summary();
+    let age_b = network_b.age_distribution().summary();
+    let elder_age_a = network_a.elder_age_distribution().summary();
+    let elder_age_b = network_b.elder_age_distribution().summary();
+    let size_a = network_a.section_size_aggregator();
+    let size_b = network_b.section_size_aggregator();
+    let imbalance_a = network_a.commit_imbalance_distribution().summary();
+    let imbalance_b = network_b.commit_imbalance_distribution().summary();
+    let outcome_a = network_a.outcome();
+    let outcome_b = network_b.outcome();
+
+    row("", "A".to_string(), "B".to_string());
+    row(
+        "Termination",
+        termination_a.reason.category().to_string(),
+        termination_b.reason.category().to_string(),
+    );
+    row(
+        "Nodes",
+        summary_a.nodes().to_string(),
+        summary_b.nodes().to_string(),
+    );
+    row(
+        "Sections",
+        summary_a.sections().to_string(),
+        summary_b.sections().to_string(),
+    );
+    row(
+        "Merges",
+        summary_a.merges().to_string(),
+        summary_b.merges().to_string(),
+    );
+    row(
+        "Splits",
+        summary_a.splits().to_string(),
+        summary_b.splits().to_string(),
+    );
+    row(
+        "Relocations",
+        summary_a.relocations().to_string(),
+        summary_b.relocations().to_string(),
+    );
+    row(
+        "Matured nodes per iteration",
+        format!("{:.3}", summary_a.matured_nodes() as f64 / (summary_a.iteration() + 1) as f64),
+        format!("{:.3}", summary_b.matured_nodes() as f64 / (summary_b.iteration() + 1) as f64),
+    );
+    row(
+        "Rejections",
+        summary_a.rejections().to_string(),
+        summary_b.rejections().to_string(),
+    );
+    row(
+        "Relocation efficiency (%)",
+        format!("{:.2}", summary_a.relocation_efficiency()),
+        format!("{:.2}", summary_b.relocation_efficiency()),
+    );
+    row(
+        "Average age",
+        format!("{:.2}", age_a.avg),
+        format!("{:.2}", age_b.avg),
+    );
+    row(
+        "Maximum age",
+        age_a.max.to_string(),
+        age_b.max.to_string(),
+    );
+    row(
+        "Section average age spread",
+        format!("{:.3}", network_a.section_age_spread()),
+        format!("{:.3}", network_b.section_age_spread()),
+    );
+    row(
+        "Average elder age",
+        format!("{:.2}", elder_age_a.avg),
+        format!("{:.2}", elder_age_b.avg),
+    );
+    row(
+        "Average section size",
+        format!("{:.2}", size_a.avg),
+        format!("{:.2}", size_b.avg),
+    );
+    row(
+        "Section size range",
+        format!("{}..{}", size_a.min, size_a.max),
+        format!("{}..{}", size_b.min, size_b.max),
+    );
+    row(
+        "Section size spread",
+        format!("{:.3}", network_a.section_size_spread()),
+        format!("{:.3}", network_b.section_size_spread()),
+    );
+    row(
+        "Average commit imbalance",
+        format!("{:.2}", imbalance_a.avg),
+        format!("{:.2}", imbalance_b.avg),
+    );
+    row(
+        "Random commit halves",
+        summary_a.random_commit_halves().to_string(),
+        summary_b.random_commit_halves().to_string(),
+    );
+    row(
+        "Average completeness (%)",
+        format!("{:.2}", 100.0 * outcome_a.completeness),
+        format!("{:.2}", 100.0 * outcome_b.completeness),
+    );
+    row(
+        "Relocation cost",
+        format!("{:.4}", outcome_a.relocation_cost),
+        format!("{:.4}", outcome_b.relocation_cost),
+    );
+    row(
+        "Rejection rate",
+        format!("{:.4}", outcome_a.rejection_rate),
+        format!("{:.4}", outcome_b.rejection_rate),
+    );
+    row(
+        "Elder churn",
+        format!("{:.4}", outcome_a.elder_churn),
+        format!("{:.4}", outcome_b.elder_churn),
+    );
+    row(
+        "Score",
+        format!("{:.4}", outcome_a.score(&params_a.score_weights)),
+        format!("{:.4}", outcome_b.score(&params_a.score_weights)),
+    );
+
+    if termination_a.reason.is_failure() || termination_b.reason.is_failure() {
+        process::exit(1);
+    }
+}
+
+// Gather the input files, the files written by the run (the section dump only
+// if this run wrote it), the final checkpoint and the report into a bundle.
+fn bundle_outputs(
+    params: &Params,
+    termination: &Termination,
+    network: &Network,
+) -> Result<Bundle, String> {
+    let mut bundle = Bundle::new();
+
+    for path in [&params.churn_trace, &params.layout_file].iter().filter_map(
+        |path| path.as_ref(),
+    )
+    {
+        bundle.add_input(path)?;
+    }
+
+    let files = [
+        &params.file,
+        &params.telemetry_file,
+        &params.split_audit_file,
+        &params.lineage_file,
+        &params.relocation_trace,
+        &params.event_trace_file,
+        &params.survival_file,
+        &params.state_hash_file,
+    ];
+    for path in files.iter().filter_map(|path| path.as_ref()) {
+        bundle.add_output(path)?;
+    }
+
+    if let Reason::SectionTooLarge = termination.reason {
+        bundle.add_output(&params.dump_file)?;
+    }
+
+    if let Some(ref dir) = params.soak_dir {
+        bundle.add_output_dir(dir)?;
+    }
+
+    // The snapshots don't support the full-chain mode (see `soak`), and after a
+    // panic the state may be inconsistent.
+    if !params.full_chain && !matches!(termination.reason, Reason::Panic(_)) {
+        bundle.add_content(
+            &format!("snapshots/{}", soak::file_name(termination.iteration)),
+            soak::checkpoint(termination.iteration, network)?.into_bytes(),
+        )?;
+    }
+
+    // Only what the seed and the parameters determine goes into the report, so
+    // no wall-clock timings, nor the settings of the console output and the
+    // path of the archive itself.
+    let mut settings = params.clone();
+    settings.timing = false;
+    settings.verbosity = 1;
+    settings.disable_colors = false;
+    settings.bundle_file = None;
+    let mut report = format!(
+        "Termination: {}\n\n{:?}\n\n{}\n",
+        termination,
+        settings,
+        network.stats().summary()
+    );
+    if let Reason::Panic(_) = termination.reason {
+        report.push_str("Partial report: the score is skipped after a panic.\n");
+    } else {
+        let outcome = network.outcome();
+        report.push_str(&format!(
+            "Score: {:.4} ({})\n",
+            outcome.score(&params.score_weights),
+            outcome
+        ));
+    }
+    bundle.add_content("report.txt", report.into_bytes())?;
+
+    Ok(bundle)
+}
+
+// Print the events of the binary event trace, one JSON object per line.
+fn print_event_trace(path: &str) {
+    let events = EventReader::open(path).unwrap_or_else(|error| panic!("{}", error));
+    for event in events {
+        let event = event.unwrap_or_else(|error| panic!("{}", error));
+        println!("{}", event.json());
+    }
+}
+
+fn run_to_completion(params: &Params, running: &AtomicBool) -> (Network, Termination) {
+    random::reseed(params.rng, params.seed);
+
+    let mut simulation = Simulation::new(params.clone());
+    let (termination, _) = simulation.run(params, 0, running);
+    (simulation.into_network(), termination)
+}
+
+fn print_derived_quantities(params: &Params) {
+    println!("{:?}\n", params);
+    println!("Quorum:                        {:>8}", params.quorum());
+    println!("Split threshold (adults/half): {:>8}", params.split_threshold());
+    println!("Split size (adults):           {:>8}", params.min_split_size());
+    println!("Merge threshold (adults):      {:>8}", params.merge_threshold());
+    match model::expected_startup_adults(params) {
+        Some(adults) => println!("Expected startup adults:       {:>8.2}", adults),
+        None => println!("Expected startup adults:       {:>8}", "-"),
     }
+    println!(
+        "Ageing relocations per churn:  {:>8.4}",
+        model::expected_relocation_rate(params)
+    );
+
+    // Nodes are stored in hash maps keyed by name, which keep one control byte
+    // per entry and are at most 7/8 full. This ignores the per-section overhead.
+    let node_bytes = (mem::size_of::<prefix::Name>() + mem::size_of::<node::Node>() + 1) as f64 *
+        8.0 / 7.0;
+    println!("Memory per node (bytes):       {:>8.0}", node_bytes);
+    println!(
+        "Memory per 1M nodes (MiB):     {:>8.1}",
+        node_bytes * 1_000_000.0 / (1024.0 * 1024.0)
+    );
 }
 
-fn print_tick_stats(network: &Network, max_prefix_len_diff: &mut u64) {
-    let prefix_len_agg = network.prefix_len_aggregator();
-    *max_prefix_len_diff = cmp::max(
-        *max_prefix_len_diff,
-        prefix_len_agg.max - prefix_len_agg.min,
+// Print the reasons why the parameters make the network unable to grow, from
+// the start or after the parameter changes, if any, and return whether there
+// are some.
+fn print_dead_ends(params: &Params) -> bool {
+    let mut dead_ends = params.dead_ends();
+    dead_ends.extend(params.dead_ends_after_changes());
+    for dead_end in &dead_ends {
+        println!("Warning: {}", dead_end);
+    }
+
+    !dead_ends.is_empty()
+}
+
+// Refuse to run with parameters that make the network unable to grow, unless
+// forced.
+fn check_dead_ends(params: &Params) {
+    if !print_dead_ends(params) {
+        return;
+    }
+
+    println!(
+        "Quorum: {}, split threshold: {} adults per half ({} per section), merge \
+         threshold: {} adults, max section size: {}",
+        params.quorum(),
+        params.split_threshold(),
+        params.min_split_size(),
+        params.merge_threshold(),
+        params.max_section_size
     );
 
+    if !params.force {
+        println!("Refusing to run, use --force to run anyway");
+        process::exit(1);
+    }
+}
+
+// Prints the network stats every `frequency` iterations, followed by the age
+// and section size histograms if enabled.
+struct TickStatsPrinter {
+    frequency: u64,
+    histogram_width: usize,
+    histogram_log: bool,
+    max_prefix_len_diff: u64,
+}
+
+impl TickStatsPrinter {
+    fn new(params: &Params) -> Self {
+        TickStatsPrinter {
+            frequency: params.stats_frequency,
+            histogram_width: params.histogram_width,
+            histogram_log: params.histogram_log,
+            max_prefix_len_diff: 0,
+        }
+    }
+
+    fn histogram<'a>(&self, distribution: &'a Distribution) -> Histogram<'a> {
+        Histogram {
+            distribution,
+            width: self.histogram_width,
+            log: self.histogram_log,
+        }
+    }
+}
+
+impl Observer for TickStatsPrinter {
+    fn observe(&mut self, iteration: u64, network: &Network) {
+        // The difference is tracked over all iterations, not just the printed
+        // ones.
+        let prefix_len_agg = network.prefix_len_aggregator();
+        self.max_prefix_len_diff = cmp::max(
+            self.max_prefix_len_diff,
+            prefix_len_agg.max - prefix_len_agg.min,
+        );
+
+        if iteration.is_multiple_of(self.frequency) {
+            print_tick_stats(network, self.max_prefix_len_diff);
+
+            if self.histogram_width > 0 {
+                println!("Ages:\n{}", self.histogram(&network.age_distribution()));
+                println!(
+                    "Section sizes:\n{}",
+                    self.histogram(&network.section_size_distribution())
+                );
+            }
+        }
+    }
+}
+
+// Prints the changes to the section table every `frequency` iterations.
+struct SectionTableDiff {
+    frequency: u64,
+    threshold: usize,
+    // Section sizes at the last print.
+    last: BTreeMap<Prefix, usize>,
+}
+
+impl SectionTableDiff {
+    fn new(frequency: u64, threshold: usize) -> Self {
+        SectionTableDiff {
+            frequency,
+            threshold,
+            last: BTreeMap::new(),
+        }
+    }
+}
+
+impl Observer for SectionTableDiff {
+    fn observe(&mut self, iteration: u64, network: &Network) {
+        if !iteration.is_multiple_of(self.frequency) {
+            return;
+        }
+
+        let sizes = network.section_sizes();
+        let mut lines = Vec::new();
+
+        for (prefix, &size) in &self.last {
+            if !sizes.contains_key(prefix) {
+                lines.push(format!("  - [{}] ({} nodes)", prefix, size));
+            }
+        }
+
+        for (prefix, &size) in &sizes {
+            match self.last.get(prefix) {
+                None => lines.push(format!("  + [{}] ({} nodes)", prefix, size)),
+                Some(&last) if cmp::max(size, last) - cmp::min(size, last) > self.threshold => {
+                    lines.push(format!("  ~ [{}] {} -> {} nodes", prefix, last, size))
+                }
+                Some(_) => (),
+            }
+        }
+
+        if !lines.is_empty() {
+            println!(
+                "Section changes at iteration {} ({} sections):\n{}",
+                iteration,
+                sizes.len(),
+                lines.join("\n")
+            );
+        }
+
+        self.last = sizes;
+    }
+}
+
+// The distribution as a histogram if enabled, otherwise as a plain table.
+fn show(params: &Params, distribution: &Distribution) -> String {
+    if params.histogram_width > 0 {
+        Histogram {
+            distribution,
+            width: params.histogram_width,
+            log: params.histogram_log,
+        }.to_string()
+    } else {
+        distribution.to_string()
+    }
+}
+
+// Note the records not listed, as only the first ones are kept.
+fn print_omitted(count: u64, listed: usize) {
+    if count > listed as u64 {
+        println!("      \t... and {} more", count - listed as u64);
+    }
+}
+
+fn print_tick_stats(network: &Network, max_prefix_len_diff: u64) {
     println!(
-        "Header {:?}, AgeDist {:?}, SectionSizeDist {:?}, PrefixLenDist {:?}, MaxPrefixLenDiff: {}",
+        "Header {:?}, AgeDist {:?}, SectionSizeDist {:?}, PrefixLenDist {:?}, MaxPrefixLenDiff: {}, \
+         NameEntropyDist {:?}",
         network.stats().summary(),
         network.age_aggregator(),
         network.section_size_aggregator(),
-        prefix_len_agg,
+        network.prefix_len_aggregator(),
         max_prefix_len_diff,
+        network.name_entropy_aggregator(),
     )
 }
 
@@ -238,12 +2478,174 @@ fn get_number<T: Number>(matches: &ArgMatches, name: &str) -> T {
     }
 }
 
+fn get_forced_changes(matches: &ArgMatches, name: &str) -> Vec<ForcedChange> {
+    matches
+        .values_of(name)
+        .map(|values| {
+            values
+                .map(|value| {
+                    value.parse().unwrap_or_else(|_| {
+                        panic!("{} must be in form `PREFIX:ITERATION`", name)
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 trait Number: FromStr {}
 impl Number for u8 {}
+impl Number for u32 {}
 impl Number for u64 {}
 impl Number for usize {}
+impl Number for f64 {}
 
 // Use these type aliases instead of the default collections to make sure
 // we use consistent hashing across runs, to enable deterministic results.
 type HashMap<K, V> = collections::HashMap<K, V, BuildHasherDefault<DefaultHasher>>;
 type HashSet<T> = collections::HashSet<T, BuildHasherDefault<DefaultHasher>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    const GOLDEN_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/default.txt");
+    const GOLDEN: &str = include_str!("../tests/golden/default.txt");
+
+    // Run short simulation with pinned seed and otherwise default parameters
+    // and compare the outcome against the checked-in golden values. Run with
+    // `UPDATE_GOLDEN=1` to regenerate them after an intentional behaviour change.
+    #[test]
+    fn golden_run() {
+        let params = test_params(&["--seed", "[1, 2, 3, 4]", "--iterations", "1000"]);
+        random::reseed(params.rng, params.seed);
+
+        let mut simulation = Simulation::new(params.clone());
+        let _ = simulation.run(&params, 0, &AtomicBool::new(true));
+        let network = simulation.into_network();
+
+        let output = format!(
+            "{:?}\nAgeDist {}\nElderAgeDist {}\nSectionSizeDist {:?}\nPrefixLenDist {:?}\n",
+            network.stats().summary(),
+            network.age_distribution().compact(),
+            network.elder_age_distribution().compact(),
+            network.section_size_aggregator(),
+            network.prefix_len_aggregator(),
+        );
+
+        if env::var("UPDATE_GOLDEN").is_ok() {
+            fs::write(GOLDEN_PATH, &output).unwrap();
+            return;
+        }
+
+        assert_eq!(
+            output,
+            GOLDEN,
+            "Simulation outcome diverged from the golden run. If this is intentional, \
+             regenerate {} by running the test with UPDATE_GOLDEN=1.",
+            GOLDEN_PATH
+        );
+    }
+
+    // Benchmark of merging two sections and checking the elders of the merged
+    // one. Run with `cargo test --release -- --ignored --nocapture merge_scales`.
+    #[test]
+    #[ignore]
+    fn merge_scales() {
+        use node::{Node, NodeIds};
+        use prefix::{Name, Prefix};
+        use rand::{Rng, SeedableRng, XorShiftRng};
+        use section::Section;
+        use std::time::Instant;
+
+        let params = test_params(&[]);
+        let mut rng = XorShiftRng::from_seed([0x2c1b_93e4, 0x7f05_d8a6, 0x41e9_0b37, 0x9a6d_52c8]);
+
+        for &size in &[100, 1_000, 10_000, 100_000] {
+            let node_ids = NodeIds::new();
+            let halves: Vec<_> = Prefix::EMPTY
+                .split()
+                .iter()
+                .enumerate()
+                .map(|(id, &prefix)| {
+                    let mut section = Section::new(id as u64 + 1, prefix, node_ids.clone());
+                    section.populate(
+                        &params,
+                        (0..size).map(|_| {
+                            let age = 4 + rng.gen::<u32>().trailing_zeros().min(20) as u8;
+                            let name = prefix.substituted_in(Name(rng.gen()));
+                            Node::new(node_ids.allocate(), name, age)
+                        }),
+                    );
+                    section
+                })
+                .collect();
+
+            let start = Instant::now();
+            let mut members: Vec<_> = halves
+                .iter()
+                .flat_map(|half| half.nodes().values())
+                .map(|node| (node.name(), node.age()))
+                .collect();
+            members.sort();
+            let snapshot = start.elapsed();
+
+            let start = Instant::now();
+            let mut merged = Section::new(0, Prefix::EMPTY, node_ids.clone());
+            for half in halves {
+                merged.merge(&params, half);
+            }
+            let merge = start.elapsed();
+
+            let start = Instant::now();
+            let mismatch = network::check_merge_elders(&params, 0, &members, &merged);
+            let check = start.elapsed();
+
+            println!(
+                "{:7} nodes per section: snapshot {:?}, merge {:?}, elder check {:?} ({})",
+                size,
+                snapshot,
+                merge,
+                check,
+                if mismatch.is_none() { "ok" } else { "mismatch" }
+            );
+        }
+    }
+
+    // Benchmark of the network ticks as the network grows, for comparing the
+    // handling of the action queue and the message buffers. Run with
+    // `cargo test --release -- --ignored --nocapture tick_scales`.
+    #[test]
+    #[ignore]
+    fn tick_scales() {
+        use std::time::Instant;
+
+        let params = test_params(&["--seed", "[1, 2, 3, 4]", "--iterations", "20000"]);
+        random::reseed(params.rng, params.seed);
+
+        let policies = Policies::new(&params);
+        let mut network = Network::new(params.clone());
+        let mut iteration = 0;
+        let mut nodes = 0;
+
+        for &limit in &[1_000, 10_000, 100_000] {
+            let first = iteration;
+            let start = Instant::now();
+            while nodes < limit && iteration < params.num_iterations {
+                network.tick(iteration, &policies, Vec::new());
+                nodes = network.section_sizes().values().sum();
+                iteration += 1;
+            }
+            let elapsed = start.elapsed();
+
+            println!(
+                "{:6} nodes: {} ticks in {:?} ({:.0} ticks/s)",
+                nodes,
+                iteration - first,
+                elapsed,
+                (iteration - first) as f64 / elapsed.as_secs_f64()
+            );
+        }
+    }
+}
@@ -1,32 +1,331 @@
+use error::SimError;
+use lineage::SectionId;
 use node::Node;
 use prefix::{Name, Prefix};
+use snapshot::{Reader, Snapshot, Writer};
+use termination::Reason;
+
+/// Id correlating the messages of a single relocation exchange (the request,
+/// the response to it and the commit or cancel): the id of the requesting
+/// section and a sequence number within it. A retry after a rejection starts a
+/// new exchange.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct ExchangeId(pub SectionId, pub u64);
+
+impl Snapshot for ExchangeId {
+    fn save(&self, writer: &mut Writer) {
+        writer.put(self.0);
+        writer.put(self.1);
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok(ExchangeId(reader.get()?, reader.get()?))
+    }
+}
 
 /// Network message (RPC).
 /// Note: these do not necessarily correspond to the RPCs of the real network,
 /// because this simulation abstracts lot of the real stuff away.
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Message {
     /// Request to relocate a node with the given name to the given target.
-    RelocateRequest { node_name: Name, target: Name },
+    RelocateRequest {
+        id: ExchangeId,
+        node_name: Name,
+        target: Name,
+    },
     /// Positive reponse to a relocate request.
-    RelocateAccept { node_name: Name, target: Name },
+    RelocateAccept {
+        id: ExchangeId,
+        node_name: Name,
+        target: Name,
+    },
     /// Negative response to a relocate request.
-    RelocateReject { node_name: Name, target: Name },
+    RelocateReject {
+        id: ExchangeId,
+        node_name: Name,
+        target: Name,
+    },
     /// Actually relocate the node.
-    RelocateCommit { node: Node, target: Name },
+    RelocateCommit {
+        id: ExchangeId,
+        node: Node,
+        target: Name,
+        cause: RelocationCause,
+    },
     /// Cancel a previously accepted relocate request (due to the node to be
     /// relocated disconnecting)
-    RelocateCancel { node_name: Name, target: Name },
+    RelocateCancel {
+        id: ExchangeId,
+        node_name: Name,
+        target: Name,
+    },
+    /// Redirect a freshly joining node to the section responsible for the
+    /// target name (derived from the hash of the node's chosen name).
+    JoinRedirect { node: Node, target: Name },
+    /// Send a node whose relocation commit didn't match any incoming relocation
+    /// back to the section responsible for its original name (see
+    /// `UnmatchedCommit::Bounce`).
+    Bounce { node: Node },
+    /// Notify the sibling section about our current size.
+    SiblingSize {
+        source: Prefix,
+        size: usize,
+        target: Name,
+    },
+    /// Ask a neighbouring section to direct its next relocations to us, as we
+    /// are short of adults.
+    NeedAdults { source: Prefix, target: Name },
 }
 
 impl Message {
+    /// Whether the message informs a neighbour about the source section (its
+    /// size or need for adults).
+    pub fn is_neighbour_info(&self) -> bool {
+        matches!(*self, Message::SiblingSize { .. } | Message::NeedAdults { .. })
+    }
+
     pub fn target(&self) -> Name {
         match *self {
             Message::RelocateRequest { target, .. } |
             Message::RelocateCommit { target, .. } |
-            Message::RelocateCancel { target, .. } => target,
+            Message::JoinRedirect { target, .. } |
+            Message::RelocateCancel { target, .. } |
+            Message::SiblingSize { target, .. } |
+            Message::NeedAdults { target, .. } => target,
             Message::RelocateAccept { node_name, .. } |
             Message::RelocateReject { node_name, .. } => node_name,
+            Message::Bounce { ref node } => node.name(),
+        }
+    }
+
+    /// A name within the section that sent this message.
+    pub fn source(&self) -> Name {
+        match *self {
+            Message::RelocateRequest { node_name, .. } |
+            Message::RelocateCancel { node_name, .. } => node_name,
+            Message::RelocateCommit { ref node, .. } |
+            Message::JoinRedirect { ref node, .. } |
+            Message::Bounce { ref node } => node.name(),
+            Message::RelocateAccept { target, .. } |
+            Message::RelocateReject { target, .. } => target,
+            Message::SiblingSize { source, .. } |
+            Message::NeedAdults { source, .. } => source.substituted_in(Name(0)),
+        }
+    }
+
+    /// Name of the relocating node, for the messages of a relocation exchange.
+    pub fn node_name(&self) -> Option<Name> {
+        match *self {
+            Message::RelocateRequest { node_name, .. } |
+            Message::RelocateAccept { node_name, .. } |
+            Message::RelocateReject { node_name, .. } |
+            Message::RelocateCancel { node_name, .. } => Some(node_name),
+            Message::RelocateCommit { ref node, .. } => Some(node.name()),
+            Message::JoinRedirect { .. } |
+            Message::Bounce { .. } |
+            Message::SiblingSize { .. } |
+            Message::NeedAdults { .. } => None,
+        }
+    }
+
+    /// Key to order messages by, independent of the order they were sent in.
+    fn sort_key(&self) -> (Name, u8, Name) {
+        let (kind, subject) = match *self {
+            Message::RelocateRequest { node_name, .. } => (0, node_name),
+            Message::RelocateAccept { target, .. } => (1, target),
+            Message::RelocateReject { target, .. } => (2, target),
+            Message::RelocateCommit { ref node, .. } => (3, node.name()),
+            Message::RelocateCancel { node_name, .. } => (4, node_name),
+            Message::JoinRedirect { ref node, .. } => (5, node.name()),
+            Message::SiblingSize { source, .. } => (6, source.substituted_in(Name(0))),
+            Message::NeedAdults { source, .. } => (7, source.substituted_in(Name(0))),
+            Message::Bounce { ref node } => (8, node.name()),
+        };
+
+        (self.target(), kind, subject)
+    }
+}
+
+impl Snapshot for Message {
+    fn save(&self, writer: &mut Writer) {
+        match *self {
+            Message::RelocateRequest {
+                id,
+                node_name,
+                target,
+            } => {
+                writer.put(0);
+                (id, node_name, target).save(writer);
+            }
+            Message::RelocateAccept {
+                id,
+                node_name,
+                target,
+            } => {
+                writer.put(1);
+                (id, node_name, target).save(writer);
+            }
+            Message::RelocateReject {
+                id,
+                node_name,
+                target,
+            } => {
+                writer.put(2);
+                (id, node_name, target).save(writer);
+            }
+            Message::RelocateCommit {
+                id,
+                ref node,
+                target,
+                cause,
+            } => {
+                writer.put(3);
+                id.save(writer);
+                node.save(writer);
+                target.save(writer);
+                cause.save(writer);
+            }
+            Message::RelocateCancel {
+                id,
+                node_name,
+                target,
+            } => {
+                writer.put(4);
+                (id, node_name, target).save(writer);
+            }
+            Message::JoinRedirect { ref node, target } => {
+                writer.put(5);
+                node.save(writer);
+                target.save(writer);
+            }
+            Message::SiblingSize {
+                source,
+                size,
+                target,
+            } => {
+                writer.put(6);
+                source.save(writer);
+                size.save(writer);
+                target.save(writer);
+            }
+            Message::NeedAdults { source, target } => {
+                writer.put(7);
+                source.save(writer);
+                target.save(writer);
+            }
+            Message::Bounce { ref node } => {
+                writer.put(8);
+                node.save(writer);
+            }
+        }
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        match reader.get::<u8>()? {
+            0 => {
+                let (id, node_name, target) = Snapshot::load(reader)?;
+                Ok(Message::RelocateRequest {
+                    id,
+                    node_name,
+                    target,
+                })
+            }
+            1 => {
+                let (id, node_name, target) = Snapshot::load(reader)?;
+                Ok(Message::RelocateAccept {
+                    id,
+                    node_name,
+                    target,
+                })
+            }
+            2 => {
+                let (id, node_name, target) = Snapshot::load(reader)?;
+                Ok(Message::RelocateReject {
+                    id,
+                    node_name,
+                    target,
+                })
+            }
+            3 => {
+                Ok(Message::RelocateCommit {
+                    id: Snapshot::load(reader)?,
+                    node: Snapshot::load(reader)?,
+                    target: Snapshot::load(reader)?,
+                    cause: Snapshot::load(reader)?,
+                })
+            }
+            4 => {
+                let (id, node_name, target) = Snapshot::load(reader)?;
+                Ok(Message::RelocateCancel {
+                    id,
+                    node_name,
+                    target,
+                })
+            }
+            5 => {
+                Ok(Message::JoinRedirect {
+                    node: Snapshot::load(reader)?,
+                    target: Snapshot::load(reader)?,
+                })
+            }
+            6 => {
+                Ok(Message::SiblingSize {
+                    source: Snapshot::load(reader)?,
+                    size: Snapshot::load(reader)?,
+                    target: Snapshot::load(reader)?,
+                })
+            }
+            7 => {
+                Ok(Message::NeedAdults {
+                    source: Snapshot::load(reader)?,
+                    target: Snapshot::load(reader)?,
+                })
+            }
+            8 => Ok(Message::Bounce { node: Snapshot::load(reader)? }),
+            tag => Err(format!("invalid message in snapshot: {}", tag)),
+        }
+    }
+}
+
+/// Reason for initiating a relocation.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RelocationCause {
+    /// Regular relocation triggered by ageing.
+    Ageing,
+    /// Elder relocated after reaching the term limit.
+    TermLimit,
+    /// Relocation to the sibling section to even out their sizes.
+    Balancing,
+    /// Ageing relocation directed to a neighbour short of adults.
+    Rescue,
+}
+
+impl RelocationCause {
+    /// Name of the cause in the traces.
+    pub fn name(self) -> &'static str {
+        match self {
+            RelocationCause::Ageing => "ageing",
+            RelocationCause::TermLimit => "term-limit",
+            RelocationCause::Balancing => "balancing",
+            RelocationCause::Rescue => "rescue",
+        }
+    }
+}
+
+impl Snapshot for RelocationCause {
+    fn save(&self, writer: &mut Writer) {
+        writer.put(*self as u8)
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        match reader.get::<u8>()? {
+            0 => Ok(RelocationCause::Ageing),
+            1 => Ok(RelocationCause::TermLimit),
+            2 => Ok(RelocationCause::Balancing),
+            3 => Ok(RelocationCause::Rescue),
+            tag => Err(format!("invalid relocation cause in snapshot: {}", tag)),
         }
     }
 }
@@ -42,4 +341,64 @@ pub enum Action {
     Split(Prefix),
     /// Send a message.
     Send(Message),
+    /// Stop the simulation due to a failure in the section.
+    Abort(Prefix, Reason),
+    /// Report a fault the section recovered from.
+    Fault(Prefix, SimError),
+}
+
+impl Action {
+    /// Key to order actions by: splits first, then merges, then messages and
+    /// rejections last. Within each kind the order is fully determined by the
+    /// action content.
+    fn sort_key(&self) -> (u8, Prefix, (Name, u8, Name)) {
+        match *self {
+            Action::Split(prefix) => (0, prefix, (Name(0), 0, Name(0))),
+            Action::Merge(prefix) => (1, prefix, (Name(0), 0, Name(0))),
+            Action::Send(ref message) => (2, Prefix::EMPTY, message.sort_key()),
+            Action::Reject(ref node) => (3, Prefix::EMPTY, (node.name(), 0, Name(0))),
+            Action::Abort(prefix, _) => (4, prefix, (Name(0), 0, Name(0))),
+            Action::Fault(prefix, _) => (5, prefix, (Name(0), 0, Name(0))),
+        }
+    }
+}
+
+/// Sort the actions into deterministic order (see `Action::sort_key`) and
+/// resolve conflicts between them:
+///
+/// - duplicate splits and merges are applied only once,
+/// - splits of sections that are about to be merged into an ancestor are dropped.
+pub fn prepare_actions(actions: &mut Vec<Action>) {
+    actions.sort_by_key(|action| action.sort_key());
+
+    let merges: Vec<_> = actions
+        .iter()
+        .filter_map(|action| match *action {
+            Action::Merge(prefix) => Some(prefix),
+            _ => None,
+        })
+        .collect();
+
+    let mut prev: Option<(u8, Prefix)> = None;
+
+    actions.retain(|action| {
+        let (kind, prefix) = match *action {
+            Action::Split(prefix) => (0, prefix),
+            Action::Merge(prefix) => (1, prefix),
+            _ => return true,
+        };
+
+        if prev == Some((kind, prefix)) {
+            debug!("Ignoring duplicate {:?}", action);
+            return false;
+        }
+        prev = Some((kind, prefix));
+
+        if kind == 0 && merges.iter().any(|merge| merge.is_ancestor(&prefix)) {
+            debug!("Ignoring {:?} superseded by merge", action);
+            return false;
+        }
+
+        true
+    })
 }
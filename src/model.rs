@@ -0,0 +1,116 @@
+//! Simple theoretical model of node ageing, to compare the simulation against.
+//!
+//! A churn event relocates a node of age `age` if the ageing hash has at least
+//! `age` trailing zeros, which happens with probability `2^-age`. The model
+//! assumes every section has nodes of all the ages the hash can select and
+//! ignores startup, splits, rejoins, cooldowns and the other relocation
+//! restrictions. Deviations from it are therefore expected, but large ones are
+//! worth investigating.
+
+use Age;
+use params::Params;
+use std::collections::BTreeMap;
+
+/// Expected number of ageing relocations per churn event.
+pub fn expected_relocation_rate(params: &Params) -> f64 {
+    let age = match min_eligible_age(params) {
+        Some(age) => age,
+        None => return 0.0,
+    };
+
+    // Every attempt relocates the youngest eligible node, unless an older one
+    // matches too.
+    let miss = 1.0 - probability(age);
+    1.0 - miss.powi(params.max_relocation_attempts as i32)
+}
+
+/// Expected fraction of nodes of each age, up to `max_age` (the remaining ones
+/// are counted at `max_age`).
+///
+/// Each relocation increments the age of the node, and a node of age `age` is
+/// relocated half as often as one of age `age - 1`, so every age has half the
+/// nodes of the previous one. Nodes that are not eligible for relocation stay at
+/// their age.
+pub fn expected_age_distribution(params: &Params, max_age: Age) -> BTreeMap<Age, f64> {
+    let mut result = BTreeMap::new();
+    let mut remaining = 1.0;
+
+    for age in params.init_age..max_age {
+        if !params.relocate_infants.allows(age < params.adult_age) {
+            break;
+        }
+
+        let _ = result.insert(age, remaining / 2.0);
+        remaining /= 2.0;
+    }
+
+    let last = result.keys().last().map_or(params.init_age, |&age| age + 1);
+    let _ = result.insert(last, remaining);
+    result
+}
+
+/// Expected number of adults to join the root section before it splits, or
+/// `None` if the nodes joining during startup are not adults. Drops during
+/// startup are ignored.
+pub fn expected_startup_adults(params: &Params) -> Option<f64> {
+    if params.startup_infants || params.startup_age < params.adult_age {
+        return None;
+    }
+
+    // The root splits as soon as both halves have `split_threshold` adults. The
+    // smaller half only grows, so the expected number of joins until then is the
+    // sum over `n` of the probability that `n` joins are not enough.
+    let limit = params.split_threshold() as u64;
+    let mut expected = 0.0;
+    let mut n = 0;
+
+    loop {
+        let not_enough = probability_min_half_below(n, limit);
+        if not_enough < 1e-12 {
+            break;
+        }
+
+        expected += not_enough;
+        n += 1;
+    }
+
+    Some(expected)
+}
+
+// Probability that, out of `n` names split uniformly into two halves, one of
+// them gets less than `limit`.
+fn probability_min_half_below(n: u64, limit: u64) -> f64 {
+    if n < 2 * limit {
+        return 1.0;
+    }
+
+    // Binomial(n, 1/2) probabilities, computed in log space to avoid overflow.
+    let log_half = -(n as f64) * 2f64.ln();
+    let mut log_binomial = 0.0;
+    let mut result = 0.0;
+
+    for k in 0..limit {
+        if k > 0 {
+            log_binomial += ((n - k + 1) as f64).ln() - (k as f64).ln();
+        }
+
+        // Both tails are symmetric.
+        result += 2.0 * (log_binomial + log_half).exp();
+    }
+
+    result
+}
+
+fn min_eligible_age(params: &Params) -> Option<Age> {
+    if params.relocate_infants.allows(params.init_age < params.adult_age) {
+        Some(params.init_age)
+    } else if params.relocate_infants.allows(false) {
+        Some(params.adult_age)
+    } else {
+        None
+    }
+}
+
+fn probability(age: Age) -> f64 {
+    2f64.powi(-i32::from(age))
+}
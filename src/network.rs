@@ -1,215 +1,2267 @@
+use Age;
 use HashMap;
+use chain::Hash;
+use churn_trace::TraceEvent;
+use error::SimError;
+use event_trace::EventTrace;
+use history::History;
+use layout::Layout;
+use lineage::{Lineage, SectionId};
 use log;
-use message::{Action, Message};
-use node;
-use params::Params;
-use prefix::Prefix;
-use section::Section;
-use stats::{Aggregator, Distribution, Stats};
-use std::ops::AddAssign;
+use message::{self, Action, ExchangeId, Message, RelocationCause};
+use node::{self, Node, NodeId, NodeIds, Origin};
+use params::{JoinModel, ParamChange, Params, Scheduler, Setting};
+use prefix::{Name, Prefix};
+use provenance::ProvenanceTable;
+use rand::Rng;
+use random::{self, Category, IndependentRng};
+use relocation_trace::RelocationTrace;
+use section::{Section, State};
+use simulation::Policies;
+use snapshot::{Reader, Snapshot, Writer};
+use stats::{AgeingCounts, Aggregator, ChaosEpochs, Churn, Counters, Distribution, ElderRotation,
+            Eras,
+            GroupedDistribution, IterationChurn, OriginCounts, Outcome, Records,
+            RelocationResponses, StaleViews, Stats};
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, VecDeque};
+use std::mem;
+use std::time::{Duration, Instant};
+use termination::{Reason, Termination};
+use victim::VictimSelector;
+
+/// Worst case of a split for the adversary (see
+/// `View::worst_case_split_elders`).
+pub struct SplitAudit {
+    pub iteration: u64,
+    /// Prefix of the section before the split.
+    pub prefix: Prefix,
+    /// Elders the adversary could have in the worse of the two halves.
+    pub elders: usize,
+}
+
+impl SplitAudit {
+    /// Number of elders the adversary has beyond what it needs for a quorum.
+    /// Non-negative if it could take over the half.
+    pub fn margin(&self, params: &Params) -> i64 {
+        self.elders as i64 - params.quorum() as i64
+    }
+}
+
+// Relocation requests deferred by `Params::global_relocation_cap`, queued by
+// the prefix of their source section and released one section at a time in
+// turn, so a section triggering many relocations doesn't hold back the others.
+#[derive(Default)]
+struct RelocationQueue {
+    queues: BTreeMap<Prefix, VecDeque<(u64, Message)>>,
+    // Sections with queued requests, in the order of their next turn.
+    turns: VecDeque<Prefix>,
+    // The queued nodes, with the section their request is queued under.
+    nodes: BTreeMap<Name, Prefix>,
+    // Number of requests ever queued.
+    deferred: u64,
+}
+
+impl RelocationQueue {
+    fn push(&mut self, iteration: u64, source: Prefix, message: Message) {
+        let queue = self.queues.entry(source).or_default();
+        if queue.is_empty() {
+            self.turns.push_back(source);
+        }
+        let _ = self.nodes.insert(message.source(), source);
+        queue.push_back((iteration, message));
+        self.deferred += 1;
+    }
+
+    // The oldest request of the section whose turn it is, with the iteration
+    // it was queued in.
+    fn pop(&mut self) -> Option<(u64, Message)> {
+        let source = self.turns.pop_front()?;
+        let queue = self.queues.get_mut(&source)?;
+        let entry = queue.pop_front()?;
+        if queue.is_empty() {
+            let _ = self.queues.remove(&source);
+        } else {
+            self.turns.push_back(source);
+        }
+        let _ = self.nodes.remove(&entry.1.source());
+        Some(entry)
+    }
+
+    fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn contains(&self, node_name: Name) -> bool {
+        self.nodes.contains_key(&node_name)
+    }
+
+    // Remove the request of the exchange relocating the node, returning
+    // whether it was queued.
+    fn remove(&mut self, id: ExchangeId, node_name: Name) -> bool {
+        let source = match self.nodes.get(&node_name) {
+            Some(&source) => source,
+            None => return false,
+        };
+        let queue = match self.queues.get_mut(&source) {
+            Some(queue) => queue,
+            None => return false,
+        };
+        let position = queue.iter().position(|(_, message)| {
+            matches!(*message, Message::RelocateRequest { id: queued, .. } if queued == id)
+        });
+        if position.and_then(|index| queue.remove(index)).is_none() {
+            return false;
+        }
+
+        if queue.is_empty() {
+            let _ = self.queues.remove(&source);
+            self.turns.retain(|&turn| turn != source);
+        }
+        let _ = self.nodes.remove(&node_name);
+        true
+    }
+
+    fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+impl Snapshot for RelocationQueue {
+    fn save(&self, writer: &mut Writer) {
+        self.queues.save(writer);
+        self.turns.save(writer);
+        self.deferred.save(writer);
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        let queues: BTreeMap<Prefix, VecDeque<(u64, Message)>> = Snapshot::load(reader)?;
+        let nodes = queues
+            .iter()
+            .flat_map(|(&source, queue)| {
+                queue.iter().map(move |(_, message)| (message.source(), source))
+            })
+            .collect();
+
+        Ok(RelocationQueue {
+            queues,
+            turns: Snapshot::load(reader)?,
+            nodes,
+            deferred: Snapshot::load(reader)?,
+        })
+    }
+}
+
+// Message of the events scheduler, arriving at the time. Messages arriving at
+// the same time keep the order they were sent in. Ordered so the earliest one
+// is the greatest, to pop it first from the heap.
+struct ScheduledMessage {
+    time: f64,
+    sequence: u64,
+    message: Message,
+}
+
+impl PartialEq for ScheduledMessage {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == cmp::Ordering::Equal
+    }
+}
+
+impl Eq for ScheduledMessage {}
+
+impl PartialOrd for ScheduledMessage {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ScheduledMessage {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        other.time.total_cmp(&self.time).then(other.sequence.cmp(&self.sequence))
+    }
+}
+
+impl Snapshot for ScheduledMessage {
+    fn save(&self, writer: &mut Writer) {
+        self.time.save(writer);
+        self.sequence.save(writer);
+        self.message.save(writer);
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok(ScheduledMessage {
+            time: Snapshot::load(reader)?,
+            sequence: Snapshot::load(reader)?,
+            message: Snapshot::load(reader)?,
+        })
+    }
+}
+
+/// Merge after which the elders of the merged section weren't the oldest nodes
+/// of the pre-merge sections.
+pub struct MergeElderMismatch {
+    pub iteration: u64,
+    /// Prefix of the merged section.
+    pub prefix: Prefix,
+    /// Nodes that should have been elders but weren't.
+    pub missing: Vec<Name>,
+    /// Elders that shouldn't have been.
+    pub extra: Vec<Name>,
+    /// Number of elders expected and actually elected.
+    pub expected: usize,
+    pub actual: usize,
+}
 
 pub struct Network {
     params: Params,
     stats: Stats,
-    sections: HashMap<Prefix, Section>,
+    sections: BTreeMap<Prefix, Section>,
+    lineage: Lineage,
+    history: History,
+    provenance: ProvenanceTable,
+    relocation_trace: RelocationTrace,
+    // Ids for the nodes joining, shared with the sections.
+    node_ids: NodeIds,
+    event_trace: Option<EventTrace>,
+    // Node events by node origin.
+    origin_counts: BTreeMap<Origin, OriginCounts>,
+    // Ageing check outcomes by node age.
+    ageing_counts: BTreeMap<Age, AgeingCounts>,
+    // Responses to relocation requests by the prefix length of the
+    // destination.
+    relocation_responses: BTreeMap<u8, RelocationResponses>,
+    // Sibling sizes and needs for adults held back until the iteration they
+    // are delivered in (see `Params::view_lag`).
+    delayed_views: Vec<(u64, Message)>,
+    stale_views: StaleViews,
+    // Relocation requests over `Params::global_relocation_cap`, and the number
+    // sent in the current iteration.
+    relocation_queue: RelocationQueue,
+    relocation_requests_sent: u64,
+    // Number of requests at the end of each iteration, and iterations each
+    // deferred one waited.
+    relocation_queue_depths: Distribution,
+    relocation_delays: Distribution,
+    elder_rotation: ElderRotation,
+    // Number of iterations sections ended in each state. Splits and merges are
+    // carried out within the tick, so sections end up splitting or merging
+    // only if the split or merge was superseded.
+    state_ticks: BTreeMap<State, u64>,
+    // Churn of the sections that already ended (split or merged): in total,
+    // and of each of them if the most churned sections are reported.
+    ended_churn_total: Churn,
+    ended_churn: Vec<(SectionId, Prefix, Churn)>,
+    // Membership changes of each section in each tick.
+    tick_churn: Distribution,
+    // Largest of those: the number, iteration and section.
+    max_tick_churn: Option<(u64, u64, Prefix)>,
+    // Averages by number of sections.
+    eras: Eras,
+    // Nodes scheduled to restart (due to network upgrade), keyed by iteration.
+    scheduled_restarts: BTreeMap<u64, Vec<Name>>,
+    // Merges and elder changes within the windows of the network upgrades, and
+    // in the other iterations.
+    upgrade_churn: IterationChurn,
+    background_churn: IterationChurn,
+    // Parameter changes applied so far.
+    param_changes: Records<ParamChange>,
+    // Metrics between the chaos mode changes.
+    chaos_epochs: ChaosEpochs,
+    // Generator of the chaos mode changes, separate from the main one so the
+    // rest of the simulation draws the same values with or without them.
+    chaos_rng: Option<IndependentRng>,
+    // Nodes which restarted in the previous iteration and are about to rejoin.
+    rejoins: Vec<Node>,
+    // Data availability measured at each merge: percentage of the sampled
+    // chunks below the replication count and the minimum number of surviving
+    // replicas of any sampled chunk.
+    merge_dips: Distribution,
+    merge_replicas: Distribution,
+    // Iteration of the last relocation from each live section (the last
+    // relocation of each node is kept by the node).
+    last_section_relocations: HashMap<SectionId, u64>,
+    // Intervals between consecutive relocations from the same section, and
+    // between consecutive relocations of the same node (by its age).
+    section_relocation_intervals: Distribution,
+    node_relocation_intervals: GroupedDistribution,
+    // Number of iterations sections had less than `group_size` elders after
+    // relocating an elder away.
+    elder_gaps: Distribution,
+    // Number of iterations sections split off took to become complete.
+    completion_gaps: Distribution,
+    // Adults imbalance between the halves of the destination section at each
+    // relocation commit.
+    commit_imbalances: Distribution,
+    // Size of the larger of each pair of sibling sections in percent of the
+    // smaller one, at the end of each iteration.
+    sibling_size_ratios: Distribution,
+    // Section ticks from the first request to the commit of every relocation.
+    relocation_latencies: Distribution,
+    first_promotion_ages: Distribution,
+    split_audits: Vec<SplitAudit>,
+    // Number of merges whose elders were checked, and the failed checks.
+    merge_elder_checks: u64,
+    merge_elder_mismatches: Records<MergeElderMismatch>,
+    // Wall-clock time spent carrying out the merges and checking their
+    // outcome.
+    merge_time: Duration,
+    // Action queue, reused across ticks to avoid reallocating it.
+    actions: Vec<Action>,
+    // Messages being routed, with the number of section ticks until delivery.
+    in_flight: Vec<(u64, Message)>,
+    // Hop count and latency of the routed messages.
+    hops: Distribution,
+    latencies: Distribution,
+    // Messages on their way in the events scheduler, the earliest arrival
+    // first, and the number of messages scheduled so far.
+    scheduled_messages: BinaryHeap<ScheduledMessage>,
+    messages_scheduled: u64,
+    // Current time and the time of the next join or leave, in the events
+    // scheduler.
+    now: f64,
+    next_event: f64,
+    // Whether the shrink test is in progress.
+    shrinking: bool,
+    // Merges performed during the shrink test: iteration, post-merge prefix and
+    // number of pre-merge sections.
+    shrink_merges: Vec<(u64, Prefix, usize)>,
+    // Recoverable faults: iteration, section (if known) and the fault.
+    faults: Records<(u64, Option<Prefix>, SimError)>,
+    // Disasters carried out: iteration, victim selection and number of nodes
+    // removed.
+    disasters: Vec<(u64, VictimSelector, usize)>,
+    // Invariant violations detected during the shrink test.
+    shrink_violations: Records<(u64, String)>,
+    // Node lifecycle violations found by the full-chain audit: iteration,
+    // section and description.
+    chain_violations: Records<(u64, Prefix, String)>,
+    // First failure that requires stopping the simulation.
+    termination: Option<Termination>,
+    // Simulation node ids of the trace nodes that joined.
+    trace_nodes: HashMap<usize, NodeId>,
 }
 
-impl Network {
-    /// Create new simulated network with the given parameters.
-    pub fn new(params: Params) -> Self {
-        let mut sections = HashMap::default();
-        let _ = sections.insert(Prefix::EMPTY, Section::new(Prefix::EMPTY));
+impl Network {
+    /// Create new simulated network with the given parameters. Panics if the
+    /// layout file is invalid (see `Layout::load`).
+    pub fn new(params: Params) -> Self {
+        let mut lineage = Lineage::new();
+        let mut sections = BTreeMap::new();
+        let node_ids = NodeIds::new();
+
+        if let Some(ref path) = params.layout_file {
+            let layout = Layout::load(path).unwrap_or_else(|error| panic!("{}", error));
+            for (&prefix, nodes) in layout.sections() {
+                let id = lineage.create(prefix, Vec::new(), 0);
+                let mut section = Section::new(id, prefix, node_ids.clone());
+                let _context = log::enter_section(prefix);
+                section.populate(
+                    &params,
+                    nodes.iter().map(|&(name, age)| {
+                        Node::new(node_ids.allocate(), name, age)
+                    }),
+                );
+                let _ = sections.insert(prefix, section);
+            }
+            info!(
+                "Loaded layout of {} nodes in {} sections",
+                layout.num_nodes(),
+                layout.sections().len()
+            );
+        } else {
+            let id = lineage.create(Prefix::EMPTY, Vec::new(), 0);
+            let section = Section::new(id, Prefix::EMPTY, node_ids.clone());
+            let _ = sections.insert(Prefix::EMPTY, section);
+        }
+
+        // Soak runs stream their stats instead of keeping them all.
+        let stats = if params.soak_dir.is_some() {
+            Stats::with_retention(params.soak_frequency as usize)
+        } else {
+            Stats::new()
+        };
+
+        let event_trace = params.event_trace_file.as_ref().map(|path| {
+            EventTrace::create(path, params.event_trace_format).unwrap_or_else(
+                |error| panic!("{}", error),
+            )
+        });
+
+        let chaos_rng = if params.chaos.is_empty() {
+            None
+        } else {
+            Some(random::independent_rng(params.rng, params.seed, 2))
+        };
+
+        Network {
+            params,
+            stats,
+            sections,
+            lineage,
+            history: History::new(),
+            relocation_trace: RelocationTrace::new(),
+            node_ids,
+            event_trace,
+            provenance: ProvenanceTable::new(),
+            ended_churn_total: Churn::default(),
+            ended_churn: Vec::new(),
+            tick_churn: Distribution::new(None),
+            max_tick_churn: None,
+            eras: Eras::default(),
+            origin_counts: BTreeMap::new(),
+            ageing_counts: BTreeMap::new(),
+            relocation_responses: BTreeMap::new(),
+            delayed_views: Vec::new(),
+            stale_views: StaleViews::default(),
+            relocation_queue: RelocationQueue::default(),
+            relocation_requests_sent: 0,
+            relocation_queue_depths: Distribution::new(None),
+            relocation_delays: Distribution::new(None),
+            elder_rotation: ElderRotation::default(),
+            state_ticks: BTreeMap::new(),
+            scheduled_restarts: BTreeMap::new(),
+            upgrade_churn: IterationChurn::default(),
+            background_churn: IterationChurn::default(),
+            param_changes: Records::new(),
+            chaos_epochs: ChaosEpochs::default(),
+            chaos_rng,
+            rejoins: Vec::new(),
+            merge_dips: Distribution::new(None),
+            merge_replicas: Distribution::new(None),
+            last_section_relocations: HashMap::default(),
+            section_relocation_intervals: Distribution::new(None),
+            elder_gaps: Distribution::new(None),
+            completion_gaps: Distribution::new(None),
+            commit_imbalances: Distribution::new(None),
+            sibling_size_ratios: Distribution::new(None),
+            relocation_latencies: Distribution::new(None),
+            first_promotion_ages: Distribution::new(None),
+            split_audits: Vec::new(),
+            merge_elder_checks: 0,
+            merge_time: Duration::default(),
+            merge_elder_mismatches: Records::new(),
+            node_relocation_intervals: GroupedDistribution::new(None),
+            actions: Vec::new(),
+            in_flight: Vec::new(),
+            hops: Distribution::new(None),
+            latencies: Distribution::new(None),
+            scheduled_messages: BinaryHeap::new(),
+            messages_scheduled: 0,
+            now: 0.0,
+            next_event: 0.0,
+            shrinking: false,
+            shrink_merges: Vec::new(),
+            disasters: Vec::new(),
+            faults: Records::new(),
+            shrink_violations: Records::new(),
+            chain_violations: Records::new(),
+            termination: None,
+            trace_nodes: HashMap::default(),
+        }
+    }
+
+    /// Network with the given parameters, in the state read from the snapshot
+    /// (see `save`).
+    pub fn restore(params: Params, reader: &mut Reader) -> Result<Self, String> {
+        let mut network = Network::new(params);
+
+        for setting in Vec::<Setting>::load(reader)? {
+            network.params.apply(setting);
+        }
+        network.stats = Snapshot::load(reader)?;
+        network.lineage = Snapshot::load(reader)?;
+        network.node_ids.resume(reader.get()?);
+
+        network.sections.clear();
+        let num_sections: usize = reader.get()?;
+        for _ in 0..num_sections {
+            let section = Section::load(reader, network.node_ids.clone())?;
+            let _ = network.sections.insert(section.prefix(), section);
+        }
+
+        network.delayed_views = Snapshot::load(reader)?;
+        network.relocation_queue = Snapshot::load(reader)?;
+        network.relocation_requests_sent = Snapshot::load(reader)?;
+        network.scheduled_restarts = Snapshot::load(reader)?;
+        network.rejoins = Snapshot::load(reader)?;
+        network.in_flight = Snapshot::load(reader)?;
+        network.shrinking = Snapshot::load(reader)?;
+        network.trace_nodes = Snapshot::load(reader)?;
+        network.origin_counts = Snapshot::load(reader)?;
+        network.ageing_counts = Snapshot::load(reader)?;
+        network.relocation_responses = Snapshot::load(reader)?;
+        network.ended_churn_total = Snapshot::load(reader)?;
+        network.last_section_relocations = Snapshot::load(reader)?;
+        network.chaos_rng = Snapshot::load(reader)?;
+        network.scheduled_messages = Snapshot::load(reader)?;
+        network.messages_scheduled = Snapshot::load(reader)?;
+
+        Ok(network)
+    }
+
+    /// Write the state the rest of the run depends on into the snapshot: the
+    /// current values of the changing parameters, the stats, the sections and
+    /// the messages and relocations in progress. The other reports (the
+    /// distributions and listings) aren't included, so after restoring they
+    /// cover only the iterations run since.
+    pub fn save(&self, writer: &mut Writer) {
+        self.params.settings().save(writer);
+        writer.newline();
+        self.stats.save(writer);
+        writer.newline();
+        self.lineage.save(writer);
+        writer.newline();
+        writer.put(self.node_ids.next());
+        writer.put(self.sections.len());
+        writer.newline();
+        for section in self.sections.values() {
+            section.save(writer);
+            writer.newline();
+        }
+
+        self.delayed_views.save(writer);
+        self.relocation_queue.save(writer);
+        self.relocation_requests_sent.save(writer);
+        self.scheduled_restarts.save(writer);
+        self.rejoins.save(writer);
+        self.in_flight.save(writer);
+        self.shrinking.save(writer);
+        self.trace_nodes.save(writer);
+        writer.newline();
+        self.origin_counts.save(writer);
+        self.ageing_counts.save(writer);
+        self.relocation_responses.save(writer);
+        self.ended_churn_total.save(writer);
+        self.last_section_relocations.save(writer);
+        writer.newline();
+        self.chaos_rng.save(writer);
+        writer.newline();
+        self.scheduled_messages.save(writer);
+        self.messages_scheduled.save(writer);
+        writer.newline();
+    }
+
+    /// Apply the parameter changes scheduled for the iteration (and the
+    /// random ones of the chaos mode). Returns the changes applied.
+    pub fn change_params(&mut self, iteration: u64) -> Vec<Setting> {
+        let mut settings = Vec::new();
+        let changes: Vec<_> = self.params
+            .param_changes
+            .iter()
+            .filter(|change| change.iteration == iteration)
+            .cloned()
+            .collect();
+        for change in changes {
+            info!("Changing parameter: {:?}", change.setting);
+            self.params.apply(change.setting);
+            settings.push(change.setting);
+            self.param_changes.push(change);
+        }
+
+        if let Some(setting) = self.draw_chaos_setting() {
+            info!("Chaos: changing parameter: {:?}", setting);
+            self.params.apply(setting);
+            self.param_changes.push(ParamChange { iteration, setting });
+            settings.push(setting);
+        }
+
+        if !self.params.chaos.is_empty() &&
+            (!settings.is_empty() || self.chaos_epochs.is_empty())
+        {
+            let current = self.chaos_settings();
+            self.chaos_epochs.start(iteration, current);
+        }
+
+        settings
+    }
+
+    /// Execute single iteration of the simulation, with the joins and drops of
+    /// the churn trace (if any) for the iteration.
+    pub fn tick(&mut self, iteration: u64, policies: &Policies, churn: Vec<TraceEvent>) {
+        let mut actions = mem::take(&mut self.actions);
+        let mut stats = Counters::default();
+        let elder_changes_before = if self.params.upgrade_interval > 0 {
+            self.total_churn().elder_changes
+        } else {
+            0
+        };
+
+        if !self.shrinking && self.params.shrink_after > 0 &&
+            self.num_nodes() >= self.params.shrink_after as u64
+        {
+            info!("Shrink test: stopping all joins at iteration {}", iteration);
+            self.shrinking = true;
+        }
+
+        let violations_before = self.chain_violations.count() + self.shrink_violations.count();
+
+        for section in self.sections.values_mut() {
+            section.set_shrinking(self.shrinking);
+            let prefix = section.prefix();
+            section.set_unreachable(self.params.blackouts.iter().any(|blackout| {
+                blackout.is_active(iteration) && blackout.prefix.is_compatible_with(&prefix)
+            }));
+            // Requests held back by the global cap don't outlive their
+            // exchange.
+            for (id, node_name) in section.prepare(&self.params, iteration) {
+                let _ = self.relocation_queue.remove(id, node_name);
+            }
+        }
+
+        if self.params.upgrade_interval > 0 && iteration > 0 &&
+            iteration.is_multiple_of(self.params.upgrade_interval)
+        {
+            self.schedule_upgrade(iteration);
+        }
+
+        if self.params.economics {
+            for section in self.sections.values_mut() {
+                section.earn_credits(&self.params);
+            }
+        }
+
+        for change in &self.params.force_splits {
+            if change.iteration == iteration {
+                info!("Forcing split of {}", log::prefix(&change.prefix));
+                actions.push(Action::Split(change.prefix));
+            }
+        }
+
+        for change in &self.params.force_merges {
+            if change.iteration == iteration {
+                // Merging a single section into itself would only replace it
+                // with a new one.
+                if self.sections.contains_key(&change.prefix) {
+                    info!(
+                        "Skipping forced merge into {}: already a single section",
+                        log::prefix(&change.prefix)
+                    );
+                    continue;
+                }
+                info!("Forcing merge into {}", log::prefix(&change.prefix));
+                actions.push(Action::Merge(change.prefix));
+            }
+        }
+
+        actions.extend(self.rejoin_nodes(policies));
+        actions.extend(self.restart_nodes(iteration, policies, &mut stats));
+        actions.extend(self.apply_trace(churn, policies));
+        actions.extend(self.apply_disasters(iteration, policies));
+        actions.extend(self.contact_joins(policies));
+
+        let mut faults = Vec::new();
+        self.deliver_delayed_views(iteration, &mut stats, &mut faults);
+        self.release_relocation_requests(iteration, &mut stats, &mut faults);
+
+        loop {
+            log::next_round();
+            self.advance_in_flight(&mut stats, &mut faults);
+
+            for section in self.sections.values_mut() {
+                let _context = log::enter_section(section.prefix());
+                section.tick(&self.params, policies, &mut actions);
+                stats += section.take_counters();
+                self.elder_gaps.extend(section.take_elder_gaps());
+                self.completion_gaps.extend(section.take_completion_gaps());
+                self.commit_imbalances.extend(section.take_commit_imbalances());
+                self.relocation_latencies.extend(section.take_relocation_latencies());
+                for (id, new_name) in section.take_relocation_names() {
+                    self.relocation_trace.renamed(id, new_name);
+                }
+                for membership in section.take_membership_events() {
+                    if let Some(ref mut event_trace) = self.event_trace {
+                        event_trace.membership(iteration, section.prefix(), &membership);
+                    }
+                    if self.params.survival_file.is_some() {
+                        self.history.record(iteration, &membership);
+                    }
+                }
+                for violation in section.take_chain_violations() {
+                    error!("Chain audit: {}", violation);
+                    self.chain_violations.push((iteration, section.prefix(), violation));
+                }
+                self.first_promotion_ages.extend(
+                    section.take_first_promotion_ages().into_iter().map(u64::from),
+                );
+                for (origin, counts) in section.take_origin_counts() {
+                    *self.origin_counts.entry(origin).or_default() += counts;
+                }
+                for (age, counts) in section.take_ageing_counts() {
+                    *self.ageing_counts.entry(age).or_default() += counts;
+                }
+                *self.relocation_responses.entry(section.prefix().len()).or_default() +=
+                    section.take_relocation_responses();
+            }
+
+            if actions.is_empty() && self.in_flight.is_empty() {
+                // Everything happening at the current time is handled, so the
+                // next scheduled message can arrive.
+                if self.deliver_scheduled(&mut stats, &mut faults) {
+                    continue;
+                }
+                break;
+            }
+
+            stats += self.handle_actions(iteration, &mut actions, &mut faults)
+        }
+
+        self.actions = actions;
+
+        if self.params.global_relocation_cap > 0 {
+            self.relocation_queue_depths.insert(self.relocation_queue.len() as u64);
+        }
+
+        let num_faults = faults.len() as u64;
+        for (section, error) in faults {
+            self.fault(iteration, section, error);
+        }
+
+        for section in self.sections.values() {
+            *self.state_ticks.entry(section.state()).or_default() += 1;
+        }
+
+        if self.params.operators > 0 {
+            for node in self.sections.values().flat_map(|section| section.nodes().values()) {
+                if let Some(operator) = node.operator() {
+                    self.elder_rotation.record(operator, node.is_elder());
+                }
+            }
+        }
+
+        for (prefix, section) in &self.sections {
+            if prefix.sibling() <= *prefix {
+                continue;
+            }
+            if let Some(sibling) = self.sections.get(&prefix.sibling()) {
+                let (small, large) = (section.nodes().len(), sibling.nodes().len());
+                let (small, large) = (cmp::min(small, large), cmp::max(small, large));
+                self.sibling_size_ratios
+                    .insert((large * 100 / cmp::max(small, 1)) as u64);
+            }
+        }
+
+        let mut membership_changes = 0;
+        for section in self.sections.values() {
+            let churn = section.tick_churn();
+            membership_changes += churn;
+            self.tick_churn.insert(churn);
+            if self.max_tick_churn.is_none_or(|(max, _, _)| churn > max) {
+                self.max_tick_churn = Some((churn, iteration, section.prefix()));
+            }
+        }
+
+        stats.oversized_sections = self.sections
+            .values()
+            .filter(|section| section.nodes().len() > self.params.max_section_size)
+            .count() as u64;
+        stats.short_elder_sections = self.sections
+            .values()
+            .filter(|section| section.lacks_eligible_elders(&self.params))
+            .count() as u64;
+
+        if self.params.upgrade_interval > 0 {
+            let elder_changes = self.total_churn().elder_changes - elder_changes_before;
+            let churn = if self.is_upgrading(iteration) {
+                &mut self.upgrade_churn
+            } else {
+                &mut self.background_churn
+            };
+            churn.iterations += 1;
+            churn.merges += stats.merges;
+            churn.elder_changes += elder_changes;
+        }
+
+        let num_nodes = self.num_nodes();
+        let num_complete_sections = self.num_complete_sections();
+        self.eras.record(
+            iteration,
+            self.sections.len() as u64,
+            num_complete_sections,
+            membership_changes,
+            &stats,
+        );
+        self.stats.record(
+            iteration,
+            num_nodes,
+            self.sections.len() as u64,
+            num_complete_sections,
+            stats,
+            self.elder_age_distribution(),
+        );
+
+        if self.params.survival_file.is_some() {
+            self.history.observe(
+                iteration,
+                self.sections.values().flat_map(|section| section.nodes().values()),
+            );
+        }
+
+        if !self.params.lookup.is_empty() {
+            self.provenance.observe(iteration, self.sections.values());
+        }
+
+        self.validate(iteration);
+
+        if self.shrinking {
+            for violation in self.check_prefixes() {
+                error!("Shrink test: {}", violation);
+                self.shrink_violations.push((iteration, violation));
+            }
+        }
+
+        if !self.params.chaos.is_empty() {
+            let violations = self.chain_violations.count() + self.shrink_violations.count() -
+                violations_before;
+            self.chaos_epochs.record(
+                membership_changes,
+                &stats,
+                num_faults,
+                violations,
+            );
+        }
+
+        if let Some(ref mut event_trace) = self.event_trace {
+            event_trace.flush();
+        }
+    }
+
+    // Random change of the chaos mode for the current iteration, if any. Values
+    // that would make the network unable to grow (see
+    // `Params::growth_dead_ends`) are skipped.
+    fn draw_chaos_setting(&mut self) -> Option<Setting> {
+        let rng = self.chaos_rng.as_mut()?;
+        let interval = cmp::max(self.params.chaos_interval, 1);
+        if rng.gen::<f64>() > 1.0 / interval as f64 {
+            return None;
+        }
+
+        let index = rng.gen_range(0, self.params.chaos.len());
+        let setting = self.params.chaos[index].draw(rng);
+
+        let mut params = self.params.clone();
+        params.apply(setting);
+        let dead_ends: Vec<_> = params
+            .growth_dead_ends()
+            .into_iter()
+            .filter(|dead_end| !self.params.growth_dead_ends().contains(dead_end))
+            .collect();
+        if !dead_ends.is_empty() {
+            info!("Chaos: skipping {}: {}", setting, dead_ends.join("; "));
+            return None;
+        }
+
+        Some(setting)
+    }
+
+    // Current values of the chaos mode parameters.
+    fn chaos_settings(&self) -> String {
+        let settings = self.params.settings();
+        self.params
+            .chaos
+            .iter()
+            .filter_map(|range| {
+                settings.iter().find(|setting| setting.name() == range.name())
+            })
+            .map(Setting::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Failure that requires stopping the simulation, if any occurred.
+    pub fn termination(&self) -> Option<&Termination> {
+        self.termination.as_ref()
+    }
+
+    /// Metrics between the chaos mode changes.
+    pub fn chaos_epochs(&self) -> &ChaosEpochs {
+        &self.chaos_epochs
+    }
+
+    /// Parameter changes applied so far, in order.
+    pub fn param_changes(&self) -> &Records<ParamChange> {
+        &self.param_changes
+    }
+
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Returns the section responsible for the given name.
+    pub fn section_for(&self, name: Name) -> Option<&Section> {
+        // The responsible section is the only one at distance zero.
+        self.k_closest_sections(name, 1).pop()
+    }
+
+    /// Returns up to `k` sections closest to the given name, by XOR distance
+    /// between the name and the closest address covered by the section.
+    pub fn k_closest_sections(&self, name: Name, k: usize) -> Vec<&Section> {
+        let mut sections = Vec::new();
+        let mut distance = Some(0);
+
+        // The sections don't overlap, so each covers its own range of distances
+        // and the walk takes one lookup per section instead of sorting them all.
+        while let Some(current) = distance {
+            if sections.len() >= k {
+                break;
+            }
+
+            let section = match prefix_for(&self.sections, Name(name.0 ^ current)) {
+                Some(prefix) => &self.sections[&prefix],
+                None => break,
+            };
+            sections.push(section);
+
+            // The section covers the distances sharing their first bits with
+            // the current one.
+            distance = Prefix::new(section.prefix().len(), Name(current))
+                .substituted_in(Name(!0))
+                .0
+                .checked_add(1);
+        }
+
+        sections
+    }
+
+    /// Returns all nodes whose names match the given prefix.
+    pub fn nodes_matching(&self, prefix: Prefix) -> Vec<&Node> {
+        let mut nodes = Vec::new();
+        let mut next = Some(prefix.substituted_in(Name(0)));
+
+        // Visit only the sections covering the names of the prefix, in order.
+        while let Some(name) = next.filter(|&name| prefix.matches(name)) {
+            let section = match self.section_for(name) {
+                Some(section) => section,
+                None => break,
+            };
+            nodes.extend(section.nodes().values().filter(|node| prefix.matches(node.name())));
+
+            next = section.prefix().substituted_in(Name(!0)).0.checked_add(1).map(Name);
+        }
+
+        nodes
+    }
+
+    pub fn section_lifetime_aggregator(&self) -> Aggregator {
+        Aggregator::new(self.lineage.lifetimes())
+    }
+
+    /// Number of distinct ancestor sections (see `Lineage::num_ancestors`) of
+    /// the current sections.
+    pub fn ancestors_aggregator(&self) -> Aggregator {
+        Aggregator::new(self.sections.values().map(|section| {
+            self.lineage.num_ancestors(section.id()) as u64
+        }))
+    }
+
+    /// Returns whether the shrink test collapsed the network back to the root
+    /// section.
+    pub fn is_collapsed(&self) -> bool {
+        self.shrinking && self.sections.len() == 1 && self.sections.contains_key(&Prefix::EMPTY)
+    }
+
+    pub fn shrink_merges(&self) -> &[(u64, Prefix, usize)] {
+        &self.shrink_merges
+    }
+
+    /// Recoverable faults that occurred (see `SimError`).
+    pub fn faults(&self) -> &Records<(u64, Option<Prefix>, SimError)> {
+        &self.faults
+    }
+
+    pub fn disasters(&self) -> &[(u64, VictimSelector, usize)] {
+        &self.disasters
+    }
+
+    pub fn shrink_violations(&self) -> &Records<(u64, String)> {
+        &self.shrink_violations
+    }
+
+    pub fn chain_violations(&self) -> &Records<(u64, Prefix, String)> {
+        &self.chain_violations
+    }
+
+    /// Returns up to `n` section prefixes with the most churn events over all
+    /// the sections that ever had them, together with the churn of each of
+    /// those sections (ordered by id).
+    pub fn most_churned(&self, n: usize) -> Vec<(Prefix, Vec<(SectionId, Churn)>)> {
+        let mut by_prefix: BTreeMap<Prefix, Vec<(SectionId, Churn)>> = BTreeMap::new();
+
+        let live = self.sections
+            .values()
+            .map(|section| (section.id(), section.prefix(), section.churn()));
+        for (id, prefix, churn) in self.ended_churn.iter().cloned().chain(live) {
+            by_prefix.entry(prefix).or_default().push((id, churn));
+        }
+
+        let mut result: Vec<_> = by_prefix.into_iter().collect();
+        for &mut (_, ref mut history) in &mut result {
+            history.sort_by_key(|&(id, _)| id);
+        }
+        result.sort_by_key(|&(prefix, ref history)| {
+            let total: u64 = history.iter().map(|&(_, churn)| churn.total()).sum();
+            (cmp::Reverse(total), prefix)
+        });
+        result.truncate(n);
+        result
+    }
+
+    pub fn lineage(&self) -> &Lineage {
+        &self.lineage
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn relocation_trace(&self) -> &RelocationTrace {
+        &self.relocation_trace
+    }
+
+    pub fn provenance(&self) -> &ProvenanceTable {
+        &self.provenance
+    }
+
+    /// Returns a section that has more than `max_section_size` nodes, if any.
+    pub fn oversized_section(&self) -> Option<&Section> {
+        self.sections
+            .values()
+            .filter(|section| section.nodes().len() > self.params.max_section_size)
+            .min_by_key(|section| section.prefix())
+    }
+
+    pub fn num_complete_sections(&self) -> u64 {
+        self.sections
+            .values()
+            .filter(|section| section.is_complete(&self.params))
+            .count() as u64
+    }
+
+    /// Digest of the canonical network state: the sections sorted by prefix,
+    /// with their nodes and pending relocations. Two runs diverge at the first
+    /// iteration their state hashes differ.
+    pub fn state_hash(&self) -> Hash {
+        let mut sections: Vec<_> = self.sections.values().collect();
+        sections.sort_by_key(|section| section.prefix());
+
+        let mut bytes = Vec::new();
+        for section in sections {
+            section.write_state(&mut bytes);
+        }
+
+        Hash::digest(&bytes)
+    }
+
+    pub fn age_distribution(&self) -> Distribution {
+        Distribution::new(
+            self.sections
+                .values()
+                .flat_map(|section| section.nodes().values())
+                .map(|node| u64::from(node.age())),
+        )
+    }
+
+    pub fn elder_age_distribution(&self) -> Distribution {
+        Distribution::new(
+            self.sections
+                .values()
+                .flat_map(|section| section.nodes().values())
+                .filter(|node| node.is_elder())
+                .map(|node| u64::from(node.age())),
+        )
+    }
+
+    /// Number of nodes and their total credits, per age.
+    pub fn credits_by_age(&self) -> BTreeMap<Age, (u64, u64)> {
+        let mut result = BTreeMap::new();
+
+        let nodes = self.sections.values().flat_map(
+            |section| section.nodes().values(),
+        );
+
+        for node in nodes {
+            let entry = result.entry(node.age()).or_insert((0, 0));
+            entry.0 += 1;
+            entry.1 += node.credits();
+        }
+
+        result
+    }
+
+    /// Age distributions of the nodes, grouped by the prefix length of their
+    /// section.
+    pub fn age_distribution_by_prefix_len(&self) -> GroupedDistribution {
+        GroupedDistribution::new(self.sections.values().flat_map(|section| {
+            let len = u64::from(section.prefix().len());
+            section.nodes().values().map(
+                move |node| (len, u64::from(node.age())),
+            )
+        }))
+    }
+
+    pub fn age_aggregator(&self) -> Aggregator {
+        Aggregator::new(
+            self.sections
+                .values()
+                .flat_map(|section| section.nodes().values())
+                .map(|node| u64::from(node.age())),
+        )
+    }
+
+    /// Standard deviation of the average node age of the sections: the better
+    /// the relocations mix the ages across the network, the lower.
+    pub fn section_age_spread(&self) -> f64 {
+        let avg_ages: Vec<_> = self.sections
+            .values()
+            .filter(|section| !section.nodes().is_empty())
+            .map(|section| {
+                let total: u64 = section.nodes().values().map(|node| u64::from(node.age())).sum();
+                total as f64 / section.nodes().len() as f64
+            })
+            .collect();
+        if avg_ages.is_empty() {
+            return 0.0;
+        }
+
+        let avg = avg_ages.iter().sum::<f64>() / avg_ages.len() as f64;
+        let variance = avg_ages.iter().map(|age| (age - avg) * (age - avg)).sum::<f64>() /
+            avg_ages.len() as f64;
+        variance.sqrt()
+    }
+
+    /// Number of nodes of every section, by prefix.
+    pub fn section_sizes(&self) -> BTreeMap<Prefix, usize> {
+        self.sections
+            .iter()
+            .map(|(&prefix, section)| (prefix, section.nodes().len()))
+            .collect()
+    }
+
+    pub fn section_size_distribution(&self) -> Distribution {
+        Distribution::new(self.sections.values().map(
+            |section| section.nodes().len() as u64,
+        ))
+    }
+
+    pub fn section_size_aggregator(&self) -> Aggregator {
+        Aggregator::new(self.sections.values().map(
+            |section| section.nodes().len() as u64,
+        ))
+    }
+
+    /// Standard deviation of the section sizes relative to their average: zero
+    /// when all sections have the same size, larger the more unbalanced the
+    /// growth of the network is.
+    pub fn section_size_spread(&self) -> f64 {
+        let num_sections = self.sections.len() as f64;
+        let sizes = self.sections.values().map(|section| section.nodes().len() as f64);
+        let avg = sizes.clone().sum::<f64>() / num_sections;
+        if avg == 0.0 {
+            return 0.0;
+        }
+
+        let variance = sizes.map(|size| (size - avg) * (size - avg)).sum::<f64>() / num_sections;
+        variance.sqrt() / avg
+    }
+
+    /// Aggregated total node size of all sections.
+    pub fn section_capacity_aggregator(&self) -> Aggregator {
+        Aggregator::new(self.sections.values().map(|section| section.capacity()))
+    }
+
+    pub fn node_size_distribution(&self) -> Distribution {
+        Distribution::new(
+            self.sections
+                .values()
+                .flat_map(|section| section.nodes().values())
+                .map(|node| node.size()),
+        )
+    }
+
+    /// Average number of times the nodes in the network were relocated, i.e.
+    /// how fast they age.
+    pub fn relocations_per_node(&self) -> f64 {
+        let (count, relocations) = self.sections
+            .values()
+            .flat_map(|section| section.nodes().values())
+            .fold((0, 0), |(count, relocations), node| {
+                (count + 1, relocations + node.relocations())
+            });
+        relocations as f64 / cmp::max(count, 1) as f64
+    }
+
+    /// Age distribution and total number of relocations of the malicious (or
+    /// honest) nodes.
+    pub fn stats_by_allegiance(&self, malicious: bool) -> (Distribution, u64) {
+        let nodes: Vec<_> = self.sections
+            .values()
+            .flat_map(|section| section.nodes().values())
+            .filter(|node| node.is_malicious() == malicious)
+            .collect();
+        let ages = Distribution::new(nodes.iter().map(|node| u64::from(node.age())));
+        let relocations = nodes.iter().map(|node| node.relocations()).sum();
+
+        (ages, relocations)
+    }
+
+    /// Number of sections controlled by the attacker.
+    pub fn num_malicious_sections(&self) -> usize {
+        self.sections
+            .values()
+            .filter(|section| section.is_malicious(&self.params))
+            .count()
+    }
+
+    /// Aggregated name entropy of all sections, in percent.
+    pub fn name_entropy_aggregator(&self) -> Aggregator {
+        Aggregator::new(self.sections.values().map(|section| {
+            (section.name_entropy(self.params.entropy_bits) * 100.0).round() as u64
+        }))
+    }
+
+    /// Node events by node origin, together with the age distribution of the
+    /// nodes of that origin currently in the network.
+    pub fn stats_by_origin(&self) -> BTreeMap<Origin, (OriginCounts, Distribution)> {
+        let mut ages: BTreeMap<Origin, Vec<u64>> = BTreeMap::new();
+        for node in self.sections.values().flat_map(|section| section.nodes().values()) {
+            ages.entry(node.origin()).or_default().push(u64::from(node.age()));
+        }
+
+        [Origin::Fresh, Origin::Rejoined, Origin::Relocated]
+            .iter()
+            .map(|&origin| {
+                let counts = self.origin_counts.get(&origin).cloned().unwrap_or_default();
+                let ages = ages.remove(&origin).unwrap_or_default();
+                (origin, (counts, Distribution::new(ages)))
+            })
+            .collect()
+    }
+
+    /// Number of iterations sections ended in each state.
+    pub fn state_ticks(&self) -> &BTreeMap<State, u64> {
+        &self.state_ticks
+    }
+
+    /// Ageing check outcomes by node age.
+    pub fn ageing_counts(&self) -> &BTreeMap<Age, AgeingCounts> {
+        &self.ageing_counts
+    }
+
+    /// Responses to relocation requests by the prefix length of the
+    /// destination.
+    pub fn relocation_responses(&self) -> &BTreeMap<u8, RelocationResponses> {
+        &self.relocation_responses
+    }
+
+    /// How the neighbour information the sections acted on differed from the
+    /// actual state of the network.
+    pub fn stale_views(&self) -> &StaleViews {
+        &self.stale_views
+    }
+
+    /// Iterations the nodes of each operator ended with an elder seat.
+    pub fn elder_rotation(&self) -> &ElderRotation {
+        &self.elder_rotation
+    }
+
+    pub fn elder_gap_distribution(&self) -> &Distribution {
+        &self.elder_gaps
+    }
+
+    pub fn completion_gap_distribution(&self) -> &Distribution {
+        &self.completion_gaps
+    }
+
+    /// Averages over the iterations, by number of sections at their end.
+    pub fn eras(&self) -> &Eras {
+        &self.eras
+    }
+
+    /// Number of relocation requests deferred by the global cap.
+    pub fn deferred_relocations(&self) -> u64 {
+        self.relocation_queue.deferred
+    }
+
+    pub fn relocation_queue_depth_distribution(&self) -> &Distribution {
+        &self.relocation_queue_depths
+    }
+
+    pub fn relocation_delay_distribution(&self) -> &Distribution {
+        &self.relocation_delays
+    }
+
+    /// Headline outcomes of the run so far, for its score.
+    pub fn outcome(&self) -> Outcome {
+        let churn = self.total_churn();
+
+        // Nodes joining or dropping, not counting the relocated ones.
+        let joins = churn.joins - churn.relocations_in;
+        let changes = cmp::max(joins + churn.drops, 1) as f64;
+        let summary = self.stats.summary();
+
+        Outcome {
+            completeness: self.eras.completeness(),
+            relocation_cost: summary.relocations() as f64 / changes,
+            rejection_rate: summary.rejections() as f64 /
+                cmp::max(summary.rejections() + joins, 1) as f64,
+            elder_churn: churn.elder_changes as f64 / changes,
+        }
+    }
+
+    // Churn of all the sections so far, ended or live.
+    fn total_churn(&self) -> Churn {
+        let mut churn = self.ended_churn_total;
+        for section in self.sections.values() {
+            churn += section.churn();
+        }
+        churn
+    }
+
+    /// Merges and elder changes within the windows of the network upgrades
+    /// (see `Params::upgrade_window`), and in the other iterations.
+    pub fn upgrade_churn(&self) -> (IterationChurn, IterationChurn) {
+        (self.upgrade_churn, self.background_churn)
+    }
+
+    pub fn tick_churn_distribution(&self) -> &Distribution {
+        &self.tick_churn
+    }
+
+    /// The largest number of nodes added to or removed from a section in a
+    /// single tick, with the iteration and the section.
+    pub fn max_tick_churn(&self) -> Option<(u64, u64, Prefix)> {
+        self.max_tick_churn
+    }
+
+    pub fn commit_imbalance_distribution(&self) -> &Distribution {
+        &self.commit_imbalances
+    }
+
+    /// Distribution of the size of the larger of each pair of sibling sections
+    /// in percent of the smaller one, over the iterations.
+    pub fn sibling_size_ratio_distribution(&self) -> &Distribution {
+        &self.sibling_size_ratios
+    }
+
+    /// Distribution of the section ticks from the first request of each
+    /// relocation (including its retries) to its commit.
+    pub fn relocation_latency_distribution(&self) -> &Distribution {
+        &self.relocation_latencies
+    }
+
+    /// Outcomes of the split audit, in the order of the splits.
+    pub fn split_audits(&self) -> &[SplitAudit] {
+        &self.split_audits
+    }
+
+    /// Number of merges whose elders were checked against the pre-merge
+    /// membership, and the checks that failed.
+    pub fn merge_elder_checks(&self) -> (u64, &Records<MergeElderMismatch>) {
+        (self.merge_elder_checks, &self.merge_elder_mismatches)
+    }
+
+    /// Wall-clock time spent merging sections (zero unless `Params::timing`).
+    pub fn merge_time(&self) -> Duration {
+        self.merge_time
+    }
+
+    pub fn split_audit_elder_distribution(&self) -> Distribution {
+        Distribution::new(self.split_audits.iter().map(|audit| audit.elders as u64))
+    }
+
+    /// Distribution of the ages at which the nodes first became elders.
+    pub fn first_promotion_age_distribution(&self) -> &Distribution {
+        &self.first_promotion_ages
+    }
+
+    pub fn section_relocation_intervals(&self) -> &Distribution {
+        &self.section_relocation_intervals
+    }
+
+    pub fn node_relocation_intervals_by_age(&self) -> &GroupedDistribution {
+        &self.node_relocation_intervals
+    }
+
+    pub fn hop_distribution(&self) -> &Distribution {
+        &self.hops
+    }
+
+    pub fn latency_distribution(&self) -> &Distribution {
+        &self.latencies
+    }
+
+    pub fn merge_dips_aggregator(&self) -> Aggregator {
+        self.merge_dips.summary()
+    }
+
+    pub fn merge_replicas_distribution(&self) -> &Distribution {
+        &self.merge_replicas
+    }
+
+    pub fn prefix_len_aggregator(&self) -> Aggregator {
+        Aggregator::new(self.sections.keys().map(|prefix| u64::from(prefix.len())))
+    }
+
+    // Start a network upgrade wave: pick a fraction of nodes (older nodes are
+    // more likely to be picked) and schedule their restart within the upgrade
+    // window.
+    // Whether the iteration falls within the window of the latest network
+    // upgrade, over which its restarts are spread.
+    fn is_upgrading(&self, iteration: u64) -> bool {
+        let interval = self.params.upgrade_interval;
+        iteration >= interval && iteration % interval < cmp::max(self.params.upgrade_window, 1)
+    }
+
+    fn schedule_upgrade(&mut self, iteration: u64) {
+        let avg_age = self.age_aggregator().avg;
+        if avg_age <= 0.0 {
+            return;
+        }
+
+        let mut count = 0;
+
+        let nodes = self.sections.values().flat_map(
+            |section| section.nodes().values(),
+        );
+
+        for node in nodes {
+            let probability = self.params.upgrade_fraction * f64::from(node.age()) / avg_age;
+            if !random::gen_bool_with_probability(probability) {
+                continue;
+            }
+
+            let delay = random::gen::<u64>() % self.params.upgrade_window.max(1);
+            self.scheduled_restarts
+                .entry(iteration + delay)
+                .or_default()
+                .push(node.name());
+            count += 1;
+        }
+
+        info!("Network upgrade: {} nodes scheduled to restart", count);
+    }
+
+    fn restart_nodes(
+        &mut self,
+        iteration: u64,
+        policies: &Policies,
+        stats: &mut Counters,
+    ) -> Vec<Action> {
+        let mut actions = Vec::new();
+        let names = self.scheduled_restarts.remove(&iteration).unwrap_or_default();
+
+        for name in names {
+            let section = if let Some(section) = section_for_mut(&mut self.sections, name) {
+                section
+            } else {
+                continue;
+            };
+
+            // The node might have been dropped or relocated in the meantime.
+            let (rejoined, elder) = if let Some(node) = section.nodes().get(&name) {
+                (node.rejoined(&self.params), node.is_elder())
+            } else {
+                continue;
+            };
+
+            stats.restarts += 1;
+            if elder {
+                stats.elder_restarts += 1;
+            }
+
+            let _context = log::enter_section(section.prefix());
+            actions.extend(section.disconnect(&self.params, policies, name));
+            self.rejoins.push(rejoined);
+        }
+
+        actions
+    }
+
+    // Let as many new nodes as there are sections contact a random existing node
+    // each and join its section. A section that already took a node this
+    // iteration turns the others away.
+    fn contact_joins(&mut self, policies: &Policies) -> Vec<Action> {
+        let mut actions = Vec::new();
+        if self.params.join_model != JoinModel::Contact || self.params.has_churn_trace() {
+            return actions;
+        }
+
+        let _category = random::category(Category::Joins);
+
+        let mut sizes: Vec<_> = self.sections
+            .values()
+            .map(|section| (section.prefix(), section.nodes().len()))
+            .collect();
+        sizes.sort();
+        let num_nodes: usize = sizes.iter().map(|&(_, size)| size).sum();
+
+        for _ in 0..sizes.len() {
+            // Before anyone joined, the first node contacts the root section.
+            let prefix = if num_nodes == 0 {
+                sizes[0].0
+            } else {
+                let mut index = random::gen_range(0, num_nodes);
+                sizes
+                    .iter()
+                    .find(|&&(_, size)| if index < size {
+                        true
+                    } else {
+                        index -= size;
+                        false
+                    })
+                    .map(|&(prefix, _)| prefix)
+                    .unwrap()
+            };
+
+            if let Some(section) = self.sections.get_mut(&prefix) {
+                let _context = log::enter_section(prefix);
+                actions.extend(section.contact_join(&self.params, policies));
+            }
+        }
+
+        actions
+    }
+
+    // Join and drop the nodes as the churn trace says.
+    fn apply_trace(&mut self, events: Vec<TraceEvent>, policies: &Policies) -> Vec<Action> {
+        let mut actions = Vec::new();
+        // Names of the nodes by id, indexed on the first leave. They don't
+        // change before the sections handle their messages, after this.
+        let mut names: Option<HashMap<NodeId, Name>> = None;
+
+        for event in events {
+            match event {
+                TraceEvent::Join(index) => {
+                    let id = self.node_ids.allocate();
+                    let mut node = Node::new(id, random::gen_name(), self.params.init_age);
+                    let _category = random::category(Category::Joins);
+                    if self.params.malicious_fraction > 0.0 {
+                        node.set_malicious(
+                            random::gen_bool_with_probability(self.params.malicious_fraction),
+                        );
+                    }
+                    if self.params.operators > 0 {
+                        node.set_operator(Some(random::gen_range(0, self.params.operators)));
+                    }
+                    let _ = self.trace_nodes.insert(index, id);
+                    let name = node.name();
+                    if let Some(section) = section_for_mut(&mut self.sections, name) {
+                        let _context = log::enter_section(section.prefix());
+                        actions.extend(section.join(&self.params, policies, node));
+
+                        // Unless the section rejected it.
+                        let joined = section.nodes().get(&name).map(|node| node.id()) == Some(id);
+                        if let (true, Some(names)) = (joined, names.as_mut()) {
+                            let _ = names.insert(id, name);
+                        }
+                    }
+                }
+                TraceEvent::Leave(index) => {
+                    let id = match self.trace_nodes.remove(&index) {
+                        Some(id) => id,
+                        None => continue,
+                    };
+
+                    // The node might have been relocated (so it has different name
+                    // now) or rejected.
+                    let sections = &self.sections;
+                    let names = names.get_or_insert_with(|| {
+                        sections
+                            .values()
+                            .flat_map(|section| section.nodes().values())
+                            .map(|node| (node.id(), node.name()))
+                            .collect()
+                    });
+                    if let Some(name) = names.remove(&id) {
+                        if let Some(section) = section_for_mut(&mut self.sections, name) {
+                            let _context = log::enter_section(section.prefix());
+                            actions.extend(section.disconnect(&self.params, policies, name));
+                        }
+                    }
+                }
+            }
+        }
+
+        actions
+    }
+
+    // Remove the victims of the disasters scheduled for the iteration.
+    fn apply_disasters(&mut self, iteration: u64, policies: &Policies) -> Vec<Action> {
+        let mut actions = Vec::new();
+
+        for disaster in &self.params.disasters {
+            if disaster.iteration != iteration {
+                continue;
+            }
+
+            let victims = if let VictimSelector::Prefix(prefix) = disaster.victims {
+                disaster.victims.select(self.nodes_matching(prefix), disaster.count)
+            } else {
+                disaster.victims.select(
+                    self.sections.values().flat_map(|section| section.nodes().values()),
+                    disaster.count,
+                )
+            };
+            info!(
+                "Disaster: removing {} nodes ({})",
+                victims.len(),
+                disaster.victims
+            );
+            self.disasters.push((iteration, disaster.victims, victims.len()));
+
+            for name in victims {
+                if let Some(section) = section_for_mut(&mut self.sections, name) {
+                    let _context = log::enter_section(section.prefix());
+                    actions.extend(section.disconnect(&self.params, policies, name));
+                }
+            }
+        }
+
+        actions
+    }
+
+    fn rejoin_nodes(&mut self, policies: &Policies) -> Vec<Action> {
+        let mut actions = Vec::new();
+
+        for node in mem::take(&mut self.rejoins) {
+            let name = node.name();
+            if let Some(section) = section_for_mut(&mut self.sections, name) {
+                let _context = log::enter_section(section.prefix());
+                actions.extend(section.join(&self.params, policies, node));
+            }
+        }
+
+        actions
+    }
+
+    fn handle_actions(
+        &mut self,
+        iteration: u64,
+        actions: &mut Vec<Action>,
+        faults: &mut Vec<(Option<Prefix>, SimError)>,
+    ) -> Counters {
+        let mut stats = Counters::default();
+
+        message::prepare_actions(actions);
+
+        for action in actions.drain(..) {
+            if let Some(ref mut event_trace) = self.event_trace {
+                event_trace.action(iteration, &action);
+            }
+
+            match action {
+                Action::Reject(_) => {
+                    stats.rejections += 1;
+                }
+                Action::Merge(target) => {
+                    let sources: Vec<_> = self.sections
+                        .keys()
+                        .filter(|prefix| prefix.is_descendant(&target))
+                        .cloned()
+                        .collect();
+
+                    if sources.is_empty() {
+                        // Duplicate merges are removed by `prepare_actions`, but
+                        // merges can still be nested: when `Merge(P)` and `Merge(P1)`
+                        // are emitted in the same batch, `Merge(P)` is handled first
+                        // (shorter prefixes sort first) and takes all descendants of
+                        // P1 with it. That's why not finding any pre-merge section is
+                        // not an error and can be safely ignored.
+                        debug!(
+                            "Pre-merge sections not found (to be merged to {})",
+                            log::prefix(&target)
+                        );
+                        continue;
+                    }
+
+                    let start = if self.params.timing {
+                        Some(Instant::now())
+                    } else {
+                        None
+                    };
+                    let sources: Vec<_> = sources
+                        .into_iter()
+                        .map(|source| self.sections.remove(&source).unwrap())
+                        .collect();
+
+                    stats.merges += 1;
+
+                    if self.shrinking {
+                        self.shrink_merges.push((iteration, target, sources.len()));
+                    }
+
+                    if self.params.merge_chunk_samples > 0 {
+                        let (dip, replicas) = merge_availability(&self.params, target, &sources);
+                        self.merge_dips.insert(dip);
+                        self.merge_replicas.insert(replicas);
+                    }
+
+                    // Snapshot of the pre-merge membership, to check the elders of
+                    // the merged section against.
+                    let mut members: Vec<_> = sources
+                        .iter()
+                        .chain(self.sections.get(&target))
+                        .flat_map(|section| section.nodes().values())
+                        .map(|node| (node.name(), node.age()))
+                        .collect();
+                    members.sort();
+
+                    let parents = sources.iter().map(|source| source.id()).collect();
+                    for source in &sources {
+                        self.lineage.end(source.id(), iteration);
+                        self.end_churn(source);
+                    }
+
+                    let lineage = &mut self.lineage;
+                    let node_ids = &self.node_ids;
+                    let section = self.sections.entry(target).or_insert_with(|| {
+                        let id = lineage.create(target, parents, iteration);
+                        Section::new(id, target, node_ids.clone())
+                    });
+                    for source in sources {
+                        let _context = log::enter_section(target);
+                        section.merge(&self.params, source);
+                    }
+
+                    self.merge_elder_checks += 1;
+                    if let Some(mismatch) =
+                        check_merge_elders(&self.params, iteration, &members, section)
+                    {
+                        debug!(
+                            "Unexpected elders after merge into {}: missing {:?}, extra {:?}",
+                            log::prefix(&target),
+                            mismatch.missing,
+                            mismatch.extra
+                        );
+                        self.merge_elder_mismatches.push(mismatch);
+                    }
+                    if let Some(start) = start {
+                        self.merge_time += start.elapsed();
+                    }
+
+                    // The pre-merge sections covered exactly the target, unless an
+                    // ancestor of it exists too.
+                    let mut ancestor = target;
+                    while ancestor != Prefix::EMPTY {
+                        ancestor = ancestor.shorten();
+                        if self.sections.contains_key(&ancestor) {
+                            let violation =
+                                format!("sections {} and {} overlap", ancestor, target);
+                            self.abort_on_partition_violations(iteration, vec![violation]);
+                            break;
+                        }
+                    }
+
+                    if self.params.paranoid {
+                        let violations = self.check_prefixes();
+                        self.abort_on_partition_violations(iteration, violations);
+                    }
+                }
+                Action::Split(source) => {
+                    stats.splits += 1;
+
+                    let source = if let Some(section) = self.sections.remove(&source) {
+                        section
+                    } else {
+                        // Splits are handled before merges, splits superseded by a
+                        // merge of an ancestor and duplicate splits are removed by
+                        // `prepare_actions`, so this only happens for forced splits
+                        // of a prefix that has no section. Ignore it, rather than
+                        // failing the whole simulation.
+                        debug!("Pre-split section {} not found", log::prefix(&source));
+                        continue;
+                    };
+
+                    if self.params.split_audit_fraction > 0.0 {
+                        self.split_audits.push(SplitAudit {
+                            iteration,
+                            prefix: source.prefix(),
+                            elders: source.view().worst_case_split_elders(
+                                &self.params,
+                                self.params.split_audit_fraction,
+                            ),
+                        });
+                    }
+
+                    let prefixes = source.prefix().split();
+                    let id0 = self.lineage.create(prefixes[0], vec![source.id()], iteration);
+                    let id1 = self.lineage.create(prefixes[1], vec![source.id()], iteration);
+                    self.lineage.end(source.id(), iteration);
+                    self.end_churn(&source);
+
+                    let (target0, target1) = {
+                        let _context = log::enter_section(source.prefix());
+                        source.split(&self.params, id0, id1)
+                    };
+                    let prefix0 = target0.prefix();
+                    let prefix1 = target1.prefix();
+
+                    assert!(
+                        self.sections.insert(prefix0, target0).is_none(),
+                        "section with prefix [{}] already exists",
+                        prefix0
+                    );
+                    assert!(
+                        self.sections.insert(prefix1, target1).is_none(),
+                        "section with prefix [{}] already exists",
+                        prefix1
+                    );
+
+                    // Replacing an existing section with its two halves keeps the
+                    // partition intact, so only the paranoid mode checks it.
+                    if self.params.paranoid {
+                        let violations = self.check_prefixes();
+                        self.abort_on_partition_violations(iteration, violations);
+                    }
+                }
+                Action::Send(mut message) => {
+                    if let Message::RelocateCommit { ref mut node, .. } = message {
+                        self.record_relocation_interval(iteration, node);
+                    }
+
+                    if self.params.relocation_trace.is_some() {
+                        self.relocation_trace.sent(iteration, &message, &self.sections);
+                    }
+
+                    if self.params.view_lag > 0 && message.is_neighbour_info() {
+                        self.delayed_views.push((iteration + self.params.view_lag, message));
+                        continue;
+                    }
+
+                    // A request still held back is withdrawn rather than
+                    // cancelled.
+                    if let Message::RelocateCancel { id, node_name, .. } = message {
+                        if self.relocation_queue.remove(id, node_name) {
+                            continue;
+                        }
+                    }
+
+                    if let Message::RelocateRequest { node_name, .. } = message {
+                        if self.params.global_relocation_cap > 0 {
+                            // Requests already waiting go first.
+                            if !self.relocation_queue.is_empty() ||
+                                self.relocation_requests_sent >=
+                                    self.params.global_relocation_cap
+                            {
+                                let source = prefix_for(&self.sections, node_name)
+                                    .unwrap_or(Prefix::EMPTY);
+                                self.relocation_queue.push(iteration, source, message);
+                                continue;
+                            }
+                            self.relocation_requests_sent += 1;
+                        }
+                    }
+
+                    if let Err(error) = self.send(message, &mut stats) {
+                        faults.push((None, error));
+                    }
+                }
+                Action::Abort(prefix, reason) => self.abort(iteration, Some(prefix), reason),
+                Action::Fault(prefix, error) => self.fault(iteration, Some(prefix), error),
+            }
+        }
+
+        stats
+    }
+
+    // Record the time since the previous relocation from the same section and
+    // since the previous relocation of the same node (keyed by the age the node
+    // had before this relocation).
+    fn record_relocation_interval(&mut self, iteration: u64, node: &mut Node) {
+        if let Some(prefix) = prefix_for(&self.sections, node.name()) {
+            let id = self.sections[&prefix].id();
+            if let Some(last) = self.last_section_relocations.insert(id, iteration) {
+                self.section_relocation_intervals.insert(iteration - last);
+            }
+        }
+
+        if let Some(last) = node.last_relocation() {
+            self.node_relocation_intervals.insert(
+                u64::from(node.age().saturating_sub(1)),
+                iteration - last,
+            );
+        }
+        node.set_last_relocation(iteration);
+    }
+
+    // Account the churn of a section which split or merged.
+    fn end_churn(&mut self, section: &Section) {
+        let _ = self.last_section_relocations.remove(&section.id());
+
+        let churn = section.churn();
+        self.ended_churn_total += churn;
+
+        if self.params.top_churned > 0 {
+            self.ended_churn.push((section.id(), section.prefix(), churn));
+        }
+    }
+
+    fn deliver(&mut self, message: Message, stats: &mut Counters) -> Result<(), SimError> {
+        if let Message::RelocateCancel { node_name, .. } = message {
+            let _category = random::category(Category::Messages);
+            if self.params.cancel_loss > 0.0 &&
+                random::gen_bool_with_probability(self.params.cancel_loss)
+            {
+                debug!("Relocation cancel of {} lost", log::name(&node_name));
+                return Ok(());
+            }
+        }
+
+        if let Message::RelocateCommit { ref node, target, cause, .. } = message {
+            self.check_neighbour_relocation(node.name(), target, cause);
+        }
+
+        let target = message.target();
+        if let Some(section) = section_for_mut(&mut self.sections, target) {
+            if let Message::RelocateRequest { .. } = message {
+                stats.relocation_requests += 1;
+            }
+
+            if let Message::RelocateReject { .. } = message {
+                stats.relocation_rejections += 1;
+            }
+
+            if let Message::JoinRedirect { .. } = message {
+                stats.join_redirects += 1;
+            }
+
+            if let Message::SiblingSize { source, .. } = message {
+                if section.prefix() != source.sibling() {
+                    self.stale_views.dropped_sizes += 1;
+                }
+            }
+
+            if let Message::RelocateCommit { cause, .. } = message {
+                stats.relocations += 1;
+
+                match cause {
+                    RelocationCause::Ageing => (),
+                    RelocationCause::TermLimit => stats.forced_relocations += 1,
+                    RelocationCause::Balancing => stats.balancing_relocations += 1,
+                    RelocationCause::Rescue => stats.rescue_relocations += 1,
+                }
+            }
+
+            section.receive(message);
+            Ok(())
+        } else {
+            Err(SimError::MissingSection(target))
+        }
+    }
+
+    // Check whether the rescue or balancing relocation still goes where it's
+    // needed.
+    fn check_neighbour_relocation(
+        &mut self,
+        node_name: Name,
+        target: Name,
+        cause: RelocationCause,
+    ) {
+        let destination = match self.section_for(target) {
+            Some(section) => section,
+            None => return,
+        };
+
+        match cause {
+            RelocationCause::Rescue => {
+                if !destination.needs_adults() {
+                    self.stale_views.wasted_rescues += 1;
+                }
+            }
+            RelocationCause::Balancing => {
+                // The source already let the node go.
+                let source_size = self.section_for(node_name)
+                    .map_or(0, |section| section.nodes().len() + 1);
+                if destination.nodes().len() >= source_size {
+                    self.stale_views.misdirected_balancing += 1;
+                }
+            }
+            RelocationCause::Ageing | RelocationCause::TermLimit => (),
+        }
+    }
+
+    // Deliver the sibling sizes and needs for adults held back until this
+    // iteration, comparing them with the current state of their sources.
+    fn deliver_delayed_views(
+        &mut self,
+        iteration: u64,
+        stats: &mut Counters,
+        faults: &mut Vec<(Option<Prefix>, SimError)>,
+    ) {
+        let (due, pending) = mem::take(&mut self.delayed_views)
+            .into_iter()
+            .partition(|&(delivery, _)| delivery <= iteration);
+        self.delayed_views = pending;
 
-        Network {
-            params,
-            stats: Stats::new(),
-            sections,
+        for (_, message) in due {
+            self.stale_views.delayed += 1;
+
+            let source = match message {
+                Message::SiblingSize { source, .. } | Message::NeedAdults { source, .. } => source,
+                _ => continue,
+            };
+            if let Some(section) = self.sections.get(&source) {
+                if let Message::SiblingSize { size, .. } = message {
+                    let actual = section.nodes().len();
+                    self.stale_views.size_error += cmp::max(size, actual) as u64 -
+                        cmp::min(size, actual) as u64;
+                }
+            } else {
+                self.stale_views.outdated += 1;
+            }
+
+            if let Err(error) = self.send(message, stats) {
+                faults.push((None, error));
+            }
         }
     }
 
-    /// Execute single iteration of the simulation.
-    pub fn tick(&mut self, iteration: u64) {
-        let mut actions = Vec::new();
-        let mut stats = TickStats::new();
+    // Send the relocation requests deferred by the global cap, as many as it
+    // allows in this iteration, taking turns between their source sections.
+    fn release_relocation_requests(
+        &mut self,
+        iteration: u64,
+        stats: &mut Counters,
+        faults: &mut Vec<(Option<Prefix>, SimError)>,
+    ) {
+        self.relocation_requests_sent = 0;
 
-        for section in self.sections.values_mut() {
-            section.prepare();
-        }
+        while self.relocation_requests_sent < self.params.global_relocation_cap {
+            let (queued, message) = match self.relocation_queue.pop() {
+                Some(entry) => entry,
+                None => break,
+            };
 
-        loop {
-            for section in self.sections.values_mut() {
-                actions.extend(section.tick(&self.params));
-            }
+            self.relocation_requests_sent += 1;
+            self.relocation_delays.insert(iteration - queued);
 
-            if actions.is_empty() {
-                break;
+            if let Err(error) = self.send(message, stats) {
+                faults.push((None, error));
             }
+        }
+    }
 
-            stats += self.handle_actions(&mut actions)
+    // Send the message: scheduled to arrive later in the events scheduler,
+    // routed or delivered directly.
+    fn send(&mut self, message: Message, stats: &mut Counters) -> Result<(), SimError> {
+        if self.params.scheduler == Scheduler::Events {
+            self.schedule(message);
+            Ok(())
+        } else if self.params.routing {
+            self.route(message, stats)
+        } else {
+            self.deliver(message, stats)
         }
+    }
 
-        self.stats.record(
-            iteration,
-            self.sections
-                .values()
-                .map(|section| section.nodes().len() as u64)
-                .sum(),
-            self.sections.len() as u64,
-            stats.merges,
-            stats.splits,
-            stats.relocations,
-            stats.rejections,
-        );
+    // Schedule the message to arrive after an exponentially distributed delay.
+    fn schedule(&mut self, message: Message) {
+        let delay = self.params.event_rates.message_delay;
+        let time = if delay > 0.0 {
+            let _category = random::category(Category::Messages);
+            self.now + random::gen_exponential(1.0 / delay)
+        } else {
+            self.now
+        };
 
-        self.validate();
+        self.scheduled_messages.push(ScheduledMessage {
+            time,
+            sequence: self.messages_scheduled,
+            message,
+        });
+        self.messages_scheduled += 1;
     }
 
-    pub fn stats(&self) -> &Stats {
-        &self.stats
+    /// Set the time of the join or leave of the current iteration and of the
+    /// next one, in the events scheduler. The messages arriving in between are
+    /// delivered in the iteration.
+    pub fn set_event_times(&mut self, time: f64, next: f64) {
+        self.now = time;
+        self.next_event = next;
     }
 
-    #[allow(unused)]
-    pub fn num_complete_sections(&self) -> u64 {
-        self.sections
-            .values()
-            .filter(|section| section.is_complete(&self.params))
-            .count() as u64
-    }
+    // Deliver the scheduled message arriving first, if it arrives before the
+    // next join or leave, and advance the time to its arrival. Returns whether
+    // a message was delivered.
+    fn deliver_scheduled(
+        &mut self,
+        stats: &mut Counters,
+        faults: &mut Vec<(Option<Prefix>, SimError)>,
+    ) -> bool {
+        let due = self.scheduled_messages.peek().is_some_and(|scheduled| {
+            scheduled.time < self.next_event
+        });
+        if !due {
+            return false;
+        }
 
-    pub fn age_distribution(&self) -> Distribution {
-        Distribution::new(
-            self.sections
-                .values()
-                .flat_map(|section| section.nodes().values())
-                .map(|node| u64::from(node.age())),
-        )
+        let scheduled = self.scheduled_messages.pop().unwrap();
+        self.now = scheduled.time;
+        if let Err(error) = self.deliver(scheduled.message, stats) {
+            faults.push((None, error));
+        }
+        true
     }
 
-    pub fn age_aggregator(&self) -> Aggregator {
-        Aggregator::new(
-            self.sections
-                .values()
-                .flat_map(|section| section.nodes().values())
-                .map(|node| u64::from(node.age())),
-        )
+    // Send the message hop by hop from its source section towards the target.
+    // Each hop takes `hop_delay` section ticks and is retried until not lost.
+    // Messages within a single section are delivered immediately.
+    fn route(&mut self, message: Message, stats: &mut Counters) -> Result<(), SimError> {
+        let hops = num_hops(&self.sections, message.source(), message.target());
+
+        let _category = random::category(Category::Messages);
+        let mut latency = 0;
+        for _ in 0..hops {
+            latency += self.params.hop_delay;
+            while random::gen_bool_with_probability(self.params.hop_loss) {
+                latency += self.params.hop_delay;
+            }
+        }
+
+        self.hops.insert(hops);
+        self.latencies.insert(latency);
+
+        if latency == 0 {
+            self.deliver(message, stats)
+        } else {
+            self.in_flight.push((latency, message));
+            Ok(())
+        }
     }
 
-    pub fn section_size_aggregator(&self) -> Aggregator {
-        Aggregator::new(self.sections.values().map(
-            |section| section.nodes().len() as u64,
-        ))
+    // Advance the routed messages by one section tick and deliver those which
+    // arrived.
+    fn advance_in_flight(
+        &mut self,
+        stats: &mut Counters,
+        faults: &mut Vec<(Option<Prefix>, SimError)>,
+    ) {
+        let mut arrived = Vec::new();
+
+        for (remaining, message) in mem::take(&mut self.in_flight) {
+            if remaining <= 1 {
+                arrived.push(message);
+            } else {
+                self.in_flight.push((remaining - 1, message));
+            }
+        }
+
+        for message in arrived {
+            if let Err(error) = self.deliver(message, stats) {
+                faults.push((None, error));
+            }
+        }
     }
 
-    pub fn prefix_len_aggregator(&self) -> Aggregator {
-        Aggregator::new(self.sections.keys().map(|prefix| u64::from(prefix.len())))
+    fn num_nodes(&self) -> u64 {
+        self.sections
+            .values()
+            .map(|section| section.nodes().len() as u64)
+            .sum()
     }
 
+    // Check that the section prefixes cover the whole name space without
+    // overlapping and that every node matches the prefix of its section.
+    // Returns the description of every violation found.
+    fn check_prefixes(&self) -> Vec<String> {
+        let mut violations = Vec::new();
 
-    fn handle_actions(&mut self, actions: &mut Vec<Action>) -> TickStats {
-        let mut stats = TickStats::new();
+        let mut prefixes: Vec<_> = self.sections.keys().cloned().collect();
+        prefixes.sort();
 
-        for action in actions.drain(..) {
-            match action {
-                Action::Reject(_) => {
-                    stats.rejections += 1;
+        for prefix in &prefixes {
+            let mut ancestor = *prefix;
+            while ancestor != Prefix::EMPTY {
+                ancestor = ancestor.shorten();
+                if self.sections.contains_key(&ancestor) {
+                    violations.push(format!("sections {} and {} overlap", ancestor, prefix));
                 }
-                Action::Merge(target) => {
-                    let sources: Vec<_> = self.sections
-                        .keys()
-                        .filter(|prefix| prefix.is_descendant(&target))
-                        .cloned()
-                        .collect();
+            }
+        }
 
-                    if sources.is_empty() {
-                        // Merge action with the same target can be potentially
-                        // emitted multiple times per tick.
-                        // This can happen for example when both pre-merge sections
-                        // lose a node in the same tick, triggering merge in both of
-                        // them. That's why not finding any pre-merge section is
-                        // not an error and can be safely ignored.
-                        debug!(
-                            "Pre-merge sections not found (to be merged to {})",
-                            log::prefix(&target)
-                        );
-                        continue;
-                    }
+        let coverage: u128 = prefixes
+            .iter()
+            .map(|prefix| 1u128 << (64 - prefix.len()))
+            .sum();
+        if coverage != 1u128 << 64 {
+            violations.push(format!(
+                "sections cover {:.6} of the name space",
+                coverage as f64 / (1u128 << 64) as f64
+            ));
+        }
 
-                    let sources: Vec<_> = sources
-                        .into_iter()
-                        .map(|source| self.sections.remove(&source).unwrap())
-                        .collect();
+        for prefix in &prefixes {
+            let section = &self.sections[prefix];
+            let strays = section
+                .nodes()
+                .keys()
+                .filter(|name| !prefix.matches(**name))
+                .count();
+            if strays > 0 {
+                violations.push(format!("section {} has {} foreign nodes", prefix, strays));
+            }
+        }
 
-                    stats.merges += 1;
+        violations
+    }
 
-                    let section = self.sections.entry(target).or_insert_with(
-                        || Section::new(target),
-                    );
-                    for source in sources {
-                        section.merge(&self.params, source);
-                    }
-                }
-                Action::Split(source) => {
-                    stats.splits += 1;
+    // Stop the simulation if the sections don't partition the name space,
+    // listing all the section prefixes.
+    fn abort_on_partition_violations(&mut self, iteration: u64, violations: Vec<String>) {
+        if violations.is_empty() {
+            return;
+        }
 
-                    let source = if let Some(section) = self.sections.remove(&source) {
-                        section
-                    } else {
-                        // This can happen for example in the following situation:
-                        // 1. Section P0 decides it needs to merge with P1.
-                        // 2. P1 gets new node (via join or relocation) which triggers
-                        //    a split.
-                        // 3. `Merge(P)` action is handled first, merging P0 and P1
-                        //    into P.
-                        // 4. `Split(P1)` action is handled next, but P1 is no longer there.
-                        //
-                        // This situation is valid, so it's OK to ignore the missing
-                        // sections here.
-                        //
-                        // On the other hand, this line should never be reached due to
-                        // `Split` being emitted more than once, because split can
-                        // only be triggered by join or relocation, and those happen
-                        // at most once per section tick.
-                        debug!("Pre-split section {} not found", log::prefix(&source));
-                        continue;
-                    };
+        let mut prefixes: Vec<_> = self.sections.keys().cloned().collect();
+        prefixes.sort();
+        let prefixes: Vec<_> = prefixes.iter().map(|prefix| format!("[{}]", prefix)).collect();
+        error!("Section prefixes: {}", prefixes.join(" "));
 
-                    let (target0, target1) = source.split(&self.params);
-                    let prefix0 = target0.prefix();
-                    let prefix1 = target1.prefix();
+        for violation in &violations {
+            error!("Partition violated: {}", violation);
+        }
 
-                    assert!(
-                        self.sections.insert(prefix0, target0).is_none(),
-                        "section with prefix [{}] already exists",
-                        prefix0
-                    );
-                    assert!(
-                        self.sections.insert(prefix1, target1).is_none(),
-                        "section with prefix [{}] already exists",
-                        prefix1
-                    );
-                }
-                Action::Send(message) => {
-                    let target = message.target();
-                    if let Some(section) = self.sections.values_mut().find(|section| {
-                        section.prefix().matches(target)
-                    })
-                    {
-                        if let Message::RelocateCommit { .. } = message {
-                            stats.relocations += 1;
-                        }
+        self.abort(iteration, None, Reason::InvalidState(violations.join("; ")));
+    }
 
-                        section.receive(message)
-                    } else {
-                        panic!("No section maching {:?} found", target)
-                    }
-                }
-            }
+    // Record the fault and carry on, or stop the simulation on it in the strict
+    // mode.
+    fn fault(&mut self, iteration: u64, section: Option<Prefix>, error: SimError) {
+        match section {
+            Some(prefix) => error!("{}: {}", log::prefix(&prefix), error),
+            None => error!("{}", error),
         }
 
-        stats
+        if self.params.strict {
+            self.abort(iteration, section, error.reason());
+        }
+        self.faults.push((iteration, section, error));
+    }
+
+    // Record the failure, unless an earlier one was already recorded.
+    fn abort(&mut self, iteration: u64, section: Option<Prefix>, reason: Reason) {
+        if self.termination.is_none() {
+            self.termination = Some(Termination {
+                reason,
+                iteration,
+                section,
+            });
+        }
     }
 
-    fn validate(&self) {
+    fn validate(&mut self, iteration: u64) {
+        let mut invalid = Vec::new();
+
+        // In the events scheduler, exchanges continue in later iterations
+        // while their messages are on their way.
+        let in_transit: BTreeSet<_> = self.scheduled_messages
+            .iter()
+            .filter_map(|scheduled| scheduled.message.node_name())
+            .collect();
+
         for section in self.sections.values() {
             if section.nodes().len() > self.params.max_section_size {
                 let prefixes = section.prefix().split();
@@ -233,50 +2285,596 @@ impl Network {
                 );
             }
 
-            let incoming = section.incoming_relocations();
-            if incoming.len() > 0 {
-                panic!(
-                    "{}: incoming relocation cache not cleared: {:?}",
-                    log::prefix(&section.prefix()),
-                    incoming,
-                )
+            // Accepted relocations must be committed or cancelled within the
+            // same iteration or, with a timeout, before they time out.
+            let incoming: Vec<_> = section
+                .incoming_relocations()
+                .filter(|&(_, relocation)| relocation.is_overdue(&self.params))
+                .map(|(name, _)| name)
+                .filter(|name| !in_transit.contains(*name))
+                .collect();
+            if !incoming.is_empty() {
+                invalid.push((
+                    section.prefix(),
+                    format!("incoming relocation cache not cleared: {:?}", incoming),
+                ));
             }
 
-            let outgoing = section.outgoing_relocations();
-            if outgoing.len() > 0 {
-                panic!(
-                    "{}: outgoing relocation cache not cleared: {:?}",
-                    log::prefix(&section.prefix()),
-                    outgoing,
-                )
+            // And so must the requested ones, except for those whose request
+            // the global cap still holds back.
+            let outgoing: Vec<_> = section
+                .outgoing_relocations()
+                .filter(|&(_, relocation)| relocation.is_overdue(&self.params))
+                .map(|(name, _)| name)
+                .filter(|name| {
+                    !self.relocation_queue.contains(**name) && !in_transit.contains(*name)
+                })
+                .collect();
+            if !outgoing.is_empty() {
+                invalid.push((
+                    section.prefix(),
+                    format!("outgoing relocation cache not cleared: {:?}", outgoing),
+                ));
+            }
+
+            if self.params.paranoid && !section.is_age_index_valid() {
+                invalid.push((section.prefix(), "age index out of sync".to_string()));
             }
         }
+
+        for (prefix, detail) in invalid {
+            error!("{}: {}", log::prefix(&prefix), detail);
+            self.abort(iteration, Some(prefix), Reason::InvalidState(detail));
+        }
     }
 }
 
-struct TickStats {
-    merges: u64,
-    splits: u64,
-    relocations: u64,
-    rejections: u64,
+// Check that the elders of the merged section are the `group_size` oldest of
+// the pre-merge `members` (sorted by name) at least `min_elder_age` old.
+// Members as old as the youngest expected elder are interchangeable with it.
+// The members are only names and ages, so no section needs to be copied.
+pub fn check_merge_elders(
+    params: &Params,
+    iteration: u64,
+    members: &[(Name, Age)],
+    section: &Section,
+) -> Option<MergeElderMismatch> {
+    let mut counts = [0usize; Age::MAX as usize + 1];
+    for &(_, age) in members {
+        counts[age as usize] += 1;
+    }
+
+    let mut expected = 0;
+    let mut youngest = Age::MAX;
+    for age in (params.min_elder_age..=Age::MAX).rev() {
+        if expected >= params.group_size {
+            break;
+        }
+        if counts[age as usize] > 0 {
+            expected = cmp::min(expected + counts[age as usize], params.group_size);
+            youngest = age;
+        }
+    }
+
+    let age_of = |name: Name| {
+        members
+            .binary_search_by_key(&name, |&(name, _)| name)
+            .ok()
+            .map(|index| members[index].1)
+    };
+
+    let mut missing = Vec::new();
+    let mut extra = Vec::new();
+    let mut actual = 0;
+    for node in section.nodes().values().filter(|node| node.is_elder()) {
+        actual += 1;
+        // Elders that weren't members at all are as wrong as young ones.
+        if age_of(node.name()).is_none_or(|age| age < youngest) {
+            extra.push(node.name());
+        }
+    }
+    for &(name, age) in members {
+        let elder = section.nodes().get(&name).is_some_and(|node| node.is_elder());
+        if age > youngest && !elder {
+            missing.push(name);
+        }
+    }
+
+    if missing.is_empty() && extra.is_empty() && actual == expected {
+        None
+    } else {
+        extra.sort();
+        Some(MergeElderMismatch {
+            iteration,
+            prefix: section.prefix(),
+            missing,
+            extra,
+            expected,
+            actual,
+        })
+    }
 }
 
-impl TickStats {
-    fn new() -> Self {
-        TickStats {
-            merges: 0,
-            splits: 0,
-            relocations: 0,
-            rejections: 0,
+// Sample random chunks in the merged section and check how many of them would
+// fall below the replication count if the nodes of the smallest pre-merge
+// section vanished at merge time. Each chunk is stored on the
+// `replication_count` nodes of the merged section closest to its name. Returns
+// the percentage of chunks below the replication count and the minimum number
+// of surviving replicas of any sampled chunk.
+fn merge_availability(params: &Params, target: Prefix, sources: &[Section]) -> (u64, u64) {
+    let lost = sources
+        .iter()
+        .min_by_key(|section| (section.nodes().len(), section.prefix()))
+        .unwrap()
+        .prefix();
+    let names: Vec<_> = sources
+        .iter()
+        .flat_map(|section| section.nodes().keys())
+        .cloned()
+        .collect();
+
+    let mut below = 0;
+    let mut min_replicas = params.replication_count as u64;
+
+    for _ in 0..params.merge_chunk_samples {
+        let chunk = target.substituted_in(random::gen());
+
+        let mut holders = names.clone();
+        holders.sort_by_key(|name| name.0 ^ chunk.0);
+        holders.truncate(params.replication_count);
+
+        let replicas = holders.iter().filter(|name| !lost.matches(**name)).count() as u64;
+        if replicas < params.replication_count as u64 {
+            below += 1;
         }
+        min_replicas = cmp::min(min_replicas, replicas);
+    }
+
+    let dip = (below as f64 * 100.0 / params.merge_chunk_samples as f64).round() as u64;
+    (dip, min_replicas)
+}
+
+/// Prefix of the section responsible for the name. Only needs one lookup per
+/// possible prefix length, instead of scanning all sections.
+pub fn prefix_for(sections: &BTreeMap<Prefix, Section>, name: Name) -> Option<Prefix> {
+    (0..65)
+        .map(|len| Prefix::new(len, name))
+        .find(|prefix| sections.contains_key(prefix))
+}
+
+// Number of hops a message takes from the section responsible for `source` to
+// the section responsible for `target`, using greedy XOR routing: each hop goes
+// to the neighbour section matching the target in the first bit where the
+// current section differs from it.
+fn num_hops(sections: &BTreeMap<Prefix, Section>, source: Name, target: Name) -> u64 {
+    let mut current = if let Some(prefix) = prefix_for(sections, source) {
+        prefix
+    } else {
+        return 0;
+    };
+    let mut hops = 0;
+
+    while !current.matches(target) {
+        let bit = (current.substituted_in(target).0 ^ target.0).leading_zeros() as u8;
+        let next = current.with_flipped_bit(bit).substituted_in(target);
+
+        current = if let Some(prefix) = prefix_for(sections, next) {
+            prefix
+        } else {
+            break;
+        };
+        hops += 1;
+    }
+
+    hops
+}
+
+fn section_for_mut(sections: &mut BTreeMap<Prefix, Section>, name: Name) -> Option<&mut Section> {
+    if let Some(prefix) = prefix_for(sections, name) {
+        sections.get_mut(&prefix)
+    } else {
+        None
     }
 }
 
-impl AddAssign for TickStats {
-    fn add_assign(&mut self, other: Self) {
-        self.merges += other.merges;
-        self.splits += other.splits;
-        self.relocations += other.relocations;
-        self.rejections += other.rejections;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simulation::Simulation;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::sync::atomic::AtomicBool;
+    use test_params;
+
+    fn request(source: SectionId, node_name: u64) -> Message {
+        Message::RelocateRequest {
+            id: ExchangeId(source, node_name),
+            node_name: Name(node_name),
+            target: Name(0),
+        }
+    }
+
+    #[test]
+    fn relocation_queue_takes_turns() {
+        let prefix0 = Prefix::new(1, Name(0));
+        let prefix1 = Prefix::new(1, Name(1 << 63));
+
+        let mut queue = RelocationQueue::default();
+        for name in 1..4 {
+            queue.push(0, prefix0, request(1, name));
+        }
+        queue.push(1, prefix1, request(2, 4));
+
+        let order: Vec<_> = (0..4)
+            .map(|_| queue.pop().map(|(_, message)| message.source()).unwrap())
+            .collect();
+        assert_eq!(order, vec![Name(1), Name(4), Name(2), Name(3)]);
+        assert!(queue.pop().is_none());
+        assert_eq!(queue.deferred, 4);
+    }
+
+    #[test]
+    fn relocation_queue_removes_by_exchange() {
+        let mut queue = RelocationQueue::default();
+        queue.push(0, Prefix::EMPTY, request(1, 5));
+        queue.push(0, Prefix::EMPTY, request(1, 6));
+        assert!(queue.contains(Name(5)));
+
+        // An earlier exchange for the same node doesn't withdraw the request.
+        assert!(!queue.remove(ExchangeId(0, 5), Name(5)));
+        assert!(queue.remove(ExchangeId(1, 5), Name(5)));
+        assert!(!queue.contains(Name(5)));
+        assert_eq!(queue.len(), 1);
+
+        assert!(queue.remove(ExchangeId(1, 6), Name(6)));
+        assert!(queue.is_empty());
+        assert!(queue.turns.is_empty());
+    }
+
+    fn run(args: &[&str]) -> Network {
+        let mut full = vec!["--seed", "[1, 2, 3, 4]"];
+        full.extend_from_slice(args);
+        let params = test_params(&full);
+        random::reseed(params.rng, params.seed);
+
+        let mut simulation = Simulation::new(params.clone());
+        let _ = simulation.run(&params, 0, &AtomicBool::new(true));
+        simulation.into_network()
+    }
+
+    #[test]
+    fn upgrade_churn_separates_the_upgrade_windows() {
+        let network = run(&["--iterations", "300", "--upgrade-interval", "50"]);
+        let (upgrades, background) = network.upgrade_churn();
+        // Windows of 10 iterations from iterations 50, 100, ..., 250.
+        assert_eq!(upgrades.iterations, 50);
+        assert_eq!(background.iterations, 250);
+        assert!(upgrades.elder_changes > 0);
+        assert_eq!(
+            upgrades.merges + background.merges,
+            network.stats().summary().merges()
+        );
+
+        let network = run(&["--iterations", "300"]);
+        assert_eq!(network.upgrade_churn().1.iterations, 0);
+    }
+
+    #[test]
+    fn ageing_audit_counts_the_same_nodes() {
+        let network = run(&["--iterations", "300", "--ageing-audit"]);
+        assert!(!network.ageing_counts().is_empty());
+        for counts in network.ageing_counts().values() {
+            assert!(counts.eligible <= counts.checked);
+            assert!(counts.selected <= counts.eligible);
+        }
+
+        let network = run(&["--iterations", "300"]);
+        assert!(network.ageing_counts().values().all(|counts| counts.checked == 0));
+    }
+
+    #[test]
+    fn expired_relocations_leave_the_queue() {
+        let network = run(&[
+            "--iterations",
+            "500",
+            "--global-relocation-cap",
+            "1",
+            "--relocation-timeout",
+            "1",
+        ]);
+
+        // Requests waiting longer than the timeout expired with their exchange
+        // instead of being sent.
+        let delays = network.relocation_delay_distribution();
+        assert!(delays.total() > 0);
+        assert_eq!(delays.count_at_least(2), 0);
+    }
+
+    #[test]
+    fn timed_out_relocations_pass_validation() {
+        // Lost cancels leave accepted relocations behind, and the global cap
+        // holds requests back until they time out.
+        let network = run(&[
+            "--iterations",
+            "1500",
+            "--relocation-timeout",
+            "1",
+            "--global-relocation-cap",
+            "1",
+            "--cancel-loss",
+            "0.9",
+        ]);
+        assert!(network.termination().is_none_or(|termination| {
+            termination.reason == Reason::Completed
+        }));
+    }
+
+    #[test]
+    fn forced_changes_with_nothing_to_do_are_ignored() {
+        let network = run(&["--iterations", "50", "--strict", "--force-split", "0101:10"]);
+        assert!(network.termination().is_none_or(|termination| {
+            termination.reason == Reason::Completed
+        }));
+        assert!(network.faults().is_empty());
+
+        // Forced merge into a prefix which already is a single section.
+        let ids = |network: &Network| {
+            network
+                .sections
+                .values()
+                .map(|section| (section.prefix(), section.id()))
+                .collect::<Vec<_>>()
+        };
+        let plain = run(&["--iterations", "50"]);
+        let network = run(&["--iterations", "50", "--force-merge", "0:45"]);
+        assert!(plain.sections.contains_key(&"0".parse().unwrap()));
+        assert_eq!(ids(&network), ids(&plain));
+        assert_eq!(network.stats().summary().merges(), plain.stats().summary().merges());
+    }
+
+    #[test]
+    fn chaos_leaves_main_random_stream_alone() {
+        let stats = |network: &Network| {
+            let mut writer = Writer::new();
+            network.stats.save(&mut writer);
+            writer.into_string()
+        };
+
+        let plain = run(&["--iterations", "300"]);
+        let plain_draws = random::draws_by_category();
+
+        // The range only has the default value, so the changes only draw from
+        // the chaos generator.
+        let chaos = run(&[
+            "--iterations",
+            "300",
+            "--chaos",
+            "adult-age=5..5",
+            "--chaos-interval",
+            "2",
+        ]);
+        assert!(chaos.param_changes().count() > 0);
+        assert_eq!(random::draws_by_category(), plain_draws);
+        assert_eq!(stats(&chaos), stats(&plain));
+    }
+
+    #[test]
+    fn chaos_skips_dead_ends() {
+        // Adult age above the init age would leave the infants stuck, as they
+        // aren't relocated.
+        let network = run(&[
+            "--iterations",
+            "100",
+            "--relocate-infants",
+            "no",
+            "--adult-age",
+            "4",
+            "--chaos",
+            "adult-age=3..6",
+            "--chaos-interval",
+            "2",
+        ]);
+        assert!(network.param_changes().count() > 0);
+        for change in network.param_changes().first() {
+            match change.setting {
+                Setting::AdultAge(age) => assert!(age <= 4, "{}", change.setting),
+                _ => panic!("unexpected change: {}", change.setting),
+            }
+        }
+    }
+
+    #[test]
+    fn events_scheduler_delivers_messages_between_events() {
+        let network = run(&[
+            "--iterations",
+            "2000",
+            "--strict",
+            "--scheduler",
+            "events",
+            "--event-message-delay",
+            "2",
+        ]);
+        assert!(network.termination().is_none_or(|termination| {
+            termination.reason == Reason::Completed
+        }));
+        assert!(network.messages_scheduled > 0);
+        assert!(network.stats().summary().relocations() > 0);
+        // Nothing is left on its way after the last event.
+        assert!(network.scheduled_messages.is_empty());
+    }
+
+    #[test]
+    fn relocation_latency_counts_section_ticks_until_commit() {
+        // The request, the accept and the commit take a section tick each.
+        let network = run(&["--iterations", "300"]);
+        let latency = network.relocation_latency_distribution();
+        assert_eq!(latency.total(), network.stats().summary().relocations());
+        assert_eq!(latency.summary().min, 2);
+
+        // Rejected requests are retried, which takes longer.
+        let network = run(&["--iterations", "300", "--elder-reject-probability", "0.3"]);
+        let rejected = network.relocation_latency_distribution().summary();
+        assert!(rejected.max > latency.summary().max);
+    }
+
+    // Run a single iteration starting from the layout.
+    fn run_layout(name: &str, layout: &str) -> Network {
+        let path = env::temp_dir().join(format!(
+            "datachains_sim-layout-{}-{}.csv",
+            name,
+            process::id()
+        ));
+        fs::write(&path, layout).unwrap();
+        let network = run(&["--iterations", "1", "--layout-file", path.to_str().unwrap()]);
+        let _ = fs::remove_file(&path);
+        network
+    }
+
+    // Network starting from the layout, before any iteration.
+    fn load_layout(name: &str, layout: &str) -> Network {
+        let path = env::temp_dir().join(format!(
+            "datachains_sim-layout-{}-{}.csv",
+            name,
+            process::id()
+        ));
+        fs::write(&path, layout).unwrap();
+        let params = test_params(&["--layout-file", path.to_str().unwrap()]);
+        let network = Network::new(params);
+        let _ = fs::remove_file(&path);
+        network
+    }
+
+    // Layout lines of `count` adults with names from `first` on.
+    fn adults(prefix: &str, first: u64, count: u64) -> String {
+        (first..first + count)
+            .map(|name| format!("{},{:016x},5\n", prefix, name))
+            .collect()
+    }
+
+    fn prefixes(network: &Network) -> Vec<String> {
+        let mut prefixes: Vec<_> =
+            network.sections.keys().map(|prefix| prefix.to_string()).collect();
+        prefixes.sort();
+        prefixes
+    }
+
+    #[test]
+    fn layout_section_short_of_adults_merges() {
+        let layout = adults("0", 1, 7) + &adults("1", 0x8000_0000_0000_0001, 20);
+        let network = run_layout("merge", &layout);
+        assert_eq!(prefixes(&network), vec![""]);
+        assert_eq!(network.stats().summary().merges(), 1);
+    }
+
+    #[test]
+    fn layout_section_with_enough_adults_in_both_halves_splits() {
+        let layout = adults("0", 1, 11) + &adults("0", 0x4000_0000_0000_0001, 11) +
+            &adults("1", 0x8000_0000_0000_0001, 20);
+        let network = run_layout("split", &layout);
+        assert_eq!(prefixes(&network), vec!["00", "01", "1"]);
+
+        // Too few adults in a half.
+        let layout = adults("0", 1, 6) + &adults("0", 0x4000_0000_0000_0001, 11) +
+            &adults("1", 0x8000_0000_0000_0001, 20);
+        let network = run_layout("no-split", &layout);
+        assert_eq!(prefixes(&network), vec!["0", "1"]);
+    }
+
+    #[test]
+    fn sibling_size_to_a_split_sibling_is_dropped() {
+        let layout = adults("0", 1, 12) + &adults("10", 0x8000_0000_0000_0001, 12) +
+            &adults("11", 0xc000_0000_0000_0001, 12);
+        let path = env::temp_dir().join(format!(
+            "datachains_sim-layout-dropped-sizes-{}.csv",
+            process::id()
+        ));
+        fs::write(&path, layout).unwrap();
+        let network = run(&[
+            "--iterations",
+            "1",
+            "--balance-threshold",
+            "1.2",
+            "--layout-file",
+            path.to_str().unwrap(),
+        ]);
+        let _ = fs::remove_file(&path);
+
+        // Only the size from 0 goes to a descendant of its sibling.
+        assert_eq!(prefixes(&network), vec!["0", "10", "11"]);
+        assert_eq!(network.stale_views().dropped_sizes, 1);
+        assert!(network.sibling_size_ratio_distribution().total() > 0);
+    }
+
+    fn closest(network: &Network, name: u64, k: usize) -> Vec<String> {
+        network
+            .k_closest_sections(Name(name), k)
+            .iter()
+            .map(|section| section.prefix().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn section_for_finds_the_section_covering_the_name() {
+        let network = load_layout("section-for", "000\n001\n01\n1\n");
+        let prefix = |name| network.section_for(Name(name)).unwrap().prefix().to_string();
+
+        assert_eq!(prefix(0), "000");
+        assert_eq!(prefix(0x3fff_ffff_ffff_ffff), "001");
+        assert_eq!(prefix(0x4000_0000_0000_0000), "01");
+        assert_eq!(prefix(!0), "1");
+    }
+
+    #[test]
+    fn k_closest_sections_order_by_xor_distance() {
+        let network = load_layout("closest", "000\n001\n01\n1\n");
+        assert_eq!(closest(&network, 0x3fff_ffff_ffff_ffff, 4), vec!["001", "000", "01", "1"]);
+        assert_eq!(closest(&network, !0, 4), vec!["1", "01", "001", "000"]);
+
+        assert_eq!(closest(&network, 0x3fff_ffff_ffff_ffff, 2), vec!["001", "000"]);
+        assert_eq!(closest(&network, 0, 10).len(), 4);
+        assert!(closest(&network, 0, 0).is_empty());
+    }
+
+    #[test]
+    fn k_closest_sections_dont_order_by_address() {
+        // The section right above the name is the closest by address, but the
+        // furthest by XOR distance, as it differs from the name in the first bit.
+        let network = load_layout("closest-address", "00\n01\n10\n11\n");
+        assert_eq!(closest(&network, 0x7fff_ffff_ffff_ffff, 4), vec!["01", "00", "11", "10"]);
+        assert_eq!(closest(&network, 0x8000_0000_0000_0000, 4), vec!["10", "11", "00", "01"]);
+    }
+
+    #[test]
+    fn nodes_matching_visit_only_the_covering_sections() {
+        let layout = adults("0", 1, 4) + &adults("0", 0x4000_0000_0000_0001, 4) +
+            &adults("10", 0x8000_0000_0000_0001, 4) +
+            &adults("11", 0xc000_0000_0000_0001, 4);
+        let network = load_layout("matching", &layout);
+        let names = |prefix: &str| {
+            let mut names: Vec<_> = network
+                .nodes_matching(prefix.parse().unwrap())
+                .iter()
+                .map(|node| node.name().0)
+                .collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(names("").len(), 16);
+        // Descendant sections.
+        assert_eq!(
+            names("1"),
+            (0x8000_0000_0000_0001..0x8000_0000_0000_0005)
+                .chain(0xc000_0000_0000_0001..0xc000_0000_0000_0005)
+                .collect::<Vec<_>>()
+        );
+        // Part of an ancestor section.
+        assert_eq!(
+            names("01"),
+            (0x4000_0000_0000_0001..0x4000_0000_0000_0005).collect::<Vec<_>>()
+        );
+        assert_eq!(names("11"), (0xc000_0000_0000_0001..0xc000_0000_0000_0005).collect::<Vec<_>>());
+        assert!(names("0111").is_empty());
     }
 }
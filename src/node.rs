@@ -1,25 +1,106 @@
 use Age;
 use params::Params;
 use prefix::{Name, Prefix};
+use snapshot::{Reader, Snapshot, Writer};
+use std::cell::Cell;
+use std::cmp;
 use std::fmt;
-use std::u8;
+use std::rc::Rc;
+
+/// Unique identifier of a node within its network, kept when the node is
+/// relocated or restarts.
+pub type NodeId = u64;
+
+/// Source of node ids, shared by the sections of a network so the ids are
+/// unique within the network and every network starts from zero.
+#[derive(Clone, Default)]
+pub struct NodeIds(Rc<Cell<NodeId>>);
+
+impl NodeIds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allocate(&self) -> NodeId {
+        let id = self.0.get();
+        self.0.set(id + 1);
+        id
+    }
+
+    /// The id the next node will get.
+    pub fn next(&self) -> NodeId {
+        self.0.get()
+    }
+
+    /// Continue allocating from the given id (e.g. when restoring a snapshot).
+    pub fn resume(&self, next: NodeId) {
+        self.0.set(next)
+    }
+}
+
+/// How a node entered its current section.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Origin {
+    /// Joined the network for the first time.
+    Fresh,
+    /// Rejoined the network after disconnecting.
+    Rejoined,
+    /// Relocated from another section.
+    Relocated,
+}
 
 #[derive(Eq, PartialEq, Hash)]
 pub struct Node {
+    id: NodeId,
     name: Name,
     age: Age,
     elder: bool,
+    // Whether the node has ever been an elder.
+    was_elder: bool,
+    term: u64,
+    // Number of iterations since this node arrived in its current section.
+    tenure: u64,
+    // Farming rewards earned so far.
+    credits: u64,
+    relocations: u64,
+    // Iteration of the last relocation of this node, if any.
+    last_relocation: Option<u64>,
+    origin: Origin,
+    // Storage contributed to the network.
+    size: u64,
+    // Whether the node is controlled by the attacker.
+    malicious: bool,
+    // Index of the operator profile the node follows, if any.
+    profile: Option<usize>,
+    // Operator running the node, if tracked (see `Params::operators`).
+    operator: Option<u32>,
 }
 
 impl Node {
-    pub fn new(name: Name, age: Age) -> Self {
+    pub fn new(id: NodeId, name: Name, age: Age) -> Self {
         Node {
+            id,
             name,
             age,
             elder: false,
+            was_elder: false,
+            term: 0,
+            tenure: 0,
+            credits: 0,
+            relocations: 0,
+            last_relocation: None,
+            origin: Origin::Fresh,
+            size: 1,
+            malicious: false,
+            profile: None,
+            operator: None,
         }
     }
 
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
     pub fn name(&self) -> Name {
         self.name
     }
@@ -28,6 +109,10 @@ impl Node {
         self.age
     }
 
+    pub fn set_age(&mut self, age: Age) {
+        self.age = age
+    }
+
     pub fn is_infant(&self, params: &Params) -> bool {
         self.age < params.adult_age
     }
@@ -41,11 +126,51 @@ impl Node {
     }
 
     pub fn promote(&mut self) {
-        self.elder = true
+        self.elder = true;
+        self.was_elder = true;
+    }
+
+    pub fn was_elder(&self) -> bool {
+        self.was_elder
     }
 
     pub fn demote(&mut self) {
-        self.elder = false
+        self.elder = false;
+        self.term = 0;
+    }
+
+    /// Number of consecutive iterations this node has been an elder for.
+    pub fn term(&self) -> u64 {
+        self.term
+    }
+
+    pub fn increment_term(&mut self) {
+        if self.elder {
+            self.term += 1
+        }
+    }
+
+    /// Number of iterations since this node arrived in its current section.
+    pub fn tenure(&self) -> u64 {
+        self.tenure
+    }
+
+    pub fn increment_tenure(&mut self) {
+        self.tenure += 1
+    }
+
+    /// Returns whether this node arrived in its section too recently to be
+    /// relocated again.
+    pub fn is_in_relocation_cooldown(&self, params: &Params) -> bool {
+        self.tenure < params.relocation_cooldown
+    }
+
+    /// Returns whether this elder has served long enough (or got old enough) to
+    /// be forcibly relocated.
+    pub fn is_over_term_limit(&self, params: &Params) -> bool {
+        self.elder &&
+            ((params.elder_term_limit > 0 && self.term >= params.elder_term_limit) ||
+                 (params.elder_age_limit > 0 && self.age >= params.elder_age_limit))
     }
 
     pub fn increment_age(&mut self) {
@@ -56,9 +181,182 @@ impl Node {
         self.age = self.age.saturating_add(1)
     }
 
-    /// Returns the probability this node will be dropped.
-    pub fn drop_probability(&self) -> f64 {
-        2f64.powf(-f64::from(self.age))
+    /// The node after rejoining the network after disconnecting. It gets half of
+    /// its current age (but at least the initial age) and keeps its id,
+    /// credits, number of relocations, size, allegiance, operator (and
+    /// profile) and whether it was ever an elder.
+    pub fn rejoined(&self, params: &Params) -> Node {
+        let age = cmp::max(params.init_age, self.age / 2);
+        let mut node = Node::new(self.id, self.name, age);
+        node.credits = self.credits;
+        node.last_relocation = self.last_relocation;
+        node.relocations = self.relocations;
+        node.origin = Origin::Rejoined;
+        node.size = self.size;
+        node.malicious = self.malicious;
+        node.profile = self.profile;
+        node.operator = self.operator;
+        node.was_elder = self.was_elder;
+        node
+    }
+
+    /// The node after its join was redirected to the new name. Apart from the
+    /// name, it stays the same node.
+    pub fn redirected(&self, new_name: Name) -> Node {
+        let mut node = Node::new(self.id, new_name, self.age);
+        node.credits = self.credits;
+        node.last_relocation = self.last_relocation;
+        node.relocations = self.relocations;
+        node.origin = self.origin;
+        node.size = self.size;
+        node.malicious = self.malicious;
+        node.profile = self.profile;
+        node.operator = self.operator;
+        node.was_elder = self.was_elder;
+        node
+    }
+
+    /// The node after being relocated under the new name. It keeps its id, age,
+    /// credits, size, allegiance, operator (and profile) and whether it was
+    /// ever an elder.
+    pub fn relocated(&self, new_name: Name) -> Node {
+        let mut node = Node::new(self.id, new_name, self.age);
+        node.credits = self.credits;
+        node.last_relocation = self.last_relocation;
+        node.relocations = self.relocations + 1;
+        node.origin = Origin::Relocated;
+        node.size = self.size;
+        node.malicious = self.malicious;
+        node.profile = self.profile;
+        node.operator = self.operator;
+        node.was_elder = self.was_elder;
+        node
+    }
+
+    pub fn origin(&self) -> Origin {
+        self.origin
+    }
+
+    /// Number of times this node has been relocated.
+    pub fn relocations(&self) -> u64 {
+        self.relocations
+    }
+
+    /// Iteration of the last relocation of this node, if any.
+    pub fn last_relocation(&self) -> Option<u64> {
+        self.last_relocation
+    }
+
+    pub fn set_last_relocation(&mut self, iteration: u64) {
+        self.last_relocation = Some(iteration);
+    }
+
+    pub fn credits(&self) -> u64 {
+        self.credits
+    }
+
+    pub fn add_credits(&mut self, amount: u64) {
+        self.credits += amount
+    }
+
+    /// Storage this node contributes to its section.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn set_size(&mut self, size: u64) {
+        self.size = size
+    }
+
+    pub fn is_malicious(&self) -> bool {
+        self.malicious
+    }
+
+    pub fn set_malicious(&mut self, malicious: bool) {
+        self.malicious = malicious
+    }
+
+    pub fn set_profile(&mut self, profile: Option<usize>) {
+        self.profile = profile
+    }
+
+    pub fn operator(&self) -> Option<u32> {
+        self.operator
+    }
+
+    pub fn set_operator(&mut self, operator: Option<u32>) {
+        self.operator = operator
+    }
+
+    /// Returns the probability this node will be dropped in the given
+    /// iteration.
+    pub fn drop_probability(&self, params: &Params, iteration: u64) -> f64 {
+        let probability = 2f64.powf(-f64::from(self.age));
+        (probability * self.drop_factor(params, iteration)).min(1.0)
+    }
+
+    /// Returns how many times more likely than usual this node is to drop in
+    /// the given iteration, following its operator profile.
+    pub fn drop_factor(&self, params: &Params, iteration: u64) -> f64 {
+        match self.profile.map(|index| &params.operator_profiles[index]) {
+            Some(profile) if profile.is_offline_window(iteration) => profile.factor,
+            _ => 1.0,
+        }
+    }
+}
+
+impl Snapshot for Origin {
+    fn save(&self, writer: &mut Writer) {
+        writer.put(*self as u8)
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        match reader.get::<u8>()? {
+            0 => Ok(Origin::Fresh),
+            1 => Ok(Origin::Rejoined),
+            2 => Ok(Origin::Relocated),
+            tag => Err(format!("invalid node origin in snapshot: {}", tag)),
+        }
+    }
+}
+
+impl Snapshot for Node {
+    fn save(&self, writer: &mut Writer) {
+        self.id.save(writer);
+        self.name.save(writer);
+        self.age.save(writer);
+        self.elder.save(writer);
+        self.was_elder.save(writer);
+        self.term.save(writer);
+        self.tenure.save(writer);
+        self.credits.save(writer);
+        self.relocations.save(writer);
+        self.last_relocation.save(writer);
+        self.origin.save(writer);
+        self.size.save(writer);
+        self.malicious.save(writer);
+        self.profile.save(writer);
+        self.operator.save(writer);
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok(Node {
+            id: Snapshot::load(reader)?,
+            name: Snapshot::load(reader)?,
+            age: Snapshot::load(reader)?,
+            elder: Snapshot::load(reader)?,
+            was_elder: Snapshot::load(reader)?,
+            term: Snapshot::load(reader)?,
+            tenure: Snapshot::load(reader)?,
+            credits: Snapshot::load(reader)?,
+            relocations: Snapshot::load(reader)?,
+            last_relocation: Snapshot::load(reader)?,
+            origin: Snapshot::load(reader)?,
+            size: Snapshot::load(reader)?,
+            malicious: Snapshot::load(reader)?,
+            profile: Snapshot::load(reader)?,
+            operator: Snapshot::load(reader)?,
+        })
     }
 }
 
@@ -94,9 +392,71 @@ pub fn count_matching_adults<'a, I: IntoIterator<Item = &'a Node>>(
         .count()
 }
 
-/// Returns the nodes sorted by age (from youngest to oldest).
-pub fn by_age<'a, I: IntoIterator<Item = &'a Node>>(nodes: I) -> Vec<&'a Node> {
-    let mut nodes: Vec<_> = nodes.into_iter().collect();
-    nodes.sort_by_key(|node| (node.age(), node.name()));
+/// Returns the total size of the adults matching the prefix.
+pub fn matching_adult_capacity<'a, I: IntoIterator<Item = &'a Node>>(
+    params: &Params,
+    prefix: Prefix,
+    nodes: I,
+) -> u64 {
     nodes
+        .into_iter()
+        .filter(|node| node.is_adult(params) && prefix.matches(node.name()))
+        .map(|node| node.size())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_params;
+
+    #[test]
+    fn ids_are_per_network() {
+        let ids = NodeIds::new();
+        let shared = ids.clone();
+        assert_eq!(ids.allocate(), 0);
+        assert_eq!(shared.allocate(), 1);
+        assert_eq!(ids.allocate(), 2);
+
+        // Another network starts over.
+        assert_eq!(NodeIds::new().allocate(), 0);
+    }
+
+    #[test]
+    fn id_survives_restart_and_relocation() {
+        let params = test_params(&[]);
+        let node = Node::new(5, Name(1), 10);
+
+        let rejoined = node.rejoined(&params);
+        assert_eq!(rejoined.id(), 5);
+        assert_eq!(rejoined.origin(), Origin::Rejoined);
+        assert_eq!(rejoined.relocated(Name(2)).id(), 5);
+    }
+
+    #[test]
+    fn redirect_keeps_the_node() {
+        let mut node = Node::new(5, Name(1), 4);
+        node.set_size(3);
+        node.set_profile(Some(1));
+        node.set_operator(Some(2));
+
+        let redirected = node.redirected(Name(9));
+        assert_eq!(redirected.name(), Name(9));
+        assert_eq!(redirected.id(), 5);
+        assert_eq!(redirected.age(), 4);
+        assert_eq!(redirected.size(), 3);
+        assert_eq!(redirected.profile, Some(1));
+        assert_eq!(redirected.operator(), Some(2));
+        assert_eq!(redirected.origin(), Origin::Fresh);
+    }
+
+    #[test]
+    fn redirect_and_relocation_keep_allegiance() {
+        let mut node = Node::new(5, Name(1), 4);
+        node.set_malicious(true);
+
+        assert!(node.redirected(Name(9)).is_malicious());
+        assert!(node.relocated(Name(9)).is_malicious());
+        assert!(!Node::new(6, Name(2), 4).redirected(Name(9)).is_malicious());
+    }
 }
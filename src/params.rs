@@ -1,9 +1,18 @@
 //! Simulation parameters.
 
-use random::Seed;
+use parse::ParseError;
+use prefix::Prefix;
+use rand::Rng;
+use random::{RngKind, Seed};
+use snapshot::{Reader, Snapshot, Writer};
+use std::fmt;
+use std::str::FromStr;
+use victim::VictimSelector;
 
 #[derive(Clone, Debug)]
 pub struct Params {
+    /// Random number generator algorithm.
+    pub rng: RngKind,
     /// Seed for the random number generator.
     pub seed: Seed,
     /// Number of simulation iterations.
@@ -12,22 +21,258 @@ pub struct Params {
     pub group_size: usize,
     /// Age of newly joined node.
     pub init_age: u8,
+    /// Whether newly joining nodes are sent to a section derived from the hash of
+    /// their name instead of joining the section of their choice.
+    pub join_relocation: bool,
+    /// Which section newly joining nodes choose.
+    pub join_model: JoinModel,
     /// Age at which a node becomes adult.
     pub adult_age: u8,
+    /// Minimum age of a node to be promoted to elder (0 to allow any age).
+    pub min_elder_age: u8,
+    /// How to choose between nodes of the same age on the elder boundary.
+    pub elder_tie_break: ElderTieBreak,
+    /// Age of nodes joining during startup (defaults to `adult_age`).
+    pub startup_age: u8,
+    /// Whether nodes joining during startup go through the infant stage
+    /// (joining with `init_age`) instead of getting `startup_age`.
+    pub startup_infants: bool,
+    /// Whether the age of every node is incremented when its section splits.
+    pub age_on_split: bool,
+    /// Number of iterations a section created by a split or a merge must exist
+    /// before it can split or merge again (0 to disable).
+    pub min_section_lifetime: u64,
     /// Maximum number of nodes a section can have before the simulation fails.
     pub max_section_size: usize,
+    /// Abort the simulation as soon as a section exceeds `max_section_size` or
+    /// on any recoverable fault (see `SimError`), e.g. a relocation commit not
+    /// matching any incoming relocation.
+    pub strict: bool,
+    /// Verify after every split and merge that the sections partition the name
+    /// space (otherwise only the cheap incremental checks run).
+    pub paranoid: bool,
+    /// Measure the wall-clock time spent merging sections. Off by default, as
+    /// it makes the output differ between otherwise identical runs.
+    pub timing: bool,
+    /// File to dump the offending section to when aborting in strict mode.
+    pub dump_file: String,
+    /// Maximum storage size of a node. Sizes of joining nodes are drawn uniformly
+    /// from 1 to this (1 means all nodes have the same size).
+    pub max_node_size: u64,
+    /// What a section must have enough of in both halves to split.
+    pub split_metric: SplitMetric,
+    /// Maximum length of a section prefix (at most `Prefix::MAX_LEN`).
+    pub max_prefix_len: u8,
+    /// What a section that should split but is already at `max_prefix_len`
+    /// does.
+    pub max_depth: MaxDepth,
+    /// Number of most recent blocks of the chain the nodes keep (0 to keep all).
+    pub chain_retention_blocks: u64,
+    /// Number of most recent ticks whose blocks the nodes keep (0 to keep all).
+    pub chain_retention_ticks: u64,
+    /// Whether the sections keep their whole chain, record the elders
+    /// dropping as `Dead` blocks and audit the lifecycle of every node in it.
+    pub full_chain: bool,
     /// Maximum number of reocation attempts after a `Live` event.
     pub max_relocation_attempts: usize,
+    /// Probability that an elder of the destination section rejects a
+    /// relocation request (the request needs a quorum of approvals).
+    pub elder_reject_probability: f64,
+    /// Probability that a relocation cancel message is lost.
+    pub cancel_loss: f64,
+    /// Number of iterations after which a section drops an accepted relocation
+    /// whose commit never arrived, or a requested relocation that was never
+    /// accepted or rejected (0 disables the timeout).
+    pub relocation_timeout: u64,
+    /// How to recover from a relocation commit not matching any incoming
+    /// relocation (ignored in strict mode, which aborts instead).
+    pub unmatched_commit: UnmatchedCommit,
+    /// How relocation (and join redirect) targets are derived from hashes.
+    pub target_derivation: TargetDerivation,
+    /// Maximum number of trailing prefix bits in which the relocation target
+    /// can differ from the source section (0 means unconstrained).
+    pub max_relocation_distance: u8,
+    /// Whether infants are eligible for relocation.
+    pub relocate_infants: InfantRelocation,
+    /// Which nodes are likely to drop.
+    pub drop_model: DropModel,
+    /// Which of the nodes passing the ageing check is relocated.
+    pub relocation_choice: RelocationChoice,
+    /// Grow the network to this many nodes, then stop all joins and keep
+    /// dropping nodes until the network collapses back to the root section
+    /// (0 to disable).
+    pub shrink_after: usize,
     /// Maximum number of infants allowed in one section.
     pub max_infants_per_section: usize,
+    /// Probability to admit an infant over `max_infants_per_section`. It decays
+    /// geometrically with each infant over the cap (0 for a hard cap).
+    pub infant_admission_probability: f64,
+    /// Number of consecutive iterations after which an elder is forcibly
+    /// relocated (0 means unlimited).
+    pub elder_term_limit: u64,
+    /// Age at which an elder is forcibly relocated (0 means unlimited).
+    pub elder_age_limit: u8,
+    /// Number of iterations after arriving in a section during which a node
+    /// can't be relocated again.
+    pub relocation_cooldown: u64,
+    /// Whether rejected join attempts count as churn events for ageing.
+    pub rejections_trigger_ageing: bool,
+    /// Whether nodes entering a section under the name of a node that is or
+    /// was in the network are rejected.
+    pub reject_reused_names: bool,
+    /// Size ratio between sibling sections above which the larger one relocates
+    /// a node to the smaller one (0 disables balancing).
+    pub balance_threshold: f64,
+    /// Difference in adults between the halves of a section below which a node
+    /// relocated to it lands in a random half instead of the one with fewer
+    /// adults (0 always picks the one with fewer adults).
+    pub commit_balance_threshold: usize,
+    /// Number of iterations a section short of adults waits for its neighbours
+    /// to relocate adults to it before merging (0 disables rescue relocations).
+    pub rescue_window: u64,
+    /// Number of iterations it takes a section to learn the sizes and needs of
+    /// its neighbours (0 = they are known right away).
+    pub view_lag: u64,
+    /// Maximum number of relocation requests sent across the network in an
+    /// iteration, the rest being deferred to the following ones (0 = no cap).
+    pub global_relocation_cap: u64,
+    /// Number of iterations between network upgrades (0 disables upgrades).
+    pub upgrade_interval: u64,
+    /// Average fraction of nodes restarting during a network upgrade.
+    pub upgrade_fraction: f64,
+    /// Number of iterations over which the restarts of one upgrade are spread.
+    pub upgrade_window: u64,
+    /// Periods during which parts of the network refuse all joins and incoming
+    /// relocations.
+    pub blackouts: Vec<Blackout>,
+    /// Splits forced regardless of the section size.
+    pub force_splits: Vec<ForcedChange>,
+    /// Merges forced regardless of the section size.
+    pub force_merges: Vec<ForcedChange>,
+    /// Nodes removed all at once at given iterations.
+    pub disasters: Vec<Disaster>,
+    /// Changes of parameters at given iterations.
+    pub param_changes: Vec<ParamChange>,
+    /// Parameters set to random values within their ranges at random
+    /// iterations (chaos mode).
+    pub chaos: Vec<ChaosRange>,
+    /// Average number of iterations between the chaos mode changes.
+    pub chaos_interval: u64,
+    /// CSV file with the sections to start with (see `layout`), instead of the
+    /// root section.
+    pub layout_file: Option<String>,
+    /// CSV file with the churn trace (see `trace`) driving all joins and drops,
+    /// instead of the random ones.
+    pub churn_trace: Option<String>,
+    /// Number of seconds of the churn trace per iteration.
+    pub trace_tick_seconds: f64,
+    /// Churn generated up front instead of by the sections (see `trace`), used
+    /// when no churn trace is given.
+    pub synthetic_churn: Option<SyntheticChurn>,
+    /// Whether the iterations are fixed ticks or churn events.
+    pub scheduler: Scheduler,
+    /// Churn and message timing of the events scheduler.
+    pub event_rates: EventRates,
+    /// Availability patterns followed by some of the joining nodes.
+    pub operator_profiles: Vec<OperatorProfile>,
+    /// Fraction of joining nodes controlled by the attacker.
+    pub malicious_fraction: f64,
+    /// Number of operators the joining nodes are spread over, uniformly at
+    /// random (0 disables tracking the operators).
+    pub operators: u32,
+    /// Whether sections with a malicious majority of elders reject all incoming
+    /// relocations.
+    pub relocation_denial: bool,
+    /// Fraction of the nodes of a splitting section assumed to be controlled by
+    /// the worst-case adversary in the split audit (0 disables the audit).
+    pub split_audit_fraction: f64,
+    /// Count, by age, the nodes the ageing check applies to and those passing
+    /// it. Off by default, as it goes through all the nodes of the section on
+    /// every attempt.
+    pub ageing_audit: bool,
+    /// Whether to track farming rewards (credits) of nodes.
+    pub economics: bool,
+    /// Average number of client requests each section handles per iteration.
+    pub client_load: f64,
+    /// Route messages hop by hop through the sections instead of delivering
+    /// them directly.
+    pub routing: bool,
+    /// Number of section ticks each routing hop takes.
+    pub hop_delay: u64,
+    /// Probability that a routing hop is lost and has to be retried.
+    pub hop_loss: f64,
+    /// Number of bits below the section prefix over which the name entropy is
+    /// measured.
+    pub entropy_bits: u8,
+    /// Number of nodes (closest to the chunk name) each chunk is stored on.
+    pub replication_count: usize,
+    /// Number of random chunks sampled at each merge to measure data
+    /// availability (0 to disable).
+    pub merge_chunk_samples: usize,
+    /// Number of most churned section prefixes to print at the end (0 to
+    /// disable).
+    pub top_churned: usize,
     /// Print statistics every Nth iteration (supress if 0)
     pub stats_frequency: u64,
+    /// Width of the bars of the distributions shown as histograms in the
+    /// summary and the periodic statistics (0 shows them as plain tables).
+    pub histogram_width: usize,
+    /// Whether the histogram bars are proportional to the logarithm of the
+    /// counts.
+    pub histogram_log: bool,
+    /// Weights of the run outcomes in its score.
+    pub score_weights: ScoreWeights,
+    /// How often to print the changes to the section table (0 disables).
+    pub diff_sections: u64,
+    /// Minimum change of a section's size for it to be printed by
+    /// `diff_sections`.
+    pub diff_sections_threshold: usize,
     /// File to store  network structure data.
     pub file: Option<String>,
+    /// Columns of the network structure data file.
+    pub file_format: FileFormat,
+    /// Directory of the soak mode checkpoints (soak mode is off if `None`).
+    pub soak_dir: Option<String>,
+    /// Write a soak mode checkpoint every Nth iteration.
+    pub soak_frequency: u64,
+    /// Number of most recent soak mode checkpoints to keep.
+    pub soak_keep: usize,
+    /// File to stream the telemetry to.
+    pub telemetry_file: Option<String>,
+    /// Write the telemetry every Nth iteration.
+    pub telemetry_frequency: u64,
+    /// Distributions summarized in the telemetry.
+    pub telemetry_summaries: Vec<TelemetrySummary>,
+    /// File to store the split audit time series.
+    pub split_audit_file: Option<String>,
+    /// File to store the lineage of all sections.
+    pub lineage_file: Option<String>,
+    /// File to store the messages of every relocation exchange.
+    pub relocation_trace: Option<String>,
+    /// File to stream every action, message, join and drop to.
+    pub event_trace_file: Option<String>,
+    /// Format of the event trace file (JSON lines or binary).
+    pub event_trace_format: TraceFormat,
+    /// File to store the history of all nodes, for survival analysis.
+    pub survival_file: Option<String>,
+    /// Compute the network state hash every Nth iteration (0 to disable).
+    pub state_hash_frequency: u64,
+    /// File to store the network state hashes.
+    pub state_hash_file: Option<String>,
+    /// File to gather all the outputs of the run into (gzipped tar).
+    pub bundle_file: Option<String>,
+    /// Name prefixes (in hex) whose provenance is printed at the end. Names are
+    /// tracked only if this is not empty.
+    pub lookup: Vec<String>,
     /// Log veribosity
     pub verbosity: usize,
     /// Disable colored output
     pub disable_colors: bool,
+    /// Only print the quantities derived from the parameters, without running
+    /// the simulation.
+    pub dry_run: bool,
+    /// Run even if the parameters make the network unable to grow.
+    pub force: bool,
 }
 
 impl Params {
@@ -35,4 +280,1136 @@ impl Params {
     pub fn quorum(&self) -> usize {
         self.group_size / 2 + 1
     }
+
+    /// Minimum number of adults each half of a section must have for the
+    /// section to split.
+    pub fn split_threshold(&self) -> usize {
+        2 * self.group_size - self.quorum()
+    }
+
+    /// Number of adults below which a section merges with its sibling.
+    pub fn merge_threshold(&self) -> usize {
+        self.group_size
+    }
+
+    /// Number of adults a section needs to be able to split.
+    pub fn min_split_size(&self) -> usize {
+        2 * self.split_threshold()
+    }
+
+    /// Returns the reasons why the parameters make the network unable to grow,
+    /// if any.
+    pub fn dead_ends(&self) -> Vec<String> {
+        let mut result = self.growth_dead_ends();
+        result.extend(self.startup_dead_ends());
+        result
+    }
+
+    /// Returns the dead ends (see `dead_ends`) past the startup of the root
+    /// section.
+    pub fn growth_dead_ends(&self) -> Vec<String> {
+        let mut result = Vec::new();
+
+        if self.max_section_size < self.min_split_size() {
+            result.push(format!(
+                "max-section-size ({}) is below the {} adults a section needs to split, so \
+                 sections reach the size limit before they can split",
+                self.max_section_size,
+                self.min_split_size()
+            ));
+        }
+
+        if self.init_age < self.adult_age && !self.relocate_infants.allows(true) &&
+            !self.age_on_split
+        {
+            result.push(format!(
+                "nodes join as infants (init-age {} < adult-age {}) and infants are never \
+                 relocated, so they never become adults",
+                self.init_age,
+                self.adult_age
+            ));
+        }
+
+        result
+    }
+
+    // Dead ends of the root section during startup. Only the initial parameters
+    // matter for those, as the scheduled changes come after the startup (and
+    // `startup_age` defaults to the initial `adult_age`).
+    fn startup_dead_ends(&self) -> Vec<String> {
+        let mut result = Vec::new();
+
+        if self.startup_infants && self.init_age < self.adult_age {
+            result.push(format!(
+                "nodes join the root section as infants (init-age {} < adult-age {}) and \
+                 nobody is relocated during startup, so the root section never splits",
+                self.init_age,
+                self.adult_age
+            ));
+        }
+
+        if !self.startup_infants && self.startup_age < self.adult_age {
+            result.push(format!(
+                "nodes join the root section as infants (startup-age {} < adult-age {}) and \
+                 nobody is relocated during startup, so the root section never splits",
+                self.startup_age,
+                self.adult_age
+            ));
+        }
+
+        result
+    }
+
+    /// Returns the dead ends (see `dead_ends`) the parameters run into after
+    /// the scheduled changes, applied in order, and at the bounds of the chaos
+    /// ranges, except those already there from the start and those of the
+    /// startup.
+    pub fn dead_ends_after_changes(&self) -> Vec<String> {
+        let initial = self.growth_dead_ends();
+        let mut result = Vec::new();
+        let mut check = |params: &Params, when: String| {
+            for dead_end in params.growth_dead_ends() {
+                if !initial.contains(&dead_end) {
+                    result.push(format!("{}: {}", when, dead_end));
+                }
+            }
+        };
+
+        let mut changes = self.param_changes.clone();
+        changes.sort_by_key(|change| change.iteration);
+        let mut params = self.clone();
+        for change in changes {
+            params.apply(change.setting);
+            check(
+                &params,
+                format!("after {} at iteration {}", change.setting, change.iteration),
+            );
+        }
+
+        for range in &self.chaos {
+            for &setting in &[range.min, range.max] {
+                let mut params = self.clone();
+                params.apply(setting);
+                check(&params, format!("with chaos setting {}", setting));
+            }
+        }
+
+        result
+    }
+
+    /// Average size of a joining node.
+    pub fn avg_node_size(&self) -> f64 {
+        (1 + self.max_node_size) as f64 / 2.0
+    }
+}
+
+/// Eligibility of infants for relocation.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InfantRelocation {
+    /// Both infants and adults can be relocated.
+    Yes,
+    /// Only adults can be relocated.
+    No,
+    /// Only infants can be relocated.
+    Only,
+}
+
+impl InfantRelocation {
+    /// Name of the option, as in the command line.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            InfantRelocation::Yes => "yes",
+            InfantRelocation::No => "no",
+            InfantRelocation::Only => "only",
+        }
+    }
+
+    /// Returns whether a node can be relocated, given whether it's an infant.
+    pub fn allows(self, infant: bool) -> bool {
+        match self {
+            InfantRelocation::Yes => true,
+            InfantRelocation::No => !infant,
+            InfantRelocation::Only => infant,
+        }
+    }
+}
+
+impl FromStr for InfantRelocation {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "yes" => Ok(InfantRelocation::Yes),
+            "no" => Ok(InfantRelocation::No),
+            "only" => Ok(InfantRelocation::Only),
+            _ => Err(ParseError),
+        }
+    }
+}
+
+/// Criterion deciding when a section has grown enough to split.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SplitMetric {
+    /// Number of adults.
+    Count,
+    /// Total size of adults, in units of the average node size (the number of
+    /// adults is still required to be enough to avoid an immediate merge).
+    Capacity,
+}
+
+impl FromStr for SplitMetric {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "count" => Ok(SplitMetric::Count),
+            "capacity" => Ok(SplitMetric::Capacity),
+            _ => Err(ParseError),
+        }
+    }
+}
+
+/// Behaviour of a section that should split but can't, because its prefix is
+/// already `Params::max_prefix_len` long.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MaxDepth {
+    /// Abort the simulation, reporting the section.
+    Abort,
+    /// Keep the section unsplit, letting it grow past the split threshold.
+    Refuse,
+}
+
+impl FromStr for MaxDepth {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "abort" => Ok(MaxDepth::Abort),
+            "refuse" => Ok(MaxDepth::Refuse),
+            _ => Err(ParseError),
+        }
+    }
+}
+
+/// Columns of the network structure data file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FileFormat {
+    /// The cumulative counters, as consumed by the existing plotting tools.
+    Legacy,
+    /// The legacy columns followed by per-sample deltas, the number of
+    /// complete sections and the newer counters.
+    Extended,
+}
+
+impl FromStr for FileFormat {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "legacy" => Ok(FileFormat::Legacy),
+            "extended" => Ok(FileFormat::Extended),
+            _ => Err(ParseError),
+        }
+    }
+}
+
+/// Distribution summarized (minimum, maximum and average) in the telemetry.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TelemetrySummary {
+    /// Ages of all nodes.
+    Age,
+    /// Ages of the elders.
+    ElderAge,
+    /// Number of nodes per section.
+    SectionSize,
+    /// Prefix lengths of the sections.
+    PrefixLen,
+}
+
+impl TelemetrySummary {
+    /// Name of the summary, as in the command line and the telemetry.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TelemetrySummary::Age => "age",
+            TelemetrySummary::ElderAge => "elder-age",
+            TelemetrySummary::SectionSize => "section-size",
+            TelemetrySummary::PrefixLen => "prefix-len",
+        }
+    }
+}
+
+impl FromStr for TelemetrySummary {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "age" => Ok(TelemetrySummary::Age),
+            "elder-age" => Ok(TelemetrySummary::ElderAge),
+            "section-size" => Ok(TelemetrySummary::SectionSize),
+            "prefix-len" => Ok(TelemetrySummary::PrefixLen),
+            _ => Err(ParseError),
+        }
+    }
+}
+
+/// Which section a joining node chooses.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum JoinModel {
+    /// Every section gets one joining node per iteration.
+    Section,
+    /// Each joining node contacts a random existing node and joins its section,
+    /// so larger sections get more joins. The network gets as many joining
+    /// nodes per iteration as it has sections.
+    Contact,
+}
+
+impl FromStr for JoinModel {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "section" => Ok(JoinModel::Section),
+            "contact" => Ok(JoinModel::Contact),
+            _ => Err(ParseError),
+        }
+    }
+}
+
+/// How the node to drop is chosen.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DropModel {
+    /// Starting from the youngest, each node drops with probability `2^-age`.
+    Age,
+    /// Any node is equally likely to drop, regardless of age. The probability
+    /// that the section drops a node at all is the same as with `Age`.
+    Uniform,
+}
+
+impl DropModel {
+    /// Name of the model, as in the command line.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            DropModel::Age => "age",
+            DropModel::Uniform => "uniform",
+        }
+    }
+}
+
+impl FromStr for DropModel {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "age" => Ok(DropModel::Age),
+            "uniform" => Ok(DropModel::Uniform),
+            _ => Err(ParseError),
+        }
+    }
+}
+
+/// Which of the nodes passing the ageing check is relocated.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RelocationChoice {
+    /// The oldest one.
+    Oldest,
+    /// One aged `adult_age - 1` if any, so the relocation makes it an adult in
+    /// the destination section. Otherwise the oldest one.
+    PreAdult,
+}
+
+impl RelocationChoice {
+    /// Name of the choice, as in the command line.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            RelocationChoice::Oldest => "oldest",
+            RelocationChoice::PreAdult => "pre-adult",
+        }
+    }
+}
+
+impl FromStr for RelocationChoice {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "oldest" => Ok(RelocationChoice::Oldest),
+            "pre-adult" => Ok(RelocationChoice::PreAdult),
+            _ => Err(ParseError),
+        }
+    }
+}
+
+/// Order of nodes of the same age when choosing elders.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ElderTieBreak {
+    /// Prefer the node with the greater name.
+    Name,
+    /// Prefer the node that has been in the section longer (then by name).
+    Seniority,
+}
+
+impl FromStr for ElderTieBreak {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "name" => Ok(ElderTieBreak::Name),
+            "seniority" => Ok(ElderTieBreak::Seniority),
+            _ => Err(ParseError),
+        }
+    }
+}
+
+/// Recovery from a relocation commit not matching any incoming relocation.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnmatchedCommit {
+    /// Admit the node as a fresh join under a new name, as if it restarted
+    /// (see `Node::rejoined`).
+    Rejoin,
+    /// Send the node back to the section it came from.
+    Bounce,
+}
+
+impl FromStr for UnmatchedCommit {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "rejoin" => Ok(UnmatchedCommit::Rejoin),
+            "bounce" => Ok(UnmatchedCommit::Bounce),
+            _ => Err(ParseError),
+        }
+    }
+}
+
+/// Format of the event trace (see `event_trace`).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TraceFormat {
+    /// One JSON object per line.
+    Jsonl,
+    /// Compact binary records.
+    Binary,
+}
+
+impl FromStr for TraceFormat {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "jsonl" => Ok(TraceFormat::Jsonl),
+            "binary" => Ok(TraceFormat::Binary),
+            _ => Err(ParseError),
+        }
+    }
+}
+
+/// Derivation of relocation and join redirect targets (see `chain::Purpose`).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TargetDerivation {
+    /// The ageing hash or the rehashed previous target or node name. Kept for
+    /// comparability with older runs.
+    Legacy,
+    /// Hashed together with the purpose, the source prefix and the attempt
+    /// (see `Hash::derive`), so retries don't follow correlated targets.
+    Separated,
+}
+
+impl FromStr for TargetDerivation {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "legacy" => Ok(TargetDerivation::Legacy),
+            "separated" => Ok(TargetDerivation::Separated),
+            _ => Err(ParseError),
+        }
+    }
+}
+
+/// Period during which the sections overlapping the prefix are unreachable.
+#[derive(Clone, Debug)]
+pub struct Blackout {
+    pub prefix: Prefix,
+    pub start: u64,
+    pub duration: u64,
+}
+
+impl Blackout {
+    pub fn is_active(&self, iteration: u64) -> bool {
+        iteration >= self.start && iteration < self.start.saturating_add(self.duration)
+    }
+}
+
+impl FromStr for Blackout {
+    type Err = ParseError;
+
+    /// Parse from `PREFIX:START:DURATION`, e.g. `0110:1000:200`.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut tokens = input.split(':');
+        let prefix = tokens.next().ok_or(ParseError)?.parse()?;
+        let start = tokens.next().ok_or(ParseError)?.parse().map_err(
+            |_| ParseError,
+        )?;
+        let duration = tokens.next().ok_or(ParseError)?.parse().map_err(
+            |_| ParseError,
+        )?;
+
+        if tokens.next().is_some() {
+            return Err(ParseError);
+        }
+
+        Ok(Blackout {
+            prefix,
+            start,
+            duration,
+        })
+    }
+}
+
+/// Churn independent of the network state, so it's identical for networks with
+/// different parameters.
+#[derive(Clone, Copy, Debug)]
+pub struct SyntheticChurn {
+    /// Average number of nodes joining per iteration.
+    pub join_rate: f64,
+    /// Probability that a node leaves in any given iteration.
+    pub leave_probability: f64,
+}
+
+/// How the simulation advances.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Scheduler {
+    /// Every iteration is a tick in which each section may see a join and a
+    /// drop.
+    Iterations,
+    /// Every iteration is a single join or leave at a continuous time, drawn
+    /// from the `event_rates`. Messages arrive after a random delay, in the
+    /// order of their arrival times, interleaved with the joins and leaves.
+    Events,
+}
+
+/// Rates of the events scheduler, per unit of time.
+#[derive(Clone, Copy, Debug)]
+pub struct EventRates {
+    /// Average number of nodes joining per unit of time.
+    pub join_rate: f64,
+    /// Rate at which every node leaves, per unit of time (0 = never).
+    pub leave_rate: f64,
+    /// Average time a message takes to arrive (0 = immediately).
+    pub message_delay: f64,
+}
+
+impl FromStr for Scheduler {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "iterations" => Ok(Scheduler::Iterations),
+            "events" => Ok(Scheduler::Events),
+            _ => Err(ParseError),
+        }
+    }
+}
+
+/// Cyclical availability of the nodes of some operators (e.g. switched off at
+/// night): during a window of each cycle, their drop probability is multiplied
+/// by a factor.
+#[derive(Clone, Debug)]
+pub struct OperatorProfile {
+    /// Fraction of joining nodes following this profile.
+    pub fraction: f64,
+    /// Length of the cycle, in iterations.
+    pub period: u64,
+    /// Offset of the cycle, in iterations.
+    pub phase: u64,
+    /// Length of the window of increased drops, in iterations.
+    pub duration: u64,
+    /// Drop probability multiplier during the window.
+    pub factor: f64,
+}
+
+impl OperatorProfile {
+    pub fn is_offline_window(&self, iteration: u64) -> bool {
+        (iteration + self.phase) % self.period < self.duration
+    }
+}
+
+impl FromStr for OperatorProfile {
+    type Err = ParseError;
+
+    /// Parse from `FRACTION:PERIOD:PHASE:DURATION:FACTOR`, e.g.
+    /// `0.2:1000:0:300:10`.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        fn value<T: FromStr>(token: Option<&str>) -> Result<T, ParseError> {
+            token.ok_or(ParseError)?.parse().map_err(|_| ParseError)
+        }
+
+        let mut tokens = input.split(':');
+        let profile = OperatorProfile {
+            fraction: value(tokens.next())?,
+            period: value(tokens.next())?,
+            phase: value(tokens.next())?,
+            duration: value(tokens.next())?,
+            factor: value(tokens.next())?,
+        };
+
+        if tokens.next().is_some() || profile.period == 0 ||
+            profile.duration > profile.period || !(0.0..=1.0).contains(&profile.fraction) ||
+            profile.factor.is_nan() || profile.factor < 0.0
+        {
+            return Err(ParseError);
+        }
+
+        Ok(profile)
+    }
+}
+
+/// Change of a parameter at the iteration.
+#[derive(Clone, Debug)]
+pub struct ParamChange {
+    pub iteration: u64,
+    pub setting: Setting,
+}
+
+impl FromStr for ParamChange {
+    type Err = ParseError;
+
+    /// Parse from `ITERATION:NAME=VALUE`, e.g. `1000000:adult-age=8`.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut tokens = input.splitn(2, ':');
+        let iteration = tokens.next().ok_or(ParseError)?.parse().map_err(
+            |_| ParseError,
+        )?;
+        let setting = tokens.next().ok_or(ParseError)?.parse()?;
+
+        Ok(ParamChange { iteration, setting })
+    }
+}
+
+/// New value of one of the parameters that can change during the simulation.
+#[derive(Clone, Copy, Debug)]
+pub enum Setting {
+    InitAge(u8),
+    AdultAge(u8),
+    MinElderAge(u8),
+    MaxInfantsPerSection(usize),
+    InfantAdmissionProbability(f64),
+    RelocateInfants(InfantRelocation),
+    MaxRelocationAttempts(usize),
+    ElderTermLimit(u64),
+    DropModel(DropModel),
+    RelocationChoice(RelocationChoice),
+    CommitBalanceThreshold(usize),
+    MaxRelocationDistance(u8),
+}
+
+impl Params {
+    /// Returns whether all joins and drops come from a churn trace (recorded,
+    /// synthetic or events) instead of the sections.
+    pub fn has_churn_trace(&self) -> bool {
+        self.churn_trace.is_some() || self.synthetic_churn.is_some() ||
+            self.scheduler == Scheduler::Events
+    }
+
+    /// Returns whether the sections record the nodes joining and dropping, for
+    /// the event trace or the node history.
+    pub fn records_membership(&self) -> bool {
+        self.event_trace_file.is_some() || self.survival_file.is_some()
+    }
+
+    /// Current values of all the parameters that can change during the
+    /// simulation.
+    pub fn settings(&self) -> Vec<Setting> {
+        vec![
+            Setting::InitAge(self.init_age),
+            Setting::AdultAge(self.adult_age),
+            Setting::MinElderAge(self.min_elder_age),
+            Setting::MaxInfantsPerSection(self.max_infants_per_section),
+            Setting::InfantAdmissionProbability(self.infant_admission_probability),
+            Setting::RelocateInfants(self.relocate_infants),
+            Setting::MaxRelocationAttempts(self.max_relocation_attempts),
+            Setting::ElderTermLimit(self.elder_term_limit),
+            Setting::DropModel(self.drop_model),
+            Setting::RelocationChoice(self.relocation_choice),
+            Setting::CommitBalanceThreshold(self.commit_balance_threshold),
+            Setting::MaxRelocationDistance(self.max_relocation_distance),
+        ]
+    }
+
+    pub fn apply(&mut self, setting: Setting) {
+        match setting {
+            Setting::InitAge(value) => self.init_age = value,
+            Setting::AdultAge(value) => self.adult_age = value,
+            Setting::MinElderAge(value) => self.min_elder_age = value,
+            Setting::MaxInfantsPerSection(value) => self.max_infants_per_section = value,
+            Setting::InfantAdmissionProbability(value) => {
+                self.infant_admission_probability = value
+            }
+            Setting::RelocateInfants(value) => self.relocate_infants = value,
+            Setting::MaxRelocationAttempts(value) => self.max_relocation_attempts = value,
+            Setting::ElderTermLimit(value) => self.elder_term_limit = value,
+            Setting::DropModel(value) => self.drop_model = value,
+            Setting::RelocationChoice(value) => self.relocation_choice = value,
+            Setting::CommitBalanceThreshold(value) => self.commit_balance_threshold = value,
+            Setting::MaxRelocationDistance(value) => self.max_relocation_distance = value,
+        }
+    }
+}
+
+impl FromStr for Setting {
+    type Err = ParseError;
+
+    /// Parse from `NAME=VALUE`, where `NAME` is the command line option of the
+    /// parameter, e.g. `max-infants-per-section=2`.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        fn value<T: FromStr>(input: &str) -> Result<T, ParseError> {
+            input.parse().map_err(|_| ParseError)
+        }
+
+        let mut tokens = input.splitn(2, '=');
+        let name = tokens.next().ok_or(ParseError)?;
+        let input = tokens.next().ok_or(ParseError)?;
+
+        match name {
+            "init-age" => Ok(Setting::InitAge(value(input)?)),
+            "adult-age" => Ok(Setting::AdultAge(value(input)?)),
+            "min-elder-age" => Ok(Setting::MinElderAge(value(input)?)),
+            "max-infants-per-section" => Ok(Setting::MaxInfantsPerSection(value(input)?)),
+            "infant-admission-probability" => {
+                let value = value(input)?;
+                if !(0.0..=1.0).contains(&value) {
+                    return Err(ParseError);
+                }
+                Ok(Setting::InfantAdmissionProbability(value))
+            }
+            "relocate-infants" => Ok(Setting::RelocateInfants(value(input)?)),
+            "max-relocation-attempts" => Ok(Setting::MaxRelocationAttempts(value(input)?)),
+            "elder-term-limit" => Ok(Setting::ElderTermLimit(value(input)?)),
+            "drop-model" => Ok(Setting::DropModel(value(input)?)),
+            "relocation-choice" => Ok(Setting::RelocationChoice(value(input)?)),
+            "commit-balance-threshold" => Ok(Setting::CommitBalanceThreshold(value(input)?)),
+            "max-relocation-distance" => Ok(Setting::MaxRelocationDistance(value(input)?)),
+            _ => Err(ParseError),
+        }
+    }
+}
+
+impl Setting {
+    /// Command line option of the parameter.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Setting::InitAge(_) => "init-age",
+            Setting::AdultAge(_) => "adult-age",
+            Setting::MinElderAge(_) => "min-elder-age",
+            Setting::MaxInfantsPerSection(_) => "max-infants-per-section",
+            Setting::InfantAdmissionProbability(_) => "infant-admission-probability",
+            Setting::RelocateInfants(_) => "relocate-infants",
+            Setting::MaxRelocationAttempts(_) => "max-relocation-attempts",
+            Setting::ElderTermLimit(_) => "elder-term-limit",
+            Setting::DropModel(_) => "drop-model",
+            Setting::RelocationChoice(_) => "relocation-choice",
+            Setting::CommitBalanceThreshold(_) => "commit-balance-threshold",
+            Setting::MaxRelocationDistance(_) => "max-relocation-distance",
+        }
+    }
+}
+
+/// Written as `NAME=VALUE`, as parsed by `from_str`.
+impl fmt::Display for Setting {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}=", self.name())?;
+        match *self {
+            Setting::InitAge(value) |
+            Setting::AdultAge(value) |
+            Setting::MinElderAge(value) |
+            Setting::MaxRelocationDistance(value) => write!(fmt, "{}", value),
+            Setting::MaxInfantsPerSection(value) |
+            Setting::MaxRelocationAttempts(value) |
+            Setting::CommitBalanceThreshold(value) => write!(fmt, "{}", value),
+            Setting::InfantAdmissionProbability(value) => write!(fmt, "{}", value),
+            Setting::RelocateInfants(value) => write!(fmt, "{}", value.name()),
+            Setting::ElderTermLimit(value) => write!(fmt, "{}", value),
+            Setting::DropModel(value) => write!(fmt, "{}", value.name()),
+            Setting::RelocationChoice(value) => write!(fmt, "{}", value.name()),
+        }
+    }
+}
+
+impl Snapshot for Setting {
+    fn save(&self, writer: &mut Writer) {
+        writer.put(self)
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        reader.get()
+    }
+}
+
+/// Range of values a numeric parameter takes in the chaos mode, given as the
+/// settings of its bounds (both of the same parameter).
+#[derive(Clone, Copy, Debug)]
+pub struct ChaosRange {
+    pub min: Setting,
+    pub max: Setting,
+}
+
+impl ChaosRange {
+    /// Command line option of the parameter.
+    pub fn name(&self) -> &'static str {
+        self.min.name()
+    }
+
+    /// Setting of the parameter to a value drawn uniformly from the range.
+    /// Probabilities are rounded to 3 decimals, to keep the changes readable.
+    pub fn draw<R: Rng>(&self, rng: &mut R) -> Setting {
+        fn integer<R: Rng>(rng: &mut R, min: u64, max: u64) -> u64 {
+            rng.gen_range(min, max.saturating_add(1))
+        }
+
+        match (self.min, self.max) {
+            (Setting::InitAge(min), Setting::InitAge(max)) => {
+                Setting::InitAge(integer(rng, min.into(), max.into()) as u8)
+            }
+            (Setting::AdultAge(min), Setting::AdultAge(max)) => {
+                Setting::AdultAge(integer(rng, min.into(), max.into()) as u8)
+            }
+            (Setting::MinElderAge(min), Setting::MinElderAge(max)) => {
+                Setting::MinElderAge(integer(rng, min.into(), max.into()) as u8)
+            }
+            (Setting::MaxRelocationDistance(min), Setting::MaxRelocationDistance(max)) => {
+                Setting::MaxRelocationDistance(integer(rng, min.into(), max.into()) as u8)
+            }
+            (Setting::MaxInfantsPerSection(min), Setting::MaxInfantsPerSection(max)) => {
+                Setting::MaxInfantsPerSection(integer(rng, min as u64, max as u64) as usize)
+            }
+            (Setting::MaxRelocationAttempts(min), Setting::MaxRelocationAttempts(max)) => {
+                Setting::MaxRelocationAttempts(integer(rng, min as u64, max as u64) as usize)
+            }
+            (Setting::CommitBalanceThreshold(min), Setting::CommitBalanceThreshold(max)) => {
+                Setting::CommitBalanceThreshold(integer(rng, min as u64, max as u64) as usize)
+            }
+            (Setting::ElderTermLimit(min), Setting::ElderTermLimit(max)) => {
+                Setting::ElderTermLimit(integer(rng, min, max))
+            }
+            (Setting::InfantAdmissionProbability(min),
+             Setting::InfantAdmissionProbability(max)) => {
+                let value = min + rng.gen::<f64>() * (max - min);
+                Setting::InfantAdmissionProbability((value * 1000.0).round() / 1000.0)
+            }
+            _ => unreachable!("chaos range of a non-numeric parameter"),
+        }
+    }
+}
+
+impl FromStr for ChaosRange {
+    type Err = ParseError;
+
+    /// Parse from `NAME=MIN..MAX`, where `NAME` is the command line option of
+    /// a numeric parameter that can change during the simulation, e.g.
+    /// `adult-age=4..8`.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut tokens = input.splitn(2, '=');
+        let name = tokens.next().ok_or(ParseError)?;
+        let mut bounds = tokens.next().ok_or(ParseError)?.splitn(2, "..");
+        let min = format!("{}={}", name, bounds.next().ok_or(ParseError)?).parse()?;
+        let max = format!("{}={}", name, bounds.next().ok_or(ParseError)?).parse()?;
+
+        let ordered = match (min, max) {
+            (Setting::InitAge(min), Setting::InitAge(max)) |
+            (Setting::AdultAge(min), Setting::AdultAge(max)) |
+            (Setting::MinElderAge(min), Setting::MinElderAge(max)) |
+            (Setting::MaxRelocationDistance(min), Setting::MaxRelocationDistance(max)) => {
+                min <= max
+            }
+            (Setting::MaxInfantsPerSection(min), Setting::MaxInfantsPerSection(max)) |
+            (Setting::MaxRelocationAttempts(min), Setting::MaxRelocationAttempts(max)) |
+            (Setting::CommitBalanceThreshold(min), Setting::CommitBalanceThreshold(max)) => {
+                min <= max
+            }
+            (Setting::ElderTermLimit(min), Setting::ElderTermLimit(max)) => min <= max,
+            (Setting::InfantAdmissionProbability(min),
+             Setting::InfantAdmissionProbability(max)) => min <= max,
+            // The other parameters aren't numeric.
+            _ => false,
+        };
+
+        if !ordered {
+            return Err(ParseError);
+        }
+
+        Ok(ChaosRange { min, max })
+    }
+}
+
+/// Weights of the run outcomes combined into its score (see
+/// `stats::Outcome::score`). The completeness counts for the run, the others
+/// against it.
+#[derive(Clone, Copy, Debug)]
+pub struct ScoreWeights {
+    pub completeness: f64,
+    pub relocation_cost: f64,
+    pub rejection_rate: f64,
+    pub elder_churn: f64,
+}
+
+impl FromStr for ScoreWeights {
+    type Err = ParseError;
+
+    /// Parse from comma-separated `NAME=WEIGHT` pairs, e.g.
+    /// `completeness=2,elder-churn=0.5`. The outcomes not listed get no
+    /// weight.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut weights = ScoreWeights {
+            completeness: 0.0,
+            relocation_cost: 0.0,
+            rejection_rate: 0.0,
+            elder_churn: 0.0,
+        };
+
+        for pair in input.split(',') {
+            let mut tokens = pair.trim().splitn(2, '=');
+            let name = tokens.next().ok_or(ParseError)?;
+            let weight: f64 = tokens.next().ok_or(ParseError)?.parse().map_err(|_| ParseError)?;
+            if weight < 0.0 || !weight.is_finite() {
+                return Err(ParseError);
+            }
+
+            match name {
+                "completeness" => weights.completeness = weight,
+                "relocation-cost" => weights.relocation_cost = weight,
+                "rejection-rate" => weights.rejection_rate = weight,
+                "elder-churn" => weights.elder_churn = weight,
+                _ => return Err(ParseError),
+            }
+        }
+
+        Ok(weights)
+    }
+}
+
+/// Split or merge of the section with the prefix, forced at the iteration.
+#[derive(Clone, Debug)]
+pub struct ForcedChange {
+    pub prefix: Prefix,
+    pub iteration: u64,
+}
+
+impl FromStr for ForcedChange {
+    type Err = ParseError;
+
+    /// Parse from `PREFIX:ITERATION`, e.g. `01:500`.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut tokens = input.split(':');
+        let prefix = tokens.next().ok_or(ParseError)?.parse()?;
+        let iteration = tokens.next().ok_or(ParseError)?.parse().map_err(
+            |_| ParseError,
+        )?;
+
+        if tokens.next().is_some() {
+            return Err(ParseError);
+        }
+
+        Ok(ForcedChange { prefix, iteration })
+    }
+}
+
+/// Removal of `count` nodes chosen by `victims` (all the eligible ones if
+/// `count` is 0) at the iteration, on top of the regular drops.
+#[derive(Clone, Debug)]
+pub struct Disaster {
+    pub iteration: u64,
+    pub count: usize,
+    pub victims: VictimSelector,
+}
+
+impl FromStr for Disaster {
+    type Err = ParseError;
+
+    /// Parse from `ITERATION:COUNT:VICTIMS`, e.g. `500:20:oldest`.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut tokens = input.split(':');
+        let iteration = tokens.next().ok_or(ParseError)?.parse().map_err(
+            |_| ParseError,
+        )?;
+        let count = tokens.next().ok_or(ParseError)?.parse().map_err(
+            |_| ParseError,
+        )?;
+        let victims = tokens.next().ok_or(ParseError)?.parse()?;
+
+        if tokens.next().is_some() {
+            return Err(ParseError);
+        }
+
+        Ok(Disaster {
+            iteration,
+            count,
+            victims,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use random;
+    use test_params;
+
+    #[test]
+    fn operator_profile_parsing() {
+        let profile: OperatorProfile = "0.2:1000:10:300:10".parse().unwrap();
+        assert_eq!(profile.fraction, 0.2);
+        assert_eq!(profile.period, 1000);
+        assert_eq!(profile.phase, 10);
+        assert_eq!(profile.duration, 300);
+        assert_eq!(profile.factor, 10.0);
+        assert!(profile.is_offline_window(0));
+        assert!(!profile.is_offline_window(290));
+        assert!(profile.is_offline_window(990));
+
+        // The window can span the whole cycle, but not more.
+        assert!("0.2:1000:0:1000:10".parse::<OperatorProfile>().is_ok());
+        assert!("0.2:1000:0:1001:10".parse::<OperatorProfile>().is_err());
+
+        assert!("1.5:1000:0:300:10".parse::<OperatorProfile>().is_err());
+        assert!("0.2:0:0:0:10".parse::<OperatorProfile>().is_err());
+        assert!("0.2:1000:0:300:-1".parse::<OperatorProfile>().is_err());
+        assert!("0.2:1000:0:300".parse::<OperatorProfile>().is_err());
+        assert!("0.2:1000:0:300:10:1".parse::<OperatorProfile>().is_err());
+    }
+
+    #[test]
+    fn blackout_lasting_past_the_last_iteration() {
+        let blackout: Blackout = "01:1000:18446744073709551615".parse().unwrap();
+        assert!(!blackout.is_active(999));
+        assert!(blackout.is_active(1000));
+        assert!(blackout.is_active(u64::MAX - 1));
+    }
+
+    #[test]
+    fn score_weights_parsing() {
+        let weights: ScoreWeights = "completeness=2, elder-churn=0.5".parse().unwrap();
+        assert_eq!(weights.completeness, 2.0);
+        assert_eq!(weights.relocation_cost, 0.0);
+        assert_eq!(weights.rejection_rate, 0.0);
+        assert_eq!(weights.elder_churn, 0.5);
+
+        assert!("completeness".parse::<ScoreWeights>().is_err());
+        assert!("completeness=-1".parse::<ScoreWeights>().is_err());
+        assert!("completeness=inf".parse::<ScoreWeights>().is_err());
+        assert!("completeness=1,speed=1".parse::<ScoreWeights>().is_err());
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let settings = [
+            Setting::InitAge(3),
+            Setting::AdultAge(6),
+            Setting::MinElderAge(7),
+            Setting::MaxInfantsPerSection(2),
+            Setting::InfantAdmissionProbability(0.25),
+            Setting::RelocateInfants(InfantRelocation::Only),
+            Setting::MaxRelocationAttempts(4),
+            Setting::ElderTermLimit(20),
+            Setting::DropModel(DropModel::Uniform),
+            Setting::RelocationChoice(RelocationChoice::PreAdult),
+            Setting::CommitBalanceThreshold(5),
+            Setting::MaxRelocationDistance(2),
+        ];
+
+        for setting in &settings {
+            let text = setting.to_string();
+            let parsed: Setting = text.parse().unwrap();
+            assert_eq!(parsed.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn chaos_range_parsing() {
+        let range: ChaosRange = "adult-age=4..8".parse().unwrap();
+        assert_eq!(range.name(), "adult-age");
+        assert_eq!(range.min.to_string(), "adult-age=4");
+        assert_eq!(range.max.to_string(), "adult-age=8");
+
+        let range: ChaosRange = "infant-admission-probability=0.2..0.5".parse().unwrap();
+        assert_eq!(range.max.to_string(), "infant-admission-probability=0.5");
+
+        assert!("adult-age=8..4".parse::<ChaosRange>().is_err());
+        assert!("adult-age=4.5..8".parse::<ChaosRange>().is_err());
+        assert!("adult-age=4".parse::<ChaosRange>().is_err());
+        assert!("infant-admission-probability=0.5..1.5".parse::<ChaosRange>().is_err());
+        assert!("drop-model=age..uniform".parse::<ChaosRange>().is_err());
+        assert!("iterations=1..5".parse::<ChaosRange>().is_err());
+    }
+
+    #[test]
+    fn chaos_draws_stay_within_range() {
+        let seed = "[1, 2, 3, 4]".parse().unwrap();
+        let mut rng = random::independent_rng(RngKind::XorShift, seed, 2);
+
+        let range: ChaosRange = "adult-age=4..6".parse().unwrap();
+        let mut drawn = [false; 3];
+        for _ in 0..100 {
+            match range.draw(&mut rng) {
+                Setting::AdultAge(age) if (4..=6).contains(&age) => drawn[age as usize - 4] = true,
+                setting => panic!("drawn out of range: {}", setting),
+            }
+        }
+        assert_eq!(drawn, [true; 3]);
+
+        let range: ChaosRange = "infant-admission-probability=0.2..0.3".parse().unwrap();
+        for _ in 0..100 {
+            match range.draw(&mut rng) {
+                Setting::InfantAdmissionProbability(value) => {
+                    assert!((0.2..=0.3).contains(&value), "{}", value)
+                }
+                setting => panic!("drawn out of range: {}", setting),
+            }
+        }
+    }
+
+    #[test]
+    fn startup_age_dead_end() {
+        assert!(test_params(&[]).dead_ends().is_empty());
+        assert!(test_params(&["--startup-age", "6"]).dead_ends().is_empty());
+
+        let dead_ends = test_params(&["--startup-age", "1"]).dead_ends();
+        assert_eq!(dead_ends.len(), 1);
+        assert!(dead_ends[0].contains("startup-age 1 < adult-age 5"));
+
+        // With startup infants, the startup age is not used.
+        let dead_ends = test_params(&["--startup-age", "1", "--startup-infants"]).dead_ends();
+        assert_eq!(dead_ends.len(), 1);
+        assert!(dead_ends[0].contains("init-age 4 < adult-age 5"));
+    }
+
+    #[test]
+    fn dead_ends_after_changes() {
+        let params = |args: &[&str]| {
+            let mut full = vec!["--relocate-infants", "no", "--adult-age", "4"];
+            full.extend_from_slice(args);
+            test_params(&full)
+        };
+
+        assert!(params(&[]).dead_ends().is_empty());
+        assert!(params(&[]).dead_ends_after_changes().is_empty());
+
+        // Infants would never become adults after the change.
+        let dead_ends = params(&["--param-change", "100:adult-age=5"]).dead_ends_after_changes();
+        assert_eq!(dead_ends.len(), 1);
+        assert!(dead_ends[0].starts_with("after adult-age=5 at iteration 100: "));
+
+        // Changing back before the later change still reaches it.
+        let dead_ends = params(&[
+            "--param-change",
+            "200:relocate-infants=yes",
+            "--param-change",
+            "100:adult-age=5",
+        ]).dead_ends_after_changes();
+        assert_eq!(dead_ends.len(), 1);
+
+        // Only the upper bound of the chaos range is a dead end.
+        let dead_ends = params(&["--chaos", "adult-age=3..6"]).dead_ends_after_changes();
+        assert_eq!(dead_ends.len(), 1);
+        assert!(dead_ends[0].starts_with("with chaos setting adult-age=6: "));
+    }
 }
@@ -1,5 +1,7 @@
 use parse::ParseError;
 use rand::{Rand, Rng};
+use snapshot::{Reader, Snapshot, Writer};
+use std::cmp;
 use std::fmt;
 use std::str::FromStr;
 
@@ -34,13 +36,28 @@ pub struct Prefix {
 
 impl Prefix {
     pub const EMPTY: Self = Prefix { bits: 0, len: 0 };
+    /// The longest prefix possible: one matching a single name.
+    pub const MAX_LEN: u8 = 64;
+
+    /// The prefix of the given length (at most `MAX_LEN`) matching the given
+    /// name.
+    pub fn new(len: u8, name: Name) -> Self {
+        let len = cmp::min(len, Self::MAX_LEN);
+        let mask = Prefix { len, bits: 0 }.len_mask();
+        Prefix {
+            len,
+            bits: name.0 & mask,
+        }
+    }
 
     pub fn len(&self) -> u8 {
         self.len
     }
 
+    /// The prefix extended by the given bit, or this prefix unchanged if it's
+    /// already `MAX_LEN` long (see `is_max_len`).
     pub fn extend(self, bit: u8) -> Prefix {
-        if self.len > 63 {
+        if self.is_max_len() {
             return self;
         }
         let bit = (u64::from(bit) & 1) << (63 - self.len);
@@ -61,6 +78,11 @@ impl Prefix {
         }
     }
 
+    /// Returns whether the prefix can't be extended any more.
+    pub fn is_max_len(&self) -> bool {
+        self.len >= Self::MAX_LEN
+    }
+
     pub fn split(self) -> [Prefix; 2] {
         [self.extend(0), self.extend(1)]
     }
@@ -108,7 +130,6 @@ impl Prefix {
         }
     }
 
-    #[allow(unused)]
     pub fn is_neighbour(&self, other: &Prefix) -> bool {
         let diff = self.bits ^ other.bits;
         let bit = diff.leading_zeros() as u8;
@@ -141,6 +162,10 @@ impl FromStr for Prefix {
     type Err = ParseError;
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
+        if input.len() > usize::from(Self::MAX_LEN) {
+            return Err(ParseError);
+        }
+
         let mut prefix = Self::EMPTY;
         for c in input.chars() {
             match c {
@@ -174,8 +199,215 @@ impl fmt::Display for Prefix {
     }
 }
 
+impl Snapshot for Name {
+    fn save(&self, writer: &mut Writer) {
+        writer.put(self.0)
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok(Name(reader.get()?))
+    }
+}
+
+// Written as the length and the bits, as the empty prefix has no digits.
+impl Snapshot for Prefix {
+    fn save(&self, writer: &mut Writer) {
+        writer.put(self.len);
+        writer.put(self.bits);
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        let len = reader.get()?;
+        let bits = reader.get()?;
+        let prefix = Prefix::new(len, Name(bits));
+        if prefix.len != len || prefix.bits != bits {
+            return Err(format!("invalid prefix in snapshot: {} {}", len, bits));
+        }
+        Ok(prefix)
+    }
+}
+
 impl fmt::Debug for Prefix {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         write!(fmt, "Prefix({})", self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, XorShiftRng};
+
+    const NUM_SAMPLES: usize = 10_000;
+
+    fn rng() -> XorShiftRng {
+        XorShiftRng::from_seed([0x193a_6754, 0xa8a7_d469, 0x9783_0e05, 0x113b_a7bb])
+    }
+
+    fn gen_prefix<R: Rng>(rng: &mut R, max_len: u8) -> Prefix {
+        let len = rng.gen_range(0, max_len + 1);
+        (0..len).fold(Prefix::EMPTY, |prefix, _| prefix.extend(rng.gen()))
+    }
+
+    #[test]
+    fn extend_then_shorten_round_trips() {
+        let mut rng = rng();
+
+        for _ in 0..NUM_SAMPLES {
+            let prefix = gen_prefix(&mut rng, 63);
+            let bit = rng.gen_range(0, 2);
+            let extended = prefix.extend(bit);
+
+            assert_eq!(extended.len(), prefix.len() + 1);
+            assert!(prefix.is_ancestor(&extended));
+            assert_eq!(extended.shorten(), prefix);
+        }
+    }
+
+    #[test]
+    fn sibling_is_involutive() {
+        let mut rng = rng();
+
+        for _ in 0..NUM_SAMPLES {
+            let prefix = gen_prefix(&mut rng, 64);
+            let sibling = prefix.sibling();
+
+            assert_eq!(sibling.sibling(), prefix);
+
+            if prefix.len() > 0 {
+                assert_ne!(sibling, prefix);
+                assert!(prefix.is_sibling(&sibling));
+                assert!(sibling.is_sibling(&prefix));
+                assert_eq!(sibling.shorten(), prefix.shorten());
+            } else {
+                assert_eq!(sibling, prefix);
+            }
+        }
+    }
+
+    #[test]
+    fn matches_is_consistent_with_substituted_in() {
+        let mut rng = rng();
+
+        for _ in 0..NUM_SAMPLES {
+            let prefix = gen_prefix(&mut rng, 64);
+            let name = rng.gen();
+            let substituted = prefix.substituted_in(name);
+
+            assert!(prefix.matches(substituted));
+            assert_eq!(prefix.substituted_in(substituted), substituted);
+
+            if prefix.matches(name) {
+                assert_eq!(substituted, name);
+            }
+        }
+    }
+
+    #[test]
+    fn is_neighbour_is_symmetric() {
+        let mut rng = rng();
+
+        for _ in 0..NUM_SAMPLES {
+            let prefix0 = gen_prefix(&mut rng, 64);
+            let prefix1 = gen_prefix(&mut rng, 64);
+
+            assert_eq!(
+                prefix0.is_neighbour(&prefix1),
+                prefix1.is_neighbour(&prefix0),
+                "{:?} {:?}",
+                prefix0,
+                prefix1
+            );
+
+            // Neighbours differ in exactly one bit, so they can't be compatible.
+            if prefix0.is_neighbour(&prefix1) {
+                assert!(!prefix0.is_compatible_with(&prefix1));
+            }
+        }
+    }
+
+    #[test]
+    fn new_matches_name() {
+        let mut rng = rng();
+
+        for _ in 0..NUM_SAMPLES {
+            let name = rng.gen();
+            let len = rng.gen_range(0, 65);
+            let prefix = Prefix::new(len, name);
+
+            assert_eq!(prefix.len(), len);
+            assert!(prefix.matches(name));
+            assert_eq!(prefix.substituted_in(name), name);
+            assert!(prefix.is_ancestor(&Prefix::new(64, name)));
+        }
+    }
+
+    #[test]
+    fn split_prefixes_partition_matching_names() {
+        let mut rng = rng();
+
+        for _ in 0..NUM_SAMPLES {
+            let prefix = gen_prefix(&mut rng, 63);
+            let name = prefix.substituted_in(rng.gen());
+            let [prefix0, prefix1] = prefix.split();
+
+            assert!(prefix0.matches(name) ^ prefix1.matches(name));
+            assert!(prefix0.is_sibling(&prefix1));
+            assert!(prefix.is_ancestor(&prefix0));
+            assert!(prefix.is_ancestor(&prefix1));
+        }
+    }
+
+    #[test]
+    fn extend_at_max_len_is_identity() {
+        let mut rng = rng();
+
+        for _ in 0..NUM_SAMPLES {
+            let name = rng.gen();
+            let prefix = Prefix::new(Prefix::MAX_LEN, name);
+
+            assert!(prefix.is_max_len());
+            assert_eq!(prefix.extend(rng.gen()), prefix);
+            assert_eq!(prefix.split(), [prefix, prefix]);
+            assert!(!prefix.shorten().is_max_len());
+            assert!(prefix.shorten().split().contains(&prefix));
+        }
+    }
+
+    #[test]
+    fn max_len_prefix_matches_single_name() {
+        let mut rng = rng();
+
+        for _ in 0..NUM_SAMPLES {
+            let name: Name = rng.gen();
+            let prefix = Prefix::new(Prefix::MAX_LEN, name);
+            let bit = rng.gen_range(0, Prefix::MAX_LEN);
+            let other = Name(name.0 ^ (1 << (63 - bit)));
+
+            assert!(prefix.matches(name));
+            assert!(!prefix.matches(other));
+            assert!(prefix.is_neighbour(&Prefix::new(Prefix::MAX_LEN, other)));
+            assert_eq!(prefix.with_flipped_bit(bit), Prefix::new(Prefix::MAX_LEN, other));
+        }
+    }
+
+    #[test]
+    fn display_then_parse_round_trips() {
+        let mut rng = rng();
+
+        for _ in 0..NUM_SAMPLES {
+            let prefix = gen_prefix(&mut rng, Prefix::MAX_LEN);
+            let parsed: Prefix = prefix.to_string().parse().unwrap();
+
+            assert_eq!(parsed, prefix);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_too_long_prefixes() {
+        let input: String = (0..=Prefix::MAX_LEN).map(|_| '1').collect();
+
+        assert!(input.parse::<Prefix>().is_err());
+        assert!(input[1..].parse::<Prefix>().is_ok());
+    }
+}
@@ -0,0 +1,93 @@
+//! Provenance of node names, for tracing individual nodes through the logs.
+
+use HashMap;
+use node::{NodeId, Origin};
+use prefix::{Name, Prefix};
+use section::Section;
+use std::fmt;
+
+/// How and when a node got its name.
+pub struct Provenance {
+    pub id: NodeId,
+    pub origin: Origin,
+    pub iteration: u64,
+    /// Section the node was in when first seen with the name.
+    pub section: Prefix,
+    /// Previous name of the node and the section it was in, if the node was
+    /// relocated.
+    pub source: Option<(Name, Prefix)>,
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "{:6}:\t{:?} node #{} in [{}]",
+            self.iteration,
+            self.origin,
+            self.id,
+            self.section
+        )?;
+
+        if let Some((name, prefix)) = self.source {
+            write!(fmt, " from {:?} in [{}]", name, prefix)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct ProvenanceTable {
+    // All the nodes that had the name, in the order they got it.
+    names: HashMap<Name, Vec<Provenance>>,
+    // Name and section of each node at the last observation.
+    last_seen: HashMap<NodeId, (Name, Prefix)>,
+}
+
+impl ProvenanceTable {
+    pub fn new() -> Self {
+        ProvenanceTable {
+            names: HashMap::default(),
+            last_seen: HashMap::default(),
+        }
+    }
+
+    /// Record the names of the nodes present at the end of the given iteration.
+    /// Names that appear and disappear within a single iteration are missed.
+    pub fn observe<'a, I: IntoIterator<Item = &'a Section>>(&mut self, iteration: u64, sections: I) {
+        for section in sections {
+            for node in section.nodes().values() {
+                let entries = self.names.entry(node.name()).or_default();
+                if entries.last().map(|entry| entry.id) != Some(node.id()) {
+                    let source = match self.last_seen.get(&node.id()) {
+                        Some(&(name, prefix)) if name != node.name() => Some((name, prefix)),
+                        _ => None,
+                    };
+
+                    entries.push(Provenance {
+                        id: node.id(),
+                        origin: node.origin(),
+                        iteration,
+                        section: section.prefix(),
+                        source,
+                    });
+                }
+
+                let _ = self.last_seen.insert(node.id(), (node.name(), section.prefix()));
+            }
+        }
+    }
+
+    /// Returns the names whose hex representation starts with the given string,
+    /// with their provenance, sorted by name.
+    pub fn lookup(&self, hex: &str) -> Vec<(Name, &[Provenance])> {
+        let hex = hex.to_lowercase();
+        let mut result: Vec<_> = self.names
+            .iter()
+            .filter(|&(name, _)| format!("{:016x}", name.0).starts_with(&hex))
+            .map(|(&name, entries)| (name, &entries[..]))
+            .collect();
+        result.sort_by_key(|&(name, _)| name);
+        result
+    }
+}
@@ -0,0 +1,165 @@
+//! Audit trail of the relocations, for investigating anomalies flagged by the
+//! correctness checks.
+//!
+//! Every relocation exchange (see `ExchangeId`) is written as one JSON object
+//! per line, with the iterations its messages were sent in, e.g.:
+//!
+//! `{"id":"12/3","node":"4a3f00c2b81d9e07","age":5,"source":"01",
+//! "destination":"110","cause":"ageing","requested":100,"accepted":100,
+//! "rejected":null,"committed":101,"cancelled":null,
+//! "new_name":"c913be04d2a17f55","new_age":6,"outcome":"completed"}`
+//!
+//! The outcome is `completed` once the destination took the node in under its
+//! new name, otherwise the last message sent: `committed` (the commit didn't
+//! match the destination's accepted relocations), `rejected`, `cancelled`,
+//! `accepted` (e.g. the source gave up waiting for the response) or
+//! `requested`.
+
+use Age;
+use json::Object;
+use message::{ExchangeId, Message, RelocationCause};
+use network;
+use prefix::{Name, Prefix};
+use section::Section;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+struct Trail {
+    name: Name,
+    age: Option<Age>,
+    source: Option<Prefix>,
+    destination: Option<Prefix>,
+    cause: Option<RelocationCause>,
+    requested: u64,
+    accepted: Option<u64>,
+    rejected: Option<u64>,
+    committed: Option<u64>,
+    cancelled: Option<u64>,
+    new_name: Option<Name>,
+    new_age: Option<Age>,
+}
+
+impl Trail {
+    fn outcome(&self) -> &'static str {
+        if self.new_name.is_some() {
+            "completed"
+        } else if self.committed.is_some() {
+            "committed"
+        } else if self.cancelled.is_some() {
+            "cancelled"
+        } else if self.rejected.is_some() {
+            "rejected"
+        } else if self.accepted.is_some() {
+            "accepted"
+        } else {
+            "requested"
+        }
+    }
+}
+
+pub struct RelocationTrace {
+    trails: BTreeMap<ExchangeId, Trail>,
+}
+
+impl RelocationTrace {
+    pub fn new() -> Self {
+        RelocationTrace { trails: BTreeMap::new() }
+    }
+
+    /// Record the relocation message sent in the given iteration.
+    pub fn sent(&mut self, iteration: u64, message: &Message, sections: &BTreeMap<Prefix, Section>) {
+        match *message {
+            Message::RelocateRequest { id, node_name, target } => {
+                let source = network::prefix_for(sections, node_name);
+                let age = source
+                    .and_then(|prefix| sections[&prefix].nodes().get(&node_name))
+                    .map(|node| node.age());
+                let _ = self.trails.insert(
+                    id,
+                    Trail {
+                        name: node_name,
+                        age,
+                        source,
+                        destination: network::prefix_for(sections, target),
+                        cause: None,
+                        requested: iteration,
+                        accepted: None,
+                        rejected: None,
+                        committed: None,
+                        cancelled: None,
+                        new_name: None,
+                        new_age: None,
+                    },
+                );
+            }
+            Message::RelocateAccept { id, .. } => {
+                if let Some(trail) = self.trails.get_mut(&id) {
+                    trail.accepted = Some(iteration);
+                }
+            }
+            Message::RelocateReject { id, .. } => {
+                if let Some(trail) = self.trails.get_mut(&id) {
+                    trail.rejected = Some(iteration);
+                }
+            }
+            Message::RelocateCommit {
+                id,
+                ref node,
+                target,
+                cause,
+            } => {
+                if let Some(trail) = self.trails.get_mut(&id) {
+                    trail.committed = Some(iteration);
+                    trail.cause = Some(cause);
+                    trail.new_age = Some(node.age());
+                    trail.destination = network::prefix_for(sections, target);
+                }
+            }
+            Message::RelocateCancel { id, .. } => {
+                if let Some(trail) = self.trails.get_mut(&id) {
+                    trail.cancelled = Some(iteration);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Record the name the destination gave to the relocated node.
+    pub fn renamed(&mut self, id: ExchangeId, new_name: Name) {
+        if let Some(trail) = self.trails.get_mut(&id) {
+            trail.new_name = Some(new_name);
+        }
+    }
+
+    /// Write one JSON object per relocation exchange, in the order of their
+    /// ids.
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) {
+        let path = path.as_ref();
+
+        let mut file = File::create(path).unwrap_or_else(|_| {
+            panic!("Couldn't create file {}!", path.display())
+        });
+
+        for (id, trail) in &self.trails {
+            let mut object = Object::new();
+            let _ = object
+                .field("id", id)
+                .field("node", &trail.name)
+                .field("age", &trail.age)
+                .field("source", &trail.source)
+                .field("destination", &trail.destination)
+                .field("cause", &trail.cause)
+                .field("requested", &trail.requested)
+                .field("accepted", &trail.accepted)
+                .field("rejected", &trail.rejected)
+                .field("committed", &trail.committed)
+                .field("cancelled", &trail.cancelled)
+                .field("new_name", &trail.new_name)
+                .field("new_age", &trail.new_age)
+                .field("outcome", trail.outcome());
+            let _ = writeln!(file, "{}", object.encode());
+        }
+    }
+}
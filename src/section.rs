@@ -1,121 +1,779 @@
+use Age;
 use HashMap;
 use HashSet;
-use chain::{Block, Chain, Event, Hash};
+use age_index::AgeIndex;
+use byteorder::{ByteOrder, LittleEndian};
+use chain::{Block, Chain, Event, Hash, Purpose};
+use decision::{self, View};
+use error::SimError;
+use event_trace::Membership;
+use lineage::SectionId;
 use log;
-use message::{Action, Message};
-use node::{self, Node};
-use params::Params;
+use message::{Action, ExchangeId, Message, RelocationCause};
+use node::{self, Node, NodeIds, Origin};
+use params::{JoinModel, MaxDepth, Params, TargetDerivation, UnmatchedCommit};
 use prefix::{Name, Prefix};
-use random;
+use random::{self, Category};
+use simulation::Policies;
+use snapshot::{Reader, Snapshot, Writer};
+use stats::{AgeingCounts, Churn, Counters, OriginCounts, RelocationResponses};
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
 use std::collections::hash_map::{self, Entry};
 use std::fmt;
 use std::mem;
-use std::u8;
+use termination::Reason;
+
+/// Lifecycle state of a section.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum State {
+    /// The root section: nodes join with the startup age and nobody is
+    /// relocated. It stays in this state until it splits, like the checks of
+    /// the root prefix it replaces.
+    Startup,
+    /// Regular operation.
+    Stable,
+    /// The section requested a split, which the network carries out at the end
+    /// of the tick.
+    Splitting,
+    /// The section requested a merge, which the network carries out at the end
+    /// of the tick.
+    Merging,
+}
+
+impl Snapshot for State {
+    fn save(&self, writer: &mut Writer) {
+        writer.put(*self as u8)
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        match reader.get::<u8>()? {
+            0 => Ok(State::Startup),
+            1 => Ok(State::Stable),
+            2 => Ok(State::Splitting),
+            3 => Ok(State::Merging),
+            tag => Err(format!("invalid section state in snapshot: {}", tag)),
+        }
+    }
+}
+
+/// A relocation exchange in progress, incoming or outgoing.
+#[derive(Debug)]
+pub struct Relocation {
+    id: ExchangeId,
+    target: Name,
+    // Number of iterations since the exchange started.
+    pending: u64,
+    // Number of section ticks since the node's relocation was first requested,
+    // across the retries (outgoing only).
+    ticks: u64,
+    // Earlier exchanges for the same node that the destination rejected
+    // (outgoing only), to tell late accepts of those from other orphans.
+    rejected: Vec<ExchangeId>,
+}
+
+impl Relocation {
+    fn new(id: ExchangeId, target: Name) -> Self {
+        Relocation {
+            id,
+            target,
+            pending: 0,
+            ticks: 0,
+            rejected: Vec::new(),
+        }
+    }
+
+    /// Whether the exchange should have ended by now: within the iteration it
+    /// started in or, with a `relocation_timeout`, before it timed out.
+    pub fn is_overdue(&self, params: &Params) -> bool {
+        params.relocation_timeout == 0 || self.pending > params.relocation_timeout
+    }
+}
+
+impl Snapshot for Relocation {
+    fn save(&self, writer: &mut Writer) {
+        self.id.save(writer);
+        self.target.save(writer);
+        self.pending.save(writer);
+        self.ticks.save(writer);
+        self.rejected.save(writer);
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok(Relocation {
+            id: Snapshot::load(reader)?,
+            target: Snapshot::load(reader)?,
+            pending: Snapshot::load(reader)?,
+            ticks: Snapshot::load(reader)?,
+            rejected: Snapshot::load(reader)?,
+        })
+    }
+}
 
 pub struct Section {
+    id: SectionId,
     prefix: Prefix,
-    nodes: HashMap<Name, Node>,
+    state: State,
+    nodes: BTreeMap<Name, Node>,
+    // The `nodes` indexed by age.
+    ages: AgeIndex,
     chain: Chain,
     messages: Vec<Message>,
-    incoming_relocations: HashMap<Name, Name>,
-    outgoing_relocations: HashMap<Name, Name>,
+    // Names of the nodes that left this section or were relocated away (also
+    // covering its ancestors), to detect names being reused.
+    past_names: HashSet<Name>,
+    // Accepted incoming relocations, by the name of the node.
+    incoming_relocations: HashMap<Name, Relocation>,
+    // Requested outgoing relocations, by the name of the node.
+    outgoing_relocations: HashMap<Name, Relocation>,
+    // Sequence number of the next relocation exchange this section starts.
+    next_exchange: u64,
+    // Causes of those `outgoing_relocations` not triggered by ageing.
+    relocation_causes: HashMap<Name, RelocationCause>,
     recent_join: bool,
     recent_drop: bool,
+    recent_term_check: bool,
+    recent_size_info: bool,
+    recent_need_info: bool,
+    // Neighbours short of adults that asked for relocations, with the number of
+    // iterations left to direct relocations to them.
+    needy_neighbours: BTreeMap<Prefix, u64>,
+    // Number of iterations since this section, short of adults, started asking
+    // its neighbours for relocations instead of merging.
+    rescue_wait: Option<u64>,
+    // Whether the neighbours were asked for relocations in this wait.
+    adults_requested: bool,
+    // Nodes the rescue relocations brought in during this wait.
+    rescued: BTreeSet<Name>,
+    // Number of relocation retries in the current tick.
+    relocation_retries: usize,
+    // Whether this section currently refuses all incoming joins and relocations.
+    unreachable: bool,
+    // Whether joins are stopped and nodes dropped at random (shrink test).
+    shrinking: bool,
+    // Churn events since this section was created.
+    churn: Churn,
+    // Membership changes of `churn` before the current tick.
+    churn_before_tick: u64,
+    // Node events by node origin, since last taken.
+    origin_counts: BTreeMap<Origin, OriginCounts>,
+    // Events counted by the section itself (unmatched relocation commits,
+    // relocation approval failures), since last taken.
+    counters: Counters,
+    // Number of iterations since an elder was relocated away, while the section
+    // still has less than `group_size` elders.
+    elder_gap: Option<u64>,
+    // Ageing check outcomes by node age, since last taken.
+    ageing_counts: BTreeMap<Age, AgeingCounts>,
+    // Responses to incoming relocation requests, since last taken.
+    relocation_responses: RelocationResponses,
+    // Lengths of the elder gaps that ended, since last taken.
+    elder_gaps: Vec<u64>,
+    // Number of iterations since this section was split off its parent, while
+    // it's not complete yet.
+    completion_gap: Option<u64>,
+    // Lengths of the completion gaps that ended, since last taken.
+    completion_gaps: Vec<u64>,
+    // Adults imbalance between our halves at each relocation commit, since
+    // last taken.
+    commit_imbalances: Vec<u64>,
+    // Section ticks from the first request to the commit of every relocation
+    // out, since last taken.
+    relocation_latencies: Vec<u64>,
+    // Names given to the nodes relocated in, by exchange, for the relocation
+    // trace.
+    relocation_names: Vec<(ExchangeId, Name)>,
+    // Nodes joined and dropped, since last taken, for the event trace.
+    membership_events: Vec<Membership>,
+    // Ages of the nodes promoted to elder for the first time, since last taken.
+    first_promotion_ages: Vec<Age>,
+    // Number of iterations since this section was created.
+    lifetime: u64,
+    // Current iteration of the simulation.
+    iteration: u64,
+    // Ids for the nodes joining, shared with the rest of the network.
+    node_ids: NodeIds,
 }
 
 impl Section {
-    pub fn new(prefix: Prefix) -> Self {
+    pub fn new(id: SectionId, prefix: Prefix, node_ids: NodeIds) -> Self {
         Section {
+            id,
             prefix,
-            nodes: HashMap::default(),
+            state: if prefix == Prefix::EMPTY {
+                State::Startup
+            } else {
+                State::Stable
+            },
+            nodes: BTreeMap::new(),
+            ages: AgeIndex::new(),
             chain: Chain::new(),
             messages: Vec::new(),
+            past_names: HashSet::default(),
             incoming_relocations: HashMap::default(),
             outgoing_relocations: HashMap::default(),
+            next_exchange: 0,
+            relocation_causes: HashMap::default(),
             recent_join: false,
             recent_drop: false,
+            recent_term_check: false,
+            recent_size_info: false,
+            recent_need_info: false,
+            needy_neighbours: BTreeMap::new(),
+            rescue_wait: None,
+            adults_requested: false,
+            rescued: BTreeSet::new(),
+            relocation_retries: 0,
+            unreachable: false,
+            shrinking: false,
+            churn: Churn::default(),
+            churn_before_tick: 0,
+            origin_counts: BTreeMap::new(),
+            counters: Counters::default(),
+            ageing_counts: BTreeMap::new(),
+            relocation_responses: RelocationResponses::default(),
+            elder_gap: None,
+            elder_gaps: Vec::new(),
+            completion_gap: None,
+            completion_gaps: Vec::new(),
+            commit_imbalances: Vec::new(),
+            relocation_latencies: Vec::new(),
+            relocation_names: Vec::new(),
+            membership_events: Vec::new(),
+            first_promotion_ages: Vec::new(),
+            lifetime: 0,
+            iteration: 0,
+            node_ids,
+        }
+    }
+
+    /// Write the state of the section into the snapshot. The full chain and
+    /// the records kept for the event and relocation traces are left out.
+    pub fn save(&self, writer: &mut Writer) {
+        self.id.save(writer);
+        self.prefix.save(writer);
+        self.state.save(writer);
+        self.nodes.save(writer);
+        self.chain.save(writer);
+        self.messages.save(writer);
+        self.past_names.save(writer);
+        self.incoming_relocations.save(writer);
+        self.outgoing_relocations.save(writer);
+        self.next_exchange.save(writer);
+        self.relocation_causes.save(writer);
+        self.recent_join.save(writer);
+        self.recent_drop.save(writer);
+        self.recent_term_check.save(writer);
+        self.recent_size_info.save(writer);
+        self.recent_need_info.save(writer);
+        self.needy_neighbours.save(writer);
+        self.rescue_wait.save(writer);
+        self.adults_requested.save(writer);
+        self.rescued.save(writer);
+        self.relocation_retries.save(writer);
+        self.unreachable.save(writer);
+        self.shrinking.save(writer);
+        self.churn.save(writer);
+        self.churn_before_tick.save(writer);
+        self.origin_counts.save(writer);
+        self.counters.save(writer);
+        self.elder_gap.save(writer);
+        self.ageing_counts.save(writer);
+        self.relocation_responses.save(writer);
+        self.elder_gaps.save(writer);
+        self.completion_gap.save(writer);
+        self.completion_gaps.save(writer);
+        self.commit_imbalances.save(writer);
+        self.relocation_latencies.save(writer);
+        self.first_promotion_ages.save(writer);
+        self.lifetime.save(writer);
+        self.iteration.save(writer);
+    }
+
+    /// Read a section written by `save`.
+    pub fn load(reader: &mut Reader, node_ids: NodeIds) -> Result<Self, String> {
+        let id = Snapshot::load(reader)?;
+        let prefix = Snapshot::load(reader)?;
+        let mut section = Section::new(id, prefix, node_ids);
+        section.state = Snapshot::load(reader)?;
+        section.nodes = Snapshot::load(reader)?;
+        for node in section.nodes.values() {
+            section.ages.insert(node);
         }
+        section.chain = Snapshot::load(reader)?;
+        section.messages = Snapshot::load(reader)?;
+        section.past_names = Snapshot::load(reader)?;
+        section.incoming_relocations = Snapshot::load(reader)?;
+        section.outgoing_relocations = Snapshot::load(reader)?;
+        section.next_exchange = Snapshot::load(reader)?;
+        section.relocation_causes = Snapshot::load(reader)?;
+        section.recent_join = Snapshot::load(reader)?;
+        section.recent_drop = Snapshot::load(reader)?;
+        section.recent_term_check = Snapshot::load(reader)?;
+        section.recent_size_info = Snapshot::load(reader)?;
+        section.recent_need_info = Snapshot::load(reader)?;
+        section.needy_neighbours = Snapshot::load(reader)?;
+        section.rescue_wait = Snapshot::load(reader)?;
+        section.adults_requested = Snapshot::load(reader)?;
+        section.rescued = Snapshot::load(reader)?;
+        section.relocation_retries = Snapshot::load(reader)?;
+        section.unreachable = Snapshot::load(reader)?;
+        section.shrinking = Snapshot::load(reader)?;
+        section.churn = Snapshot::load(reader)?;
+        section.churn_before_tick = Snapshot::load(reader)?;
+        section.origin_counts = Snapshot::load(reader)?;
+        section.counters = Snapshot::load(reader)?;
+        section.elder_gap = Snapshot::load(reader)?;
+        section.ageing_counts = Snapshot::load(reader)?;
+        section.relocation_responses = Snapshot::load(reader)?;
+        section.elder_gaps = Snapshot::load(reader)?;
+        section.completion_gap = Snapshot::load(reader)?;
+        section.completion_gaps = Snapshot::load(reader)?;
+        section.commit_imbalances = Snapshot::load(reader)?;
+        section.relocation_latencies = Snapshot::load(reader)?;
+        section.first_promotion_ages = Snapshot::load(reader)?;
+        section.lifetime = Snapshot::load(reader)?;
+        section.iteration = Snapshot::load(reader)?;
+        Ok(section)
+    }
+
+    pub fn id(&self) -> SectionId {
+        self.id
     }
 
     pub fn prefix(&self) -> Prefix {
         self.prefix
     }
 
-    pub fn nodes(&self) -> &HashMap<Name, Node> {
+    pub fn nodes(&self) -> &BTreeMap<Name, Node> {
         &self.nodes
     }
 
-    #[allow(unused)]
+    /// Add the nodes of the initial layout, without counting them as joins, and
+    /// elect the elders.
+    pub fn populate<I: IntoIterator<Item = Node>>(&mut self, params: &Params, nodes: I) {
+        if params.full_chain {
+            self.chain.keep_all();
+        }
+        for node in nodes {
+            self.ages.insert(&node);
+            let _ = self.nodes.insert(node.name(), node);
+        }
+        self.update_elders(params);
+    }
+
+    /// Returns whether the age index is in sync with the nodes.
+    pub fn is_age_index_valid(&self) -> bool {
+        self.ages.matches(&self.nodes)
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Returns whether the attacker controls this section.
+    pub fn is_malicious(&self, params: &Params) -> bool {
+        self.view().is_malicious(params)
+    }
+
+    /// Total size of the nodes in this section.
+    pub fn capacity(&self) -> u64 {
+        self.nodes.values().map(|node| node.size()).sum()
+    }
+
+    /// Returns whether the section has enough adults and a full group of
+    /// elders.
     pub fn is_complete(&self, params: &Params) -> bool {
-        node::count_adults(params, self.nodes.values()) >= params.group_size
+        node::count_adults(params, self.nodes.values()) >= params.group_size &&
+            self.num_elders() >= params.group_size
+    }
+
+    /// Returns whether the section has at least `group_size` nodes, but not
+    /// enough of them are old enough to form a full group of elders.
+    pub fn lacks_eligible_elders(&self, params: &Params) -> bool {
+        self.nodes.len() >= params.group_size &&
+            self.nodes
+                .values()
+                .filter(|node| node.age() >= params.min_elder_age)
+                .count() < params.group_size
+    }
+
+    /// Returns whether the section is short of adults and waiting for its
+    /// neighbours to relocate some to it.
+    pub fn needs_adults(&self) -> bool {
+        self.rescue_wait.is_some()
+    }
+
+    fn num_elders(&self) -> usize {
+        self.nodes.values().filter(|node| node.is_elder()).count()
+    }
+
+    /// Shannon entropy of the distribution of node names over the `bits` bits
+    /// following the section prefix, normalized to the range `[0, 1]`. A value
+    /// of 1 means the names cover the sub-space perfectly evenly.
+    pub fn name_entropy(&self, bits: u8) -> f64 {
+        if self.nodes.is_empty() || bits == 0 {
+            return 0.0;
+        }
+
+        let mut counts = vec![0u64; 1 << bits];
+        for name in self.nodes.keys() {
+            let shifted = name.0.checked_shl(u32::from(self.prefix.len())).unwrap_or(0);
+            counts[(shifted >> (64 - u32::from(bits))) as usize] += 1;
+        }
+
+        let total = self.nodes.len() as f64;
+        let entropy: f64 = counts
+            .into_iter()
+            .filter(|&count| count > 0)
+            .map(|count| {
+                let p = count as f64 / total;
+                -p * p.log2()
+            })
+            .sum();
+
+        entropy / f64::from(bits)
+    }
+
+    pub fn incoming_relocations(&self) -> hash_map::Iter<'_, Name, Relocation> {
+        self.incoming_relocations.iter()
+    }
+
+    pub fn outgoing_relocations(&self) -> hash_map::Iter<'_, Name, Relocation> {
+        self.outgoing_relocations.iter()
+    }
+
+    /// Append the canonical serialization of the section state (prefix, nodes
+    /// and pending relocations, all sorted by name) to `bytes`.
+    pub fn write_state(&self, bytes: &mut Vec<u8>) {
+        fn write_name(bytes: &mut Vec<u8>, name: Name) {
+            let mut buffer = [0; 8];
+            LittleEndian::write_u64(&mut buffer, name.0);
+            bytes.extend_from_slice(&buffer);
+        }
+
+        bytes.push(self.prefix.len());
+        write_name(bytes, self.prefix.substituted_in(Name(0)));
+
+        let mut nodes: Vec<_> = self.nodes.values().collect();
+        nodes.sort_by_key(|node| node.name());
+        write_name(bytes, Name(nodes.len() as u64));
+        for node in nodes {
+            write_name(bytes, node.name());
+            bytes.push(node.age());
+            bytes.push(node.is_elder() as u8);
+        }
+
+        let mut incoming: Vec<_> = self.incoming_relocations
+            .iter()
+            .map(|(&name, relocation)| (name, relocation.target))
+            .collect();
+        let mut outgoing: Vec<_> = self.outgoing_relocations
+            .iter()
+            .map(|(&name, relocation)| (name, relocation.target))
+            .collect();
+        incoming.sort();
+        outgoing.sort();
+
+        for relocations in &[incoming, outgoing] {
+            write_name(bytes, Name(relocations.len() as u64));
+            for &(name, target) in relocations {
+                write_name(bytes, name);
+                write_name(bytes, target);
+            }
+        }
+    }
+
+    /// Detailed human readable description of the section state, for
+    /// diagnostics.
+    pub fn dump(&self) -> String {
+        let mut output = format!(
+            "Section {:?} ({:?})\n\nNodes ({}):\n",
+            self.prefix,
+            self.state,
+            self.nodes.len()
+        );
+
+        for node in self.ages.iter().rev().map(|name| &self.nodes[&name]) {
+            output.push_str(&format!(
+                "    {:?}{}\n",
+                node,
+                if node.is_elder() { " (elder)" } else { "" }
+            ));
+        }
+
+        output.push_str(&format!(
+            "\nIncoming relocations: {:?}\nOutgoing relocations: {:?}\n",
+            self.incoming_relocations,
+            self.outgoing_relocations,
+        ));
+
+        output.push_str(&format!("\nPending messages ({}):\n", self.messages.len()));
+        for message in &self.messages {
+            output.push_str(&format!("    {:?}\n", message));
+        }
+
+        if let Some(num_blocks) = self.chain.num_blocks() {
+            output.push_str(&format!("\nBlocks in the full chain: {}\n", num_blocks));
+        }
+
+        output.push_str("\nRecent blocks:\n");
+        for block in self.chain.recent() {
+            output.push_str(&format!("    {:?}\n", block));
+        }
+
+        output
+    }
+
+    /// Mark this section as unreachable (refusing all joins and incoming
+    /// relocations) or reachable again.
+    pub fn set_unreachable(&mut self, unreachable: bool) {
+        self.unreachable = unreachable
+    }
+
+    pub fn churn(&self) -> Churn {
+        self.churn
+    }
+
+    /// Number of nodes added or removed in the current tick (or since the
+    /// section was created by a split or merge in it).
+    pub fn tick_churn(&self) -> u64 {
+        self.churn.membership_changes() - self.churn_before_tick
+    }
+
+    /// Returns the node events counted by node origin since the last call, and
+    /// resets them.
+    pub fn take_origin_counts(&mut self) -> BTreeMap<Origin, OriginCounts> {
+        mem::take(&mut self.origin_counts)
     }
 
-    pub fn incoming_relocations(&self) -> hash_map::Keys<Name, Name> {
-        self.incoming_relocations.keys()
+    pub fn take_ageing_counts(&mut self) -> BTreeMap<Age, AgeingCounts> {
+        mem::take(&mut self.ageing_counts)
     }
 
-    pub fn outgoing_relocations(&self) -> hash_map::Keys<Name, Name> {
-        self.outgoing_relocations.keys()
+    pub fn take_relocation_responses(&mut self) -> RelocationResponses {
+        mem::take(&mut self.relocation_responses)
+    }
+
+    /// Stop (or resume) all joins to this section and drop its nodes regardless
+    /// of age.
+    pub fn set_shrinking(&mut self, shrinking: bool) {
+        self.shrinking = shrinking
+    }
+
+    /// Returns the events counted by this section since the last call, and
+    /// resets them.
+    pub fn take_counters(&mut self) -> Counters {
+        mem::take(&mut self.counters)
+    }
+
+    /// Take the number of iterations each elder relocated away since last
+    /// called took to be replaced.
+    pub fn take_elder_gaps(&mut self) -> Vec<u64> {
+        mem::take(&mut self.elder_gaps)
+    }
+
+    /// Take the number of iterations each section split off since last called
+    /// took to become complete.
+    pub fn take_completion_gaps(&mut self) -> Vec<u64> {
+        mem::take(&mut self.completion_gaps)
+    }
+
+    pub fn take_commit_imbalances(&mut self) -> Vec<u64> {
+        mem::take(&mut self.commit_imbalances)
+    }
+
+    pub fn take_relocation_latencies(&mut self) -> Vec<u64> {
+        mem::take(&mut self.relocation_latencies)
+    }
+
+    pub fn take_relocation_names(&mut self) -> Vec<(ExchangeId, Name)> {
+        mem::take(&mut self.relocation_names)
+    }
+
+    pub fn take_membership_events(&mut self) -> Vec<Membership> {
+        mem::take(&mut self.membership_events)
+    }
+
+    pub fn take_chain_violations(&mut self) -> Vec<String> {
+        self.chain.take_violations()
+    }
+
+    pub fn take_first_promotion_ages(&mut self) -> Vec<Age> {
+        mem::take(&mut self.first_promotion_ages)
     }
 
     /// Call this at the begining of each simulation tick to reset some internal state.
-    pub fn prepare(&mut self) {
+    /// Returns the outgoing relocations that expired (see `Params::relocation_timeout`).
+    pub fn prepare(&mut self, params: &Params, iteration: u64) -> Vec<(ExchangeId, Name)> {
+        self.iteration = iteration;
         self.recent_join = false;
         self.recent_drop = false;
+        self.recent_term_check = false;
+        self.recent_size_info = false;
+        self.recent_need_info = false;
+        for ticks in self.needy_neighbours.values_mut() {
+            *ticks -= 1;
+        }
+        self.needy_neighbours.retain(|_, ticks| *ticks > 0);
+        self.relocation_retries = 0;
+        self.churn_before_tick = self.churn.membership_changes();
+        self.lifetime += 1;
+        if params.full_chain {
+            self.chain.keep_all();
+        }
+        self.chain.tick();
+
+        // The network carries out the split or merge in the same tick, unless
+        // it was superseded (e.g. by a merge of an ancestor).
+        if self.state == State::Splitting || self.state == State::Merging {
+            self.state = State::Stable;
+        }
+
+        if let Some(ref mut gap) = self.elder_gap {
+            *gap += 1;
+        }
+
+        if let Some(ref mut gap) = self.completion_gap {
+            *gap += 1;
+        }
+
+        if let Some(ref mut wait) = self.rescue_wait {
+            *wait += 1;
+        }
+
+        for node in self.nodes.values_mut() {
+            node.increment_term();
+            node.increment_tenure();
+        }
+
+        self.expire_relocations(params)
     }
 
-    /// Single simulation iteration of this section.
+    /// Single simulation iteration of this section. The resulting actions are
+    /// appended to `actions`.
     /// Note: there can be multiple section ticks per network tick.
-    pub fn tick(&mut self, params: &Params) -> Vec<Action> {
-        let mut actions = Vec::new();
+    pub fn tick(&mut self, params: &Params, policies: &Policies, actions: &mut Vec<Action>) {
         let mut relocated_in = false;
 
-        for message in mem::replace(&mut self.messages, Vec::new()) {
-            debug!(
-                "{}: received {}",
-                log::prefix(&self.prefix),
-                log::message(&message)
-            );
+        for relocation in self.outgoing_relocations.values_mut() {
+            relocation.ticks += 1;
+        }
+
+        // Reuse the message buffer across ticks, to avoid reallocating it.
+        let mut messages = mem::take(&mut self.messages);
+
+        for message in messages.drain(..) {
+            debug!("received {}", log::message(&message));
 
             match message {
-                Message::RelocateRequest { node_name, target } => {
+                Message::RelocateRequest {
+                    id,
+                    node_name,
+                    target,
+                } => {
                     actions.push(if relocated_in {
-                        Action::Send(Message::RelocateReject { node_name, target })
+                        Action::Send(Message::RelocateReject {
+                            id,
+                            node_name,
+                            target,
+                        })
                     } else {
-                        self.handle_relocate_request(params, node_name, target)
+                        self.handle_relocate_request(params, id, node_name, target)
                     })
                 }
-                Message::RelocateAccept { node_name, target } => {
-                    actions.extend(self.handle_relocate_accept(node_name, target))
+                Message::RelocateAccept { id, node_name, .. } => {
+                    actions.extend(self.handle_relocate_accept(params, id, node_name))
                 }
-                Message::RelocateReject { node_name, target } => {
-                    actions.extend(self.handle_relocate_reject(params, node_name, target));
+                Message::RelocateReject { id, node_name, .. } => {
+                    actions.extend(self.handle_relocate_reject(params, id, node_name));
                 }
-                Message::RelocateCommit { node, .. } => {
-                    if let Some(action) = self.handle_relocate_commit(params, &node) {
+                Message::RelocateCommit {
+                    id,
+                    node,
+                    target,
+                    cause,
+                } => {
+                    let num_actions = actions.len();
+                    actions.extend(
+                        self.handle_relocate_commit(params, policies, id, &node, target, cause),
+                    );
+                    if actions.len() > num_actions {
                         relocated_in = true;
-                        actions.push(action);
                     }
                 }
-                Message::RelocateCancel { node_name, .. } => self.handle_relocate_cancel(node_name),
+                Message::RelocateCancel { id, node_name, .. } => {
+                    self.handle_relocate_cancel(id, node_name)
+                }
+                Message::JoinRedirect { node, target } => {
+                    actions.extend(self.handle_join_redirect(params, policies, node, target))
+                }
+                Message::Bounce { node } => {
+                    actions.extend(self.handle_bounce(params, policies, node))
+                }
+                Message::SiblingSize { source, size, .. } => {
+                    actions.extend(self.handle_sibling_size(params, source, size))
+                }
+                Message::NeedAdults { source, .. } => self.handle_need_adults(params, source),
             }
         }
 
+        self.messages = messages;
+
+        actions.extend(self.send_size_info(params));
+        actions.extend(self.send_need_info(params));
+        actions.extend(self.try_force_relocate(params));
+
         if !relocated_in {
             if self.incoming_relocations.is_empty() {
-                if random::gen() {
-                    actions.extend(self.random_join(params));
-                    actions.extend(self.random_drop(params));
+                let join_first = {
+                    let _category = random::category(Category::TieBreaking);
+                    random::gen()
+                };
+
+                if join_first {
+                    actions.extend(self.random_join(params, policies));
+                    actions.extend(self.random_drop(params, policies));
                 } else {
-                    actions.extend(self.random_drop(params));
-                    actions.extend(self.random_join(params));
+                    actions.extend(self.random_drop(params, policies));
+                    actions.extend(self.random_join(params, policies));
                 }
             } else {
-                actions.extend(self.random_drop(params));
+                actions.extend(self.random_drop(params, policies));
             }
         }
+    }
 
-        actions
+    /// Credit the nodes for serving this tick and for handling client requests.
+    /// Each node earns one credit per tick, plus one credit per request handled.
+    /// On average `client_load` requests arrive per tick, each handled by a
+    /// random adult.
+    pub fn earn_credits(&mut self, params: &Params) {
+        for node in self.nodes.values_mut() {
+            node.add_credits(1);
+        }
+
+        let mut adults: Vec<_> = self.nodes
+            .values()
+            .filter(|node| node.is_adult(params))
+            .map(|node| node.name())
+            .collect();
+        if adults.is_empty() {
+            return;
+        }
+        adults.sort();
+
+        let mut num_requests = params.client_load.trunc() as u64;
+        if random::gen_bool_with_probability(params.client_load.fract()) {
+            num_requests += 1;
+        }
+
+        for _ in 0..num_requests {
+            let name = adults[random::gen::<usize>() % adults.len()];
+            if let Some(node) = self.nodes.get_mut(&name) {
+                node.add_credits(1);
+            }
+        }
     }
 
     /// Receive a message. The messages are actually handled later, during `tick`.
@@ -123,31 +781,82 @@ impl Section {
         self.messages.push(message)
     }
 
-    pub fn split(self, params: &Params) -> (Section, Section) {
+    /// Disconnect the node with the given name (e.g. due to restart).
+    pub fn disconnect(&mut self, params: &Params, policies: &Policies, name: Name) -> Vec<Action> {
+        self.handle_dead(params, policies, name)
+    }
+
+    /// Let the given node (fresh, or one that previously disconnected) join
+    /// this section.
+    pub fn join(&mut self, params: &Params, policies: &Policies, node: Node) -> Vec<Action> {
+        if self.unreachable {
+            return vec![self.reject_node(node)];
+        }
+
+        self.handle_live(params, policies, node)
+    }
+
+    /// Split this section into two, giving them the ids `id0` and `id1`.
+    pub fn split(mut self, params: &Params, id0: SectionId, id1: SectionId) -> (Section, Section) {
         let prefixes = self.prefix.split();
 
         debug!(
-            "{}: splitting into {} and {}",
-            log::prefix(&self.prefix),
+            "splitting into {} and {}",
             log::prefix(&prefixes[0]),
             log::prefix(&prefixes[1]),
         );
 
-        let mut section0 = Section::new(prefixes[0]);
-        let mut section1 = Section::new(prefixes[1]);
+        let mut section0 = Section::new(id0, prefixes[0], self.node_ids.clone());
+        let mut section1 = Section::new(id1, prefixes[1], self.node_ids.clone());
+
+        section0.unreachable = self.unreachable;
+        section1.unreachable = self.unreachable;
+        section0.shrinking = self.shrinking;
+        section1.shrinking = self.shrinking;
+        section0.origin_counts = self.origin_counts;
+        section0.ageing_counts = self.ageing_counts;
+        section0.relocation_responses = self.relocation_responses;
+        // A gap still open is not counted, as both halves elect a full set of
+        // elders anew if they can.
+        section0.elder_gaps = self.elder_gaps;
+        section0.completion_gaps = self.completion_gaps;
+        section0.commit_imbalances = self.commit_imbalances;
+        section0.relocation_latencies = self.relocation_latencies;
+        section0.relocation_names = self.relocation_names;
+        section0.membership_events = self.membership_events;
+        section0.completion_gap = Some(0);
+        section1.completion_gap = Some(0);
+        section0.first_promotion_ages = self.first_promotion_ages;
 
         section0.chain = self.chain.clone();
         section1.chain = self.chain;
+        section0.chain.retain_lifecycles(prefixes[0]);
+        section1.chain.retain_lifecycles(prefixes[1]);
 
         // Nodes
+        if params.age_on_split {
+            for node in self.nodes.values_mut() {
+                node.increment_age();
+                if node.age() == params.adult_age {
+                    section0.counters.matured_nodes += 1;
+                }
+            }
+        }
+
         let (nodes0, nodes1) = split(self.nodes, prefixes[0], prefixes[1], |&(name, _)| name);
 
         section0.nodes = nodes0;
+        section0.ages = AgeIndex::build(section0.nodes.values());
         section0.update_elders(params);
 
         section1.nodes = nodes1;
+        section1.ages = AgeIndex::build(section1.nodes.values());
         section1.update_elders(params);
 
+        let (names0, names1) = split(self.past_names, prefixes[0], prefixes[1], |&name| name);
+        section0.past_names = names0;
+        section1.past_names = names1;
+
         // Outgoing relocations
         let (nodes0, nodes1) = split(
             self.outgoing_relocations,
@@ -159,12 +868,22 @@ impl Section {
         section0.outgoing_relocations = nodes0;
         section1.outgoing_relocations = nodes1;
 
+        let (nodes0, nodes1) = split(
+            self.relocation_causes,
+            prefixes[0],
+            prefixes[1],
+            |&(name, _)| name,
+        );
+
+        section0.relocation_causes = nodes0;
+        section1.relocation_causes = nodes1;
+
         // Incoming relocations
         let (nodes0, nodes1) = split(
             self.incoming_relocations,
             prefixes[0],
             prefixes[1],
-            |&(_, target)| target,
+            |(_, relocation)| relocation.target,
         );
 
         section0.incoming_relocations = nodes0;
@@ -188,61 +907,130 @@ impl Section {
 
     pub fn merge(&mut self, params: &Params, other: Section) {
         debug!(
-            "{}: merging {} adults from {}",
-            log::prefix(&self.prefix),
+            "merging {} adults from {}",
             node::count_adults(params, other.nodes.values()),
             log::prefix(&other.prefix),
         );
 
         self.chain.extend(other.chain);
         self.nodes.extend(other.nodes);
+        self.ages.extend(other.ages);
         self.messages.extend(other.messages);
+        self.past_names.extend(other.past_names);
         self.incoming_relocations.extend(other.incoming_relocations);
         self.outgoing_relocations.extend(other.outgoing_relocations);
+        self.relocation_causes.extend(other.relocation_causes);
+        self.unreachable |= other.unreachable;
+        self.shrinking |= other.shrinking;
+        for (origin, counts) in other.origin_counts {
+            *self.origin_counts.entry(origin).or_default() += counts;
+        }
+        for (age, counts) in other.ageing_counts {
+            *self.ageing_counts.entry(age).or_default() += counts;
+        }
+        self.relocation_responses += other.relocation_responses;
+        self.elder_gap = cmp::max(self.elder_gap, other.elder_gap);
+        self.elder_gaps.extend(other.elder_gaps);
+        // A section merged before becoming complete never ends its gap.
+        self.completion_gaps.extend(other.completion_gaps);
+        self.commit_imbalances.extend(other.commit_imbalances);
+        self.relocation_latencies.extend(other.relocation_latencies);
+        self.relocation_names.extend(other.relocation_names);
+        self.membership_events.extend(other.membership_events);
+        self.first_promotion_ages.extend(other.first_promotion_ages);
         self.update_elders(params);
     }
 
-    fn handle_live(&mut self, params: &Params, mut node: Node) -> Option<Action> {
-        // During startup, nodes joining as adult (age of 5), and no relocation.
-        if self.prefix == Prefix::EMPTY {
-            node = Node::new(node.name(), params.adult_age)
-        } else if node.is_infant(params) &&
-                   node::count_infants(params, self.nodes.values()) >=
-                       params.max_infants_per_section
+    fn handle_live(&mut self, params: &Params, policies: &Policies, mut node: Node) -> Vec<Action> {
+        // Restarted nodes rejoin under their old name, anybody else reusing a
+        // name would tangle the per-node history.
+        if node.origin() != Origin::Rejoined &&
+            (self.nodes.contains_key(&node.name()) || self.past_names.contains(&node.name()))
         {
-            return Some(self.reject_node(node));
+            self.counters.reused_names += 1;
+            if params.reject_reused_names {
+                return vec![self.reject_node(node)];
+            }
+        }
+
+        // During startup, nodes joining with the startup age (adult by default),
+        // and no relocation.
+        if self.state == State::Startup {
+            if !params.startup_infants {
+                node.set_age(params.startup_age);
+            }
+        } else if node.is_infant(params) && !policies.admission.admit_infant(params, &self.view()) {
+            return self.handle_rejection(params, policies, node);
         }
 
         let name = node.name();
         let age = node.age();
         let is_adult = node.is_adult(params);
 
+        if params.records_membership() {
+            self.membership_events.push(Membership::joined(&node));
+        }
         self.join_node(node);
         self.update_elders(params);
 
         if let Some(action) = self.try_split(params) {
-            Some(action)
+            vec![action]
         } else if is_adult {
-            self.try_relocate(params, &Block::new(Event::Live, name, age))
+            self.try_relocate(params, policies, &Block::new(Event::Live, name, age))
+                .into_iter()
+                .collect()
         } else {
-            None
+            Vec::new()
+        }
+    }
+
+    fn handle_rejection(
+        &mut self,
+        params: &Params,
+        policies: &Policies,
+        node: Node,
+    ) -> Vec<Action> {
+        let block = Block::new(Event::Rejected, node.name(), node.age());
+        let mut actions = vec![self.reject_node(node)];
+
+        // Optionally treat the rejected join attempt as a churn event that
+        // counts towards ageing the existing members.
+        if params.rejections_trigger_ageing {
+            actions.extend(self.try_relocate(params, policies, &block));
         }
+
+        actions
     }
 
-    fn handle_dead(&mut self, params: &Params, name: Name) -> Vec<Action> {
+    fn handle_dead(&mut self, params: &Params, policies: &Policies, name: Name) -> Vec<Action> {
         let mut actions = Vec::new();
 
         if let Some(node) = self.drop_node(name) {
-            if let Some(target) = self.outgoing_relocations.remove(&node.name()) {
+            self.churn.drops += 1;
+            self.origin_counts.entry(node.origin()).or_default().drops += 1;
+            if params.records_membership() {
+                self.membership_events.push(Membership::dropped(&node));
+            }
+
+            // Only the full chain records the elders dropping, so the default
+            // chain (and the relocations its retention limits allow) is
+            // unaffected.
+            if params.full_chain && node.is_elder() {
+                self.chain.insert(Block::new(Event::Dead, node.name(), node.age()));
+            }
+
+            if let Some(relocation) = self.outgoing_relocations.remove(&node.name()) {
+                let _ = self.relocation_causes.remove(&node.name());
+
                 debug!(
-                    "{}: cancelling relocation of {} (node dropped)",
-                    log::prefix(&self.prefix),
+                    "cancelling relocation of {} (node dropped)",
                     log::name(&node.name())
                 );
 
                 actions.push(Action::Send(Message::RelocateCancel {
+                    id: relocation.id,
                     node_name: node.name(),
-                    target,
+                    target: relocation.target,
                 }));
             }
 
@@ -251,7 +1039,15 @@ impl Section {
             if node.is_adult(params) {
                 self.update_elders(params);
                 if let Some(block) = self.chain.last_live() {
-                    actions.extend(self.try_relocate(params, &block));
+                    if self.chain.is_last_live_retained(
+                        params.chain_retention_blocks,
+                        params.chain_retention_ticks,
+                    )
+                    {
+                        actions.extend(self.try_relocate(params, policies, &block));
+                    } else {
+                        self.counters.truncated_lookups += 1;
+                    }
                 }
             }
         }
@@ -262,202 +1058,679 @@ impl Section {
     fn handle_relocate_request(
         &mut self,
         params: &Params,
+        id: ExchangeId,
         node_name: Name,
         target: Name,
     ) -> Action {
-        if !self.incoming_relocations.is_empty() || self.nodes.len() >= params.max_section_size {
-            debug!(
-                "{}: rejecting relocation of {}",
-                log::prefix(&self.prefix),
-                log::name(&node_name),
-            );
+        let busy = !self.incoming_relocations.is_empty();
+        if self.unreachable || busy || self.nodes.len() >= params.max_section_size ||
+            self.deny_relocation(params) || !self.approve_relocation(params)
+        {
+            debug!("rejecting relocation of {}", log::name(&node_name));
 
-            Action::Send(Message::RelocateReject { node_name, target })
-        } else {
-            debug!(
-                "{}: accepting relocation of {}",
-                log::prefix(&self.prefix),
-                log::name(&node_name),
-            );
+            self.relocation_responses.rejected += 1;
+            if busy {
+                self.relocation_responses.busy += 1;
+            }
 
-            let _ = self.incoming_relocations.insert(node_name, target);
-            Action::Send(Message::RelocateAccept { node_name, target })
+            Action::Send(Message::RelocateReject {
+                id,
+                node_name,
+                target,
+            })
+        } else {
+            debug!("accepting relocation of {}", log::name(&node_name));
+
+            self.relocation_responses.accepted += 1;
+            let _ = self.incoming_relocations.insert(node_name, Relocation::new(id, target));
+            Action::Send(Message::RelocateAccept {
+                id,
+                node_name,
+                target,
+            })
         }
     }
 
-    fn handle_relocate_accept(&mut self, node_name: Name, target: Name) -> Option<Action> {
-        if self.outgoing_relocations.remove(&node_name).is_some() {
-            if let Some(mut node) = self.nodes.remove(&node_name) {
-                node.increment_age();
-                if node.is_elder() {
-                    node.demote();
-                    self.chain.insert(
-                        Block::new(Event::Dead, node_name, node.age()),
-                    );
-                }
+    // Relocation denial attack: a section controlled by the attacker rejects all
+    // incoming relocations.
+    fn deny_relocation(&mut self, params: &Params) -> bool {
+        if params.relocation_denial && self.view().is_malicious(params) {
+            self.counters.denied_relocations += 1;
+            true
+        } else {
+            false
+        }
+    }
 
-                return Some(Action::Send(Message::RelocateCommit { node, target }));
-            }
+    // Each elder independently rejects the relocation with the configured
+    // probability (modelling a stale view of the network). Returns whether a
+    // quorum of the elders approved.
+    fn approve_relocation(&mut self, params: &Params) -> bool {
+        if params.elder_reject_probability <= 0.0 {
+            return true;
         }
 
-        None
+        let approvals = self.nodes
+            .values()
+            .filter(|node| node.is_elder())
+            .filter(|_| !random::gen_bool_with_probability(params.elder_reject_probability))
+            .count();
+
+        if approvals >= params.quorum() {
+            true
+        } else {
+            self.counters.approval_failures += 1;
+            false
+        }
     }
 
-    fn handle_relocate_reject(
+    fn handle_relocate_accept(
         &mut self,
         params: &Params,
+        id: ExchangeId,
         node_name: Name,
-        target: Name,
     ) -> Option<Action> {
-        match self.outgoing_relocations.entry(node_name) {
-            Entry::Occupied(mut entry) => {
-                // Do not retry the relocation during startup or if it would trigger merge.
-                if self.prefix == Prefix::EMPTY ||
-                    node::count_adults(params, self.nodes.values()) <= params.group_size
-                {
-                    debug!(
-                        "{}: cancelling relocation of {} (not beneficial anymore)",
-                        log::prefix(&self.prefix),
-                        log::name(entry.key())
-                    );
-
-                    entry.remove();
-                    None
-                } else {
-                    // Calculate new relocation target.
-                    let target = Hash::from(target).rehash().into();
-
-                    debug!(
-                        "{}: re-initiating relocation of {} to {}",
-                        log::prefix(&self.prefix),
-                        log::name(entry.key()),
-                        log::name(&target)
-                    );
+        let relocation = self.take_response(id, node_name)?;
+        let cause = self.relocation_causes.remove(&node_name).unwrap_or(
+            RelocationCause::Ageing,
+        );
 
-                    *entry.get_mut() = target;
-                    Some(Action::Send(Message::RelocateRequest { node_name, target }))
-                }
-            }
-            Entry::Vacant(_) => None,
+        let mut node = self.nodes.remove(&node_name)?;
+        self.relocation_latencies.push(relocation.ticks);
+        self.ages.remove(&node);
+        let _ = self.past_names.insert(node_name);
+        self.churn.relocations_out += 1;
+        if cause == RelocationCause::Ageing || cause == RelocationCause::Rescue {
+            self.ageing_counts.entry(node.age()).or_default().relocated += 1;
         }
-    }
-
-    fn handle_relocate_commit(&mut self, params: &Params, node: &Node) -> Option<Action> {
-        if self.incoming_relocations.remove(&node.name()).is_none() {
-            panic!(
-                "{}: cannot commit relocation of {}: not found in incoming relocation cache",
-                log::prefix(&self.prefix),
-                log::name(&node.name())
+        node.increment_age();
+        if node.age() == params.adult_age {
+            self.counters.matured_nodes += 1;
+        }
+        if node.is_elder() {
+            node.demote();
+            self.chain.insert(
+                Block::new(Event::Dead, node_name, node.age()),
             );
+            self.elder_gap = self.elder_gap.or(Some(0));
         }
 
-        // Pick the new node name so it would fall into the subsection with
-        // fewer members, to keep the section balanced.
-        let prefixes = self.prefix.split();
-        let count0 = node::count_matching_adults(params, prefixes[0], self.nodes.values());
-        let count1 = node::count_matching_adults(params, prefixes[1], self.nodes.values());
-
-        let new_name = random::gen();
-        let new_name = if count0 < count1 {
-            prefixes[0].substituted_in(new_name)
-        } else {
-            prefixes[1].substituted_in(new_name)
-        };
-
-        debug!(
-            "{}: relocating {} -> {}",
-            log::prefix(&self.prefix),
-            log::name(&node.name()),
-            log::name(&new_name),
-        );
-
-        self.handle_live(params, Node::new(new_name, node.age()))
+        Some(Action::Send(Message::RelocateCommit {
+            id,
+            node,
+            target: relocation.target,
+            cause,
+        }))
     }
 
-    fn handle_relocate_cancel(&mut self, node_name: Name) {
-        let _ = self.incoming_relocations.remove(&node_name);
+    fn handle_relocate_reject(
+        &mut self,
+        params: &Params,
+        id: ExchangeId,
+        node_name: Name,
+    ) -> Option<Action> {
+        let relocation = self.take_response(id, node_name)?;
+
+        // Do not retry the relocation during startup or if it would trigger merge.
+        // Balancing relocations are only worth it towards the sibling, so
+        // don't retry those either. With the targets constrained by distance,
+        // the few candidate sections might all keep rejecting, so also give
+        // up after too many retries in a single tick.
+        self.relocation_retries += 1;
+
+        if self.state == State::Startup ||
+            node::count_adults(params, self.nodes.values()) <= params.merge_threshold() ||
+            self.relocation_causes.get(&node_name) == Some(&RelocationCause::Balancing) ||
+            params.max_relocation_distance > 0 &&
+                self.relocation_retries > params.max_relocation_attempts
+        {
+            debug!(
+                "cancelling relocation of {} (not beneficial anymore)",
+                log::name(&node_name)
+            );
+
+            let _ = self.relocation_causes.remove(&node_name);
+            return None;
+        }
+
+        // A rejected rescue falls back to a regular ageing relocation.
+        if self.relocation_causes.get(&node_name) == Some(&RelocationCause::Rescue) {
+            let _ = self.relocation_causes.remove(&node_name);
+        }
+
+        // Calculate new relocation target.
+        let target = derive_target(
+            params,
+            Purpose::Retry,
+            self.prefix,
+            relocation.rejected.len() as u32 + 1,
+            &Hash::from(relocation.target),
+        );
+        let target = constrain_target(params, self.prefix, target);
+
+        debug!(
+            "re-initiating relocation of {} to {}",
+            log::name(&node_name),
+            log::name(&target)
+        );
+
+        Some(self.request_relocation(node_name, target, Some(relocation)))
     }
 
-    // Simulate random node attempt to join this section.
-    fn random_join(&mut self, params: &Params) -> Option<Action> {
-        if self.recent_join {
+    // Remove the outgoing relocation of the node the response to the given
+    // exchange is for. Responses not matching the current exchange of the node
+    // are counted as protocol anomalies and ignored.
+    fn take_response(&mut self, id: ExchangeId, node_name: Name) -> Option<Relocation> {
+        match self.outgoing_relocations.entry(node_name) {
+            Entry::Occupied(ref entry) if entry.get().id == id => (),
+            Entry::Occupied(ref entry) if entry.get().rejected.contains(&id) => {
+                error!(
+                    "response to already rejected relocation of {} ({:?})",
+                    log::name(&node_name),
+                    id
+                );
+                self.counters.accepts_after_reject += 1;
+                return None;
+            }
+            _ => {
+                debug!(
+                    "ignoring orphaned response to relocation of {} ({:?})",
+                    log::name(&node_name),
+                    id
+                );
+                self.counters.orphaned_responses += 1;
+                return None;
+            }
+        }
+
+        self.outgoing_relocations.remove(&node_name)
+    }
+
+    fn handle_relocate_commit(
+        &mut self,
+        params: &Params,
+        policies: &Policies,
+        id: ExchangeId,
+        node: &Node,
+        target: Name,
+        cause: RelocationCause,
+    ) -> Vec<Action> {
+        let relocation = match self.incoming_relocations.remove(&node.name()) {
+            Some(relocation) => relocation,
+            None => return self.handle_unmatched_commit(params, policies, node),
+        };
+
+        // The node is still taken in, as before correlating the exchanges.
+        if relocation.id != id || relocation.target != target {
+            error!(
+                "relocation commit of {} ({:?} to {}) doesn't match the accepted one \
+                 ({:?} to {})",
+                log::name(&node.name()),
+                id,
+                log::name(&target),
+                relocation.id,
+                log::name(&relocation.target)
+            );
+            self.counters.mismatched_commits += 1;
+        }
+
+        // Pick the new node name so it would fall into the subsection with
+        // fewer members, to keep the section balanced. If they are almost
+        // balanced already, pick either, to avoid always loading the same side.
+        let prefixes = self.prefix.split();
+        let count0 = node::count_matching_adults(params, prefixes[0], self.nodes.values());
+        let count1 = node::count_matching_adults(params, prefixes[1], self.nodes.values());
+        let imbalance = cmp::max(count0, count1) - cmp::min(count0, count1);
+        self.commit_imbalances.push(imbalance as u64);
+
+        let new_name = random::gen_name();
+        let half = if imbalance < params.commit_balance_threshold {
+            self.counters.random_commit_halves += 1;
+            let _category = random::category(Category::TieBreaking);
+            random::gen_range(0, 2)
+        } else if count0 < count1 {
+            0
+        } else {
+            1
+        };
+        let new_name = prefixes[half].substituted_in(new_name);
+
+        debug!(
+            "relocating {} -> {}",
+            log::name(&node.name()),
+            log::name(&new_name),
+        );
+
+        self.churn.relocations_in += 1;
+        self.counters.completed_relocations += 1;
+        if params.relocation_trace.is_some() {
+            self.relocation_names.push((id, new_name));
+        }
+        if cause == RelocationCause::Rescue && self.rescue_wait.is_some() {
+            let _ = self.rescued.insert(new_name);
+        }
+        self.handle_live(params, policies, node.relocated(new_name))
+    }
+
+    fn handle_unmatched_commit(
+        &mut self,
+        params: &Params,
+        policies: &Policies,
+        node: &Node,
+    ) -> Vec<Action> {
+        // In the strict mode, the network stops the simulation on the fault, so
+        // the node is left alone.
+        let mut actions = vec![
+            Action::Fault(self.prefix, SimError::UnmatchedCommit(node.name())),
+        ];
+        if params.strict {
+            return actions;
+        }
+
+        self.counters.unmatched_commits += 1;
+
+        actions.extend(match params.unmatched_commit {
+            UnmatchedCommit::Rejoin => {
+                // The node keeps its id, but joins afresh under a new name.
+                let name = self.prefix.substituted_in(random::gen_name());
+                self.admit(params, policies, node.rejoined(params).redirected(name))
+            }
+            UnmatchedCommit::Bounce => {
+                // The original name of the node falls into its source section.
+                vec![Action::Send(Message::Bounce { node: node.redirected(node.name()) })]
+            }
+        });
+        actions
+    }
+
+    // Drop the accepted incoming relocations whose commit didn't arrive within
+    // `relocation_timeout` iterations (e.g. because the cancel got lost), and the
+    // outgoing ones that were neither accepted nor rejected within that time.
+    // Returns the exchanges of the expired outgoing ones, with their nodes.
+    fn expire_relocations(&mut self, params: &Params) -> Vec<(ExchangeId, Name)> {
+        for relocation in self.incoming_relocations.values_mut() {
+            relocation.pending += 1;
+        }
+        for relocation in self.outgoing_relocations.values_mut() {
+            relocation.pending += 1;
+        }
+
+        if params.relocation_timeout == 0 {
+            return Vec::new();
+        }
+
+        let expired: Vec<_> = self.incoming_relocations
+            .iter()
+            .filter(|&(_, relocation)| relocation.pending > params.relocation_timeout)
+            .map(|(&node_name, _)| node_name)
+            .collect();
+
+        for node_name in expired {
+            debug!(
+                "relocation of {} accepted but never committed, dropping it",
+                log::name(&node_name)
+            );
+
+            let _ = self.incoming_relocations.remove(&node_name);
+            self.counters.zombie_relocations += 1;
+        }
+
+        // The destination might have accepted the relocation, in which case
+        // its own timeout drops it.
+        let mut expired: Vec<_> = self.outgoing_relocations
+            .iter()
+            .filter(|&(_, relocation)| relocation.pending > params.relocation_timeout)
+            .map(|(&node_name, relocation)| (relocation.id, node_name))
+            .collect();
+        expired.sort();
+
+        for &(_, node_name) in &expired {
+            debug!(
+                "relocation of {} requested but never accepted or rejected, dropping it",
+                log::name(&node_name)
+            );
+
+            let _ = self.outgoing_relocations.remove(&node_name);
+            let _ = self.relocation_causes.remove(&node_name);
+            self.counters.expired_relocation_requests += 1;
+        }
+
+        expired
+    }
+
+    fn handle_relocate_cancel(&mut self, id: ExchangeId, node_name: Name) {
+        let matches = self.incoming_relocations.get(&node_name).map(|relocation| {
+            relocation.id == id
+        });
+
+        if matches == Some(true) {
+            let _ = self.incoming_relocations.remove(&node_name);
+            self.counters.cancelled_relocations += 1;
+        }
+    }
+
+    fn handle_join_redirect(
+        &mut self,
+        params: &Params,
+        policies: &Policies,
+        node: Node,
+        target: Name,
+    ) -> Vec<Action> {
+        // The node takes the target name, which falls into our section.
+        let node = node.redirected(target);
+
+        if self.unreachable {
+            return vec![self.reject_node(node)];
+        }
+
+        debug!("accepting redirected join of {}", log::name(&target));
+
+        self.handle_live(params, policies, node)
+    }
+
+    fn handle_bounce(&mut self, params: &Params, policies: &Policies, node: Node) -> Vec<Action> {
+        if self.unreachable {
+            return vec![self.reject_node(node)];
+        }
+
+        debug!("accepting bounced node {}", log::name(&node.name()));
+
+        self.handle_live(params, policies, node)
+    }
+
+    fn handle_sibling_size(
+        &mut self,
+        params: &Params,
+        source: Prefix,
+        size: usize,
+    ) -> Option<Action> {
+        // The message could have been routed to a descendant of our sibling.
+        if source != self.prefix.sibling() {
+            return None;
+        }
+
+        let node_name = self.view().balancing_relocation(params, size)?;
+        let target = source.substituted_in(random::gen_name());
+
+        let _ = self.relocation_causes.insert(
+            node_name,
+            RelocationCause::Balancing,
+        );
+
+        debug!(
+            "initiating balancing relocation of {} to {} ({} vs {} nodes)",
+            log::name(&node_name),
+            log::prefix(&source),
+            self.nodes.len(),
+            size,
+        );
+
+        Some(self.request_relocation(node_name, target, None))
+    }
+
+    // Let the sibling know our size, so it can decide whether to balance. Once
+    // the sibling split, only its leftmost descendant gets it, and drops it:
+    // sections only balance with a sibling section (the network counts the
+    // dropped sizes).
+    fn send_size_info(&mut self, params: &Params) -> Option<Action> {
+        if self.recent_size_info {
+            return None;
+        }
+        self.recent_size_info = true;
+
+        if params.balance_threshold <= 0.0 || self.state == State::Startup {
             return None;
         }
+
+        Some(Action::Send(Message::SiblingSize {
+            source: self.prefix,
+            size: self.nodes.len(),
+            target: self.prefix.sibling().substituted_in(Name(0)),
+        }))
+    }
+
+    fn handle_need_adults(&mut self, params: &Params, source: Prefix) {
+        // The message could have been routed to a descendant of the neighbour.
+        if self.state == State::Stable && source.is_neighbour(&self.prefix) {
+            let _ = self.needy_neighbours.insert(source, params.rescue_window);
+        }
+    }
+
+    // While short of adults, ask the neighbours (once) to direct their
+    // relocations to us for the rescue window, until we recover or the window
+    // runs out and we merge after all.
+    fn send_need_info(&mut self, params: &Params) -> Vec<Action> {
+        if self.recent_need_info {
+            return Vec::new();
+        }
+        self.recent_need_info = true;
+
+        let wait = if let Some(wait) = self.rescue_wait {
+            wait
+        } else {
+            return Vec::new();
+        };
+
+        if !self.view().should_merge(params) {
+            if self.is_rescued(params) {
+                debug!("merge prevented by rescue relocations");
+                self.counters.prevented_merges += 1;
+            }
+
+            self.rescue_wait = None;
+            self.adults_requested = false;
+            self.rescued.clear();
+            return Vec::new();
+        }
+
+        if wait >= params.rescue_window {
+            return self.try_merge(params).into_iter().collect();
+        }
+
+        if self.adults_requested {
+            return Vec::new();
+        }
+        self.adults_requested = true;
+
+        (0..self.prefix.len())
+            .map(|bit| {
+                Action::Send(Message::NeedAdults {
+                    source: self.prefix,
+                    target: self.prefix.with_flipped_bit(bit).substituted_in(Name(0)),
+                })
+            })
+            .collect()
+    }
+
+    // Whether the section would still be short of adults without those the
+    // rescue relocations brought in.
+    fn is_rescued(&self, params: &Params) -> bool {
+        let adults = self.nodes
+            .values()
+            .filter(|node| !self.rescued.contains(&node.name()))
+            .filter(|node| node.is_adult(params))
+            .count();
+        adults < params.merge_threshold()
+    }
+
+    // Simulate random node attempt to join this section.
+    fn random_join(&mut self, params: &Params, policies: &Policies) -> Vec<Action> {
+        // With a churn trace, all joins come from the trace. With contact joins,
+        // the network picks the sections.
+        if self.recent_join || self.shrinking || params.has_churn_trace() ||
+            params.join_model == JoinModel::Contact
+        {
+            return Vec::new();
+        }
+        self.recent_join = true;
+
+        self.new_join(params, policies)
+    }
+
+    /// A new node joins the section, having contacted one of its nodes. Like
+    /// with the section join model, the section takes at most one new node per
+    /// iteration; the others contacting it don't join.
+    pub fn contact_join(&mut self, params: &Params, policies: &Policies) -> Vec<Action> {
+        if self.recent_join || self.shrinking {
+            return Vec::new();
+        }
         self.recent_join = true;
 
-        let name = self.prefix.substituted_in(random::gen());
-        self.handle_live(params, Node::new(name, params.init_age))
+        self.new_join(params, policies)
+    }
+
+    fn new_join(&mut self, params: &Params, policies: &Policies) -> Vec<Action> {
+        let name = self.prefix.substituted_in(random::gen_name());
+        let _category = random::category(Category::Joins);
+
+        let mut node = Node::new(self.node_ids.allocate(), name, params.init_age);
+        if params.max_node_size > 1 {
+            node.set_size(random::gen_range(1, params.max_node_size + 1));
+        }
+        if params.malicious_fraction > 0.0 {
+            node.set_malicious(random::gen_bool_with_probability(params.malicious_fraction));
+        }
+        if !params.operator_profiles.is_empty() {
+            node.set_profile(draw_profile(params));
+        }
+        if params.operators > 0 {
+            node.set_operator(Some(random::gen_range(0, params.operators)));
+        }
+
+        // Measure how often the attacker gets its nodes into the sections it
+        // chose.
+        if node.is_malicious() {
+            self.counters.malicious_joins += 1;
+            if self.join_target(params, name).is_none_or(|target| self.prefix.matches(target)) {
+                self.counters.chosen_section_joins += 1;
+            }
+        }
+
+        self.admit(params, policies, node)
+    }
+
+    // Let the node join the section like any new node: unless the section is
+    // unreachable or the node gets redirected, it goes through the admission
+    // policy.
+    fn admit(&mut self, params: &Params, policies: &Policies, node: Node) -> Vec<Action> {
+        if self.unreachable {
+            return vec![self.reject_node(node)];
+        }
+
+        // Instead of letting the node join the section it chose, send it to the
+        // section derived from the hash of its name.
+        let name = node.name();
+        if let Some(target) = self.join_target(params, name) {
+            debug!(
+                "redirecting join of {} to {}",
+                log::name(&name),
+                log::name(&target),
+            );
+
+            return vec![Action::Send(Message::JoinRedirect { node, target })];
+        }
+
+        self.handle_live(params, policies, node)
+    }
+
+    // Name a node joining under `name` gets redirected to, if joins are
+    // redirected.
+    fn join_target(&self, params: &Params, name: Name) -> Option<Name> {
+        if !params.join_relocation || self.state == State::Startup {
+            return None;
+        }
+
+        Some(derive_target(
+            params,
+            Purpose::JoinRedirect,
+            self.prefix,
+            0,
+            &Hash::from(name),
+        ))
     }
 
     // Simulate random node disconnecting.
-    fn random_drop(&mut self, params: &Params) -> Vec<Action> {
-        if self.recent_drop {
+    fn random_drop(&mut self, params: &Params, policies: &Policies) -> Vec<Action> {
+        if self.recent_drop || params.has_churn_trace() {
             return Vec::new();
         }
         self.recent_drop = true;
 
-        let name = node::by_age(self.nodes.values())
-            .into_iter()
-            .find(|node| {
-                random::gen_bool_with_probability(node.drop_probability())
-            })
-            .map(|node| node.name());
+        let _category = random::category(Category::Drops);
+        let name = if self.shrinking {
+            // Any node can drop, no matter its age.
+            let mut names: Vec<_> = self.nodes.keys().cloned().collect();
+            names.sort();
+            random::sample(names, 1).pop()
+        } else {
+            policies.drop.select(params, &self.view(), self.iteration)
+        };
 
         if let Some(name) = name {
-            self.handle_dead(params, name)
+            self.handle_dead(params, policies, name)
         } else {
             Vec::new()
         }
     }
 
     fn try_split(&mut self, params: &Params) -> Option<Action> {
-        // We can only split if both section post-split would remain with at least
-        // 2 * GROUP_SIZE - QUORUM adults.
-
-        let prefixes = self.prefix.split();
+        if self.is_transitioning() || self.is_settling(params) ||
+            !self.view().should_split(params)
+        {
+            return None;
+        }
 
-        if prefixes[0] == self.prefix || prefixes[1] == self.prefix {
-            panic!(
-                "{:?}: Maximum prefix length reached. Can't split",
-                self.prefix
-            );
+        if self.prefix.is_max_len() || self.prefix.len() >= params.max_prefix_len {
+            match params.max_depth {
+                MaxDepth::Abort => {
+                    error!(
+                        "maximum prefix length reached with {} nodes, can't split",
+                        self.nodes.len()
+                    );
+                    return Some(Action::Abort(self.prefix, Reason::MaxPrefixLength));
+                }
+                MaxDepth::Refuse => {
+                    debug!("maximum prefix length reached, not splitting");
+                    self.counters.refused_splits += 1;
+                    return None;
+                }
+            }
         }
 
-        let num_adults0 = node::count_matching_adults(params, prefixes[0], self.nodes.values());
-        let num_adults1 = node::count_matching_adults(params, prefixes[1], self.nodes.values());
-        let limit = 2 * params.group_size - params.quorum();
+        let prefixes = self.prefix.split();
+        // The root section stays in startup until the split is carried out.
+        if self.state == State::Stable {
+            self.state = State::Splitting;
+        }
 
-        if num_adults0 >= limit && num_adults1 >= limit {
-            debug!(
-                "{}: initiating split into {} and {}",
-                log::prefix(&self.prefix),
-                log::prefix(&prefixes[0]),
-                log::prefix(&prefixes[1])
-            );
+        debug!(
+            "initiating split into {} and {}",
+            log::prefix(&prefixes[0]),
+            log::prefix(&prefixes[1])
+        );
 
-            Some(Action::Split(self.prefix))
-        } else {
-            None
-        }
+        Some(Action::Split(self.prefix))
     }
 
     fn try_merge(&mut self, params: &Params) -> Option<Action> {
-        if self.prefix == Prefix::EMPTY {
-            // We are the root section - nobody to merge with.
+        if self.is_transitioning() || self.is_settling(params) ||
+            !self.view().should_merge(params)
+        {
             return None;
         }
 
-        if node::count_adults(params, self.nodes.values()) >= params.group_size {
-            // We have enough adults, not need to merge.
+        // Give the neighbours a chance to relocate adults to us first.
+        if params.rescue_window > 0 && *self.rescue_wait.get_or_insert(0) < params.rescue_window {
             return None;
         }
 
+        self.state = State::Merging;
+
         let sibling = self.prefix.sibling();
         let target = self.prefix.shorten();
 
         debug!(
-            "{}: initiating merge with {} into {}",
-            log::prefix(&self.prefix),
+            "initiating merge with {} into {}",
             log::prefix(&sibling),
             log::prefix(&target)
         );
@@ -465,37 +1738,61 @@ impl Section {
         Some(Action::Merge(target))
     }
 
-    fn try_relocate(&mut self, params: &Params, live_block: &Block) -> Option<Action> {
+    // Whether the section already requested a split or merge.
+    fn is_transitioning(&self) -> bool {
+        self.state == State::Splitting || self.state == State::Merging
+    }
+
+    // Whether the section was created too recently to split or merge again
+    // (anti-flapping).
+    fn is_settling(&self, params: &Params) -> bool {
+        self.lifetime < params.min_section_lifetime
+    }
+
+    fn try_relocate(
+        &mut self,
+        params: &Params,
+        policies: &Policies,
+        live_block: &Block,
+    ) -> Option<Action> {
         // Do not relocate during startup.
-        if self.prefix == Prefix::EMPTY {
+        if self.state == State::Startup {
             return None;
         }
 
-        // If the relocation would trigger merge, don't relocate.
-        if node::count_adults(params, self.nodes.values()) <= params.group_size {
-            return None;
-        }
+        self.counters.ageing_events += 1;
 
-        // When there is alread node waiting for relocation, don't relocate.
-        if !self.outgoing_relocations.is_empty() {
+        if !self.view().can_relocate(params) {
             return None;
         }
 
         let mut hash = live_block.hash();
 
-        for _ in 0..params.max_relocation_attempts {
-            if let Some(node_name) = self.check_relocate(&hash) {
-                let target = hash.into();
-                let _ = self.outgoing_relocations.insert(node_name, target);
+        for attempt in 0..params.max_relocation_attempts {
+            if params.ageing_audit {
+                self.count_ageing_check(params, &hash);
+            }
+
+            if let Some(node_name) = policies.relocation.select(params, &self.view(), &hash) {
+                let age = self.nodes[&node_name].age();
+                self.ageing_counts.entry(age).or_default().selected += 1;
+
+                let target = if let Some(target) = self.rescue_target(&hash) {
+                    let _ = self.relocation_causes.insert(node_name, RelocationCause::Rescue);
+                    target
+                } else {
+                    let purpose = Purpose::Ageing;
+                    let target = derive_target(params, purpose, self.prefix, attempt as u32, &hash);
+                    constrain_target(params, self.prefix, target)
+                };
 
                 debug!(
-                    "{}: initiating relocation of {} to {}",
-                    log::prefix(&self.prefix),
+                    "initiating relocation of {} to {}",
                     log::name(&node_name),
                     log::name(&target)
                 );
 
-                return Some(Action::Send(Message::RelocateRequest { node_name, target }));
+                return Some(self.request_relocation(node_name, target, None));
             } else {
                 hash = hash.rehash();
             }
@@ -504,93 +1801,150 @@ impl Section {
         None
     }
 
-    fn check_relocate(&self, hash: &Hash) -> Option<Name> {
-        // Find the oldest node for which `hash % 2^age == 0`.
-        // If there is more than one, apply the tie-breaking rule.
+    // Target in one of the neighbours short of adults, if any, picked by the
+    // ageing hash.
+    fn rescue_target(&self, hash: &Hash) -> Option<Name> {
+        if self.needy_neighbours.is_empty() {
+            return None;
+        }
+
+        let target = hash.truncate_to_name();
+        let index = (target.0 % self.needy_neighbours.len() as u64) as usize;
+        self.needy_neighbours.keys().nth(index).map(|prefix| {
+            prefix.substituted_in(target)
+        })
+    }
+
+    // Count, by age, the nodes the ageing check applies to and those among them
+    // passing it.
+    fn count_ageing_check(&mut self, params: &Params, hash: &Hash) {
+        let checked: Vec<_> = self.nodes
+            .values()
+            .filter(|node| decision::is_relocation_candidate(params, node))
+            .map(|node| node.age())
+            .collect();
+        for age in checked {
+            self.ageing_counts.entry(age).or_default().checked += 1;
+        }
+
+        let eligible: Vec<_> = self.view()
+            .relocation_candidates(params, hash)
+            .into_iter()
+            .map(|node| node.age())
+            .collect();
+        for age in eligible {
+            self.ageing_counts.entry(age).or_default().eligible += 1;
+        }
+    }
 
-        let mut candidates = self.relocation_candidates(hash);
-        if candidates.is_empty() {
+    // Relocate the longest serving elder that exceeded the term limit, regardless
+    // of whether the ageing hash would select it.
+    fn try_force_relocate(&mut self, params: &Params) -> Option<Action> {
+        if self.recent_term_check {
             return None;
         }
+        self.recent_term_check = true;
+
+        let node_name = self.view().term_limit_relocation(params)?;
+        let target = derive_target(
+            params,
+            Purpose::TermLimit,
+            self.prefix,
+            0,
+            &Hash::from(node_name),
+        );
+        let target = constrain_target(params, self.prefix, target);
 
-        candidates.sort_by_key(|node| u8::MAX - node.age());
+        let _ = self.relocation_causes.insert(
+            node_name,
+            RelocationCause::TermLimit,
+        );
 
-        let age = candidates[0].age();
-        let index = candidates
-            .iter()
-            .position(|node| node.age() != age)
-            .unwrap_or_else(|| candidates.len());
-        candidates.truncate(index);
+        debug!(
+            "initiating forced relocation of {} to {} (term limit reached)",
+            log::name(&node_name),
+            log::name(&target)
+        );
 
-        if candidates.len() == 1 {
-            Some(candidates[0].name())
-        } else {
-            break_ties(candidates)
+        Some(self.request_relocation(node_name, target, None))
+    }
+
+    // Start a new outgoing relocation exchange for the node, as a retry of the
+    // `rejected` one if given.
+    fn request_relocation(
+        &mut self,
+        node_name: Name,
+        target: Name,
+        rejected: Option<Relocation>,
+    ) -> Action {
+        let id = ExchangeId(self.id, self.next_exchange);
+        self.next_exchange += 1;
+
+        let mut relocation = Relocation::new(id, target);
+        if let Some(rejected) = rejected {
+            relocation.rejected = rejected.rejected;
+            relocation.rejected.push(rejected.id);
+            relocation.ticks = rejected.ticks;
         }
+        let _ = self.outgoing_relocations.insert(node_name, relocation);
+
+        Action::Send(Message::RelocateRequest {
+            id,
+            node_name,
+            target,
+        })
     }
 
-    fn relocation_candidates(&self, hash: &Hash) -> Vec<&Node> {
-        // The actual formula is: `hash % 2^age == 0`, the following is equivalent
-        // but more efficient:
-        let trailing_zeros = hash.trailing_zeros() as u8;
-        self.nodes
-            .values()
-            .filter(|node| node.age() <= trailing_zeros)
-            .collect()
+    pub fn view(&self) -> View<'_> {
+        View {
+            prefix: self.prefix,
+            state: self.state,
+            nodes: &self.nodes,
+            ages: &self.ages,
+            relocating: !self.outgoing_relocations.is_empty(),
+        }
     }
 
     fn join_node(&mut self, node: Node) {
-        debug!(
-            "{}: added {}",
-            log::prefix(&self.prefix),
-            log::name(&node.name())
-        );
+        debug!("added {}", log::name(&node.name()));
+        self.churn.joins += 1;
+        self.origin_counts.entry(node.origin()).or_default().arrivals += 1;
+        self.ages.insert(&node);
         let _ = self.nodes.insert(node.name(), node);
     }
 
     fn reject_node(&self, node: Node) -> Action {
-        debug!(
-            "{}: rejected {}",
-            log::prefix(&self.prefix),
-            log::name(&node.name())
-        );
+        debug!("rejected {}", log::name(&node.name()));
         Action::Reject(node)
     }
 
     fn drop_node(&mut self, name: Name) -> Option<Node> {
         if let Some(node) = self.nodes.remove(&name) {
-            debug!(
-                "{}: dropped {}",
-                log::prefix(&self.prefix),
-                log::name(&name)
-            );
+            self.ages.remove(&node);
+            let _ = self.past_names.insert(name);
+            debug!("dropped {}", log::name(&name));
             Some(node)
         } else {
             None
         }
     }
 
-    // Promote/demote nodes so only the `GROUP_SIZE` oldest nodes are elders.
+    // Promote/demote nodes so only the `GROUP_SIZE` oldest nodes (of at least
+    // `min_elder_age`) are elders.
     fn update_elders(&mut self, params: &Params) {
         let old: HashSet<_> = self.nodes
             .values()
             .filter(|node| node.is_elder())
             .map(|node| node.name())
             .collect();
-        let new: HashSet<_> = {
-            let mut new = node::by_age(self.nodes.values());
-            new.reverse();
-            new.into_iter()
-                .take(params.group_size)
-                .map(|node| node.name())
-                .collect()
-        };
+        let new = self.view().elders(params);
 
         for node in self.nodes.values_mut() {
             let old = old.contains(&node.name());
             let new = new.contains(&node.name());
 
             if old && !new {
+                self.churn.elder_changes += 1;
                 node.demote();
                 self.chain.insert(
                     Block::new(Event::Gone, node.name(), node.age()),
@@ -598,12 +1952,25 @@ impl Section {
             }
 
             if new && !old {
+                self.churn.elder_changes += 1;
+                self.origin_counts.entry(node.origin()).or_default().promotions += 1;
+                if !node.was_elder() {
+                    self.first_promotion_ages.push(node.age());
+                }
                 node.promote();
                 self.chain.insert(
                     Block::new(Event::Live, node.name(), node.age()),
                 );
             }
         }
+
+        if self.elder_gap.is_some() && new.len() >= params.group_size {
+            self.elder_gaps.extend(self.elder_gap.take());
+        }
+
+        if self.completion_gap.is_some() && self.is_complete(params) {
+            self.completion_gaps.extend(self.completion_gap.take());
+        }
     }
 }
 
@@ -613,10 +1980,55 @@ impl fmt::Debug for Section {
     }
 }
 
-fn break_ties(mut nodes: Vec<&Node>) -> Option<Name> {
-    let total = nodes.iter().fold(0, |total, node| total ^ node.name().0);
-    nodes.sort_by_key(|node| node.name().0 ^ total);
-    nodes.first().map(|node| node.name())
+// Pick the operator profile of a joining node according to the profile
+// fractions, or none.
+fn draw_profile(params: &Params) -> Option<usize> {
+    let mut value = random::gen::<f64>();
+    for (index, profile) in params.operator_profiles.iter().enumerate() {
+        if value < profile.fraction {
+            return Some(index);
+        }
+        value -= profile.fraction;
+    }
+
+    None
+}
+
+// Target name derived from the seed as `Params::target_derivation` says. The
+// legacy derivation takes the ageing hash as is and rehashes the other seeds,
+// ignoring the source and attempt.
+fn derive_target(
+    params: &Params,
+    purpose: Purpose,
+    source: Prefix,
+    attempt: u32,
+    seed: &Hash,
+) -> Name {
+    match params.target_derivation {
+        TargetDerivation::Legacy if purpose == Purpose::Ageing => seed.truncate_to_name(),
+        TargetDerivation::Legacy => seed.rehash().truncate_to_name(),
+        TargetDerivation::Separated => {
+            Hash::derive(purpose, source, attempt, seed).truncate_to_name()
+        }
+    }
+}
+
+// If relocations are limited to nearby sections, modify the target so that it
+// only differs from the source prefix in the last `max_relocation_distance` bits
+// (but still lies outside of the source section).
+fn constrain_target(params: &Params, source: Prefix, target: Name) -> Name {
+    if params.max_relocation_distance == 0 {
+        return target;
+    }
+
+    let len = source.len().saturating_sub(params.max_relocation_distance);
+    let target = Prefix::new(len, source.substituted_in(Name(0))).substituted_in(target);
+
+    if source.matches(target) {
+        source.sibling().substituted_in(target)
+    } else {
+        target
+    }
 }
 
 fn split<S, T, F>(nodes: S, prefix0: Prefix, prefix1: Prefix, mut name: F) -> (T, T)
@@ -636,3 +2048,231 @@ where
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use params::SyntheticChurn;
+    use simulation::AdmissionPolicy;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use test_params;
+
+    fn params(args: &[&str]) -> Params {
+        let mut full = vec!["--seed", "[1, 2, 3, 4]"];
+        full.extend_from_slice(args);
+        let mut params = test_params(&full);
+        random::reseed(params.rng, params.seed);
+
+        // No random joins and drops.
+        params.synthetic_churn = Some(SyntheticChurn {
+            join_rate: 0.0,
+            leave_probability: 0.0,
+        });
+        params
+    }
+
+    fn tick(section: &mut Section, params: &Params, iteration: u64) -> Vec<Action> {
+        let _ = section.prepare(params, iteration);
+        let mut actions = Vec::new();
+        section.tick(params, &Policies::new(params), &mut actions);
+        actions
+    }
+
+    // Section [0] with `group_size` adults, one of which then drops, so it
+    // waits for its neighbours to rescue it instead of merging.
+    fn short_of_adults(params: &Params) -> Section {
+        let policies = Policies::new(params);
+        let mut section = Section::new(1, Prefix::EMPTY.extend(0), NodeIds::new());
+        for index in 0..params.group_size as u64 {
+            let _ = section.join(params, &policies, Node::new(index, Name(index << 32), 6));
+        }
+        let _ = section.disconnect(params, &policies, Name(0));
+        assert!(section.needs_adults());
+        section
+    }
+
+    // Relocate a node with the cause to the section short of adults, and
+    // return the number of merges that counted as prevented.
+    fn relocate_in(cause: RelocationCause) -> u64 {
+        let params = params(&["--rescue-window", "10"]);
+        let mut section = short_of_adults(&params);
+        let id = ExchangeId(2, 0);
+        let node_name = Name(0x8000_0000_0000_0000);
+        let target = Name(0x4000_0000_0000_0000);
+
+        section.receive(Message::RelocateRequest { id, node_name, target });
+        let actions = tick(&mut section, &params, 0);
+        assert!(actions.iter().any(|action| {
+            matches!(*action, Action::Send(Message::RelocateAccept { .. }))
+        }));
+
+        section.receive(Message::RelocateCommit {
+            id,
+            node: Node::new(100, node_name, 6),
+            target,
+            cause,
+        });
+        let _ = tick(&mut section, &params, 1);
+        assert!(!section.needs_adults());
+        section.take_counters().prevented_merges
+    }
+
+    // Never admits an infant, counting the times it was asked.
+    struct NoInfants(Rc<Cell<u64>>);
+
+    impl AdmissionPolicy for NoInfants {
+        fn admit_infant(&self, _: &Params, _: &View) -> bool {
+            self.0.set(self.0.get() + 1);
+            false
+        }
+    }
+
+    fn tick_unmatched_commit(params: &Params, policies: &Policies) -> (Section, Vec<Action>) {
+        // Past the startup, so the admission policy applies.
+        let mut section = Section::new(0, Prefix::EMPTY.extend(1), NodeIds::new());
+        section.receive(Message::RelocateCommit {
+            id: ExchangeId(1, 0),
+            node: Node::new(100, Name(0x8000_0000_0000_0000), 6),
+            target: Name(0x8000_0000_0000_0000),
+            cause: RelocationCause::Ageing,
+        });
+
+        let mut actions = Vec::new();
+        section.tick(params, policies, &mut actions);
+        (section, actions)
+    }
+
+    #[test]
+    fn neighbours_are_asked_for_adults_once() {
+        let params = params(&["--rescue-window", "10"]);
+        let mut section = short_of_adults(&params);
+        let count_requests = |actions: Vec<Action>| {
+            actions
+                .iter()
+                .filter(|action| {
+                    matches!(**action, Action::Send(Message::NeedAdults { .. }))
+                })
+                .count()
+        };
+
+        assert_eq!(count_requests(tick(&mut section, &params, 0)), 1);
+        for iteration in 1..5 {
+            assert_eq!(count_requests(tick(&mut section, &params, iteration)), 0);
+        }
+    }
+
+    #[test]
+    fn needy_neighbours_are_remembered_for_the_rescue_window() {
+        let params = params(&["--rescue-window", "3"]);
+        let mut section = Section::new(1, Prefix::EMPTY.extend(0), NodeIds::new());
+        let hash = Hash::digest(b"test");
+        section.receive(Message::NeedAdults {
+            source: Prefix::EMPTY.extend(1),
+            target: Name(0),
+        });
+
+        let _ = tick(&mut section, &params, 0);
+        for iteration in 1..3 {
+            assert!(section.rescue_target(&hash).is_some());
+            let _ = tick(&mut section, &params, iteration);
+        }
+        assert!(section.rescue_target(&hash).is_some());
+        let _ = section.prepare(&params, 3);
+        assert_eq!(section.rescue_target(&hash), None);
+    }
+
+    #[test]
+    fn only_rescues_count_as_prevented_merges() {
+        assert_eq!(relocate_in(RelocationCause::Rescue), 1);
+        assert_eq!(relocate_in(RelocationCause::Ageing), 0);
+    }
+
+    #[test]
+    fn unmatched_commit_in_strict_mode_only_faults() {
+        let params = params(&["--strict"]);
+        let (section, actions) = tick_unmatched_commit(&params, &Policies::new(&params));
+        assert_eq!(actions.len(), 1);
+        assert!(matches!(
+            actions[0],
+            Action::Fault(_, SimError::UnmatchedCommit(_))
+        ));
+        assert!(section.nodes().is_empty());
+    }
+
+    #[test]
+    fn unmatched_commit_rejoins_by_default() {
+        let params = params(&[]);
+        let (section, actions) = tick_unmatched_commit(&params, &Policies::new(&params));
+        assert!(actions.iter().any(|action| {
+            matches!(*action, Action::Fault(_, SimError::UnmatchedCommit(_)))
+        }));
+        assert_eq!(section.nodes().len(), 1);
+        // The same node, under a new name in the section.
+        let node = section.nodes().values().next().unwrap();
+        assert_eq!(node.id(), 100);
+        assert!(section.prefix().matches(node.name()));
+    }
+
+    #[test]
+    fn unmatched_commit_rejoin_asks_the_admission_policy() {
+        let params = params(&[]);
+        let asked = Rc::new(Cell::new(0));
+        let policies = Policies {
+            admission: Box::new(NoInfants(Rc::clone(&asked))),
+            ..Policies::new(&params)
+        };
+
+        let (section, actions) = tick_unmatched_commit(&params, &policies);
+        assert_eq!(asked.get(), 1);
+        assert!(actions.iter().any(|action| {
+            matches!(*action, Action::Reject(ref node) if node.id() == 100)
+        }));
+        assert!(section.nodes().is_empty());
+    }
+
+    #[test]
+    fn unmatched_commit_bounces_node_without_redirecting_join() {
+        let params = params(&["--unmatched-commit", "bounce"]);
+        let (section, actions) = tick_unmatched_commit(&params, &Policies::new(&params));
+        assert!(actions.iter().any(|action| {
+            matches!(*action, Action::Send(Message::Bounce { .. }))
+        }));
+        assert!(!actions.iter().any(|action| {
+            matches!(*action, Action::Send(Message::JoinRedirect { .. }))
+        }));
+        assert!(section.nodes().is_empty());
+    }
+
+    // Join 11 adults into each half of the section and return the actions.
+    fn fill_halves(params: &Params, section: &mut Section) -> Vec<Action> {
+        let policies = Policies::new(params);
+        let prefixes = section.prefix().split();
+        let mut actions = Vec::new();
+        for index in 0..22u64 {
+            let name = prefixes[index as usize % 2].substituted_in(Name(index + 1));
+            actions.extend(section.join(params, &policies, Node::new(index, name, 6)));
+        }
+        actions
+    }
+
+    #[test]
+    fn root_section_stays_in_startup_until_split() {
+        let params = params(&[]);
+
+        let mut root = Section::new(0, Prefix::EMPTY, NodeIds::new());
+        let actions = fill_halves(&params, &mut root);
+        assert!(actions.iter().any(|action| {
+            matches!(*action, Action::Split(prefix) if prefix == Prefix::EMPTY)
+        }));
+        assert_eq!(root.state(), State::Startup);
+
+        let prefix = Prefix::EMPTY.extend(0);
+        let mut section = Section::new(1, prefix, NodeIds::new());
+        let actions = fill_halves(&params, &mut section);
+        assert!(actions.iter().any(|action| {
+            matches!(*action, Action::Split(split) if split == prefix)
+        }));
+        assert_eq!(section.state(), State::Splitting);
+    }
+}
@@ -0,0 +1,474 @@
+//! Top-level simulation: the network together with the churn driving it, the
+//! policies its sections follow and the observers watching it.
+//!
+//! The policies decide which node drops, whether an infant is admitted and
+//! which node the ageing check relocates. The parameters only choose among the
+//! implementations here (see `Policies::new`), so alternatives can be compared
+//! by plugging in different implementations instead of adding more parameters.
+
+use chain::Hash;
+use churn_trace::ChurnTrace;
+use colored::Colorize;
+use decision::View;
+use log;
+use network::Network;
+use node;
+use params::{DropModel, Params, RelocationChoice, Scheduler, Setting};
+use prefix::Name;
+use random::{self, Category};
+use std::fs;
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use termination::{Reason, Termination};
+
+/// Chooses the node of the section to drop in an iteration, if any.
+pub trait DropPolicy {
+    fn select(&self, params: &Params, view: &View, iteration: u64) -> Option<Name>;
+}
+
+/// Decides whether the section admits a joining infant.
+pub trait AdmissionPolicy {
+    fn admit_infant(&self, params: &Params, view: &View) -> bool;
+}
+
+/// Chooses the node to relocate for the given ageing hash, if any.
+pub trait RelocationStrategy {
+    fn select(&self, params: &Params, view: &View, hash: &Hash) -> Option<Name>;
+}
+
+/// Watches the network after every iteration.
+pub trait Observer {
+    fn observe(&mut self, iteration: u64, network: &Network);
+}
+
+pub struct Policies {
+    pub drop: Box<dyn DropPolicy>,
+    pub admission: Box<dyn AdmissionPolicy>,
+    pub relocation: Box<dyn RelocationStrategy>,
+}
+
+impl Policies {
+    /// The policies chosen by the parameters.
+    pub fn new(params: &Params) -> Self {
+        Policies {
+            drop: drop_policy(params.drop_model),
+            admission: Box::new(InfantLimit),
+            relocation: relocation_strategy(params.relocation_choice),
+        }
+    }
+
+    /// Switch to the policy chosen by a parameter changed during the run, if
+    /// the parameter chooses one.
+    pub fn apply(&mut self, setting: Setting) {
+        match setting {
+            Setting::DropModel(model) => self.drop = drop_policy(model),
+            Setting::RelocationChoice(choice) => self.relocation = relocation_strategy(choice),
+            _ => (),
+        }
+    }
+}
+
+fn drop_policy(model: DropModel) -> Box<dyn DropPolicy> {
+    match model {
+        DropModel::Age => Box::new(DropByAge),
+        DropModel::Uniform => Box::new(DropUniformly),
+    }
+}
+
+fn relocation_strategy(choice: RelocationChoice) -> Box<dyn RelocationStrategy> {
+    match choice {
+        RelocationChoice::Oldest => Box::new(RelocateOldest),
+        RelocationChoice::PreAdult => Box::new(RelocatePreAdult),
+    }
+}
+
+/// Starting from the youngest, each node drops with its drop probability (see
+/// `Node::drop_probability`).
+pub struct DropByAge;
+
+impl DropPolicy for DropByAge {
+    fn select(&self, params: &Params, view: &View, iteration: u64) -> Option<Name> {
+        let _category = random::category(Category::Drops);
+
+        view.ages
+            .iter()
+            .map(|name| &view.nodes[&name])
+            .find(|node| {
+                random::gen_bool_with_probability(node.drop_probability(params, iteration))
+            })
+            .map(|node| node.name())
+    }
+}
+
+/// The section drops a node as likely as with `DropByAge`, but any node is
+/// equally likely to be the one, except for the operator profiles (see
+/// `Node::drop_factor`).
+pub struct DropUniformly;
+
+impl DropPolicy for DropUniformly {
+    fn select(&self, params: &Params, view: &View, iteration: u64) -> Option<Name> {
+        let _category = random::category(Category::Drops);
+
+        let keep: f64 = view.nodes
+            .values()
+            .map(|node| 1.0 - node.drop_probability(params, iteration))
+            .product();
+        if !random::gen_bool_with_probability(1.0 - keep) {
+            return None;
+        }
+
+        let mut names: Vec<_> = view.nodes.keys().cloned().collect();
+        names.sort();
+        if params.operator_profiles.is_empty() {
+            return random::sample(names, 1).pop();
+        }
+
+        // Nodes in the offline window of their profile are as many times more
+        // likely to be the one dropping.
+        let weights: Vec<_> = names
+            .iter()
+            .map(|name| view.nodes[name].drop_factor(params, iteration))
+            .collect();
+        let mut value = random::gen::<f64>() * weights.iter().sum::<f64>();
+        for (name, weight) in names.iter().zip(&weights) {
+            if value < *weight {
+                return Some(*name);
+            }
+            value -= weight;
+        }
+        names.last().cloned()
+    }
+}
+
+/// Admits infants up to `Params::max_infants_per_section`, and beyond that
+/// with `Params::infant_admission_probability` per excess infant.
+pub struct InfantLimit;
+
+impl AdmissionPolicy for InfantLimit {
+    fn admit_infant(&self, params: &Params, view: &View) -> bool {
+        let _category = random::category(Category::Joins);
+
+        let count = node::count_infants(params, view.nodes.values());
+        if count < params.max_infants_per_section {
+            return true;
+        }
+
+        if params.infant_admission_probability <= 0.0 {
+            return false;
+        }
+
+        let excess = (count - params.max_infants_per_section + 1) as i32;
+        random::gen_bool_with_probability(params.infant_admission_probability.powi(excess))
+    }
+}
+
+/// Relocates the oldest node passing the ageing check (see
+/// `View::ageing_relocation`).
+pub struct RelocateOldest;
+
+impl RelocationStrategy for RelocateOldest {
+    fn select(&self, params: &Params, view: &View, hash: &Hash) -> Option<Name> {
+        view.ageing_relocation(params, hash)
+    }
+}
+
+/// Relocates a node passing the ageing check that becomes an adult by the
+/// relocation, if any (see `View::pre_adult_relocation`).
+pub struct RelocatePreAdult;
+
+impl RelocationStrategy for RelocatePreAdult {
+    fn select(&self, params: &Params, view: &View, hash: &Hash) -> Option<Name> {
+        view.pre_adult_relocation(params, hash)
+    }
+}
+
+pub struct Simulation {
+    network: Network,
+    // Churn trace driving the joins and drops, if any.
+    churn: Option<ChurnTrace>,
+    policies: Policies,
+    observers: Vec<Box<dyn Observer>>,
+}
+
+impl Simulation {
+    pub fn new(params: Params) -> Self {
+        let policies = Policies::new(&params);
+        Self::with_policies(params, policies)
+    }
+
+    pub fn with_policies(params: Params, policies: Policies) -> Self {
+        Self::with_network(Network::new(params), policies)
+    }
+
+    /// Simulation continuing with the given network, e.g. one restored from a
+    /// checkpoint.
+    pub fn with_network(network: Network, policies: Policies) -> Self {
+        let churn = churn(network.params());
+        Simulation {
+            network,
+            churn,
+            policies,
+            observers: Vec::new(),
+        }
+    }
+
+    pub fn add_observer(&mut self, observer: Box<dyn Observer>) {
+        self.observers.push(observer)
+    }
+
+    /// Execute single iteration of the simulation and let the observers watch
+    /// its outcome.
+    pub fn tick(&mut self, iteration: u64) {
+        let _iteration = log::enter_iteration(iteration);
+
+        for setting in self.network.change_params(iteration) {
+            self.policies.apply(setting);
+        }
+
+        let churn = match self.churn {
+            Some(ref mut churn) => {
+                // The messages arriving before the next event are delivered in
+                // this iteration.
+                if let Some(time) = churn.time(iteration) {
+                    let next = churn.time(iteration + 1).unwrap_or(f64::INFINITY);
+                    self.network.set_event_times(time, next);
+                }
+                churn.take(iteration)
+            }
+            None => Vec::new(),
+        };
+        self.network.tick(iteration, &self.policies, churn);
+
+        for observer in &mut self.observers {
+            observer.observe(iteration, &self.network);
+        }
+    }
+
+    /// Run the iterations from `first` on, until the last one or until the
+    /// run stops early: on a fault or panic, an oversized section in the
+    /// strict mode, the network collapsing or `running` being cleared (e.g. by
+    /// Ctrl+C). Returns why it stopped and the state hashes computed on the
+    /// way (see `Params::state_hash_frequency`), one `ITERATION HASH` per line.
+    pub fn run(
+        &mut self,
+        params: &Params,
+        first: u64,
+        running: &AtomicBool,
+    ) -> (Termination, String) {
+        let mut state_hashes = String::new();
+
+        for i in first..params.num_iterations {
+            info!(
+                "{}",
+                format!("Iteration: {}", format!("{}", i).bold()).green()
+            );
+
+            let result = panic::catch_unwind(panic::AssertUnwindSafe(|| self.tick(i)));
+            if let Err(payload) = result {
+                let message = payload
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+                    .unwrap_or_default();
+                return (stop(Reason::Panic(message), i), state_hashes);
+            }
+
+            let network = &self.network;
+            if let Some(failure) = network.termination() {
+                return (failure.clone(), state_hashes);
+            }
+
+            if params.strict {
+                if let Some(section) = network.oversized_section() {
+                    error!(
+                        "{}: section size limit exceeded, dumping to {}",
+                        log::prefix(&section.prefix()),
+                        params.dump_file
+                    );
+
+                    let dump = format!(
+                        "Iteration: {}\nSeed: {:?}\n\n{}",
+                        i,
+                        params.seed,
+                        section.dump()
+                    );
+                    if let Err(error) = fs::write(&params.dump_file, dump) {
+                        error!("Couldn't write {}: {}", params.dump_file, error);
+                    }
+
+                    let termination = Termination {
+                        reason: Reason::SectionTooLarge,
+                        iteration: i,
+                        section: Some(section.prefix()),
+                    };
+                    return (termination, state_hashes);
+                }
+            }
+
+            if params.state_hash_frequency > 0 && i.is_multiple_of(params.state_hash_frequency) {
+                let line = format!("{} {}", i, network.state_hash());
+                println!("State hash: {}", line);
+                state_hashes.push_str(&line);
+                state_hashes.push('\n');
+            }
+
+            if network.is_collapsed() {
+                info!("Network collapsed to the root section at iteration {}", i);
+                return (stop(Reason::Collapsed, i), state_hashes);
+            }
+
+            if !running.load(Ordering::Relaxed) {
+                return (stop(Reason::Interrupted, i), state_hashes);
+            }
+        }
+
+        let last = params.num_iterations.saturating_sub(1);
+        (stop(Reason::Completed, last), state_hashes)
+    }
+
+    /// Simulated time at the end of the iteration, when driven by events.
+    pub fn time(&self, iteration: u64) -> Option<f64> {
+        self.churn.as_ref().and_then(|churn| churn.time(iteration))
+    }
+
+    pub fn into_network(self) -> Network {
+        self.network
+    }
+}
+
+// The churn trace the parameters ask for, either generated or loaded.
+fn churn(params: &Params) -> Option<ChurnTrace> {
+    if params.scheduler == Scheduler::Events {
+        let mut rng = random::independent_rng(params.rng, params.seed, 1);
+        Some(ChurnTrace::generate_events(
+            &mut rng,
+            params.num_iterations,
+            params.event_rates.join_rate,
+            params.event_rates.leave_rate,
+        ))
+    } else if let Some(churn) = params.synthetic_churn {
+        let mut rng = random::independent_rng(params.rng, params.seed, 1);
+        Some(ChurnTrace::generate(
+            &mut rng,
+            params.num_iterations,
+            churn.join_rate,
+            churn.leave_probability,
+        ))
+    } else {
+        params.churn_trace.as_ref().map(|path| {
+            let trace = ChurnTrace::load(path, params.trace_tick_seconds).unwrap_or_else(
+                |error| panic!("{}", error),
+            );
+            info!(
+                "Loaded churn trace of {} nodes over {} iterations",
+                trace.num_nodes(),
+                trace.num_ticks()
+            );
+            trace
+        })
+    }
+}
+
+fn stop(reason: Reason, iteration: u64) -> Termination {
+    Termination {
+        reason,
+        iteration,
+        section: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use age_index::AgeIndex;
+    use node::Node;
+    use prefix::Prefix;
+    use section::State;
+    use std::cell::Cell;
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+    use test_params;
+
+    // Never drops a node, counting the times it was asked.
+    struct NoDrops(Rc<Cell<u64>>);
+
+    impl DropPolicy for NoDrops {
+        fn select(&self, _: &Params, _: &View, _: u64) -> Option<Name> {
+            self.0.set(self.0.get() + 1);
+            None
+        }
+    }
+
+    struct NoRelocations;
+
+    impl RelocationStrategy for NoRelocations {
+        fn select(&self, _: &Params, _: &View, _: &Hash) -> Option<Name> {
+            None
+        }
+    }
+
+    #[test]
+    fn plugged_in_policies_replace_the_defaults() {
+        let params = test_params(&["--seed", "[1, 2, 3, 4]", "--iterations", "200"]);
+        let run = |policies| {
+            random::reseed(params.rng, params.seed);
+            let mut simulation = Simulation::with_policies(params.clone(), policies);
+            let _ = simulation.run(&params, 0, &AtomicBool::new(true));
+            simulation.into_network().stats().summary()
+        };
+
+        let summary = run(Policies::new(&params));
+        assert!(summary.ageing_relocations() > 0);
+
+        let asked = Rc::new(Cell::new(0));
+        let summary = run(Policies {
+            drop: Box::new(NoDrops(Rc::clone(&asked))),
+            relocation: Box::new(NoRelocations),
+            ..Policies::new(&params)
+        });
+        assert!(asked.get() > 0);
+        assert_eq!(summary.ageing_relocations(), 0);
+    }
+
+    #[test]
+    fn uniform_drops_follow_operator_profiles() {
+        // Nodes of age 0 always drop, so a node drops in every iteration.
+        let params = test_params(&[
+            "--seed",
+            "[1, 2, 3, 4]",
+            "--drop-model",
+            "uniform",
+            "--operator-profile",
+            "0.5:10:0:5:100",
+        ]);
+        let nodes: BTreeMap<Name, Node> = (0..10)
+            .map(|i| {
+                let mut node = Node::new(i, Name(i), 0);
+                if i == 3 {
+                    node.set_profile(Some(0));
+                }
+                (Name(i), node)
+            })
+            .collect();
+        let ages = AgeIndex::build(nodes.values());
+        let view = View {
+            prefix: Prefix::EMPTY,
+            state: State::Stable,
+            nodes: &nodes,
+            ages: &ages,
+            relocating: false,
+        };
+
+        random::reseed(params.rng, params.seed);
+        let count = |iteration| {
+            (0..1000)
+                .filter(|_| DropUniformly.select(&params, &view, iteration) == Some(Name(3)))
+                .count()
+        };
+
+        // In its offline window, the node weighs 100 times as much as each of
+        // the 9 others; otherwise the same.
+        assert!(count(0) > 850);
+        assert!(count(5) < 200);
+    }
+}
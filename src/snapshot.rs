@@ -0,0 +1,275 @@
+//! Plain-text snapshots of the simulation state, for the soak mode checkpoints
+//! (see `soak`).
+//!
+//! A snapshot is a sequence of tokens separated by whitespace. Every type
+//! writes its fields in a fixed order and reads them back in the same order;
+//! collections are written as their length followed by their elements. Maps
+//! and sets are written in the order of their keys, so the same state always
+//! gives the same snapshot.
+
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fmt::Display;
+use std::hash::{BuildHasher, Hash};
+use std::str::{FromStr, SplitWhitespace};
+
+/// State that can be written into a snapshot and read back.
+pub trait Snapshot: Sized {
+    fn save(&self, writer: &mut Writer);
+    fn load(reader: &mut Reader) -> Result<Self, String>;
+}
+
+pub struct Writer {
+    output: String,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Writer { output: String::new() }
+    }
+
+    pub fn put<T: Display>(&mut self, token: T) {
+        if !self.output.is_empty() && !self.output.ends_with('\n') {
+            self.output.push(' ');
+        }
+        self.output.push_str(&token.to_string());
+    }
+
+    /// Start a new line, to keep the snapshot readable.
+    pub fn newline(&mut self) {
+        self.output.push('\n');
+    }
+
+    pub fn into_string(self) -> String {
+        self.output
+    }
+}
+
+pub struct Reader<'a> {
+    tokens: SplitWhitespace<'a>,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Reader { tokens: input.split_whitespace() }
+    }
+
+    pub fn get<T: FromStr>(&mut self) -> Result<T, String> {
+        let token = self.tokens.next().ok_or("snapshot truncated")?;
+        token.parse().map_err(
+            |_| format!("invalid token in snapshot: {}", token),
+        )
+    }
+
+    /// Returns an error unless all the tokens were read.
+    pub fn finish(mut self) -> Result<(), String> {
+        match self.tokens.next() {
+            Some(token) => Err(format!("unexpected token at the end of snapshot: {}", token)),
+            None => Ok(()),
+        }
+    }
+}
+
+macro_rules! impl_snapshot_for_tokens {
+    ($($ty:ty),*) => {
+        $(
+            impl Snapshot for $ty {
+                fn save(&self, writer: &mut Writer) {
+                    writer.put(self)
+                }
+
+                fn load(reader: &mut Reader) -> Result<Self, String> {
+                    reader.get()
+                }
+            }
+        )*
+    }
+}
+
+impl_snapshot_for_tokens!(u8, u32, u64, usize, bool, f64);
+
+impl<T: Snapshot> Snapshot for Option<T> {
+    fn save(&self, writer: &mut Writer) {
+        match *self {
+            Some(ref value) => {
+                writer.put(1);
+                value.save(writer);
+            }
+            None => writer.put(0),
+        }
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        match reader.get::<u8>()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::load(reader)?)),
+            tag => Err(format!("invalid option tag in snapshot: {}", tag)),
+        }
+    }
+}
+
+impl<A: Snapshot, B: Snapshot> Snapshot for (A, B) {
+    fn save(&self, writer: &mut Writer) {
+        self.0.save(writer);
+        self.1.save(writer);
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok((A::load(reader)?, B::load(reader)?))
+    }
+}
+
+impl<A: Snapshot, B: Snapshot, C: Snapshot> Snapshot for (A, B, C) {
+    fn save(&self, writer: &mut Writer) {
+        self.0.save(writer);
+        self.1.save(writer);
+        self.2.save(writer);
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok((A::load(reader)?, B::load(reader)?, C::load(reader)?))
+    }
+}
+
+impl<T: Snapshot> Snapshot for Vec<T> {
+    fn save(&self, writer: &mut Writer) {
+        save_all(writer, self.len(), self.iter())
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        load_all(reader)
+    }
+}
+
+impl<T: Snapshot> Snapshot for VecDeque<T> {
+    fn save(&self, writer: &mut Writer) {
+        save_all(writer, self.len(), self.iter())
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok(load_all(reader)?.into())
+    }
+}
+
+impl<K: Snapshot + Ord, V: Snapshot> Snapshot for BTreeMap<K, V> {
+    fn save(&self, writer: &mut Writer) {
+        writer.put(self.len());
+        for (key, value) in self {
+            key.save(writer);
+            value.save(writer);
+        }
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        let pairs: Vec<(K, V)> = load_all(reader)?;
+        Ok(pairs.into_iter().collect())
+    }
+}
+
+impl<T: Snapshot + Ord> Snapshot for BTreeSet<T> {
+    fn save(&self, writer: &mut Writer) {
+        save_all(writer, self.len(), self.iter())
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok(load_all::<T>(reader)?.into_iter().collect())
+    }
+}
+
+impl<T: Snapshot + Ord> Snapshot for BinaryHeap<T> {
+    fn save(&self, writer: &mut Writer) {
+        let mut values: Vec<_> = self.iter().collect();
+        values.sort();
+        save_all(writer, values.len(), values.into_iter())
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok(load_all::<T>(reader)?.into())
+    }
+}
+
+impl<K, V, S> Snapshot for HashMap<K, V, S>
+where
+    K: Snapshot + Ord + Hash,
+    V: Snapshot,
+    S: BuildHasher + Default,
+{
+    fn save(&self, writer: &mut Writer) {
+        let mut keys: Vec<_> = self.keys().collect();
+        keys.sort();
+
+        writer.put(keys.len());
+        for key in keys {
+            key.save(writer);
+            self[key].save(writer);
+        }
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        let pairs: Vec<(K, V)> = load_all(reader)?;
+        Ok(pairs.into_iter().collect())
+    }
+}
+
+impl<T, S> Snapshot for HashSet<T, S>
+where
+    T: Snapshot + Ord + Hash,
+    S: BuildHasher + Default,
+{
+    fn save(&self, writer: &mut Writer) {
+        let mut values: Vec<_> = self.iter().collect();
+        values.sort();
+        save_all(writer, values.len(), values.into_iter())
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok(load_all::<T>(reader)?.into_iter().collect())
+    }
+}
+
+fn save_all<'a, T, I>(writer: &mut Writer, len: usize, values: I)
+where
+    T: Snapshot + 'a,
+    I: Iterator<Item = &'a T>,
+{
+    writer.put(len);
+    for value in values {
+        value.save(writer);
+    }
+}
+
+fn load_all<T: Snapshot>(reader: &mut Reader) -> Result<Vec<T>, String> {
+    let len: usize = reader.get()?;
+    (0..len).map(|_| T::load(reader)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut map = BTreeMap::new();
+        let _ = map.insert(3u64, vec![Some(1.5f64), None]);
+        let _ = map.insert(1, Vec::new());
+        let set: HashSet<u32> = [5, 2, 9].iter().cloned().collect();
+
+        let mut writer = Writer::new();
+        map.save(&mut writer);
+        writer.newline();
+        set.save(&mut writer);
+        (true, 7u8).save(&mut writer);
+        let output = writer.into_string();
+
+        let mut reader = Reader::new(&output);
+        assert_eq!(BTreeMap::<u64, Vec<Option<f64>>>::load(&mut reader).unwrap(), map);
+        assert_eq!(HashSet::<u32>::load(&mut reader).unwrap(), set);
+        assert_eq!(<(bool, u8)>::load(&mut reader).unwrap(), (true, 7));
+        reader.finish().unwrap();
+    }
+
+    #[test]
+    fn truncated_snapshot_is_an_error() {
+        let mut reader = Reader::new("3 1 2");
+        assert!(Vec::<u64>::load(&mut reader).is_err());
+    }
+}
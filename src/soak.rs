@@ -0,0 +1,280 @@
+//! Soak mode, for runs lasting days or weeks.
+//!
+//! The network is checkpointed every few iterations into a directory, keeping
+//! only the most recent checkpoints. A checkpoint is a snapshot of the network
+//! (see `snapshot` and `Network::save`), headed by the iteration it was taken
+//! after and the state of the random number generator. A run started on a
+//! directory that already has checkpoints resumes from the latest one, and
+//! continues exactly as the interrupted run would have.
+//!
+//! The reports other than the stats (the distributions and listings printed at
+//! the end) cover only the iterations run since resuming.
+
+use network::Network;
+use params::Params;
+use random::{self, RngState};
+use simulation::Observer;
+use snapshot::{Reader, Writer};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PREFIX: &str = "checkpoint-";
+const EXTENSION: &str = ".txt";
+
+/// A checkpoint written by a previous run.
+pub struct Checkpoint {
+    pub path: PathBuf,
+    pub iteration: u64,
+    pub rng: RngState,
+    // The snapshot of the network, following the header.
+    snapshot: String,
+}
+
+impl Checkpoint {
+    /// The most recent checkpoint in the directory, if any.
+    pub fn latest<P: AsRef<Path>>(dir: P) -> Result<Option<Self>, String> {
+        let path = match list(dir.as_ref())?.pop() {
+            Some((_, path)) => path,
+            None => return Ok(None),
+        };
+
+        let content = fs::read_to_string(&path).map_err(|error| {
+            format!("Couldn't read {}: {}", path.display(), error)
+        })?;
+        let header = |key: &str| {
+            let key = format!("# {}: ", key);
+            content
+                .lines()
+                .take_while(|line| line.starts_with('#'))
+                .find(|line| line.starts_with(&key))
+                .map(|line| line[key.len()..].to_string())
+                .ok_or_else(|| format!("{}: missing {}header", path.display(), key))
+        };
+
+        let iteration = header("iteration")?.parse().map_err(|_| {
+            format!("{}: invalid iteration", path.display())
+        })?;
+        let rng = header("rng")?.parse().map_err(|_| {
+            format!("{}: invalid rng state", path.display())
+        })?;
+
+        let snapshot = content
+            .lines()
+            .skip_while(|line| line.starts_with('#'))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        Ok(Some(Checkpoint {
+            path,
+            iteration,
+            rng,
+            snapshot,
+        }))
+    }
+
+    /// Restore the random number generator and the network with the given
+    /// parameters to their state at the checkpoint.
+    pub fn restore(&self, params: Params) -> Result<Network, String> {
+        let mut reader = Reader::new(&self.snapshot);
+        let network = Network::restore(params, &mut reader).and_then(|network| {
+            reader.finish()?;
+            Ok(network)
+        });
+        let network = network.map_err(|error| format!("{}: {}", self.path.display(), error))?;
+
+        random::restore_state(self.rng);
+        Ok(network)
+    }
+}
+
+/// Writes the checkpoints, removing the old ones.
+pub struct Checkpointer {
+    dir: PathBuf,
+    frequency: u64,
+    keep: usize,
+    // Checkpoints written so far (including by previous runs), oldest first.
+    written: Vec<PathBuf>,
+}
+
+impl Checkpointer {
+    pub fn new<P: AsRef<Path>>(dir: P, frequency: u64, keep: usize) -> Result<Self, String> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir).map_err(|error| {
+            format!("Couldn't create {}: {}", dir.display(), error)
+        })?;
+
+        Ok(Checkpointer {
+            dir: dir.to_path_buf(),
+            frequency,
+            keep,
+            written: list(dir)?.into_iter().map(|(_, path)| path).collect(),
+        })
+    }
+
+    fn write(&mut self, iteration: u64, network: &Network) -> Result<(), String> {
+        let content = checkpoint(iteration, network)?;
+
+        // Write to a temporary file first, so an interrupted write never
+        // leaves a truncated checkpoint behind.
+        let path = self.dir.join(file_name(iteration));
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, content).map_err(|error| {
+            format!("Couldn't write {}: {}", temp.display(), error)
+        })?;
+        fs::rename(&temp, &path).map_err(|error| {
+            format!("Couldn't rename {}: {}", temp.display(), error)
+        })?;
+        self.written.push(path);
+
+        while self.written.len() > self.keep {
+            let old = self.written.remove(0);
+            fs::remove_file(&old).map_err(|error| {
+                format!("Couldn't remove {}: {}", old.display(), error)
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Observer for Checkpointer {
+    fn observe(&mut self, iteration: u64, network: &Network) {
+        if !(iteration + 1).is_multiple_of(self.frequency) {
+            return;
+        }
+
+        if let Err(error) = self.write(iteration, network) {
+            error!("Couldn't checkpoint iteration {}: {}", iteration, error);
+        } else {
+            info!("Checkpointed iteration {}", iteration);
+        }
+    }
+}
+
+/// Content of the checkpoint of the network after the iteration, with the
+/// current state of the random number generator.
+pub fn checkpoint(iteration: u64, network: &Network) -> Result<String, String> {
+    let rng = random::save_state().ok_or("random number generator in use")?;
+    let mut content = format!(
+        "# datachains_sim checkpoint\n# iteration: {}\n# rng: {}\n",
+        iteration,
+        rng
+    );
+    let mut writer = Writer::new();
+    network.save(&mut writer);
+    content.push_str(&writer.into_string());
+    Ok(content)
+}
+
+/// Name of the checkpoint file of the iteration.
+pub fn file_name(iteration: u64) -> String {
+    format!("{}{:020}{}", PREFIX, iteration, EXTENSION)
+}
+
+// The checkpoints in the directory, sorted by iteration.
+fn list(dir: &Path) -> Result<Vec<(u64, PathBuf)>, String> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let entries = fs::read_dir(dir).map_err(|error| {
+        format!("Couldn't read {}: {}", dir.display(), error)
+    })?;
+
+    let mut result: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(PREFIX) || !name.ends_with(EXTENSION) {
+                return None;
+            }
+            let iteration = name[PREFIX.len()..name.len() - EXTENSION.len()].parse().ok()?;
+            Some((iteration, entry.path()))
+        })
+        .collect();
+    result.sort();
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simulation::{Policies, Simulation};
+    use std::env;
+    use std::process;
+    use std::sync::atomic::AtomicBool;
+    use test_params;
+
+    fn params(args: &[&str]) -> Params {
+        let mut full = vec!["--seed", "[1, 2, 3, 4]", "--iterations", "300"];
+        full.extend_from_slice(args);
+        test_params(&full)
+    }
+
+    fn snapshot(network: &Network) -> String {
+        let mut writer = Writer::new();
+        network.save(&mut writer);
+        writer.into_string()
+    }
+
+    // Run to the end, interrupted after iteration 149 and resumed from the
+    // checkpoint if `dir` is given. Returns the final snapshot.
+    fn run(params: &Params, dir: Option<&Path>) -> String {
+        let running = AtomicBool::new(true);
+        random::reseed(params.rng, params.seed);
+
+        let mut simulation = Simulation::new(params.clone());
+        let first = if let Some(dir) = dir {
+            simulation.add_observer(Box::new(Checkpointer::new(dir, 50, 2).unwrap()));
+            let mut interrupted = params.clone();
+            interrupted.num_iterations = 150;
+            let _ = simulation.run(&interrupted, 0, &running);
+
+            // Whatever the interrupted run left behind must not matter.
+            random::reseed(params.rng, "[9, 9, 9, 9]".parse().unwrap());
+            let checkpoint = Checkpoint::latest(dir).unwrap().unwrap();
+            assert_eq!(checkpoint.iteration, 149);
+            let network = checkpoint.restore(params.clone()).unwrap();
+            simulation = Simulation::with_network(network, Policies::new(params));
+            checkpoint.iteration + 1
+        } else {
+            0
+        };
+
+        let _ = simulation.run(params, first, &running);
+        snapshot(&simulation.into_network())
+    }
+
+    #[test]
+    fn resumed_run_matches_uninterrupted_run() {
+        let configs: &[&[&str]] = &[
+            &[],
+            &["--global-relocation-cap", "2", "--view-lag", "2", "--rescue-window", "10"],
+            &["--join-relocation", "--relocation-timeout", "5", "--cancel-loss", "0.2"],
+            &["--routing", "--hop-loss", "0.1"],
+            &["--malicious-fraction", "0.2", "--relocation-denial"],
+            &["--chaos", "adult-age=4..6", "--chaos-interval", "30"],
+            &["--upgrade-interval", "50", "--rng", "chacha"],
+            &["--scheduler", "events", "--event-message-delay", "1"],
+        ];
+
+        for (index, args) in configs.iter().enumerate() {
+            let dir = env::temp_dir().join(format!(
+                "datachains_sim-soak-test-{}-{}",
+                process::id(),
+                index
+            ));
+            let _ = fs::remove_dir_all(&dir);
+
+            let params = params(args);
+            let expected = run(&params, None);
+            let actual = run(&params, Some(&dir));
+            let written = list(&dir).unwrap().len();
+            let _ = fs::remove_dir_all(&dir);
+
+            assert_eq!(written, 2, "{:?}", args);
+            assert!(actual == expected, "resumed run diverged with {:?}", args);
+        }
+    }
+}
@@ -1,10 +1,12 @@
+use params::{FileFormat, ScoreWeights};
+use snapshot::{Reader, Snapshot, Writer};
 use std::cmp;
 use std::collections::BTreeMap;
 use std::fmt;
 use std::fs::File;
 use std::io::Write;
+use std::ops::AddAssign;
 use std::path::Path;
-use std::u64;
 
 pub struct Aggregator {
     pub min: u64,
@@ -71,6 +73,7 @@ impl fmt::Display for Aggregator {
     }
 }
 
+#[derive(Default)]
 pub struct Distribution(BTreeMap<u64, u64>);
 
 impl Distribution {
@@ -87,6 +90,39 @@ impl Distribution {
         Distribution(map)
     }
 
+    /// Compact single-token representation: `value:count` pairs separated by
+    /// commas, or `-` if empty.
+    pub fn compact(&self) -> String {
+        if self.0.is_empty() {
+            return "-".to_string();
+        }
+
+        self.0
+            .iter()
+            .map(|(key, value)| format!("{}:{}", key, value))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    pub fn insert(&mut self, value: u64) {
+        *self.0.entry(value).or_insert(0) += 1;
+    }
+
+    /// Number of occurrences of the given value.
+    pub fn count(&self, value: u64) -> u64 {
+        self.0.get(&value).cloned().unwrap_or(0)
+    }
+
+    /// Number of occurrences of values greater than or equal to the given one.
+    pub fn count_at_least(&self, value: u64) -> u64 {
+        self.0.range(value..).map(|(_, count)| count).sum()
+    }
+
+    /// Total number of values.
+    pub fn total(&self) -> u64 {
+        self.0.values().sum()
+    }
+
     pub fn summary(&self) -> Aggregator {
         if self.0.is_empty() {
             return Aggregator::empty();
@@ -108,6 +144,24 @@ impl Distribution {
     }
 }
 
+impl Snapshot for Distribution {
+    fn save(&self, writer: &mut Writer) {
+        self.0.save(writer)
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok(Distribution(Snapshot::load(reader)?))
+    }
+}
+
+impl Extend<u64> for Distribution {
+    fn extend<I: IntoIterator<Item = u64>>(&mut self, values: I) {
+        for value in values {
+            self.insert(value);
+        }
+    }
+}
+
 impl fmt::Display for Distribution {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         for (key, value) in &self.0 {
@@ -118,15 +172,968 @@ impl fmt::Display for Distribution {
     }
 }
 
+/// The distribution as a table with a bar per value, the longest `width`
+/// characters long. With `log`, the bars are proportional to the logarithm of
+/// the counts, so the rare values stay visible next to the common ones.
+pub struct Histogram<'a> {
+    pub distribution: &'a Distribution,
+    pub width: usize,
+    pub log: bool,
+}
+
+impl<'a> Histogram<'a> {
+    fn scale(&self, count: u64) -> f64 {
+        if self.log {
+            (count as f64).ln_1p()
+        } else {
+            count as f64
+        }
+    }
+}
+
+impl<'a> fmt::Display for Histogram<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let max = self.distribution.0.values().cloned().max().unwrap_or(0);
+        let max = self.scale(max).max(f64::MIN_POSITIVE);
+
+        for (key, &value) in &self.distribution.0 {
+            // Every occurring value gets at least one character.
+            let len = (self.width as f64 * self.scale(value) / max).round() as usize;
+            let bar = "#".repeat(cmp::max(len, 1));
+            writeln!(fmt, "{:6}:\t{:6} {}", key, value, bar)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Distributions of values grouped by a key.
+pub struct GroupedDistribution(BTreeMap<u64, Distribution>);
+
+impl GroupedDistribution {
+    /// Create from an iterator of `(key, value)` pairs.
+    pub fn new<I>(pairs: I) -> Self
+    where
+        I: IntoIterator<Item = (u64, u64)>,
+    {
+        let mut map = BTreeMap::new();
+
+        for (key, value) in pairs {
+            let dist = map.entry(key).or_insert_with(|| Distribution(BTreeMap::new()));
+            *dist.0.entry(value).or_insert(0) += 1;
+        }
+
+        GroupedDistribution(map)
+    }
+
+    pub fn insert(&mut self, key: u64, value: u64) {
+        self.0.entry(key).or_default().insert(value);
+    }
+}
+
+/// Number of occurrences of an event, with the details of the first few of
+/// them, so rare events can be listed without their records growing over a
+/// long run.
+pub struct Records<T> {
+    count: u64,
+    first: Vec<T>,
+}
+
+impl<T> Records<T> {
+    /// Number of occurrences whose details are kept.
+    pub const LIMIT: usize = 100;
+
+    pub fn new() -> Self {
+        Records {
+            count: 0,
+            first: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, record: T) {
+        self.count += 1;
+        if self.first.len() < Self::LIMIT {
+            self.first.push(record);
+        }
+    }
+
+    /// Number of occurrences, including those whose details weren't kept.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Details of the first occurrences.
+    pub fn first(&self) -> &[T] {
+        &self.first
+    }
+}
+
+impl fmt::Display for GroupedDistribution {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for (key, dist) in &self.0 {
+            writeln!(
+                fmt,
+                "{:6}:\t{:6} values, {:?}",
+                key,
+                dist.0.values().sum::<u64>(),
+                dist.summary()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Counters of network events.
+#[derive(Clone, Copy, Default)]
+pub struct Counters {
+    pub merges: u64,
+    pub splits: u64,
+    pub relocations: u64,
+    pub forced_relocations: u64,
+    pub balancing_relocations: u64,
+    pub rejections: u64,
+    pub relocation_rejections: u64,
+    pub join_redirects: u64,
+    pub restarts: u64,
+    pub elder_restarts: u64,
+    /// Number of sections with more than `max_section_size` nodes (summed over
+    /// iterations, this is the number of section-iterations spent above the
+    /// limit).
+    pub oversized_sections: u64,
+    /// Number of relocation commits that didn't match any incoming relocation.
+    pub unmatched_commits: u64,
+    /// Number of sections with too few nodes old enough to be elders (summed
+    /// over iterations, like `oversized_sections`).
+    pub short_elder_sections: u64,
+    /// Number of relocation requests delivered.
+    pub relocation_requests: u64,
+    /// Number of relocation requests rejected for lack of elder approvals.
+    pub approval_failures: u64,
+    /// Number of churn events that ran the ageing check.
+    pub ageing_events: u64,
+    /// Number of accepted relocations dropped by the destination after their
+    /// commit didn't arrive within the timeout.
+    pub zombie_relocations: u64,
+    /// Number of relocation requests rejected by sections controlled by the
+    /// attacker.
+    pub denied_relocations: u64,
+    /// Number of relocation commits matched by the destination.
+    pub completed_relocations: u64,
+    /// Number of accepted relocations cancelled because the node dropped before
+    /// the commit.
+    pub cancelled_relocations: u64,
+    /// Number of drops that couldn't trigger the ageing check because the last
+    /// `Live` block was no longer retained.
+    pub truncated_lookups: u64,
+    /// Number of relocations given up by the source after they weren't
+    /// accepted or rejected within the timeout.
+    pub expired_relocation_requests: u64,
+    /// Ageing relocations directed to a neighbour short of adults.
+    pub rescue_relocations: u64,
+    /// Sections short of adults that recovered while waiting for rescue
+    /// relocations, and would still have been short of adults without the
+    /// nodes those brought in.
+    pub prevented_merges: u64,
+    /// Nodes entering a section under the name of a node that is or was in the
+    /// network (not counting restarted nodes rejoining).
+    pub reused_names: u64,
+    /// Nodes that reached the adult age (by being relocated or on split).
+    pub matured_nodes: u64,
+    /// Number of times a section that should split didn't, because its prefix
+    /// was already at the maximum length.
+    pub refused_splits: u64,
+    /// Number of relocation responses not matching the current exchange of
+    /// their node (e.g. arriving after it was cancelled or timed out).
+    pub orphaned_responses: u64,
+    /// Number of relocation responses to exchanges already rejected.
+    pub accepts_after_reject: u64,
+    /// Number of relocation commits whose exchange or target differs from the
+    /// accepted relocation of the node.
+    pub mismatched_commits: u64,
+    /// Number of relocated nodes put in a random half of the section, as its
+    /// halves were within `commit_balance_threshold` adults of each other.
+    pub random_commit_halves: u64,
+    /// Number of new nodes of the attacker joining.
+    pub malicious_joins: u64,
+    /// Number of new nodes of the attacker placed in the section they chose
+    /// (not redirected, or redirected back into it).
+    pub chosen_section_joins: u64,
+}
+
+impl Counters {
+    // All the counters, in declaration order.
+    fn values_mut(&mut self) -> [&mut u64; 33] {
+        [
+            &mut self.merges,
+            &mut self.splits,
+            &mut self.relocations,
+            &mut self.forced_relocations,
+            &mut self.balancing_relocations,
+            &mut self.rejections,
+            &mut self.relocation_rejections,
+            &mut self.join_redirects,
+            &mut self.restarts,
+            &mut self.elder_restarts,
+            &mut self.oversized_sections,
+            &mut self.unmatched_commits,
+            &mut self.short_elder_sections,
+            &mut self.relocation_requests,
+            &mut self.approval_failures,
+            &mut self.ageing_events,
+            &mut self.zombie_relocations,
+            &mut self.denied_relocations,
+            &mut self.completed_relocations,
+            &mut self.cancelled_relocations,
+            &mut self.truncated_lookups,
+            &mut self.expired_relocation_requests,
+            &mut self.rescue_relocations,
+            &mut self.prevented_merges,
+            &mut self.reused_names,
+            &mut self.matured_nodes,
+            &mut self.refused_splits,
+            &mut self.orphaned_responses,
+            &mut self.accepts_after_reject,
+            &mut self.mismatched_commits,
+            &mut self.random_commit_halves,
+            &mut self.malicious_joins,
+            &mut self.chosen_section_joins,
+        ]
+    }
+}
+
+impl Snapshot for Counters {
+    fn save(&self, writer: &mut Writer) {
+        let mut counters = *self;
+        for value in counters.values_mut().iter() {
+            writer.put(**value);
+        }
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        let mut counters = Counters::default();
+        for value in counters.values_mut().iter_mut() {
+            **value = reader.get()?;
+        }
+        Ok(counters)
+    }
+}
+
+impl AddAssign for Counters {
+    fn add_assign(&mut self, other: Self) {
+        self.merges += other.merges;
+        self.splits += other.splits;
+        self.relocations += other.relocations;
+        self.forced_relocations += other.forced_relocations;
+        self.balancing_relocations += other.balancing_relocations;
+        self.rejections += other.rejections;
+        self.relocation_rejections += other.relocation_rejections;
+        self.join_redirects += other.join_redirects;
+        self.restarts += other.restarts;
+        self.elder_restarts += other.elder_restarts;
+        self.oversized_sections += other.oversized_sections;
+        self.unmatched_commits += other.unmatched_commits;
+        self.short_elder_sections += other.short_elder_sections;
+        self.relocation_requests += other.relocation_requests;
+        self.approval_failures += other.approval_failures;
+        self.ageing_events += other.ageing_events;
+        self.zombie_relocations += other.zombie_relocations;
+        self.denied_relocations += other.denied_relocations;
+        self.completed_relocations += other.completed_relocations;
+        self.cancelled_relocations += other.cancelled_relocations;
+        self.truncated_lookups += other.truncated_lookups;
+        self.expired_relocation_requests += other.expired_relocation_requests;
+        self.rescue_relocations += other.rescue_relocations;
+        self.prevented_merges += other.prevented_merges;
+        self.reused_names += other.reused_names;
+        self.matured_nodes += other.matured_nodes;
+        self.refused_splits += other.refused_splits;
+        self.orphaned_responses += other.orphaned_responses;
+        self.accepts_after_reject += other.accepts_after_reject;
+        self.mismatched_commits += other.mismatched_commits;
+        self.random_commit_halves += other.random_commit_halves;
+        self.malicious_joins += other.malicious_joins;
+        self.chosen_section_joins += other.chosen_section_joins;
+    }
+}
+
+/// Cumulative churn events of a single section.
+#[derive(Clone, Copy, Default)]
+pub struct Churn {
+    /// Nodes added to the section (including relocated ones).
+    pub joins: u64,
+    pub drops: u64,
+    pub relocations_in: u64,
+    pub relocations_out: u64,
+    /// Promotions and demotions of elders.
+    pub elder_changes: u64,
+}
+
+impl Churn {
+    pub fn total(&self) -> u64 {
+        self.joins + self.drops + self.relocations_in + self.relocations_out + self.elder_changes
+    }
+
+    /// Number of nodes added or removed (the relocated in ones are among the
+    /// `joins`).
+    pub fn membership_changes(&self) -> u64 {
+        self.joins + self.drops + self.relocations_out
+    }
+}
+
+impl AddAssign for Churn {
+    fn add_assign(&mut self, other: Self) {
+        self.joins += other.joins;
+        self.drops += other.drops;
+        self.relocations_in += other.relocations_in;
+        self.relocations_out += other.relocations_out;
+        self.elder_changes += other.elder_changes;
+    }
+}
+
+impl Snapshot for Churn {
+    fn save(&self, writer: &mut Writer) {
+        writer.put(self.joins);
+        writer.put(self.drops);
+        writer.put(self.relocations_in);
+        writer.put(self.relocations_out);
+        writer.put(self.elder_changes);
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok(Churn {
+            joins: reader.get()?,
+            drops: reader.get()?,
+            relocations_in: reader.get()?,
+            relocations_out: reader.get()?,
+            elder_changes: reader.get()?,
+        })
+    }
+}
+
+impl fmt::Debug for Churn {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "{{ joins: {}, drops: {}, relocations in: {}, relocations out: {}, \
+             elder changes: {} }}",
+            self.joins,
+            self.drops,
+            self.relocations_in,
+            self.relocations_out,
+            self.elder_changes
+        )
+    }
+}
+
+/// Node events counted by the origin of the node (see `node::Origin`).
+#[derive(Clone, Copy, Default)]
+pub struct OriginCounts {
+    pub arrivals: u64,
+    pub drops: u64,
+    pub promotions: u64,
+}
+
+impl Snapshot for OriginCounts {
+    fn save(&self, writer: &mut Writer) {
+        writer.put(self.arrivals);
+        writer.put(self.drops);
+        writer.put(self.promotions);
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok(OriginCounts {
+            arrivals: reader.get()?,
+            drops: reader.get()?,
+            promotions: reader.get()?,
+        })
+    }
+}
+
+impl AddAssign for OriginCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.arrivals += other.arrivals;
+        self.drops += other.drops;
+        self.promotions += other.promotions;
+    }
+}
+
+/// Progress of the nodes of one age through the ageing check: how many times a
+/// node was checked against the ageing hash, passed the check, was picked among
+/// the nodes that passed it and was actually relocated. Only the nodes that can
+/// be relocated are checked, and the checks are counted only with
+/// `Params::ageing_audit`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AgeingCounts {
+    pub checked: u64,
+    pub eligible: u64,
+    pub selected: u64,
+    pub relocated: u64,
+}
+
+impl Snapshot for AgeingCounts {
+    fn save(&self, writer: &mut Writer) {
+        writer.put(self.checked);
+        writer.put(self.eligible);
+        writer.put(self.selected);
+        writer.put(self.relocated);
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok(AgeingCounts {
+            checked: reader.get()?,
+            eligible: reader.get()?,
+            selected: reader.get()?,
+            relocated: reader.get()?,
+        })
+    }
+}
+
+impl AddAssign for AgeingCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.checked += other.checked;
+        self.eligible += other.eligible;
+        self.selected += other.selected;
+        self.relocated += other.relocated;
+    }
+}
+
+/// Responses of a destination section to relocation requests. `busy` counts
+/// the rejections (among `rejected`) due to another incoming relocation in
+/// progress.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RelocationResponses {
+    pub accepted: u64,
+    pub rejected: u64,
+    pub busy: u64,
+}
+
+impl RelocationResponses {
+    /// Fraction of the requests accepted.
+    pub fn acceptance_rate(&self) -> f64 {
+        self.accepted as f64 / cmp::max(self.accepted + self.rejected, 1) as f64
+    }
+}
+
+impl Snapshot for RelocationResponses {
+    fn save(&self, writer: &mut Writer) {
+        writer.put(self.accepted);
+        writer.put(self.rejected);
+        writer.put(self.busy);
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok(RelocationResponses {
+            accepted: reader.get()?,
+            rejected: reader.get()?,
+            busy: reader.get()?,
+        })
+    }
+}
+
+impl AddAssign for RelocationResponses {
+    fn add_assign(&mut self, other: Self) {
+        self.accepted += other.accepted;
+        self.rejected += other.rejected;
+        self.busy += other.busy;
+    }
+}
+
+/// Merges and elder changes over some of the iterations (see
+/// `Network::upgrade_churn`).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IterationChurn {
+    pub iterations: u64,
+    pub merges: u64,
+    pub elder_changes: u64,
+}
+
+impl IterationChurn {
+    pub fn merges_per_iteration(&self) -> f64 {
+        self.merges as f64 / cmp::max(self.iterations, 1) as f64
+    }
+
+    pub fn elder_changes_per_iteration(&self) -> f64 {
+        self.elder_changes as f64 / cmp::max(self.iterations, 1) as f64
+    }
+}
+
+/// How the neighbour information the sections acted on compares with the
+/// actual state of the network (see `Params::view_lag`).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StaleViews {
+    /// Sibling sizes and needs for adults delivered late.
+    pub delayed: u64,
+    /// Delayed ones whose source section split or merged in the meantime.
+    pub outdated: u64,
+    /// Total difference between the delayed sibling sizes and the actual ones.
+    pub size_error: u64,
+    /// Rescue relocations committed to a section no longer short of adults.
+    pub wasted_rescues: u64,
+    /// Balancing relocations committed to a section at least as large as the
+    /// source.
+    pub misdirected_balancing: u64,
+    /// Sibling sizes delivered to a descendant of the sibling, which split in
+    /// the meantime, and dropped there.
+    pub dropped_sizes: u64,
+}
+
+/// Number of iterations the nodes of each operator (see `Params::operators`)
+/// ended with an elder seat, for every operator which ever had a node in the
+/// network.
+#[derive(Default)]
+pub struct ElderRotation(BTreeMap<u32, u64>);
+
+impl ElderRotation {
+    pub fn record(&mut self, operator: u32, elder: bool) {
+        *self.0.entry(operator).or_insert(0) += elder as u64;
+    }
+
+    /// Number of operators which ever had a node in the network.
+    pub fn num_operators(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Number of operators which ever had an elder.
+    pub fn num_elder_operators(&self) -> usize {
+        self.0.values().filter(|&&time| time > 0).count()
+    }
+
+    /// Elder iterations per operator.
+    pub fn summary(&self) -> Aggregator {
+        Distribution::new(self.0.values().cloned()).summary()
+    }
+
+    /// Fraction of all the elder iterations held by the given fraction of the
+    /// operators with the most of them.
+    pub fn top_share(&self, fraction: f64) -> f64 {
+        let mut times: Vec<_> = self.0.values().cloned().collect();
+        times.sort_by(|a, b| b.cmp(a));
+        let count = (fraction * times.len() as f64).ceil() as usize;
+        let total: u64 = times.iter().sum();
+        times.iter().take(count).sum::<u64>() as f64 / cmp::max(total, 1) as f64
+    }
+
+    /// Gini coefficient of the elder iterations across the operators: 0 if
+    /// they are spread evenly, approaching 1 if a single operator holds them
+    /// all.
+    pub fn gini(&self) -> f64 {
+        let mut times: Vec<_> = self.0.values().cloned().collect();
+        times.sort();
+        let total: u64 = times.iter().sum();
+        if total == 0 {
+            return 0.0;
+        }
+
+        let count = times.len() as f64;
+        let weighted: f64 = times
+            .iter()
+            .enumerate()
+            .map(|(index, &time)| (index + 1) as f64 * time as f64)
+            .sum();
+        2.0 * weighted / (count * total as f64) - (count + 1.0) / count
+    }
+}
+
+impl fmt::Display for ElderRotation {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            fmt,
+            "Operators ever elder:     {} of {} ({:.2}%)",
+            self.num_elder_operators(),
+            self.num_operators(),
+            100.0 * self.num_elder_operators() as f64 / cmp::max(self.num_operators(), 1) as f64
+        )?;
+        writeln!(fmt, "Elder iterations:         {:?}", self.summary())?;
+        writeln!(
+            fmt,
+            "Held by the top 10%:      {:.2}%",
+            100.0 * self.top_share(0.1)
+        )?;
+        writeln!(fmt, "Gini coefficient:         {:.3}", self.gini())
+    }
+}
+
+/// Totals of the iterations spent in one era of the network: era 0 is a single
+/// section, era `k` from `4^(k-1) + 1` to `4^k` sections.
+#[derive(Clone, Copy, Default)]
+struct Era {
+    first_iteration: u64,
+    iterations: u64,
+    membership_changes: u64,
+    relocations: u64,
+    rejections: u64,
+    // Sum over the iterations of the fraction of complete sections.
+    completeness: f64,
+}
+
+/// Per-era averages, showing how the network behaves as it grows.
+#[derive(Default)]
+pub struct Eras(BTreeMap<u32, Era>);
+
+impl Eras {
+    pub fn record(
+        &mut self,
+        iteration: u64,
+        total_sections: u64,
+        complete_sections: u64,
+        membership_changes: u64,
+        counters: &Counters,
+    ) {
+        let era = self.0.entry(Self::era(total_sections)).or_insert_with(|| {
+            Era {
+                first_iteration: iteration,
+                ..Era::default()
+            }
+        });
+
+        era.iterations += 1;
+        era.membership_changes += membership_changes;
+        era.relocations += counters.relocations;
+        era.rejections += counters.rejections;
+        if total_sections > 0 {
+            era.completeness += complete_sections as f64 / total_sections as f64;
+        }
+    }
+
+    /// Average fraction of complete sections over all the iterations.
+    pub fn completeness(&self) -> f64 {
+        let iterations: u64 = self.0.values().map(|era| era.iterations).sum();
+        let completeness: f64 = self.0.values().map(|era| era.completeness).sum();
+        completeness / cmp::max(iterations, 1) as f64
+    }
+
+    fn era(total_sections: u64) -> u32 {
+        let mut era = 0;
+        let mut limit = 1;
+        while total_sections > limit {
+            era += 1;
+            limit *= 4;
+        }
+        era
+    }
+
+    // The range of section counts of the era.
+    fn bounds(era: u32) -> (u64, u64) {
+        match era {
+            0 => (1, 1),
+            _ => (4u64.pow(era - 1) + 1, 4u64.pow(era)),
+        }
+    }
+}
+
+impl fmt::Display for Eras {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            fmt,
+            "{:>11} {:>10} {:>10} {:>10} {:>10} {:>10} {:>9}",
+            "Sections",
+            "From",
+            "Iterations",
+            "Churn",
+            "Reloc.",
+            "Reject.",
+            "Complete"
+        )?;
+
+        for (&era, totals) in &self.0 {
+            let (min, max) = Self::bounds(era);
+            let iterations = totals.iterations as f64;
+            writeln!(
+                fmt,
+                "{:>11} {:>10} {:>10} {:>10.2} {:>10.2} {:>10.2} {:>8.1}%",
+                if min == max {
+                    format!("{}", min)
+                } else {
+                    format!("{}-{}", min, max)
+                },
+                totals.first_iteration,
+                totals.iterations,
+                totals.membership_changes as f64 / iterations,
+                totals.relocations as f64 / iterations,
+                totals.rejections as f64 / iterations,
+                100.0 * totals.completeness / iterations
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Headline outcomes of a run, normalised so runs of different lengths and
+/// sizes compare.
+#[derive(Clone, Copy, Default)]
+pub struct Outcome {
+    /// Average fraction of complete sections per iteration.
+    pub completeness: f64,
+    /// Relocations per node joining or dropping.
+    pub relocation_cost: f64,
+    /// Fraction of the join attempts rejected.
+    pub rejection_rate: f64,
+    /// Elder promotions and demotions per node joining or dropping.
+    pub elder_churn: f64,
+}
+
+impl Outcome {
+    /// Weighted combination of the outcomes, higher being better, for ranking
+    /// runs with different parameters. Every outcome is first brought to the
+    /// range 0 to 1, so the weights alone set how much each one counts: the
+    /// fractions are taken as they are and the per-node rates `x` as
+    /// `x / (1 + x)`.
+    pub fn score(&self, weights: &ScoreWeights) -> f64 {
+        fn normalise(rate: f64) -> f64 {
+            rate / (1.0 + rate)
+        }
+
+        weights.completeness * self.completeness -
+            weights.relocation_cost * normalise(self.relocation_cost) -
+            weights.rejection_rate * self.rejection_rate -
+            weights.elder_churn * normalise(self.elder_churn)
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "completeness {:.4}, relocation cost {:.4}, rejection rate {:.4}, \
+             elder churn {:.4}",
+            self.completeness,
+            self.relocation_cost,
+            self.rejection_rate,
+            self.elder_churn
+        )
+    }
+}
+
+/// Totals of the iterations between two chaos mode changes.
+struct ChaosEpoch {
+    first_iteration: u64,
+    // Values of the chaos mode parameters during the epoch.
+    settings: String,
+    iterations: u64,
+    membership_changes: u64,
+    relocations: u64,
+    rejections: u64,
+    merges: u64,
+    // Sums over the iterations of the sections with too many nodes or too few
+    // elder candidates.
+    oversized_sections: u64,
+    short_elder_sections: u64,
+    faults: u64,
+    violations: u64,
+}
+
+/// Per-epoch averages of the chaos mode (see `Params::chaos`), to tell which
+/// parameter values the faults, invariant violations and metric spikes
+/// happened under.
+#[derive(Default)]
+pub struct ChaosEpochs(Vec<ChaosEpoch>);
+
+impl ChaosEpochs {
+    /// Start a new epoch with the given parameter values.
+    pub fn start(&mut self, iteration: u64, settings: String) {
+        self.0.push(ChaosEpoch {
+            first_iteration: iteration,
+            settings,
+            iterations: 0,
+            membership_changes: 0,
+            relocations: 0,
+            rejections: 0,
+            merges: 0,
+            oversized_sections: 0,
+            short_elder_sections: 0,
+            faults: 0,
+            violations: 0,
+        });
+    }
+
+    /// Record an iteration of the current epoch, with the faults and invariant
+    /// violations found in it.
+    pub fn record(
+        &mut self,
+        membership_changes: u64,
+        counters: &Counters,
+        faults: u64,
+        violations: u64,
+    ) {
+        if let Some(epoch) = self.0.last_mut() {
+            epoch.iterations += 1;
+            epoch.membership_changes += membership_changes;
+            epoch.relocations += counters.relocations;
+            epoch.rejections += counters.rejections;
+            epoch.merges += counters.merges;
+            epoch.oversized_sections += counters.oversized_sections;
+            epoch.short_elder_sections += counters.short_elder_sections;
+            epoch.faults += faults;
+            epoch.violations += violations;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Parameter values of the current epoch.
+    pub fn current(&self) -> Option<&str> {
+        self.0.last().map(|epoch| &epoch.settings[..])
+    }
+}
+
+impl fmt::Display for ChaosEpochs {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            fmt,
+            "{:>10} {:>10} {:>8} {:>8} {:>8} {:>8} {:>9} {:>9} {:>7} {:>7}  Settings",
+            "From",
+            "Iterations",
+            "Churn",
+            "Reloc.",
+            "Reject.",
+            "Merges",
+            "Oversize",
+            "Short",
+            "Faults",
+            "Viol."
+        )?;
+
+        for epoch in &self.0 {
+            let iterations = cmp::max(epoch.iterations, 1) as f64;
+            writeln!(
+                fmt,
+                "{:>10} {:>10} {:>8.2} {:>8.2} {:>8.2} {:>8.3} {:>9.3} {:>9.3} {:>7} {:>7}  {}",
+                epoch.first_iteration,
+                epoch.iterations,
+                epoch.membership_changes as f64 / iterations,
+                epoch.relocations as f64 / iterations,
+                epoch.rejections as f64 / iterations,
+                epoch.merges as f64 / iterations,
+                epoch.oversized_sections as f64 / iterations,
+                epoch.short_elder_sections as f64 / iterations,
+                epoch.faults,
+                epoch.violations,
+                epoch.settings
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Clone, Copy, Default)]
 pub struct Sample {
     iteration: u64,
     nodes: u64,
     sections: u64,
-    merges: u64,
-    splits: u64,
-    relocations: u64,
-    rejections: u64,
+    // Sections with enough adults and a full group of elders.
+    complete_sections: u64,
+    // Cumulative counts up to and including this iteration.
+    totals: Counters,
+}
+
+impl Sample {
+    pub fn iteration(&self) -> u64 {
+        self.iteration
+    }
+
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    pub fn sections(&self) -> u64 {
+        self.sections
+    }
+
+    pub fn complete_sections(&self) -> u64 {
+        self.complete_sections
+    }
+
+    pub fn merges(&self) -> u64 {
+        self.totals.merges
+    }
+
+    pub fn splits(&self) -> u64 {
+        self.totals.splits
+    }
+
+    pub fn rejections(&self) -> u64 {
+        self.totals.rejections
+    }
+
+    pub fn relocations(&self) -> u64 {
+        self.totals.relocations
+    }
+
+    /// Nodes that reached the adult age.
+    pub fn matured_nodes(&self) -> u64 {
+        self.totals.matured_nodes
+    }
+
+    pub fn relocation_requests(&self) -> u64 {
+        self.totals.relocation_requests
+    }
+
+    pub fn random_commit_halves(&self) -> u64 {
+        self.totals.random_commit_halves
+    }
+
+    pub fn balancing_relocations(&self) -> u64 {
+        self.totals.balancing_relocations
+    }
+
+    pub fn malicious_joins(&self) -> u64 {
+        self.totals.malicious_joins
+    }
+
+    /// Nodes of the attacker placed in the section they chose.
+    pub fn chosen_section_joins(&self) -> u64 {
+        self.totals.chosen_section_joins
+    }
+
+    /// Number of relocations triggered by the ageing check (not forced nor
+    /// balancing ones).
+    pub fn ageing_relocations(&self) -> u64 {
+        self.totals.relocations - self.totals.forced_relocations -
+            self.totals.balancing_relocations
+    }
+
+    pub fn ageing_events(&self) -> u64 {
+        self.totals.ageing_events
+    }
+
+    /// Percentage of the relocations accepted by the destination that
+    /// completed, as opposed to those cancelled, timed out or whose commit
+    /// didn't match.
+    pub fn relocation_efficiency(&self) -> f64 {
+        let completed = self.totals.completed_relocations;
+        let wasted = self.totals.cancelled_relocations + self.totals.zombie_relocations +
+            self.totals.unmatched_commits;
+        100.0 * completed as f64 / cmp::max(completed + wasted, 1) as f64
+    }
+}
+
+impl Snapshot for Sample {
+    fn save(&self, writer: &mut Writer) {
+        writer.put(self.iteration);
+        writer.put(self.nodes);
+        writer.put(self.sections);
+        writer.put(self.complete_sections);
+        self.totals.save(writer);
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok(Sample {
+            iteration: reader.get()?,
+            nodes: reader.get()?,
+            sections: reader.get()?,
+            complete_sections: reader.get()?,
+            totals: Snapshot::load(reader)?,
+        })
+    }
 }
 
 impl fmt::Debug for Sample {
@@ -138,15 +1145,73 @@ impl fmt::Debug for Sample {
             sections: {}, \
             merges: {}, \
             splits: {}, \
-            relocations: {} \
-            rejections: {} }}",
+            relocations: {}, \
+            rejections: {}, \
+            forced relocations: {}, \
+            balancing relocations: {}, \
+            relocation rejections: {}, \
+            join redirects: {}, \
+            restarts: {}, \
+            elder restarts: {}, \
+            oversized: {}, \
+            unmatched commits: {}, \
+            short of elders: {}, \
+            relocation requests: {}, \
+            approval failures: {}, \
+            ageing events: {}, \
+            zombie relocations: {}, \
+            denied relocations: {}, \
+            completed relocations: {}, \
+            cancelled relocations: {}, \
+            truncated lookups: {}, \
+            expired relocation requests: {}, \
+            rescue relocations: {}, \
+            prevented merges: {}, \
+            reused names: {}, \
+            matured nodes: {}, \
+            refused splits: {}, \
+            orphaned responses: {}, \
+            accepts after reject: {}, \
+            mismatched commits: {}, \
+            random commit halves: {}, \
+            malicious joins: {}, \
+            chosen section joins: {} }}",
             self.iteration,
             self.nodes,
             self.sections,
-            self.merges,
-            self.splits,
-            self.relocations,
-            self.rejections,
+            self.totals.merges,
+            self.totals.splits,
+            self.totals.relocations,
+            self.totals.rejections,
+            self.totals.forced_relocations,
+            self.totals.balancing_relocations,
+            self.totals.relocation_rejections,
+            self.totals.join_redirects,
+            self.totals.restarts,
+            self.totals.elder_restarts,
+            self.totals.oversized_sections,
+            self.totals.unmatched_commits,
+            self.totals.short_elder_sections,
+            self.totals.relocation_requests,
+            self.totals.approval_failures,
+            self.totals.ageing_events,
+            self.totals.zombie_relocations,
+            self.totals.denied_relocations,
+            self.totals.completed_relocations,
+            self.totals.cancelled_relocations,
+            self.totals.truncated_lookups,
+            self.totals.expired_relocation_requests,
+            self.totals.rescue_relocations,
+            self.totals.prevented_merges,
+            self.totals.reused_names,
+            self.totals.matured_nodes,
+            self.totals.refused_splits,
+            self.totals.orphaned_responses,
+            self.totals.accepts_after_reject,
+            self.totals.mismatched_commits,
+            self.totals.random_commit_halves,
+            self.totals.malicious_joins,
+            self.totals.chosen_section_joins,
         )
     }
 }
@@ -160,87 +1225,302 @@ impl fmt::Display for Sample {
              Merges:      {:>8}\n\
              Splits:      {:>8}\n\
              Relocations: {:>8}\n\
-             Rejections:  {:>8}",
+             Rejections:  {:>8}\n\
+             Forced rel.: {:>8}\n\
+             Balancing:   {:>8}\n\
+             Reloc. rej.: {:>8}\n\
+             Join redir.: {:>8}\n\
+             Restarts:    {:>8}\n\
+             Elder rest.: {:>8}\n\
+             Oversized:   {:>8}\n\
+             Unmatched:   {:>8}\n\
+             Short eld.:  {:>8}\n\
+             Reloc. req.: {:>8}\n\
+             Appr. fail.: {:>8}\n\
+             Ageing ev.:  {:>8}\n\
+             Zombie rel.: {:>8}\n\
+             Denied rel.: {:>8}\n\
+             Completed:   {:>8}\n\
+             Cancelled:   {:>8}\n\
+             Truncated:   {:>8}\n\
+             Expired req: {:>8}\n\
+             Rescues:     {:>8}\n\
+             Prev. merge: {:>8}\n\
+             Reused:      {:>8}\n\
+             Matured:     {:>8}\n\
+             Refused spl.:{:>8}\n\
+             Orphaned:    {:>8}\n\
+             Late accept: {:>8}\n\
+             Mism. comm.: {:>8}\n\
+             Random half: {:>8}\n\
+             Mal. joins:  {:>8}\n\
+             Chosen sec.: {:>8}",
             self.iteration,
             self.nodes,
             self.sections,
-            self.merges,
-            self.splits,
-            self.relocations,
-            self.rejections,
+            self.totals.merges,
+            self.totals.splits,
+            self.totals.relocations,
+            self.totals.rejections,
+            self.totals.forced_relocations,
+            self.totals.balancing_relocations,
+            self.totals.relocation_rejections,
+            self.totals.join_redirects,
+            self.totals.restarts,
+            self.totals.elder_restarts,
+            self.totals.oversized_sections,
+            self.totals.unmatched_commits,
+            self.totals.short_elder_sections,
+            self.totals.relocation_requests,
+            self.totals.approval_failures,
+            self.totals.ageing_events,
+            self.totals.zombie_relocations,
+            self.totals.denied_relocations,
+            self.totals.completed_relocations,
+            self.totals.cancelled_relocations,
+            self.totals.truncated_lookups,
+            self.totals.expired_relocation_requests,
+            self.totals.rescue_relocations,
+            self.totals.prevented_merges,
+            self.totals.reused_names,
+            self.totals.matured_nodes,
+            self.totals.refused_splits,
+            self.totals.orphaned_responses,
+            self.totals.accepts_after_reject,
+            self.totals.mismatched_commits,
+            self.totals.random_commit_halves,
+            self.totals.malicious_joins,
+            self.totals.chosen_section_joins,
         )
     }
 }
 
 pub struct Stats {
     samples: Vec<Sample>,
-    total_merges: u64,
-    total_splits: u64,
-    total_relocations: u64,
-    total_rejections: u64,
+    // Age distribution of elders, one per sample.
+    elder_ages: Vec<Distribution>,
+    totals: Counters,
+    // Number of most recent samples to keep (0 to keep all).
+    retention: usize,
+    // Totals of the last sample trimmed away, so the first one kept still
+    // counts the changes since its predecessor.
+    trimmed: Counters,
 }
 
 impl Stats {
     pub fn new() -> Self {
+        Self::with_retention(0)
+    }
+
+    /// Stats keeping only (at least) the `retention` most recent samples, to
+    /// bound the memory of long runs. The totals still cover the whole run.
+    pub fn with_retention(retention: usize) -> Self {
         Stats {
             samples: Vec::new(),
-            total_merges: 0,
-            total_splits: 0,
-            total_relocations: 0,
-            total_rejections: 0,
+            elder_ages: Vec::new(),
+            totals: Counters::default(),
+            retention,
+            trimmed: Counters::default(),
         }
     }
 
-    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
     pub fn record(
         &mut self,
         iteration: u64,
         total_nodes: u64,
         total_sections: u64,
-        merges: u64,
-        splits: u64,
-        relocations: u64,
-        rejections: u64,
+        complete_sections: u64,
+        counters: Counters,
+        elder_ages: Distribution,
     ) {
-        self.total_merges += merges;
-        self.total_splits += splits;
-        self.total_relocations += relocations;
-        self.total_rejections += rejections;
+        self.totals += counters;
+        self.elder_ages.push(elder_ages);
 
         self.samples.push(Sample {
             iteration,
             nodes: total_nodes,
             sections: total_sections,
-            merges: self.total_merges,
-            splits: self.total_splits,
-            relocations: self.total_relocations,
-            rejections: self.total_rejections,
-        })
+            complete_sections,
+            totals: self.totals,
+        });
+
+        // Trim in batches, so the samples aren't shifted on every record.
+        if self.retention > 0 && self.samples.len() >= 2 * self.retention {
+            let excess = self.samples.len() - self.retention;
+            self.trimmed = self.samples[excess - 1].totals;
+            let _ = self.samples.drain(..excess);
+            let _ = self.elder_ages.drain(..excess);
+        }
     }
 
     pub fn summary(&self) -> Sample {
         self.samples.last().cloned().unwrap_or_default()
     }
 
-    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) {
+    /// Write one line per sample. The legacy columns are frozen, as existing
+    /// tools read them by position. The extended format appends the number of
+    /// relocations, merges, splits and rejections since the previous sample,
+    /// the number of complete sections and the counters added since.
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P, format: FileFormat) {
         let path = path.as_ref();
 
-        let mut file =
-            File::create(path).expect(&format!("Couldn't create file {}!", path.display()));
+        let mut file = File::create(path).unwrap_or_else(|_| {
+            panic!("Couldn't create file {}!", path.display())
+        });
 
-        for sample in &self.samples {
-            let _ =
-                write!(
+        let mut previous = self.trimmed;
+
+        for (sample, elder_ages) in self.samples.iter().zip(&self.elder_ages) {
+            let _ = write!(
                 file,
-                "{} {} {} {} {} {} {}\n",
+                "{} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {}",
                 sample.iteration,
                 sample.nodes,
                 sample.sections,
-                sample.merges,
-                sample.splits,
-                sample.relocations,
-                sample.rejections,
+                sample.totals.merges,
+                sample.totals.splits,
+                sample.totals.relocations,
+                sample.totals.rejections,
+                sample.totals.forced_relocations,
+                sample.totals.balancing_relocations,
+                sample.totals.restarts,
+                sample.totals.elder_restarts,
+                sample.totals.relocation_rejections,
+                sample.totals.oversized_sections,
+                sample.totals.join_redirects,
+                sample.totals.unmatched_commits,
+                sample.totals.short_elder_sections,
+                sample.totals.relocation_requests,
+                sample.totals.approval_failures,
+                sample.totals.ageing_events,
+                sample.totals.zombie_relocations,
+                sample.totals.denied_relocations,
+                sample.totals.completed_relocations,
+                sample.totals.cancelled_relocations,
+                elder_ages.compact(),
             );
+
+            if format == FileFormat::Extended {
+                let _ = write!(
+                    file,
+                    " {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {}",
+                    sample.totals.relocations - previous.relocations,
+                    sample.totals.merges - previous.merges,
+                    sample.totals.splits - previous.splits,
+                    sample.totals.rejections - previous.rejections,
+                    sample.complete_sections,
+                    sample.totals.truncated_lookups,
+                    sample.totals.expired_relocation_requests,
+                    sample.totals.rescue_relocations,
+                    sample.totals.prevented_merges,
+                    sample.totals.reused_names,
+                    sample.totals.matured_nodes,
+                    sample.totals.refused_splits,
+                    sample.totals.orphaned_responses,
+                    sample.totals.accepts_after_reject,
+                    sample.totals.mismatched_commits,
+                    sample.totals.random_commit_halves,
+                );
+            }
+
+            let _ = writeln!(file);
+            previous = sample.totals;
         }
     }
 }
+
+impl Snapshot for Stats {
+    fn save(&self, writer: &mut Writer) {
+        self.samples.save(writer);
+        self.elder_ages.save(writer);
+        self.totals.save(writer);
+        self.retention.save(writer);
+        self.trimmed.save(writer);
+    }
+
+    fn load(reader: &mut Reader) -> Result<Self, String> {
+        Ok(Stats {
+            samples: Snapshot::load(reader)?,
+            elder_ages: Snapshot::load(reader)?,
+            totals: Snapshot::load(reader)?,
+            retention: Snapshot::load(reader)?,
+            trimmed: Snapshot::load(reader)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn records_keep_count_beyond_limit() {
+        let mut records = Records::new();
+        for value in 0..Records::<u64>::LIMIT as u64 + 10 {
+            records.push(value);
+        }
+
+        assert_eq!(records.count(), Records::<u64>::LIMIT as u64 + 10);
+        assert_eq!(records.first().len(), Records::<u64>::LIMIT);
+        assert_eq!(records.first()[0], 0);
+    }
+
+    #[test]
+    fn trimmed_stats_write_changes_since_the_trimmed_sample() {
+        let mut stats = Stats::with_retention(2);
+        for iteration in 0..4 {
+            let counters = Counters {
+                relocations: 3,
+                ..Counters::default()
+            };
+            stats.record(iteration, 0, 0, 0, counters, Distribution::new(None));
+        }
+        assert_eq!(stats.samples.len(), 2);
+
+        let path = env::temp_dir().join(format!("datachains_sim-stats-{}", process::id()));
+        stats.write_to_file(&path, FileFormat::Extended);
+        let content = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        // The legacy columns and the elder ages come before the changes.
+        for line in content.lines() {
+            let columns: Vec<_> = line.split(' ').collect();
+            assert_eq!(columns[5], if columns[0] == "2" { "9" } else { "12" });
+            assert_eq!(columns[24], "3");
+        }
+    }
+
+    #[test]
+    fn score_normalises_outcomes() {
+        let weights: ScoreWeights = "completeness=2,relocation-cost=1".parse().unwrap();
+        let outcome = Outcome {
+            completeness: 0.5,
+            relocation_cost: 3.0,
+            rejection_rate: 0.2,
+            elder_churn: 1.0,
+        };
+        assert!((outcome.score(&weights) - (1.0 - 0.75)).abs() < 1e-9);
+
+        // However high the rates, each outcome counts at most its weight.
+        let worst = Outcome {
+            completeness: 0.0,
+            relocation_cost: 1e9,
+            rejection_rate: 1.0,
+            elder_churn: 1e9,
+        };
+        let best = Outcome {
+            completeness: 1.0,
+            ..Outcome::default()
+        };
+        let weights: ScoreWeights = "completeness=1,relocation-cost=1,rejection-rate=1,\
+                                     elder-churn=1"
+            .parse()
+            .unwrap();
+        assert!(worst.score(&weights) > -3.0);
+        assert_eq!(best.score(&weights), 1.0);
+        assert!(best.score(&weights) > outcome.score(&weights));
+    }
+}
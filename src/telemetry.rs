@@ -0,0 +1,97 @@
+//! Telemetry stream for external dashboards.
+//!
+//! One JSON object per sampled iteration is appended to the file as soon as
+//! the iteration completes, one per line, so the file can be ingested while
+//! the simulation is still running. Each object holds the core counters
+//! (cumulative since the start of the run) and the requested distribution
+//! summaries, e.g.:
+//!
+//! `{"iteration":100,"nodes":120,"sections":8,"complete_sections":8,
+//! "merges":1,"splits":8,"relocations":80,"rejections":120,
+//! "relocation_requests":82,"age":{"min":4,"max":9,"avg":5.12}}`
+
+use json::Object;
+use network::Network;
+use params::TelemetrySummary;
+use simulation::Observer;
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::path::Path;
+
+pub struct Telemetry {
+    file: Option<LineWriter<File>>,
+    frequency: u64,
+    summaries: Vec<TelemetrySummary>,
+}
+
+impl Telemetry {
+    /// Stream the telemetry to the file, replacing its content unless
+    /// `append` is set.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        append: bool,
+        frequency: u64,
+        summaries: Vec<TelemetrySummary>,
+    ) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)
+            .map_err(|error| format!("Couldn't create {}: {}", path.display(), error))?;
+
+        Ok(Telemetry {
+            file: Some(LineWriter::new(file)),
+            frequency,
+            summaries,
+        })
+    }
+
+    fn line(&self, network: &Network) -> String {
+        let sample = network.stats().summary();
+        let mut object = Object::new();
+        let _ = object
+            .field("iteration", &sample.iteration())
+            .field("nodes", &sample.nodes())
+            .field("sections", &sample.sections())
+            .field("complete_sections", &sample.complete_sections())
+            .field("merges", &sample.merges())
+            .field("splits", &sample.splits())
+            .field("relocations", &sample.relocations())
+            .field("rejections", &sample.rejections())
+            .field("relocation_requests", &sample.relocation_requests());
+
+        for summary in &self.summaries {
+            let aggregator = match *summary {
+                TelemetrySummary::Age => network.age_distribution().summary(),
+                TelemetrySummary::ElderAge => network.elder_age_distribution().summary(),
+                TelemetrySummary::SectionSize => network.section_size_aggregator(),
+                TelemetrySummary::PrefixLen => network.prefix_len_aggregator(),
+            };
+            let _ = object.field(summary.name(), &aggregator);
+        }
+
+        object.encode()
+    }
+}
+
+impl Observer for Telemetry {
+    fn observe(&mut self, iteration: u64, network: &Network) {
+        if !iteration.is_multiple_of(self.frequency) {
+            return;
+        }
+
+        let line = self.line(network);
+        let result = match self.file {
+            Some(ref mut file) => writeln!(file, "{}", line),
+            None => return,
+        };
+
+        if let Err(error) = result {
+            error!("Couldn't write telemetry, stopping it: {}", error);
+            self.file = None;
+        }
+    }
+}
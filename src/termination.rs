@@ -0,0 +1,77 @@
+//! Reason why the simulation stopped.
+
+use prefix::Prefix;
+use std::fmt;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Reason {
+    /// All the iterations were run.
+    Completed,
+    /// Interrupted by the user (Ctrl+C).
+    Interrupted,
+    /// The network collapsed back to the root section (shrink test).
+    Collapsed,
+    /// A section exceeded the maximum section size (strict mode).
+    SectionTooLarge,
+    /// A section reached the maximum prefix length and can't split.
+    MaxPrefixLength,
+    /// A relocation commit didn't match any incoming relocation (strict mode).
+    UnmatchedCommit,
+    /// End of tick validation failed.
+    InvalidState(String),
+    /// Unexpected panic.
+    Panic(String),
+}
+
+impl Reason {
+    /// Short name of the reason, for categorizing the outcomes of many runs.
+    pub fn category(&self) -> &'static str {
+        match *self {
+            Reason::Completed => "completed",
+            Reason::Interrupted => "interrupted",
+            Reason::Collapsed => "collapsed",
+            Reason::SectionTooLarge => "section-too-large",
+            Reason::MaxPrefixLength => "max-prefix-length",
+            Reason::UnmatchedCommit => "unmatched-commit",
+            Reason::InvalidState(_) => "invalid-state",
+            Reason::Panic(_) => "panic",
+        }
+    }
+
+    /// Returns whether the simulation stopped due to a failure.
+    pub fn is_failure(&self) -> bool {
+        !matches!(
+            *self,
+            Reason::Completed | Reason::Interrupted | Reason::Collapsed
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Termination {
+    pub reason: Reason,
+    pub iteration: u64,
+    /// Section that triggered the termination, if any.
+    pub section: Option<Prefix>,
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "{} at iteration {}",
+            self.reason.category(),
+            self.iteration
+        )?;
+
+        if let Some(prefix) = self.section {
+            write!(fmt, " in section [{}]", prefix)?;
+        }
+
+        match self.reason {
+            Reason::InvalidState(ref detail) |
+            Reason::Panic(ref detail) => write!(fmt, ": {}", detail),
+            _ => Ok(()),
+        }
+    }
+}
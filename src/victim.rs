@@ -0,0 +1,91 @@
+//! Selection of the nodes removed by injected disasters (see
+//! `Params::disasters`), independent of the background drop model.
+
+use node::Node;
+use parse::ParseError;
+use prefix::{Name, Prefix};
+use random::{self, Category};
+use std::fmt;
+use std::str::FromStr;
+
+/// Which nodes a disaster removes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VictimSelector {
+    /// The oldest nodes (ties broken by name).
+    Oldest,
+    /// Random elders.
+    Elders,
+    /// Random nodes.
+    Uniform,
+    /// Random nodes with names matching the prefix.
+    Prefix(Prefix),
+    /// Random nodes controlled by the attacker (see
+    /// `Params::malicious_fraction`), e.g. leaving all at once.
+    Malicious,
+}
+
+impl VictimSelector {
+    /// Names of up to `count` nodes to remove (all the eligible ones if
+    /// `count` is 0).
+    pub fn select<'a, I>(&self, nodes: I, count: usize) -> Vec<Name>
+    where
+        I: IntoIterator<Item = &'a Node>,
+    {
+        let mut eligible: Vec<_> = nodes
+            .into_iter()
+            .filter(|node| match *self {
+                VictimSelector::Oldest | VictimSelector::Uniform => true,
+                VictimSelector::Elders => node.is_elder(),
+                VictimSelector::Prefix(prefix) => prefix.matches(node.name()),
+                VictimSelector::Malicious => node.is_malicious(),
+            })
+            .map(|node| (node.age(), node.name()))
+            .collect();
+
+        if count == 0 || count >= eligible.len() {
+            eligible.sort();
+            return eligible.into_iter().map(|(_, name)| name).collect();
+        }
+
+        if let VictimSelector::Oldest = *self {
+            eligible.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+            return eligible.into_iter().take(count).map(|(_, name)| name).collect();
+        }
+
+        // Sorted, so the sample doesn't depend on the order the nodes came in.
+        let mut names: Vec<_> = eligible.into_iter().map(|(_, name)| name).collect();
+        names.sort();
+        let _category = random::category(Category::Drops);
+        random::sample(names, count)
+    }
+}
+
+impl FromStr for VictimSelector {
+    type Err = ParseError;
+
+    /// Parse from `oldest`, `elders`, `uniform`, `malicious` or `prefix=BITS`.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "oldest" => Ok(VictimSelector::Oldest),
+            "elders" => Ok(VictimSelector::Elders),
+            "uniform" => Ok(VictimSelector::Uniform),
+            "malicious" => Ok(VictimSelector::Malicious),
+            _ if input.starts_with("prefix=") => {
+                Ok(VictimSelector::Prefix(input["prefix=".len()..].parse()?))
+            }
+            _ => Err(ParseError),
+        }
+    }
+}
+
+impl fmt::Display for VictimSelector {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VictimSelector::Oldest => write!(fmt, "oldest"),
+            VictimSelector::Elders => write!(fmt, "elders"),
+            VictimSelector::Uniform => write!(fmt, "uniform"),
+            VictimSelector::Prefix(prefix) => write!(fmt, "prefix={}", prefix),
+            VictimSelector::Malicious => write!(fmt, "malicious"),
+        }
+    }
+}